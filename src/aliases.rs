@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::fs;
+use regex::Regex;
+use crate::states::RESERVED_WORDS;
+
+/// Aliases that are always available, so programs written for other BASIC dialects can use
+/// the keywords they are used to.
+const DEFAULT_ALIASES: [(&str, &str); 3] = [
+    ("print", "output"),
+    ("end", "quit"),
+    ("stop", "quit"),
+];
+
+/// Returns the built-in alias table, mapping each alias to the keyword it stands for.
+pub fn default_aliases() -> HashMap<String, String> {
+    DEFAULT_ALIASES.iter()
+        .map(|(alias, keyword)| (alias.to_string(), keyword.to_string()))
+        .collect()
+}
+
+/// Parses an alias file and adds its aliases to `aliases`. Each line of the file has the form
+/// 'alias = keyword'. Blank lines and lines starting with '#' are ignored.
+///
+/// # Arguments
+/// * `file_string` - Contents of the alias file
+/// * `aliases` - Alias table to add the new aliases to
+///
+/// # Returns
+/// * `Ok(())` - Every alias was added
+/// * `Err(String)` - A message detailing the first line that could not be used
+pub fn parse_aliases(file_string: &str, aliases: &mut HashMap<String, String>) -> Result<(), String> {
+    let alias_regex = Regex::new(r"^(\w+)\s*=\s*(\w+)$").unwrap();
+
+    for (line_number, line) in file_string.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let captures = alias_regex.captures(line)
+            .ok_or(format!("Line {}: malformed alias: {}", line_number + 1, line))?;
+        let alias = &captures[1];
+        let keyword = &captures[2];
+
+        if RESERVED_WORDS.contains(&alias) {
+            return Err(format!("Line {}: {} is already a keyword!", line_number + 1, alias));
+        }
+        if !RESERVED_WORDS.contains(&keyword) {
+            return Err(format!("Line {}: {} is not a keyword!", line_number + 1, keyword));
+        }
+        aliases.insert(alias.to_string(), keyword.to_string());
+    }
+    Ok(())
+}
+
+/// Loads the built-in aliases together with the aliases defined in the file at `file_path`.
+pub fn load_aliases(file_path: &str) -> Result<HashMap<String, String>, String> {
+    let file_string = fs::read_to_string(file_path).map_err(|err| format!("{}: {}", file_path, err))?;
+    let mut aliases = default_aliases();
+    parse_aliases(&file_string, &mut aliases).map_err(|err| format!("{}: {}", file_path, err))?;
+    Ok(aliases)
+}
+
+/// Replaces the first word of `line` with the keyword it stands for, if it is an alias.
+pub fn resolve_alias(aliases: &HashMap<String, String>, line: &str) -> String {
+    let mut parts = line.splitn(2, ' ');
+    let first_word = parts.next().unwrap_or("");
+    match (aliases.get(first_word), parts.next()) {
+        (Some(keyword), Some(rest)) => format!("{} {}", keyword, rest),
+        (Some(keyword), None) => keyword.clone(),
+        (None, _) => line.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{default_aliases, parse_aliases, resolve_alias};
+
+    #[test]
+    fn resolve_default_aliases() {
+        let aliases = default_aliases();
+        assert_eq!(resolve_alias(&aliases, "print $a"), "output $a");
+        assert_eq!(resolve_alias(&aliases, "end"), "quit");
+        assert_eq!(resolve_alias(&aliases, "let $print = 5"), "let $print = 5");
+    }
+
+    #[test]
+    fn parse_alias_file() {
+        let mut aliases = default_aliases();
+        parse_aliases("# classic keywords\n\nshow = output\njump=goto\n", &mut aliases).unwrap();
+        assert_eq!(resolve_alias(&aliases, "show $a"), "output $a");
+        assert_eq!(resolve_alias(&aliases, "jump 4"), "goto 4");
+    }
+
+    #[test]
+    fn parse_alias_for_unknown_keyword() {
+        let result = parse_aliases("show = display\n", &mut default_aliases());
+        assert_eq!(result.err().unwrap(), "Line 1: display is not a keyword!");
+    }
+
+    #[test]
+    fn parse_alias_redefining_keyword() {
+        let result = parse_aliases("goto = quit\n", &mut default_aliases());
+        assert_eq!(result.err().unwrap(), "Line 1: goto is already a keyword!");
+    }
+
+    #[test]
+    fn parse_malformed_alias() {
+        let result = parse_aliases("show output\n", &mut default_aliases());
+        assert_eq!(result.err().unwrap(), "Line 1: malformed alias: show output");
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use crate::console::Console;
+use crate::parser::{Expression, Instruction};
+use crate::prog_data::ProgramData;
+use crate::runner::{Outcome, RuntimeError};
+use crate::states::{get_state, NewState, States};
+
+/// Number of steps a program may take before it lets other tasks run.
+const STEPS_PER_YIELD: usize = 256;
+
+/// Future returned by the methods of `AsyncIo`.
+pub type IoFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, String>> + Send + 'a>>;
+
+/// Provides the input and output of a program run with `run_async`. This is the asynchronous
+/// counterpart of `Console`, and can be implemented on top of any async runtime.
+pub trait AsyncIo: Send {
+    /// Reads a single line of input without its line terminator. `Ok(None)` is returned once
+    /// the input has been exhausted.
+    fn read_line(&mut self) -> IoFuture<'_, Option<String>>;
+
+    /// Reads a single line of input, giving up after `timeout`. The default implementation never
+    /// gives up, as timers belong to the async runtime: implementations should override it
+    /// (with `tokio::time::timeout`, for example) to support 'input timeout'.
+    fn read_line_timeout(&mut self, _timeout: Duration) -> IoFuture<'_, Option<String>> {
+        self.read_line()
+    }
+
+    /// Writes a line of program output.
+    fn write_line(&mut self, line: String) -> IoFuture<'_, ()>;
+
+    /// Writes a prompt for the input that is about to be read, without ending the line. The
+    /// default implementation writes it as a line of its own.
+    fn write_prompt(&mut self, prompt: String) -> IoFuture<'_, ()> {
+        self.write_line(prompt)
+    }
+}
+
+/// Input that has been awaited ahead of the instruction that reads it, and output waiting to
+/// be written.
+#[derive(Default)]
+struct Exchange {
+    // `None` stands for a read that found no line (a timeout, or the end of the input)
+    input: VecDeque<Option<String>>,
+    output: Vec<String>,
+}
+
+/// Console installed while a program runs asynchronously. It never blocks: input is awaited
+/// before the instruction that needs it runs, and output is written once the step is done.
+struct ExchangeConsole {
+    exchange: Arc<Mutex<Exchange>>,
+}
+
+impl Console for ExchangeConsole {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        Ok(self.exchange.lock().unwrap().input.pop_front().flatten())
+    }
+
+    fn read_line_timeout(&mut self, _timeout: Duration) -> Result<Option<String>, String> {
+        self.read_line()
+    }
+
+    fn read_all(&mut self) -> Result<String, String> {
+        let lines: Vec<String> = self.exchange.lock().unwrap().input.drain(..).flatten().collect();
+        Ok(lines.into_iter().map(|line| line + "\n").collect())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        self.exchange.lock().unwrap().output.push(line.to_string());
+        Ok(())
+    }
+
+    // Prompts are written by `await_input`, as they must come out before the input is awaited
+    fn write_prompt(&mut self, _prompt: &str) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Future that is pending once, so that other tasks on the same thread get a chance to run.
+struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Awaits the input that the current instruction is about to read, after writing its prompt if
+/// the instruction is just `starting`. Only the instruction itself is looked at: the tail of an
+/// 'if' statement is run as an instruction of its own, so input is never awaited for a branch
+/// that is not taken.
+async fn await_input(data: &ProgramData, exchange: &Mutex<Exchange>, io: &mut dyn AsyncIo,
+                     starting: bool, input_done: &mut bool) -> Result<(), String> {
+    let expression = match data.instruction() {
+        Some(Ok(Instruction::Let { value, .. })) => value,
+        _ => return Ok(()),
+    };
+    // An instruction runs through several states, but its prompt is only written once
+    if let (true, Expression::PromptedInput(prompt) | Expression::InputNum(Some(prompt))) = (starting, expression) {
+        io.write_prompt(prompt.clone()).await?;
+    }
+    if *input_done || !exchange.lock().unwrap().input.is_empty() {
+        return Ok(());
+    }
+
+    let mut lines = Vec::new();
+    match expression {
+        Expression::Input | Expression::PromptedInput(_) | Expression::InputNum(_) => lines.push(io.read_line().await?),
+        Expression::TimedInput { timeout, .. } => lines.push(io.read_line_timeout(Duration::from_secs(*timeout)).await?),
+        Expression::InputAll => {
+            while let Some(line) = io.read_line().await? {
+                lines.push(Some(line));
+            }
+            *input_done = true;
+        },
+        _ => return Ok(()),
+    }
+    if matches!(expression, Expression::Input | Expression::PromptedInput(_) | Expression::InputNum(_)) && lines == [None] {
+        *input_done = true;
+    }
+    exchange.lock().unwrap().input.extend(lines);
+    Ok(())
+}
+
+/// Runs a program until it finishes or encounters an error, like `runner::run`, but reads and
+/// writes through `io` instead of blocking on the program's console. Programs that do no I/O
+/// for a while still let other tasks run every few hundred steps, so many programs can share
+/// the threads of an async runtime. The networking instructions still block.
+///
+/// # Arguments
+/// * `data` - The program to run. Its console is replaced by `io`.
+/// * `io` - Where the program reads its input from and writes its output to
+///
+/// # Returns
+/// * `Ok(Outcome)` - The exit code and the final program data of a program that finished
+/// * `Err(RuntimeError)` - The error that stopped the program
+pub async fn run_async(mut data: ProgramData, io: &mut dyn AsyncIo) -> Result<Outcome, RuntimeError> {
+    let exchange = Arc::new(Mutex::new(Exchange::default()));
+    data.set_console(Box::new(ExchangeConsole { exchange: exchange.clone() }));
+
+    let mut state = get_state(States::ExecuteState);
+    let mut input_done = false;
+    let mut steps = 0;
+    loop {
+        let line = data.get_index();
+        let io_error = |message: String| RuntimeError { line, message, called_from: Vec::new() };
+
+        await_input(&data, &exchange, io, state.starts_instruction(), &mut input_done).await.map_err(io_error)?;
+        let result = state.execute(data);
+
+        let output: Vec<String> = exchange.lock().unwrap().output.drain(..).collect();
+        for output_line in output {
+            io.write_line(output_line).await.map_err(io_error)?;
+        }
+
+        match result {
+            NewState::Continue(next_data, next_state) => {
+                data = next_data;
+                state = next_state;
+            },
+            NewState::Finished(data, exit_code) => return Ok(Outcome { exit_code, data }),
+            NewState::Error(data, msg) => return Err(RuntimeError::in_program(&data, line, &msg)),
+        }
+
+        steps += 1;
+        if steps % STEPS_PER_YIELD == 0 {
+            YieldNow { yielded: false }.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, LinkedList, VecDeque};
+    use std::future::Future;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+    use crate::prog_data::ProgramData;
+    use super::{run_async, AsyncIo, IoFuture};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Minimal executor, standing in for an async runtime.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    struct ScriptedIo {
+        input: VecDeque<String>,
+        output: Vec<String>,
+        reads: usize,
+    }
+
+    impl AsyncIo for ScriptedIo {
+        fn read_line(&mut self) -> IoFuture<'_, Option<String>> {
+            self.reads += 1;
+            Box::pin(async move { Ok(self.input.pop_front()) })
+        }
+
+        fn write_line(&mut self, line: String) -> IoFuture<'_, ()> {
+            Box::pin(async move {
+                self.output.push(line);
+                Ok(())
+            })
+        }
+    }
+
+    fn program(lines: Vec<&str>) -> ProgramData {
+        ProgramData::new(lines.into_iter().map(String::from).collect(), HashMap::new(), LinkedList::new(), 0)
+    }
+
+    fn io(input: Vec<&str>) -> ScriptedIo {
+        ScriptedIo { input: input.into_iter().map(String::from).collect(), output: Vec::new(), reads: 0 }
+    }
+
+    #[test]
+    fn run_program_with_async_io() {
+        let mut io = io(vec!["3", "4"]);
+        let data = program(vec!["let $a = input", "let $b = input", "let $c = $a + $b", "output $c", "quit"]);
+        let outcome = block_on(run_async(data, &mut io)).ok().unwrap();
+        assert_eq!(outcome.exit_code, 0);
+        assert_eq!(io.output, vec!["7"]);
+    }
+
+    #[test]
+    fn prompt_is_written_before_input_is_awaited() {
+        let mut io = io(vec!["Bob"]);
+        let data = program(vec!["let $a = input \"Name\"", "output $a"]);
+        block_on(run_async(data, &mut io)).ok().unwrap();
+        assert_eq!(io.output, vec!["Name", "Bob"]);
+    }
+
+    #[test]
+    fn input_is_only_awaited_when_read() {
+        // The 'if' is never taken, so its input is never asked for
+        let mut io = io(vec!["hello"]);
+        let data = program(vec!["let $a = 1", "let $b = 2", "if $a = $b let $c = input", "let $d = input", "output $d"]);
+        block_on(run_async(data, &mut io)).ok().unwrap();
+        assert_eq!(io.output, vec!["hello"]);
+        assert_eq!(io.reads, 1);
+    }
+
+    #[test]
+    fn read_all_async_input() {
+        let mut io = io(vec!["a", "b"]);
+        let data = program(vec!["let $all = input all", "let $x = input", "quit"]);
+        let outcome = block_on(run_async(data, &mut io)).ok().unwrap();
+        assert_eq!(outcome.data.get_var("all").unwrap(), "a\nb");
+        assert_eq!(outcome.data.get_var("x").unwrap(), "");
+    }
+
+    #[test]
+    fn long_running_program_yields() {
+        let mut io = io(Vec::new());
+        let mut data = program(vec!["let $a = 0", "let $one = 1", "let $a = $a + $one", "if $a < $limit goto 2",
+                                    "output $a", "quit"]);
+        data.set_var(String::from("limit"), String::from("1000"));
+        data.set_dialect(crate::dialect::Dialect::V2);
+        block_on(run_async(data, &mut io)).ok().unwrap();
+        assert_eq!(io.output, vec!["1000"]);
+    }
+}
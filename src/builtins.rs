@@ -0,0 +1,162 @@
+use std::cmp::Ordering;
+use crate::prog_data::Value;
+
+/// An argument of a builtin: the text it was given as (a literal, or a variable with its '$'),
+/// which errors name it by, and its value.
+pub type Argument = (String, Value);
+
+/// A function built into the language, called as `let $x = <name> <arg> ...`. Each argument is a
+/// literal or a variable.
+pub struct Builtin {
+    pub name: &'static str,
+    /// The number of arguments the builtin takes
+    pub arity: usize,
+    /// Computes the result of the builtin from its arguments
+    pub apply: fn(&[Argument]) -> Result<Value, String>,
+    /// The Python function `builtin_<name>` that programs exported to Python call instead, which
+    /// takes the value and the text of each argument
+    pub python: &'static str,
+}
+
+/// Every builtin of the language. Adding an entry here is all it takes to add a builtin: the
+/// parser, the interpreter, the Python exporter and the editor grammars all read this table.
+pub const BUILTINS: [Builtin; 5] = [
+    Builtin {
+        name: "abs",
+        arity: 1,
+        apply: abs,
+        python: "def builtin_abs(value, source):\n    return str(abs(numeric(value, source)))\n",
+    },
+    Builtin {
+        name: "sign",
+        arity: 1,
+        apply: sign,
+        python: "def builtin_sign(value, source):\n    number = numeric(value, source)\n    return str((number > 0) - (number < 0))\n",
+    },
+    Builtin {
+        name: "sqrt",
+        arity: 1,
+        apply: sqrt,
+        python: "def builtin_sqrt(value, source):\n    number = numeric(value, source)\n    if number < 0:\n        \
+                 fail(\"Cannot take the square root of \" + source + \", which is negative!\")\n    return str(math.sqrt(number))\n",
+    },
+    Builtin {
+        name: "min",
+        arity: 2,
+        apply: min,
+        python: "def builtin_min(lhs, lhs_source, rhs, rhs_source):\n    lhs_number = numeric(lhs, lhs_source)\n    \
+                 return rhs if numeric(rhs, rhs_source) < lhs_number else lhs\n",
+    },
+    Builtin {
+        name: "max",
+        arity: 2,
+        apply: max,
+        python: "def builtin_max(lhs, lhs_source, rhs, rhs_source):\n    lhs_number = numeric(lhs, lhs_source)\n    \
+                 return rhs if numeric(rhs, rhs_source) > lhs_number else lhs\n",
+    },
+];
+
+/// Finds the builtin with the given name.
+pub fn find_builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+/// Reads an argument as a float, which every number can be read as.
+fn float((source, value): &Argument) -> Result<f64, String> {
+    value.as_float().ok_or(format!("{} is not a numeric value!", source))
+}
+
+/// Compares two numbers, exactly if they are both integers.
+fn compare(lhs: &Argument, rhs: &Argument) -> Result<Ordering, String> {
+    let (lhs_float, rhs_float) = (float(lhs)?, float(rhs)?);
+    Ok(match (lhs.1.as_int(), rhs.1.as_int()) {
+        (Some(lhs), Some(rhs)) => lhs.cmp(&rhs),
+        // Arguments are finite, so they always compare
+        _ => lhs_float.partial_cmp(&rhs_float).unwrap(),
+    })
+}
+
+fn abs(args: &[Argument]) -> Result<Value, String> {
+    let (source, value) = &args[0];
+    match value.as_int() {
+        Some(number) => number.checked_abs().map(Value::Int)
+            .ok_or(format!("The absolute value of {} is too large to hold!", source)),
+        None => Ok(Value::Float(float(&args[0])?.abs())),
+    }
+}
+
+/// -1, 0 or 1, as the number is negative, zero or positive.
+fn sign(args: &[Argument]) -> Result<Value, String> {
+    let sign = match args[0].1.as_int() {
+        Some(number) => number.signum(),
+        None => match float(&args[0])? {
+            number if number > 0.0 => 1,
+            number if number < 0.0 => -1,
+            _ => 0,
+        },
+    };
+    Ok(Value::Int(sign))
+}
+
+/// The square root, which is always a float.
+fn sqrt(args: &[Argument]) -> Result<Value, String> {
+    let number = float(&args[0])?;
+    if number < 0.0 {
+        return Err(format!("Cannot take the square root of {}, which is negative!", args[0].0));
+    }
+    Ok(Value::Float(number.sqrt()))
+}
+
+/// The smaller of two numbers, as it was given. The first is given if they are equal.
+fn min(args: &[Argument]) -> Result<Value, String> {
+    let smaller = if compare(&args[1], &args[0])? == Ordering::Less { &args[1] } else { &args[0] };
+    Ok(smaller.1.clone())
+}
+
+/// The larger of two numbers, as it was given. The first is given if they are equal.
+fn max(args: &[Argument]) -> Result<Value, String> {
+    let larger = if compare(&args[1], &args[0])? == Ordering::Greater { &args[1] } else { &args[0] };
+    Ok(larger.1.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::prog_data::Value;
+    use super::{find_builtin, BUILTINS};
+
+    fn call(name: &str, args: &[(&str, &str)]) -> Result<String, String> {
+        let args: Vec<(String, Value)> = args.iter().map(|(source, value)| (source.to_string(), Value::from_text(value))).collect();
+        (find_builtin(name).unwrap().apply)(&args).map(|value| value.to_string())
+    }
+
+    #[test]
+    fn apply_builtins() {
+        assert_eq!(call("abs", &[("$a", "-7")]), Ok(String::from("7")));
+        assert_eq!(call("abs", &[("$a", "-2.5")]), Ok(String::from("2.5")));
+        assert_eq!(call("sign", &[("$a", "-7")]), Ok(String::from("-1")));
+        assert_eq!(call("sign", &[("$a", "0.0")]), Ok(String::from("0")));
+        assert_eq!(call("sign", &[("$a", "0.5")]), Ok(String::from("1")));
+        assert_eq!(call("sqrt", &[("$a", "16")]), Ok(String::from("4.0")));
+        assert_eq!(call("min", &[("$a", "3"), ("$b", "2.5")]), Ok(String::from("2.5")));
+        assert_eq!(call("max", &[("$a", "3"), ("4", "4")]), Ok(String::from("4")));
+        // Integers too large for a float to tell apart are still compared exactly
+        assert_eq!(call("max", &[("$a", "18014398509481985"), ("$b", "18014398509481984")]),
+                   Ok(String::from("18014398509481985")));
+    }
+
+    #[test]
+    fn report_invalid_arguments() {
+        assert_eq!(call("abs", &[("$a", "seven")]), Err(String::from("$a is not a numeric value!")));
+        assert_eq!(call("min", &[("$a", "1"), ("$b", "two")]), Err(String::from("$b is not a numeric value!")));
+        assert_eq!(call("sqrt", &[("-4", "-4")]), Err(String::from("Cannot take the square root of -4, which is negative!")));
+        assert_eq!(call("abs", &[("$a", &i128::MIN.to_string())]),
+                   Err(String::from("The absolute value of $a is too large to hold!")));
+    }
+
+    #[test]
+    fn python_helpers_match_their_builtins() {
+        for builtin in &BUILTINS {
+            assert!(builtin.python.starts_with(&format!("def builtin_{}(", builtin.name)), "{} has no Python helper", builtin.name);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::fs;
+
+/// File extension of program bundles.
+pub const BUNDLE_EXTENSION: &str = ".basz";
+
+/// Names the main program of a bundle may have, in the order they are looked for.
+const MAIN_PROGRAMS: [&str; 2] = ["main.bas", "main.txt"];
+
+const BLOCK_SIZE: usize = 512;
+
+/// Largest bundle, in bytes, that will be opened.
+const MAX_BUNDLE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// A program bundle: a tar archive holding a main program along with the modules and data files
+/// it uses. Files are looked up by their path relative to the root of the bundle.
+pub struct Bundle {
+    files: HashMap<String, Vec<u8>>,
+}
+
+/// Reads a NUL terminated string from a tar header field.
+fn header_field(header: &[u8]) -> String {
+    let end = header.iter().position(|byte| *byte == 0).unwrap_or(header.len());
+    String::from_utf8_lossy(&header[..end]).trim().to_string()
+}
+
+/// Reads an octal number from a tar header field.
+fn header_number(header: &[u8]) -> Result<usize, String> {
+    let field = header_field(header);
+    usize::from_str_radix(&field, 8).map_err(|_| format!("Invalid number in bundle header: {}", field))
+}
+
+/// Removes './' and leading '/' from a path, so every file can be found by its plain relative path.
+fn normalize_path(path: &str) -> String {
+    path.split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
+impl Bundle {
+    /// Opens the bundle at `file_path`.
+    pub fn open(file_path: &str) -> Result<Self, String> {
+        let metadata = fs::metadata(file_path).map_err(|err| format!("{}: {}", file_path, err))?;
+        if metadata.len() > MAX_BUNDLE_SIZE {
+            return Err(format!("{}: bundle is larger than {} bytes!", file_path, MAX_BUNDLE_SIZE));
+        }
+        let bytes = fs::read(file_path).map_err(|err| format!("{}: {}", file_path, err))?;
+        Bundle::from_tar(&bytes).map_err(|err| format!("{}: {}", file_path, err))
+    }
+
+    /// Reads a bundle from the bytes of a tar archive. Directories, links and other special
+    /// entries are skipped.
+    ///
+    /// # Returns
+    /// * `Ok(Bundle)` - The files held by the archive
+    /// * `Err(String)` - A message detailing why the archive could not be read
+    pub fn from_tar(bytes: &[u8]) -> Result<Self, String> {
+        let mut files = HashMap::new();
+        let mut offset = 0;
+
+        while offset + BLOCK_SIZE <= bytes.len() {
+            let header = &bytes[offset..offset + BLOCK_SIZE];
+
+            // The archive ends with blocks of zeroes
+            if header.iter().all(|byte| *byte == 0) {
+                return Ok(Bundle { files });
+            }
+
+            // The checksum is the sum of every header byte, counting the checksum field as spaces
+            let checksum: usize = header.iter().enumerate()
+                .map(|(index, byte)| if (148..156).contains(&index) { b' ' as usize } else { *byte as usize })
+                .sum();
+            if header_number(&header[148..156])? != checksum {
+                return Err(String::from("Bundle is corrupt: header checksum does not match!"));
+            }
+
+            let name = match header_field(&header[345..500]) {
+                prefix if prefix.is_empty() => header_field(&header[0..100]),
+                prefix => format!("{}/{}", prefix, header_field(&header[0..100])),
+            };
+            let size = header_number(&header[124..136])?;
+            let start = offset + BLOCK_SIZE;
+            if start + size > bytes.len() {
+                return Err(format!("Bundle is corrupt: {} is truncated!", name));
+            }
+
+            // Regular files have the type '0', or NUL in older archives
+            if header[156] == b'0' || header[156] == 0 {
+                files.insert(normalize_path(&name), bytes[start..start + size].to_vec());
+            }
+            offset = start + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+        }
+        Err(String::from("Bundle is corrupt: missing end of archive!"))
+    }
+
+    /// Returns the contents of the file at `path`, relative to the root of the bundle.
+    pub fn read(&self, path: &str) -> Result<&[u8], String> {
+        self.files.get(&normalize_path(path))
+            .map(Vec::as_slice)
+            .ok_or(format!("{} not found in bundle!", path))
+    }
+
+    /// Returns the path of the main program of the bundle.
+    pub fn main_program(&self) -> Result<&'static str, String> {
+        MAIN_PROGRAMS.iter()
+            .find(|path| self.files.contains_key(**path))
+            .copied()
+            .ok_or(format!("Bundle has no main program! (expected {})", MAIN_PROGRAMS.join(" or ")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Bundle;
+
+    /// Builds a tar archive holding the given files.
+    fn tar(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (name, contents) in files {
+            let mut header = [0u8; 512];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[100..107].copy_from_slice(b"0000644");
+            header[124..135].copy_from_slice(format!("{:011o}", contents.len()).as_bytes());
+            header[156] = b'0';
+            header[257..262].copy_from_slice(b"ustar");
+            header[148..156].copy_from_slice(b"        ");
+            let checksum: usize = header.iter().map(|byte| *byte as usize).sum();
+            header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+
+            bytes.extend_from_slice(&header);
+            bytes.extend_from_slice(contents.as_bytes());
+            bytes.resize(bytes.len().div_ceil(512) * 512, 0);
+        }
+        bytes.resize(bytes.len() + 1024, 0);
+        bytes
+    }
+
+    #[test]
+    fn read_files_from_bundle() {
+        let bundle = Bundle::from_tar(&tar(&[("./main.bas", "0 quit\n"), ("data/words.txt", "hello")])).unwrap();
+        assert_eq!(bundle.main_program().unwrap(), "main.bas");
+        assert_eq!(bundle.read("main.bas").unwrap(), b"0 quit\n");
+        assert_eq!(bundle.read("./data/words.txt").unwrap(), b"hello");
+        assert_eq!(bundle.read("missing.txt").err().unwrap(), "missing.txt not found in bundle!");
+    }
+
+    #[test]
+    fn bundle_without_main_program() {
+        let bundle = Bundle::from_tar(&tar(&[("lib.bas", "0 quit\n")])).unwrap();
+        assert_eq!(bundle.main_program().err().unwrap(), "Bundle has no main program! (expected main.bas or main.txt)");
+    }
+
+    #[test]
+    fn reject_corrupt_bundle() {
+        let mut bytes = tar(&[("main.bas", "0 quit\n")]);
+        bytes[0] = b'n';
+        assert_eq!(Bundle::from_tar(&bytes).err().unwrap(), "Bundle is corrupt: header checksum does not match!");
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::BTreeMap;
+use crate::parser::parse_line;
+use crate::states::RESERVED_WORDS;
+
+/*
+Subset of classic BASIC that can be converted. Keywords and variable names are not case
+sensitive, and a '$' at the end of a variable name (a string variable) is allowed.
+
+line      := number statement
+statement := "PRINT" [value] | "INPUT" [string ";"] variable
+           | ["LET"] variable "=" value [operator value]
+           | "IF" value relation value "THEN" (number | statement)
+           | "GOTO" number | "END" | "STOP" | "REM" anything
+value     := variable | number | string
+relation  := "=" | "<>" | "<" | "<=" | ">" | ">="
+operator  := "+" | "-" | "*" | "/"
+ */
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Number(String),
+    Str(String),
+    Symbol(String),
+}
+
+/// Splits a line of classic BASIC (without its line number) into tokens. Words are upper-cased.
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        let take_while = |pos: usize, accept: &dyn Fn(char) -> bool| {
+            (pos..chars.len()).find(|i| !accept(chars[*i])).unwrap_or(chars.len())
+        };
+        if c.is_whitespace() {
+            pos += 1;
+        } else if c.is_ascii_alphabetic() {
+            let mut end = take_while(pos, &|c| c.is_ascii_alphanumeric());
+            if chars.get(end) == Some(&'$') {
+                end += 1;
+            }
+            tokens.push(Token::Word(chars[pos..end].iter().collect::<String>().to_ascii_uppercase()));
+            pos = end;
+        } else if c.is_ascii_digit() || c == '.' {
+            let end = take_while(pos, &|c| c.is_ascii_digit() || c == '.');
+            tokens.push(Token::Number(chars[pos..end].iter().collect()));
+            pos = end;
+        } else if c == '"' {
+            let end = (pos + 1..chars.len()).find(|i| chars[*i] == '"').ok_or("unterminated string")?;
+            tokens.push(Token::Str(chars[pos + 1..end].iter().collect()));
+            pos = end + 1;
+        } else {
+            let rest: String = chars[pos..].iter().collect();
+            let symbol = ["<>", "<=", ">=", "=", "<", ">", "+", "-", "*", "/", ";", ",", ":", "(", ")"].iter()
+                .find(|symbol| rest.starts_with(*symbol))
+                .ok_or(format!("unexpected character '{}'", c))?;
+            tokens.push(Token::Symbol(symbol.to_string()));
+            pos += symbol.len();
+        }
+    }
+    Ok(tokens)
+}
+
+/// An instruction of the converted program. Jumps hold the classic line number they go to,
+/// which is only known in the converted program once every line has been converted.
+#[derive(Clone, Debug, PartialEq)]
+enum Converted {
+    Instruction(String),
+    Jump { prefix: String, target: u64 },
+}
+
+/// Conversion of a single statement: instructions that set up the constants it uses, which are
+/// safe to run ahead of time, followed by the instructions of the statement itself.
+#[derive(Default)]
+struct Statement {
+    setup: Vec<Converted>,
+    body: Vec<Converted>,
+}
+
+/// Converts the statements of a single line.
+struct LineConverter {
+    tokens: Vec<Token>,
+    pos: usize,
+    // Number of constants set up so far, used to name them
+    constants: usize,
+}
+
+fn is_keyword(word: &str) -> bool {
+    ["PRINT", "INPUT", "LET", "IF", "THEN", "GOTO", "END", "STOP", "REM"].contains(&word)
+}
+
+/// Names the variable a classic BASIC variable is converted to: 'A1' becomes '$a1' and the
+/// string variable 'N$' becomes '$n_str'.
+fn variable_name(name: &str) -> Result<String, String> {
+    let converted = match name.strip_suffix('$') {
+        Some(name) => format!("{}_str", name.to_ascii_lowercase()),
+        None => name.to_ascii_lowercase(),
+    };
+    if RESERVED_WORDS.contains(&converted.as_str()) {
+        return Err(format!("variable {} is a keyword of COS341Basic", name));
+    }
+    Ok(format!("${}", converted))
+}
+
+impl LineConverter {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Symbol(found)) if found == symbol => Ok(()),
+            _ => Err(format!("expected '{}'", symbol)),
+        }
+    }
+
+    fn line_number(&mut self) -> Result<u64, String> {
+        match self.next() {
+            Some(Token::Number(number)) => number.parse().map_err(|_| format!("invalid line number {}", number)),
+            _ => Err(String::from("expected a line number")),
+        }
+    }
+
+    fn variable(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Word(word)) if !is_keyword(&word) => variable_name(&word),
+            _ => Err(String::from("expected a variable")),
+        }
+    }
+
+    /// Converts a value to a variable, setting up a constant to hold it if it is a literal.
+    fn operand(&mut self, statement: &mut Statement) -> Result<String, String> {
+        let literal = match self.peek() {
+            Some(Token::Number(number)) if number.contains('.') => return Err(String::from("only whole numbers are supported")),
+            Some(Token::Number(number)) => number.clone(),
+            Some(Token::Str(text)) => format!("\"{}\"", text),
+            Some(Token::Symbol(symbol)) if symbol == "-" => return Err(String::from("negative numbers are not supported")),
+            Some(Token::Symbol(symbol)) if symbol == "(" => return Err(String::from("parentheses are not supported")),
+            _ => return self.variable(),
+        };
+        self.next();
+        self.constants += 1;
+        let constant = format!("$_c{}", self.constants);
+        statement.setup.push(Converted::Instruction(format!("let {} = {}", constant, literal)));
+        Ok(constant)
+    }
+
+    /// Converts the value of an assignment, which is a single value or two values and an operator.
+    fn value(&mut self, statement: &mut Statement) -> Result<String, String> {
+        let literal = match self.peek() {
+            Some(Token::Number(number)) if !number.contains('.') => Some(number.clone()),
+            Some(Token::Str(text)) => Some(format!("\"{}\"", text)),
+            _ => None,
+        };
+        let is_operator = |token: Option<&Token>| matches!(token, Some(Token::Symbol(symbol)) if ["+", "-", "*", "/"].contains(&symbol.as_str()));
+        // A literal on its own is assigned directly
+        if let Some(literal) = literal.filter(|_| !is_operator(self.tokens.get(self.pos + 1))) {
+            self.next();
+            return Ok(literal);
+        }
+
+        let lhs = self.operand(statement)?;
+        if !is_operator(self.peek()) {
+            return Ok(lhs);
+        }
+        let Some(Token::Symbol(operator)) = self.next() else {
+            unreachable!()
+        };
+        let rhs = self.operand(statement)?;
+        if is_operator(self.peek()) {
+            return Err(String::from("expressions may only have one operator"));
+        }
+        Ok(format!("{} {} {}", lhs, operator, rhs))
+    }
+
+    fn statement(&mut self) -> Result<Statement, String> {
+        let mut statement = Statement::default();
+        let keyword = match self.peek() {
+            Some(Token::Word(word)) if is_keyword(word) => {
+                let word = word.clone();
+                self.next();
+                word
+            },
+            Some(Token::Word(_)) => String::from("LET"),
+            Some(_) => return Err(String::from("expected a statement")),
+            None => return Err(String::from("expected a statement")),
+        };
+
+        match keyword.as_str() {
+            "PRINT" if self.peek().is_none() => {
+                statement.body.push(Converted::Instruction(String::from("let $_blank = \"\"")));
+                statement.body.push(Converted::Instruction(String::from("output $_blank")));
+            },
+            "PRINT" => {
+                let value = self.operand(&mut statement)?;
+                statement.body.push(Converted::Instruction(format!("output {}", value)));
+            },
+            "INPUT" => {
+                if let Some(Token::Str(prompt)) = self.peek().cloned() {
+                    self.next();
+                    self.expect_symbol(";")?;
+                    self.constants += 1;
+                    let constant = format!("$_c{}", self.constants);
+                    statement.body.push(Converted::Instruction(format!("let {} = \"{}\"", constant, prompt)));
+                    statement.body.push(Converted::Instruction(format!("output {}", constant)));
+                }
+                let name = self.variable()?;
+                statement.body.push(Converted::Instruction(format!("let {} = input", name)));
+            },
+            "LET" => {
+                let name = self.variable()?;
+                self.expect_symbol("=")?;
+                let value = self.value(&mut statement)?;
+                statement.body.push(Converted::Instruction(format!("let {} = {}", name, value)));
+            },
+            "IF" => {
+                let lhs = self.operand(&mut statement)?;
+                let relation = match self.next() {
+                    Some(Token::Symbol(symbol)) if symbol == "<>" => String::from("!="),
+                    Some(Token::Symbol(symbol)) if ["=", "<", "<=", ">", ">="].contains(&symbol.as_str()) => symbol,
+                    _ => return Err(String::from("expected a comparison")),
+                };
+                let rhs = self.operand(&mut statement)?;
+                let condition = format!("if {} {} {} ", lhs, relation, rhs);
+                let then = match self.next() {
+                    Some(Token::Word(word)) if word == "THEN" && matches!(self.peek(), Some(Token::Number(_))) => {
+                        Converted::Jump { prefix: String::from("goto "), target: self.line_number()? }
+                    },
+                    Some(Token::Word(word)) if word == "THEN" || word == "GOTO" => {
+                        if word == "GOTO" {
+                            self.pos -= 1;
+                        }
+                        let then = self.statement()?;
+                        let [then_body] = <[Converted; 1]>::try_from(then.body)
+                            .map_err(|_| String::from("the statement after THEN must convert to a single instruction"))?;
+                        statement.setup.extend(then.setup);
+                        then_body
+                    },
+                    _ => return Err(String::from("expected THEN")),
+                };
+                statement.body.push(match then {
+                    Converted::Instruction(then) => Converted::Instruction(condition + &then),
+                    Converted::Jump { prefix, target } => Converted::Jump { prefix: condition + &prefix, target },
+                });
+            },
+            "GOTO" => {
+                let target = self.line_number()?;
+                statement.body.push(Converted::Jump { prefix: String::from("goto "), target });
+            },
+            "END" | "STOP" => statement.body.push(Converted::Instruction(String::from("quit"))),
+            "REM" => self.pos = self.tokens.len(),
+            _ => return Err(format!("{} is not supported", keyword)),
+        }
+        Ok(statement)
+    }
+
+    /// Converts the whole line, which must hold a single statement.
+    fn line(&mut self) -> Result<Vec<Converted>, String> {
+        if let Some(Token::Word(word)) = self.peek() {
+            if !is_keyword(word) && !matches!(self.tokens.get(1), Some(Token::Symbol(symbol)) if symbol == "=") {
+                return Err(format!("{} is not supported", word));
+            }
+        }
+        let statement = self.statement()?;
+        match self.peek() {
+            None => {},
+            Some(Token::Symbol(symbol)) if symbol == ":" => return Err(String::from("only one statement per line is supported")),
+            Some(Token::Symbol(symbol)) if symbol == ";" || symbol == "," => {
+                return Err(String::from("PRINT can only print a single value"))
+            },
+            Some(_) => return Err(String::from("unexpected text at the end of the line")),
+        }
+        Ok(statement.setup.into_iter().chain(statement.body).collect())
+    }
+}
+
+/// Converts a program written in a subset of classic BASIC into a COS341Basic program. Every
+/// line of the classic program becomes one or more instructions, and `GOTO` targets are renumbered
+/// to match. The converted program is declared as dialect v2, so numbers are compared by value as
+/// they are in classic BASIC. Literals used in comparisons and arithmetic are stored in `$_c`
+/// variables first, and string variables such as `N$` become `$n_str`.
+///
+/// # Arguments
+/// * `source` - The classic BASIC program, one numbered line per line
+///
+/// # Returns
+/// * `Ok(String)` - The converted program, ready to be saved and run
+/// * `Err(String)` - Every line that could not be converted, one per line, with the reason why
+pub fn convert_classic(source: &str) -> Result<String, String> {
+    let mut lines = BTreeMap::new();
+    let mut errors = Vec::new();
+    for line in source.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let digits = line.find(|c: char| !c.is_ascii_digit()).unwrap_or(line.len());
+        let Ok(number) = line[..digits].parse::<u64>() else {
+            errors.push(format!("Line without a line number: {}", line));
+            continue;
+        };
+        let converted = tokenize(&line[digits..]).and_then(|tokens| LineConverter { tokens, pos: 0, constants: 0 }.line());
+        let converted = converted.map_err(|err| format!("Line {}: {}", number, err));
+        if lines.insert(number, converted).is_some() {
+            errors.push(format!("Line {}: line number is used more than once", number));
+        }
+    }
+
+    // Every classic line starts at the first instruction converted from it, or from the lines
+    // after it if it converted to nothing (such as a REM)
+    let mut starts = BTreeMap::new();
+    let mut next_index = 1;
+    for (number, converted) in &lines {
+        starts.insert(*number, next_index);
+        next_index += converted.as_ref().map(Vec::len).unwrap_or(0);
+    }
+
+    let mut code = vec![String::from("dialect v2")];
+    for (number, converted) in lines {
+        let converted = match converted {
+            Ok(converted) => converted,
+            Err(err) => {
+                errors.push(err);
+                continue;
+            },
+        };
+        for instruction in converted {
+            let instruction = match instruction {
+                Converted::Instruction(instruction) => instruction,
+                Converted::Jump { prefix, target } => match starts.get(&target) {
+                    // A jump to the end of the program runs past its last line
+                    Some(index) => format!("{}{}", prefix, index),
+                    None => {
+                        errors.push(format!("Line {}: GOTO {} points to a line that does not exist", number, target));
+                        continue;
+                    },
+                },
+            };
+            // The parser has the last word on what can be written in COS341Basic, such as
+            // which characters strings may hold
+            match parse_line(&instruction) {
+                Ok(_) => code.push(instruction),
+                Err(err) => errors.push(format!("Line {}: cannot be converted to '{}' ({})", number, instruction, err)),
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+    Ok(code.iter().enumerate().map(|(index, instruction)| format!("{} {}\n", index, instruction)).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::code_loader::load_code_from_string;
+    use crate::console::BufferConsole;
+    use crate::runner::run;
+    use super::convert_classic;
+
+    #[test]
+    fn convert_classic_program() {
+        let source = "10 REM Prints the larger number\n20 INPUT \"First number\"; A\n30 input b\n\
+                      40 IF A > B THEN 70\n50 PRINT B\n60 GOTO 80\n70 PRINT A\n80 LET N$ = \"done\"\n\
+                      90 C = A + 1\n100 IF C = 5 THEN PRINT \"five\"\n110 END\n";
+        let converted = convert_classic(source).unwrap();
+        assert_eq!(converted, "0 dialect v2\n1 let $_c1 = \"First number\"\n2 output $_c1\n3 let $a = input\n\
+                               4 let $b = input\n5 if $a > $b goto 8\n6 output $b\n7 goto 9\n8 output $a\n\
+                               9 let $n_str = \"done\"\n10 let $_c1 = 1\n11 let $c = $a + $_c1\n\
+                               12 let $_c1 = 5\n13 let $_c2 = \"five\"\n14 if $c = $_c1 output $_c2\n15 quit\n");
+
+        let mut data = load_code_from_string(&converted).unwrap().into_program_data();
+        data.set_dialect(crate::dialect::Dialect::V2);
+        let console = BufferConsole::new("4\n10");
+        let output = console.output.clone();
+        data.set_console(Box::new(console));
+        run(data).ok().unwrap();
+        assert_eq!(*output.lock().unwrap(), vec!["First number", "10", "five"]);
+    }
+
+    #[test]
+    fn report_untranslatable_lines() {
+        let source = "20 GOSUB 100\n30 PRINT A; B\n40 LET A = B + C * 2\n\
+                      50 GOTO 70\n60 A = 1 : B = 2\nREM\n";
+        assert_eq!(convert_classic(source).err().unwrap(), "Line without a line number: REM\n\
+                   Line 20: GOSUB is not supported\n\
+                   Line 30: PRINT can only print a single value\n\
+                   Line 40: expressions may only have one operator\n\
+                   Line 50: GOTO 70 points to a line that does not exist\n\
+                   Line 60: only one statement per line is supported");
+    }
+}
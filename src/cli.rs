@@ -0,0 +1,610 @@
+use std::path::PathBuf;
+use cos341basic::dialect::Dialect;
+use cos341basic::grammar::GrammarFormat;
+
+/// What the interpreter has been asked to do with the program.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run,
+    Test,
+    Grammar(GrammarFormat),
+    /// Runs the program against the scripted session in the given file
+    Expect(String),
+    /// Compares the execution traces in the given files
+    TraceDiff(String, String),
+    /// Converts the classic BASIC program in the program file to COS341Basic
+    Convert,
+    /// Exports the program in the program file as a Python program
+    Export,
+    /// Writes the program in the program file with short variable names and compact spacing
+    Minify,
+    /// Renumbers the lines of the program in the program file
+    Renumber,
+}
+
+/// Options that control how the interpreter runs a program.
+pub struct Options {
+    pub command: Command,
+    pub program_file: Option<String>,
+    /// The arguments after the program file, which the program reads with 'arg' and 'argc'
+    pub program_args: Vec<String>,
+    pub allow_net: bool,
+    pub allow_exec: bool,
+    pub allow_fs: bool,
+    pub allow_env: bool,
+    pub inputs: Option<Vec<String>>,
+    pub input_default: Option<String>,
+    pub input_file: Option<String>,
+    pub output_file: Option<String>,
+    pub transcript_file: Option<String>,
+    pub trace_file: Option<String>,
+    pub events_file: Option<String>,
+    pub show_stats: bool,
+    pub dump_vars: bool,
+    pub stats_file: Option<String>,
+    pub record_file: Option<String>,
+    pub replay_file: Option<String>,
+    /// The seed the program's random numbers start from, so that every run gives the same numbers
+    pub seed: Option<u64>,
+    pub dialect: Option<Dialect>,
+    pub alias_file: Option<String>,
+    pub max_output_lines: Option<usize>,
+    pub max_output_bytes: Option<usize>,
+    pub stride: Option<usize>,
+    pub registers: Option<usize>,
+    /// The directory of the project being run, which is set by its manifest rather than a flag
+    pub project_dir: Option<PathBuf>,
+    /// The public key whose signature a program must carry before it is run
+    pub verify_key: Option<String>,
+    /// The detached signature of the program, which defaults to the program file with '.sig' added
+    pub signature_file: Option<String>,
+    pub checksum_file: Option<String>,
+    pub show_warnings: bool,
+    pub debug: bool,
+    pub debug_on_error: bool,
+}
+
+/// Parses the command line arguments passed to the interpreter. Flags may appear before or
+/// after the program file. A subcommand (such as 'test', 'expect', 'trace-diff' or 'grammar')
+/// must be the first argument.
+///
+/// # Arguments
+/// * `args` - Command line arguments, excluding the name of the executable
+///
+/// # Returns
+/// * `Ok(Options)` - The options specified on the command line
+/// * `Err(String)` - A message detailing why the arguments could not be parsed
+pub fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut options = Options {
+        command: Command::Run,
+        program_file: None,
+        program_args: Vec::new(),
+        allow_net: false,
+        allow_exec: false,
+        allow_fs: false,
+        allow_env: false,
+        inputs: None,
+        input_default: None,
+        input_file: None,
+        output_file: None,
+        transcript_file: None,
+        trace_file: None,
+        events_file: None,
+        show_stats: false,
+        dump_vars: false,
+        stats_file: None,
+        record_file: None,
+        replay_file: None,
+        seed: None,
+        dialect: None,
+        alias_file: None,
+        max_output_lines: None,
+        max_output_bytes: None,
+        stride: None,
+        registers: None,
+        project_dir: None,
+        verify_key: None,
+        signature_file: None,
+        checksum_file: None,
+        show_warnings: false,
+        debug: false,
+        debug_on_error: false,
+    };
+
+    let mut args = args.into_iter().peekable();
+    match args.peek().map(String::as_str) {
+        // Running the program is what happens without a subcommand, but it can be asked for
+        Some("run") => {
+            args.next();
+        },
+        Some("test") => {
+            options.command = Command::Test;
+            args.next();
+        },
+        Some("expect") => {
+            args.next();
+            let session_file = args.next_if(|arg| !arg.starts_with("--"));
+            options.command = Command::Expect(option_value("expect", session_file)?);
+        },
+        Some("convert") => {
+            options.command = Command::Convert;
+            args.next();
+        },
+        Some("export") => {
+            options.command = Command::Export;
+            args.next();
+        },
+        Some("minify") => {
+            options.command = Command::Minify;
+            args.next();
+        },
+        Some("renumber") => {
+            options.command = Command::Renumber;
+            args.next();
+        },
+        Some("trace-diff") => {
+            args.next();
+            let first = option_value("trace-diff", args.next_if(|arg| !arg.starts_with("--")))?;
+            let second = option_value("trace-diff", args.next_if(|arg| !arg.starts_with("--")))?;
+            options.command = Command::TraceDiff(first, second);
+        },
+        Some("grammar") => {
+            args.next();
+            // The format is optional, and defaults to a TextMate grammar
+            let format = match args.next_if(|arg| !arg.starts_with("--")) {
+                Some(format) => format.parse::<GrammarFormat>()?,
+                None => GrammarFormat::TextMate,
+            };
+            options.command = Command::Grammar(format);
+        },
+        _ => {}
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--allow-net" => options.allow_net = true,
+            "--allow-exec" => options.allow_exec = true,
+            "--allow-fs" => options.allow_fs = true,
+            "--allow-env" => options.allow_env = true,
+            "--warnings" => options.show_warnings = true,
+            "--debug" => options.debug = true,
+            "--debug-on-error" => options.debug_on_error = true,
+            "--stats" => options.show_stats = true,
+            "--dump-vars" => options.dump_vars = true,
+            "--inputs" => {
+                let inputs = option_value(&arg, args.next())?;
+                options.inputs = Some(inputs.split(',').map(String::from).collect());
+            },
+            "--input-default" => options.input_default = Some(option_value(&arg, args.next())?),
+            "--input" => options.input_file = Some(option_value(&arg, args.next())?),
+            "--output" => options.output_file = Some(option_value(&arg, args.next())?),
+            "--dialect" => options.dialect = Some(option_value(&arg, args.next())?.parse::<Dialect>()?),
+            "--aliases" => options.alias_file = Some(option_value(&arg, args.next())?),
+            "--max-output-lines" => options.max_output_lines = Some(limit_value(&arg, args.next())?),
+            "--max-output-bytes" => options.max_output_bytes = Some(limit_value(&arg, args.next())?),
+            "--stride" => {
+                let stride = limit_value(&arg, args.next())?;
+                if stride == 0 {
+                    return Err(String::from("Invalid value for --stride: 0"));
+                }
+                options.stride = Some(stride);
+            },
+            "--registers" => options.registers = Some(limit_value(&arg, args.next())?),
+            "--verify" => options.verify_key = Some(option_value(&arg, args.next())?),
+            "--signature" => options.signature_file = Some(option_value(&arg, args.next())?),
+            "--checksum" => options.checksum_file = Some(option_value(&arg, args.next())?),
+            "--transcript" => options.transcript_file = Some(option_value(&arg, args.next())?),
+            "--trace" => options.trace_file = Some(option_value(&arg, args.next())?),
+            "--events" => options.events_file = Some(option_value(&arg, args.next())?),
+            "--stats-json" => options.stats_file = Some(option_value(&arg, args.next())?),
+            "--record" => options.record_file = Some(option_value(&arg, args.next())?),
+            "--replay" => options.replay_file = Some(option_value(&arg, args.next())?),
+            "--seed" => {
+                let seed = option_value(&arg, args.next())?;
+                options.seed = Some(seed.parse::<u64>().map_err(|_| format!("Invalid value for --seed: {}", seed))?);
+            },
+            // Everything after '--' is passed to the program, even if it looks like an option
+            "--" if options.program_file.is_some() => {
+                options.program_args.extend(args.by_ref());
+            },
+            flag if flag.starts_with("--") => return Err(format!("Unknown option: {}", flag)),
+            _ => {
+                if options.program_file.is_some() {
+                    options.program_args.push(arg);
+                } else {
+                    options.program_file = Some(arg);
+                }
+            }
+        }
+    }
+
+    // Only a program that is run has anywhere to put its arguments
+    if let Some(arg) = options.program_args.first().filter(|_| options.command != Command::Run) {
+        return Err(format!("Unexpected argument: {}", arg));
+    }
+    if options.input_file.is_some() && (options.inputs.is_some() || options.input_default.is_some()) {
+        return Err(String::from("--input cannot be combined with --inputs or --input-default"));
+    }
+    if (options.debug || options.debug_on_error) && options.command != Command::Run {
+        return Err(String::from("--debug and --debug-on-error can only be used when running a program"));
+    }
+    let observed = options.trace_file.is_some() || options.events_file.is_some() || options.show_stats
+        || options.stats_file.is_some();
+    if observed && options.command != Command::Run {
+        return Err(String::from("--trace, --events, --stats and --stats-json can only be used when running a program"));
+    }
+    if observed && (options.debug || options.debug_on_error) {
+        return Err(String::from("--trace, --events, --stats and --stats-json cannot be combined with --debug or --debug-on-error"));
+    }
+    if options.dump_vars && options.command != Command::Run {
+        return Err(String::from("--dump-vars can only be used when running a program"));
+    }
+    if (options.record_file.is_some() || options.replay_file.is_some()) && options.command != Command::Run {
+        return Err(String::from("--record and --replay can only be used when running a program"));
+    }
+    if options.stride.is_some() && options.command != Command::Renumber {
+        return Err(String::from("--stride can only be used with the renumber command"));
+    }
+    // A replay gives the program all of its input, and the seed of its random numbers
+    if options.replay_file.is_some() && (options.record_file.is_some() || options.input_file.is_some()
+            || options.inputs.is_some() || options.input_default.is_some() || options.seed.is_some()) {
+        return Err(String::from("--replay cannot be combined with --record, --input, --inputs, --input-default or --seed"));
+    }
+    if options.seed.is_some() && !matches!(options.command, Command::Run | Command::Test | Command::Expect(_)) {
+        return Err(String::from("--seed can only be used when running or testing a program"));
+    }
+    // A session gives the program all of its input and checks all of its output
+    if matches!(options.command, Command::Expect(_)) && (options.input_file.is_some() || options.inputs.is_some()
+            || options.input_default.is_some() || options.output_file.is_some() || options.transcript_file.is_some()) {
+        return Err(String::from("expect cannot be combined with --input, --inputs, --input-default, --output or --transcript"));
+    }
+
+    Ok(options)
+}
+
+/// Ensures that an option which expects a value was given one.
+fn option_value(option: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or(format!("Missing value for {}", option))
+}
+
+/// Ensures that an option which expects a limit was given a positive whole number.
+fn limit_value(option: &str, value: Option<String>) -> Result<usize, String> {
+    let value = option_value(option, value)?;
+    value.parse::<usize>().map_err(|_| format!("Invalid value for {}: {}", option, value))
+}
+
+#[cfg(test)]
+mod test {
+    use cos341basic::dialect::Dialect;
+    use cos341basic::grammar::GrammarFormat;
+    use super::{parse_args, Command};
+
+    #[test]
+    fn parse_program_file() {
+        let options = parse_args(vec![String::from("prog.txt")]).unwrap();
+        assert_eq!(options.program_file.unwrap(), "prog.txt");
+        assert_eq!(options.command, Command::Run);
+        assert!(!options.allow_net);
+    }
+
+    #[test]
+    fn parse_test_command() {
+        let options = parse_args(vec![String::from("test"), String::from("prog.txt")]).unwrap();
+        assert_eq!(options.command, Command::Test);
+        assert_eq!(options.program_file.unwrap(), "prog.txt");
+    }
+
+    #[test]
+    fn parse_expect_command() {
+        let options = parse_args(vec![String::from("expect"), String::from("session.txt"), String::from("prog.txt")]).unwrap();
+        assert_eq!(options.command, Command::Expect(String::from("session.txt")));
+        assert_eq!(options.program_file.unwrap(), "prog.txt");
+
+        let result = parse_args(vec![String::from("expect"), String::from("--inputs"), String::from("1")]);
+        assert_eq!(result.err().unwrap(), "Missing value for expect");
+        let result = parse_args(vec![
+            String::from("expect"), String::from("session.txt"),
+            String::from("--inputs"), String::from("1"), String::from("prog.txt"),
+        ]);
+        assert_eq!(result.err().unwrap(),
+                   "expect cannot be combined with --input, --inputs, --input-default, --output or --transcript");
+    }
+
+    #[test]
+    fn parse_grammar_command() {
+        let options = parse_args(vec![String::from("grammar")]).unwrap();
+        assert_eq!(options.command, Command::Grammar(GrammarFormat::TextMate));
+
+        let options = parse_args(vec![String::from("grammar"), String::from("tree-sitter")]).unwrap();
+        assert_eq!(options.command, Command::Grammar(GrammarFormat::TreeSitter));
+        assert!(options.program_file.is_none());
+    }
+
+    #[test]
+    fn parse_allow_net() {
+        let options = parse_args(vec![String::from("--allow-net"), String::from("prog.txt")]).unwrap();
+        assert!(options.allow_net);
+    }
+
+    #[test]
+    fn parse_allow_exec() {
+        let options = parse_args(vec![String::from("prog.txt"), String::from("--allow-exec")]).unwrap();
+        assert!(options.allow_exec);
+        assert!(!options.allow_net);
+    }
+
+    #[test]
+    fn parse_allow_fs() {
+        let options = parse_args(vec![String::from("--allow-fs"), String::from("prog.txt")]).unwrap();
+        assert!(options.allow_fs);
+        assert!(!options.allow_exec);
+    }
+
+    #[test]
+    fn parse_allow_env() {
+        let options = parse_args(vec![String::from("--allow-env"), String::from("prog.txt")]).unwrap();
+        assert!(options.allow_env);
+        assert!(!options.allow_fs);
+    }
+
+    #[test]
+    fn parse_program_args() {
+        let args = ["prog.txt", "5", "--allow-env", "hello", "--", "--seed", "x"];
+        let options = parse_args(args.iter().map(|arg| arg.to_string()).collect()).unwrap();
+        assert_eq!(options.program_file.unwrap(), "prog.txt");
+        assert_eq!(options.program_args, vec!["5", "hello", "--seed", "x"]);
+        assert!(options.allow_env);
+
+        let result = parse_args(vec![String::from("export"), String::from("prog.txt"), String::from("5")]);
+        assert_eq!(result.err().unwrap(), "Unexpected argument: 5");
+    }
+
+    #[test]
+    fn parse_inputs() {
+        let options = parse_args(vec![
+            String::from("prog.txt"),
+            String::from("--inputs"), String::from("3,5,hello"),
+            String::from("--input-default"), String::from("0"),
+        ]).unwrap();
+        assert_eq!(options.inputs.unwrap(), vec!["3", "5", "hello"]);
+        assert_eq!(options.input_default.unwrap(), "0");
+    }
+
+    #[test]
+    fn parse_input_file() {
+        let options = parse_args(vec![String::from("--input"), String::from("data.txt"), String::from("prog.txt")]).unwrap();
+        assert_eq!(options.input_file.unwrap(), "data.txt");
+        assert_eq!(options.program_file.unwrap(), "prog.txt");
+    }
+
+    #[test]
+    fn parse_output_file() {
+        let options = parse_args(vec![String::from("prog.txt"), String::from("--output"), String::from("result.txt")]).unwrap();
+        assert_eq!(options.output_file.unwrap(), "result.txt");
+    }
+
+    #[test]
+    fn parse_transcript_file() {
+        let options = parse_args(vec![String::from("prog.txt"), String::from("--transcript"), String::from("t.log")]).unwrap();
+        assert_eq!(options.transcript_file.unwrap(), "t.log");
+    }
+
+    #[test]
+    fn parse_input_file_with_inputs() {
+        let result = parse_args(vec![
+            String::from("--input"), String::from("data.txt"),
+            String::from("--inputs"), String::from("1,2"),
+        ]);
+        assert_eq!(result.err().unwrap(), "--input cannot be combined with --inputs or --input-default");
+    }
+
+    #[test]
+    fn parse_debug() {
+        let options = parse_args(vec![String::from("--debug"), String::from("prog.txt")]).unwrap();
+        assert!(options.debug);
+        let result = parse_args(vec![String::from("test"), String::from("--debug"), String::from("prog.txt")]);
+        assert_eq!(result.err().unwrap(), "--debug and --debug-on-error can only be used when running a program");
+    }
+
+    #[test]
+    fn parse_trace() {
+        let options = parse_args(vec![String::from("--trace"), String::from("run.trace"), String::from("prog.txt")]).unwrap();
+        assert_eq!(options.trace_file.unwrap(), "run.trace");
+        let result = parse_args(vec![String::from("--trace"), String::from("run.trace"), String::from("--debug"),
+                                     String::from("prog.txt")]);
+        assert_eq!(result.err().unwrap(), "--trace, --events, --stats and --stats-json cannot be combined with --debug or --debug-on-error");
+    }
+
+    #[test]
+    fn parse_registers() {
+        let options = parse_args(vec![String::from("--registers"), String::from("8"), String::from("prog.txt")]).unwrap();
+        assert_eq!(options.registers, Some(8));
+        let result = parse_args(vec![String::from("--registers"), String::from("eight"), String::from("prog.txt")]);
+        assert_eq!(result.err().unwrap(), "Invalid value for --registers: eight");
+    }
+
+    #[test]
+    fn parse_events() {
+        let options = parse_args(vec![String::from("--events"), String::from("run.jsonl"), String::from("prog.txt")]).unwrap();
+        assert_eq!(options.events_file.unwrap(), "run.jsonl");
+        let result = parse_args(vec![String::from("test"), String::from("--events"), String::from("run.jsonl"), String::from("prog.txt")]);
+        assert_eq!(result.err().unwrap(), "--trace, --events, --stats and --stats-json can only be used when running a program");
+    }
+
+    #[test]
+    fn parse_stats() {
+        let options = parse_args(vec![String::from("--stats"), String::from("--stats-json"), String::from("stats.json"),
+                                      String::from("prog.txt")]).unwrap();
+        assert!(options.show_stats);
+        assert_eq!(options.stats_file.unwrap(), "stats.json");
+        let result = parse_args(vec![String::from("test"), String::from("--stats"), String::from("prog.txt")]);
+        assert_eq!(result.err().unwrap(), "--trace, --events, --stats and --stats-json can only be used when running a program");
+    }
+
+    #[test]
+    fn parse_dump_vars() {
+        let options = parse_args(vec![String::from("prog.txt"), String::from("--dump-vars")]).unwrap();
+        assert!(options.dump_vars);
+        let result = parse_args(vec![String::from("test"), String::from("--dump-vars"), String::from("prog.txt")]);
+        assert_eq!(result.err().unwrap(), "--dump-vars can only be used when running a program");
+    }
+
+    #[test]
+    fn parse_convert_command() {
+        let options = parse_args(vec![String::from("convert"), String::from("classic.bas")]).unwrap();
+        assert_eq!(options.command, Command::Convert);
+        assert_eq!(options.program_file.unwrap(), "classic.bas");
+    }
+
+    #[test]
+    fn parse_run_command() {
+        let options = parse_args(vec![String::from("run"), String::from("project/"), String::from("--allow-fs")]).unwrap();
+        assert_eq!(options.command, Command::Run);
+        assert_eq!(options.program_file.unwrap(), "project/");
+        assert!(options.allow_fs);
+    }
+
+    #[test]
+    fn parse_minify_command() {
+        let options = parse_args(vec![String::from("minify"), String::from("prog.txt")]).unwrap();
+        assert_eq!(options.command, Command::Minify);
+        assert_eq!(options.program_file.unwrap(), "prog.txt");
+    }
+
+    #[test]
+    fn parse_renumber_command() {
+        let options = parse_args(vec![String::from("renumber"), String::from("--stride"), String::from("10"), String::from("prog.txt")]).unwrap();
+        assert_eq!(options.command, Command::Renumber);
+        assert_eq!(options.program_file.unwrap(), "prog.txt");
+        assert_eq!(options.stride, Some(10));
+        let result = parse_args(vec![String::from("renumber"), String::from("--stride"), String::from("0"), String::from("prog.txt")]);
+        assert_eq!(result.err().unwrap(), "Invalid value for --stride: 0");
+        let result = parse_args(vec![String::from("--stride"), String::from("10"), String::from("prog.txt")]);
+        assert_eq!(result.err().unwrap(), "--stride can only be used with the renumber command");
+    }
+
+    #[test]
+    fn parse_export_command() {
+        let options = parse_args(vec![String::from("export"), String::from("prog.txt"), String::from("--dialect"), String::from("v1")]).unwrap();
+        assert_eq!(options.command, Command::Export);
+        assert_eq!(options.program_file.unwrap(), "prog.txt");
+        assert_eq!(options.dialect, Some(Dialect::V1));
+    }
+
+    #[test]
+    fn parse_trace_diff_command() {
+        let options = parse_args(vec![String::from("trace-diff"), String::from("a.trace"), String::from("b.trace")]).unwrap();
+        assert_eq!(options.command, Command::TraceDiff(String::from("a.trace"), String::from("b.trace")));
+        assert!(options.program_file.is_none());
+        let result = parse_args(vec![String::from("trace-diff"), String::from("a.trace")]);
+        assert_eq!(result.err().unwrap(), "Missing value for trace-diff");
+    }
+
+    #[test]
+    fn parse_debug_on_error() {
+        let options = parse_args(vec![String::from("--debug-on-error"), String::from("prog.txt")]).unwrap();
+        assert!(options.debug_on_error);
+        assert!(!options.debug);
+        let result = parse_args(vec![String::from("expect"), String::from("s.txt"), String::from("--debug-on-error"),
+                                     String::from("prog.txt")]);
+        assert_eq!(result.err().unwrap(), "--debug and --debug-on-error can only be used when running a program");
+    }
+
+    #[test]
+    fn parse_record_and_replay() {
+        let options = parse_args(vec![String::from("--record"), String::from("run.replay"), String::from("prog.txt")]).unwrap();
+        assert_eq!(options.record_file.unwrap(), "run.replay");
+        let options = parse_args(vec![String::from("--replay"), String::from("run.replay"), String::from("prog.txt")]).unwrap();
+        assert_eq!(options.replay_file.unwrap(), "run.replay");
+
+        let result = parse_args(vec![String::from("--replay"), String::from("run.replay"), String::from("--inputs"), String::from("1")]);
+        assert_eq!(result.err().unwrap(), "--replay cannot be combined with --record, --input, --inputs, --input-default or --seed");
+        let result = parse_args(vec![String::from("test"), String::from("--record"), String::from("run.replay")]);
+        assert_eq!(result.err().unwrap(), "--record and --replay can only be used when running a program");
+    }
+
+    #[test]
+    fn parse_seed() {
+        let options = parse_args(vec![String::from("--seed"), String::from("42"), String::from("prog.txt")]).unwrap();
+        assert_eq!(options.seed, Some(42));
+        let options = parse_args(vec![String::from("test"), String::from("prog.txt"), String::from("--seed"), String::from("7")]).unwrap();
+        assert_eq!(options.seed, Some(7));
+
+        let result = parse_args(vec![String::from("--seed"), String::from("-1")]);
+        assert_eq!(result.err().unwrap(), "Invalid value for --seed: -1");
+        let result = parse_args(vec![String::from("--replay"), String::from("run.replay"), String::from("--seed"), String::from("1")]);
+        assert_eq!(result.err().unwrap(), "--replay cannot be combined with --record, --input, --inputs, --input-default or --seed");
+        let result = parse_args(vec![String::from("export"), String::from("--seed"), String::from("1")]);
+        assert_eq!(result.err().unwrap(), "--seed can only be used when running or testing a program");
+    }
+
+    #[test]
+    fn parse_dialect() {
+        let options = parse_args(vec![String::from("--dialect"), String::from("v2"), String::from("prog.txt")]).unwrap();
+        assert_eq!(options.dialect.unwrap(), Dialect::V2);
+
+        let result = parse_args(vec![String::from("--dialect"), String::from("v0")]);
+        assert_eq!(result.err().unwrap(), "Unknown dialect: v0 (expected v1, v2 or v3)");
+    }
+
+    #[test]
+    fn parse_alias_file() {
+        let options = parse_args(vec![String::from("--aliases"), String::from("aliases.txt"), String::from("prog.txt")]).unwrap();
+        assert_eq!(options.alias_file.unwrap(), "aliases.txt");
+    }
+
+    #[test]
+    fn parse_missing_option_value() {
+        let result = parse_args(vec![String::from("prog.txt"), String::from("--inputs")]);
+        assert_eq!(result.err().unwrap(), "Missing value for --inputs");
+    }
+
+    #[test]
+    fn parse_unknown_option() {
+        let result = parse_args(vec![String::from("--meme")]);
+        assert_eq!(result.err().unwrap(), "Unknown option: --meme");
+    }
+
+    #[test]
+    fn parse_show_warnings() {
+        let options = parse_args(vec![String::from("prog.txt"), String::from("--warnings")]).unwrap();
+        assert!(options.show_warnings);
+    }
+
+    #[test]
+    fn parse_checksum_file() {
+        let options = parse_args(vec![String::from("--checksum"), String::from("prog.sha256"), String::from("prog.txt")]).unwrap();
+        assert_eq!(options.checksum_file.unwrap(), "prog.sha256");
+    }
+
+    #[test]
+    fn parse_verify_key() {
+        let options = parse_args(vec![String::from("--verify"), String::from("key.pub"), String::from("prog.txt")]).unwrap();
+        assert_eq!(options.verify_key.unwrap(), "key.pub");
+        assert!(options.signature_file.is_none());
+
+        let options = parse_args(vec![
+            String::from("--verify"), String::from("key.pub"),
+            String::from("--signature"), String::from("prog.sig"),
+            String::from("prog.txt"),
+        ]).unwrap();
+        assert_eq!(options.signature_file.unwrap(), "prog.sig");
+    }
+
+    #[test]
+    fn parse_output_limits() {
+        let options = parse_args(vec![
+            String::from("prog.txt"),
+            String::from("--max-output-lines"), String::from("100"),
+            String::from("--max-output-bytes"), String::from("4096"),
+        ]).unwrap();
+        assert_eq!(options.max_output_lines, Some(100));
+        assert_eq!(options.max_output_bytes, Some(4096));
+    }
+
+    #[test]
+    fn parse_invalid_output_limit() {
+        let result = parse_args(vec![String::from("prog.txt"), String::from("--max-output-lines"), String::from("lots")]);
+        assert_eq!(result.err().unwrap(), "Invalid value for --max-output-lines: lots");
+    }
+}
@@ -1,115 +1,709 @@
-use std::fs;
-use regex::Regex;
-use crate::errors::segment_errors::{error, ERROR_MESSAGES, ErrorTypes, SegmentErrorTypes};
+use std::collections::{HashMap, LinkedList};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use regex::{Captures, Regex};
+use crate::bundle::{Bundle, BUNDLE_EXTENSION};
+use crate::dialect::Dialect;
+use crate::imports::{append_imports, module_file};
+use crate::macros::{append_modules, expand_macros};
+use crate::aliases::default_aliases;
+use crate::parser::{parse_line, Instruction, ParsedCode};
+use crate::prog_data::{ProgramData, Value};
+use crate::storage::CodeStore;
+use crate::errors::{LoadError, Segment};
+use crate::states::RESERVED_WORDS;
 
-/// Loads COS341Basic data from a file and creates two vectors, one for the register data and
-/// the other for the code data. If an error is encountered while loading program data, a
-/// message detailing the error is returned.
+/// Largest program file, in bytes, that will be loaded.
+const MAX_PROGRAM_SIZE: u64 = 1024 * 1024;
+
+/// Program path that stands for stdin, so programs can be run without any filesystem access.
+pub const STDIN_PATH: &str = "-";
+
+/// Byte order mark some editors (notably Notepad on Windows) write at the start of UTF-8 files.
+const UTF8_BOM: char = '\u{feff}';
+
+/// A program loaded from a program file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Program {
+    /// The instructions of the program, indexed by line number
+    pub code: Vec<String>,
+    /// Values to push onto the stack before the program starts, in the order they are pushed
+    pub stack: Vec<String>,
+    /// Values the registers M0, M1, ... start with, for programs written for the original
+    /// register model
+    pub registers: Vec<String>,
+    // The code parsed into instructions, which is shared by every run of the program
+    parsed: Arc<ParsedCode>,
+}
+
+impl Program {
+    fn new(code: Vec<String>, stack: Vec<String>) -> Self {
+        let parsed = Arc::new(ParsedCode::parse(code.iter().map(String::as_str), &default_aliases()));
+        Program { code, stack, registers: Vec::new(), parsed }
+    }
+
+    /// Creates the program data for running the program, with its stack segment already pushed.
+    pub fn into_program_data(self) -> ProgramData {
+        Arc::new(self).shared_program_data()
+    }
+
+    /// Creates the program data for one run of a program that is shared between many runs (for
+    /// example, running the same submission against many inputs in parallel). Every run has its
+    /// own variables and stack, but the code and its parsed instructions are not copied.
+    pub fn shared_program_data(self: &Arc<Self>) -> ProgramData {
+        ProgramData::with_storage(Box::new(self.clone()), Box::new(self.initial_vars()), Box::new(self.initial_stack()), 0)
+    }
+
+    /// Gives the program a register file, so that its code can address the registers M0, M1, ...
+    /// directly, as programs written for the original register model do. Every register becomes
+    /// a variable of the same name, so `let M0 = 3` runs as `let $M0 = 3`.
+    ///
+    /// # Arguments
+    /// * `registers` - The values the registers start with, from M0 upwards
+    ///
+    /// # Returns
+    /// * `Ok(())` - The code now addresses the registers as variables
+    /// * `Err(LoadError)` - The program already has registers, or addresses one that does not exist
+    pub fn use_registers(&mut self, registers: Vec<String>) -> Result<(), LoadError> {
+        if !self.registers.is_empty() {
+            return Err(LoadError::RegistersDeclared);
+        }
+        self.code = address_registers(&self.code, registers.len())?;
+        self.parsed = Arc::new(ParsedCode::parse(self.code.iter().map(String::as_str), self.parsed.aliases()));
+        self.registers = registers;
+        Ok(())
+    }
+
+    /// Replaces the keyword aliases of the program, which start as the built-in ones, and parses
+    /// its code again with them. Aliases are resolved when the program is loaded, so no line has
+    /// its alias replaced while it runs.
+    pub fn set_aliases(&mut self, aliases: &HashMap<String, String>) {
+        self.parsed = Arc::new(ParsedCode::parse(self.code.iter().map(String::as_str), aliases));
+    }
+
+    /// Returns the variables the program starts with, which are its registers.
+    fn initial_vars(&self) -> HashMap<String, Value> {
+        self.registers.iter()
+            .enumerate()
+            .map(|(index, value)| (format!("M{}", index), Value::from_text(value)))
+            .collect()
+    }
+
+    /// Returns the stack the program starts with. Entries are pushed in order, so the last entry
+    /// of the stack segment is on top.
+    fn initial_stack(&self) -> LinkedList<Value> {
+        let mut stack = LinkedList::new();
+        for value in &self.stack {
+            stack.push_front(Value::from_text(value));
+        }
+        stack
+    }
+}
+
+impl CodeStore for Arc<Program> {
+    fn get(&self, index: usize) -> Option<&String> {
+        self.code.get(index)
+    }
+
+    fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    fn parsed(&self) -> Option<Arc<ParsedCode>> {
+        Some(self.parsed.clone())
+    }
+}
+
+/// Loads COS341Basic data from a file and creates two vectors, one for the stack data and
+/// the other for the code data. If the file is a bundle (ending in '.basz'), the main program of
+/// the bundle is loaded, and if the path is '-' the program is read from stdin. Modules imported
+/// by the program are read from the directory (or bundle) it is in, or from the current
+/// directory for a program read from stdin.
 ///
 /// # Arguments
 /// * `file_path` - Path of the file to load
 ///
 /// # Returns
-/// * `Ok((Vec<String>, Vec<String>))` - a tuple containing the register and code vectors
-/// * `Err(String)` - a message detailing any error that occurred while loading the program
-pub fn load_code_from_file(file_path: String) -> Result<Vec<String>, String>{
-    let file_data = fs::read_to_string(file_path.clone());
-    match file_data {
-        Ok(file_string) => {
-            let code_vec = if file_string.len() > 0 {
-                let code_segment = load_code_segment(file_string.as_str());
+/// * `Ok(Program)` - the stack and code vectors of the program
+/// * `Err(LoadError)` - the reason the program could not be loaded
+pub fn load_code_from_file(file_path: String) -> Result<Program, LoadError>{
+    if file_path == STDIN_PATH {
+        let file_string = read_program(io::stdin().lock(), MAX_PROGRAM_SIZE)
+            .map_err(|err| LoadError::in_file("stdin", err))?;
+        return load_code_importing(&file_string, &[], &|module| read_program_file(&module_file(module)));
+    }
+    if file_path.ends_with(BUNDLE_EXTENSION) {
+        let bundle = Bundle::open(&file_path).map_err(LoadError::Bundle)?;
+        let main_program = bundle.main_program().map_err(|err| LoadError::in_file(&file_path, LoadError::Bundle(err)))?;
+        // Files of the bundle are named by the bundle and their path inside it
+        let read_member = |path: &str| bundle.read(path)
+            .map_err(LoadError::Bundle)
+            .and_then(|contents| read_program(contents, MAX_PROGRAM_SIZE))
+            .map_err(|err| LoadError::in_file(&file_path, LoadError::in_file(path, err)));
+        let file_string = read_member(main_program)?;
+        let import = |module: &str| read_member(&Path::new(main_program).with_file_name(module_file(module)).to_string_lossy());
+        return load_code_importing(&file_string, &[], &import);
+    }
 
-                if code_segment.is_err() {
-                    return Err(ERROR_MESSAGES[code_segment.err().unwrap() as usize].parse().unwrap());
-                }
-                code_segment.unwrap()
-            } else {
-                Vec::new()
-            };
+    let import = |module: &str| read_program_file(&Path::new(&file_path).with_file_name(module_file(module)).to_string_lossy());
+    load_code_importing(&read_program_file(&file_path)?, &[], &import)
+}
 
-            Ok(code_vec)
-        },
-        Err(msg) => {
-            Err(format!("{}: {}", file_path, msg).to_string())
+/// Reads the program file at `file_path`, refusing files larger than a program may be.
+pub(crate) fn read_program_file(file_path: &str) -> Result<String, LoadError> {
+    let read = || {
+        // Refuse oversized files up front, before reading any of their contents
+        if fs::metadata(file_path)?.len() > MAX_PROGRAM_SIZE {
+            return Err(LoadError::TooLarge { max_size: MAX_PROGRAM_SIZE });
+        }
+        read_program(BufReader::new(File::open(file_path)?), MAX_PROGRAM_SIZE)
+    };
+    read().map_err(|err| LoadError::in_file(file_path, err))
+}
+
+/// Reads a program line by line, stopping as soon as it grows past `max_size` bytes or holds
+/// a line that is not valid UTF-8. The size is checked again while reading, as the file may
+/// grow after its size was first checked (or may not report a size at all, like a pipe).
+/// A UTF-8 byte order mark at the start of the program is removed.
+///
+/// # Arguments
+/// * `reader` - Source to read the program from
+/// * `max_size` - Largest number of bytes the program may hold
+///
+/// # Returns
+/// * `Ok(String)` - The contents of the program
+/// * `Err(LoadError)` - The reason the program could not be read
+fn read_program(reader: impl BufRead, max_size: u64) -> Result<String, LoadError> {
+    // Never buffer more than one byte past the limit, however long the lines are
+    let mut reader = reader.take(max_size + 1);
+    let mut contents = Vec::new();
+    loop {
+        let line_start = contents.len();
+        if reader.read_until(b'\n', &mut contents)? == 0 {
+            break;
+        }
+        if contents.len() as u64 > max_size {
+            return Err(LoadError::TooLarge { max_size });
+        }
+        if let Err(err) = std::str::from_utf8(&contents[line_start..]) {
+            return Err(LoadError::InvalidUtf8 { offset: line_start + err.valid_up_to() });
+        }
+    }
+
+    let contents = String::from_utf8(contents).map_err(|err| LoadError::InvalidUtf8 { offset: err.utf8_error().valid_up_to() })?;
+    Ok(contents.strip_prefix(UTF8_BOM).map(String::from).unwrap_or(contents))
+}
+
+/// Loads the code of a program from the contents of a program file.
+pub fn load_code_from_string(file_string: &str) -> Result<Program, LoadError> {
+    load_code_with_modules(file_string, &[])
+}
+
+/// Loads the code of a program from the contents of a program file, along with modules whose
+/// macros the program can call (see `macros::append_modules`). The program cannot import modules,
+/// as it has no file for them to be found next to.
+///
+/// # Arguments
+/// * `file_string` - The contents of the program file
+/// * `modules` - The name and contents of each module file, which are numbered like a program
+///
+/// # Returns
+/// * `Ok(Program)` - The program, with the macros of every module expanded
+/// * `Err(LoadError)` - The reason the program or one of its modules could not be loaded
+pub fn load_code_with_modules(file_string: &str, modules: &[(String, String)]) -> Result<Program, LoadError> {
+    let import = |module: &str| Err(LoadError::NoModuleFiles(module_file(module)));
+    load_code_importing(file_string, modules, &import)
+}
+
+/// Loads the code of a program from the contents of a program file, along with modules whose
+/// macros the program can call (see `macros::append_modules`) and the modules it imports (see
+/// `imports::append_imports`).
+///
+/// # Arguments
+/// * `file_string` - The contents of the program file
+/// * `modules` - The name and contents of each module file, which are numbered like a program
+/// * `import` - Reads the contents of the module file imported under the given name
+///
+/// # Returns
+/// * `Ok(Program)` - The program, with the macros of every module expanded and every import added
+/// * `Err(LoadError)` - The reason the program or one of its modules could not be loaded
+pub fn load_code_importing(file_string: &str, modules: &[(String, String)],
+                           import: &dyn Fn(&str) -> Result<String, LoadError>) -> Result<Program, LoadError> {
+    let modules = modules.iter()
+        .map(|(name, contents)| strip_comments(contents)
+            .and_then(|contents| load_code_segment(&join_continued_lines(&contents)))
+            .map(|lines| (name.clone(), lines))
+            .map_err(|err| LoadError::in_file(name, err)))
+        .collect::<Result<Vec<(String, Vec<String>)>, LoadError>>()?;
+    // Programs written for the original register model keep their registers and code in
+    // BEGIN_/END_ segments
+    if let Some((register_string, code_string)) = split_register_segments(file_string)? {
+        let mut program = Program::new(load_code(&code_string, &modules, import)?, Vec::new());
+        if let Some(register_string) = register_string {
+            let registers = load_register_segment(&register_string)?;
+            program.use_registers(registers)?;
         }
+        return Ok(program);
+    }
+
+    let (stack_string, code_string) = split_segments(file_string)?;
+    let stack = match stack_string {
+        Some(stack_string) => load_stack_segment(&stack_string)?,
+        None => Vec::new(),
+    };
+    Ok(Program::new(load_code(&code_string, &modules, import)?, stack))
+}
+
+/// Loads the code segment of a program, joining continued lines, adding the modules it imports
+/// and expanding macros, including those of its modules.
+fn load_code(code_string: &str, modules: &[(String, Vec<String>)],
+             import: &dyn Fn(&str) -> Result<String, LoadError>) -> Result<Vec<String>, LoadError> {
+    if code_string.is_empty() {
+        return Ok(Vec::new());
     }
+    let code_string = join_continued_lines(&strip_comments(code_string)?);
+    let code_segment = load_code_segment(code_string.as_str())?;
+    // Imported modules are loaded like a program, and named by their file in errors
+    let load_module = |module: &str| import(module).and_then(|contents| strip_comments(&contents)
+        .and_then(|contents| load_code_segment(&join_continued_lines(&contents)))
+        .map_err(|err| LoadError::in_file(module_file(module), err)));
+    let code_vec = resolve_labels(expand_macros(append_modules(append_imports(code_segment, &load_module)?, modules)?)?)?;
+    validate_variable_names(&code_vec)?;
+    validate_blocks(&code_vec)?;
+    validate_switches(&code_vec)?;
+    Ok(code_vec)
+}
+
+/// Splits a program file into its stack and code segments. The stack segment is optional: if
+/// the first line of the file is 'stack', every line up to a 'code' line belongs to the stack
+/// segment, and the rest of the file is the code segment. Otherwise the whole file is code.
+/// Blank lines before the 'stack' line are skipped.
+///
+/// # Returns
+/// * `Ok((Option<String>, String))` - The stack segment (if there is one) and the code segment
+/// * `Err(LoadError)` - The file has a stack segment that is not followed by a code segment
+fn split_segments(file_string: &str) -> Result<(Option<String>, String), LoadError> {
+    let lines: Vec<&str> = file_string.lines().collect();
+    let stack_start = match lines.iter().position(|line| !line.trim().is_empty()) {
+        Some(first) if lines[first].trim() == "stack" => first + 1,
+        _ => return Ok((None, file_string.to_string())),
+    };
+
+    let code_start = lines.iter().position(|line| line.trim() == "code")
+        .ok_or(LoadError::NoSegment(Segment::Code))?;
+    Ok((Some(lines[stack_start..code_start].join("\n")), lines[code_start + 1..].join("\n")))
+}
+
+/// Splits a program file written for the original register model into its register and code
+/// segments. Such a file starts with a 'BEGIN_REGISTER_SEGMENT' line, followed by the registers
+/// and an 'END_REGISTER_SEGMENT' line, or leaves the registers out and starts with the code. The
+/// code follows a 'BEGIN_CODE_SEGMENT' line and runs up to an 'END_CODE_SEGMENT' line or the end
+/// of the file.
+///
+/// # Returns
+/// * `Ok(Some((Option<String>, String)))` - The register segment (if there is one) and the code
+///   segment of a file written for the register model
+/// * `Ok(None)` - The file is not written for the register model
+/// * `Err(LoadError)` - One of the segments is not closed or is missing
+fn split_register_segments(file_string: &str) -> Result<Option<(Option<String>, String)>, LoadError> {
+    let lines: Vec<&str> = file_string.lines().map(str::trim).collect();
+    let find = |marker: &str, from: usize| lines.iter().skip(from).position(|line| *line == marker).map(|pos| pos + from);
+    let (register_string, code_search_start) = match lines.iter().find(|line| !line.is_empty()) {
+        Some(&"BEGIN_REGISTER_SEGMENT") => {
+            let start = find("BEGIN_REGISTER_SEGMENT", 0).unwrap() + 1;
+            let end = find("END_REGISTER_SEGMENT", start).ok_or(LoadError::MalformedSegment(Segment::Register))?;
+            (Some(lines[start..end].join("\n")), end + 1)
+        },
+        Some(&"BEGIN_CODE_SEGMENT") => (None, 0),
+        _ => return Ok(None),
+    };
+
+    let start = find("BEGIN_CODE_SEGMENT", code_search_start).ok_or(LoadError::NoSegment(Segment::Code))? + 1;
+    let end = find("END_CODE_SEGMENT", start).unwrap_or(lines.len());
+    Ok(Some((register_string, lines[start..end].join("\n"))))
+}
+
+/// Turns the registers M0, M1, ... of the original register model into the variables $M0, $M1,
+/// ... that hold them. Registers can be used wherever a variable can.
+///
+/// # Arguments
+/// * `code` - The lines of the program
+/// * `count` - The number of registers the program has
+///
+/// # Returns
+/// * `Ok(Vec<String>)` - The lines of the program, addressing the registers as variables
+/// * `Err(LoadError)` - The line holding the first register that does not exist
+fn address_registers(code: &[String], count: usize) -> Result<Vec<String>, LoadError> {
+    let register_regex = Regex::new(r"(^|[^$\w])M(\d+)\b").unwrap();
+    code.iter().enumerate().map(|(line_number, line)| {
+        let mut missing = None;
+        let addressed = outside_strings(line, |piece| register_regex.replace_all(piece, |captures: &regex::Captures| {
+            match captures[2].parse::<usize>() {
+                Ok(register) if register < count => format!("{}$M{}", &captures[1], register),
+                _ => {
+                    missing.get_or_insert_with(|| captures[2].to_string());
+                    captures[0].to_string()
+                }
+            }
+        }).to_string());
+        let registers = match count {
+            0 => String::from("The program has no registers."),
+            count => format!("The registers run from M0 to M{}.", count - 1),
+        };
+        match missing {
+            Some(register) => Err(LoadError::invalid_code(line_number, format!("Register M{} does not exist! {}", register, registers))),
+            None => Ok(addressed),
+        }
+    }).collect()
 }
 
 /// Parses a variable string using a provided Regex, extracts the data from the string and returns a Vec
 /// containing the data in a 1:1 mapping according to the index of the data in the string
 ///
 /// # Arguments
-/// * `segment_error_type` - Tells the function which segment type error codes the function returns should
-///                          the parser encounter any error.
+/// * `segment` - The segment being parsed, which is reported in any error the parser encounters.
 /// * `variable_string` - A string that holds variable data in the format 'index value'. Each variable
-///                       in this string is separated by '\n' or '\r\n'.
+///   in this string is separated by '\n' or '\r\n'.
 /// # Returns
 /// * `Ok(Vec<String>)` - An array holding the declared values.
-/// * `Err(u32)` - An error code. This happens when there was an error parsing the variable string.
+/// * `Err(LoadError)` - The reason the variable string could not be parsed.
 ///
 /// # Examples
+/// ```ignore
+/// let result = load_segment(Segment::Code, "0let M0 = 3", split_regex);
+/// assert_eq!(result.err().unwrap(), LoadError::MalformedSegment(Segment::Code))
 /// ```
-/// let expected_result = VariableErrorCodes{
-///             error: ErrorTypes::MalformedAssignment
-/// };
-/// let result = load_segment(SegmentErrorTypes::Variable, segment, split_regex);
-/// assert_eq!(result.err().unwrap(), expected_result.value())
-/// ```
-fn load_segment(segment_error_type: SegmentErrorTypes, variable_string: &str, split_regex: Regex) -> Result<Vec<String>, u32> {
+fn load_segment(segment: Segment, variable_string: &str, split_regex: Regex) -> Result<Vec<String>, LoadError> {
 
     let mut memory_vec : Vec<String> = Vec::new();
-    let mut err = error(&segment_error_type, ErrorTypes::AllOk).value();
-    let mut variable_index = 0;
 
     //return empty array if no registers are declared
-    if variable_string.len() == 0 {
+    if variable_string.is_empty() {
         return Ok(memory_vec)
     }
 
     //Register segment was not declared correctly
     if !split_regex.is_match(variable_string.trim()) {
-        return Err(error(&segment_error_type, ErrorTypes::MalformedSegment).value());
+        return Err(LoadError::MalformedSegment(segment));
     }
 
     //split the string by lines
-    let variables = Regex::new(r"(\n|\r\n)").unwrap()
-        .split(variable_string)
-        .collect::<Vec<&str>>();
+    for (line_index, var) in variable_string.lines().enumerate() {
+        // Lines may be indented to show the structure of a program, and trailing whitespace
+        // (including the '\r' of a stray CR) is invisible in most editors
+        let var = var.trim();
 
-    for var in variables {
-
-        if var.len() == 0 {
+        if var.is_empty() {
             continue;
         }
 
-        let item = split_regex.captures(var);
-
         // ensure that variables have a 'index value' structure
-        if item.is_none() {
-            err = error(&segment_error_type, ErrorTypes::MalformedAssignment).value();
-            break;
-        }
+        let item = split_regex.captures(var).ok_or_else(|| LoadError::MalformedAssignment {
+            segment,
+            line: line_index + 1,
+            text: var.to_string(),
+        })?;
 
-        let pos = (&item).as_ref().unwrap().get(1).unwrap();
-        let val = item.unwrap().get(2).unwrap();
+        let pos = item.get(1).unwrap().as_str().parse::<usize>().unwrap();
+        let val = item.get(2).unwrap();
 
         /*
         Ensure that the variable indices are created in chronological order, starting from 0.
         These indices have a 1:1 mapping in the resulting array.
          */
-        if pos.as_str().parse::<usize>().unwrap() != variable_index {
-            err = error(&segment_error_type, ErrorTypes::NotChronological).value();
-            break;
+        if pos != memory_vec.len() {
+            return Err(LoadError::NotChronological {
+                segment,
+                line: line_index + 1,
+                expected: memory_vec.len(),
+                found: pos,
+            });
         }
-        variable_index += 1;
         memory_vec.push(val.as_str().to_string())
     }
 
-    //return the vector if there was no error, otherwise return the error
-    match err {
-        0 => Ok(memory_vec),
-        _ => Err(err)
+    Ok(memory_vec)
+}
+
+/// Applies `replace` to the parts of a line outside its strings, which are left as they are, so
+/// that a string such as "goto 5" or "$price" is never read as part of the program.
+pub(crate) fn outside_strings(line: &str, mut replace: impl FnMut(&str) -> String) -> String {
+    // Strings cannot hold a '"', so every other piece between quotes is a string
+    line.split('"')
+        .enumerate()
+        .map(|(index, piece)| if index % 2 == 0 { replace(piece) } else { piece.to_string() })
+        .collect::<Vec<String>>()
+        .join("\"")
+}
+
+/// Checks whether a line of a program file is a comment: a line starting with '#' or 'rem'.
+pub(crate) fn is_comment_line(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with('#') || line.split_whitespace().next() == Some("rem")
+}
+
+/// Removes the comments from the code segment of a program file. Comment lines are left empty,
+/// so that errors still report the line they are on, and a '#' outside a string ends the
+/// instruction before it.
+///
+/// # Returns
+/// * `Ok(String)` - The code segment without comments
+/// * `Err(LoadError)` - A comment is given a line number
+///
+/// # Examples
+/// ```ignore
+/// let stripped = strip_comments("# Adds one\n0 let $a = $a + $b # $b holds 1\n1 quit");
+/// assert_eq!(stripped.unwrap(), "\n0 let $a = $a + $b\n1 quit");
+/// ```
+fn strip_comments(segment: &str) -> Result<String, LoadError> {
+    let number_regex = Regex::new(r"^\s*\d+[ \t]+(.*)$").unwrap();
+    let mut lines = Vec::new();
+    for (line_index, line) in segment.lines().enumerate() {
+        if is_comment_line(line) {
+            lines.push("");
+            continue;
+        }
+        if number_regex.captures(line).is_some_and(|captures| is_comment_line(&captures[1])) {
+            return Err(LoadError::NumberedComment { line: line_index + 1, text: line.trim().to_string() });
+        }
+        let mut in_string = false;
+        let end = line.char_indices()
+            .find(|(_, c)| {
+                in_string ^= *c == '"';
+                *c == '#' && !in_string
+            })
+            .map_or(line.len(), |(pos, _)| pos);
+        lines.push(line[..end].trim_end());
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Joins instructions that have been split over several physical lines. A line ending in '\\'
+/// continues on the next line, and a line starting with '...' continues the line before it.
+/// The pieces are joined with a single space.
+///
+/// # Examples
+/// ```ignore
+/// let joined = join_continued_lines("0 output \\\n  $a\n1 quit");
+/// assert_eq!(joined, "0 output $a\n1 quit");
+/// ```
+fn join_continued_lines(file_string: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut continues = false;
+
+    for line in file_string.lines() {
+        let (line, continues_next) = match line.trim_end().strip_suffix('\\') {
+            Some(stripped) => (stripped.trim_end(), true),
+            None => (line, false),
+        };
+        let continuation = line.trim_start().strip_prefix("...");
+
+        match lines.last_mut() {
+            // A comment inside a continued instruction leaves an empty line, which adds nothing
+            Some(previous) if continues || continuation.is_some() => {
+                let piece = continuation.unwrap_or(line).trim();
+                if !piece.is_empty() {
+                    previous.push(' ');
+                    previous.push_str(piece);
+                }
+            },
+            _ => lines.push(line.to_string()),
+        }
+        continues = continues_next;
+    }
+
+    lines.join("\n")
+}
+
+/// Points every jump to a label ('goto name' or 'gosub name', including those in 'if'
+/// statements) at the line the label is declared on, so that the program only jumps by number
+/// while it runs. Labels stay in the program and do nothing when they are run.
+///
+/// # Returns
+/// * `Ok(Vec<String>)` - The lines of the program, jumping to line numbers
+/// * `Err(LoadError)` - The line of the first label that is declared twice, or jumped to but
+///   never declared
+pub(crate) fn resolve_labels(code: Vec<String>) -> Result<Vec<String>, LoadError> {
+    // A jump ends its line, or the clause before an 'else'
+    let jump_regex = Regex::new(r"\b((?:goto|gosub)\s+)([A-Za-z_]\w*)(\s+else\b|\s*$)").unwrap();
+    let mut labels = HashMap::new();
+    for (line_number, line) in code.iter().enumerate() {
+        if let Ok(Instruction::Label(name)) = parse_line(line) {
+            if RESERVED_WORDS.contains(&name.as_str()) {
+                return Err(LoadError::invalid_code(line_number, format!("{} is a reserved word and cannot be used as a label!", name)));
+            }
+            if let Some(first_line) = labels.insert(name.clone(), line_number) {
+                return Err(LoadError::invalid_code(line_number, format!("Label {} is already declared on line {}!", name, first_line)));
+            }
+        }
+    }
+
+    code.into_iter().enumerate().map(|(line_number, line)| {
+        let mut missing = None;
+        let resolved = outside_strings(&line, |piece| jump_regex.replace_all(piece, |jump: &Captures| match labels.get(&jump[2]) {
+            Some(target) => format!("{}{}{}", &jump[1], target, &jump[3]),
+            None => {
+                missing.get_or_insert_with(|| jump[2].to_string());
+                jump[0].to_string()
+            },
+        }).to_string());
+        match missing {
+            Some(label) => Err(LoadError::invalid_code(line_number, format!("Label {} does not exist!", label))),
+            None => Ok(resolved),
+        }
+    }).collect()
+}
+
+/// Ensures that no variable in the program is named after a keyword, which would otherwise
+/// confuse the instruction decoding.
+///
+/// # Returns
+/// * `Ok(())` - Every variable name is allowed
+/// * `Err(LoadError)` - The line of the first offending variable
+fn validate_variable_names(code: &[String]) -> Result<(), LoadError> {
+    let variable_regex = Regex::new(r"\$(\w+)").unwrap();
+    for (line_number, line) in code.iter().enumerate() {
+        let mut reserved = None;
+        outside_strings(line, |piece| {
+            let found = variable_regex.captures_iter(piece).find(|captures| RESERVED_WORDS.contains(&&captures[1]));
+            if let Some(captures) = found {
+                reserved.get_or_insert_with(|| captures[1].to_string());
+            }
+            piece.to_string()
+        });
+        if let Some(name) = reserved {
+            return Err(LoadError::invalid_code(line_number, format!("${} is a reserved word and cannot be used as a variable name!", name)));
+        }
+    }
+    Ok(())
+}
+
+/// Ensures that every 'for' loop is closed by a 'next' of the same variable and every 'while'
+/// loop by an 'endwhile', with loops inside other loops closed before the loops around them, so
+/// that the jumps at either end of a loop always find the line at its other end.
+///
+/// # Returns
+/// * `Ok(())` - Every loop is closed
+/// * `Err(LoadError)` - The line of the first loop that is not closed
+fn validate_blocks(code: &[String]) -> Result<(), LoadError> {
+    let mut open = Vec::new();
+    for (line_number, line) in code.iter().enumerate() {
+        match parse_line(line) {
+            Ok(instruction @ (Instruction::For { .. } | Instruction::While(_))) => open.push((line_number, instruction)),
+            Ok(Instruction::Next(var)) => match open.pop() {
+                Some((_, Instruction::For { var: counter, .. })) if counter == var => {},
+                Some((_, Instruction::For { var: counter, .. })) => {
+                    return Err(LoadError::invalid_code(line_number, format!("next ${} cannot close the loop over ${}!", var, counter)));
+                },
+                Some((start, _)) => {
+                    return Err(LoadError::invalid_code(line_number, format!("next ${} cannot close the while loop on line {}!", var, start)));
+                },
+                None => return Err(LoadError::invalid_code(line_number, format!("next ${} has no matching for!", var))),
+            },
+            Ok(Instruction::EndWhile) => match open.pop() {
+                Some((_, Instruction::For { var, .. })) => {
+                    return Err(LoadError::invalid_code(line_number, format!("endwhile cannot close the loop over ${}!", var)));
+                },
+                Some(_) => {},
+                None => return Err(LoadError::invalid_code(line_number, "endwhile has no matching while!")),
+            },
+            _ => {},
+        }
+    }
+    match open.pop() {
+        Some((line_number, Instruction::For { var, .. })) => Err(LoadError::invalid_code(line_number, format!("Loop over ${} has no matching next!", var))),
+        Some((line_number, _)) => Err(LoadError::invalid_code(line_number, "while has no matching endwhile!")),
+        None => Ok(()),
+    }
+}
+
+/// Ensures that every 'switch' is followed by its cases, that every 'case' and 'default' line
+/// belongs to a 'switch' (with the 'default' after the cases), and that every case jumps to a
+/// line of the program.
+///
+/// # Returns
+/// * `Ok(())` - Every switch and case is valid
+/// * `Err(LoadError)` - The first invalid line
+fn validate_switches(code: &[String]) -> Result<(), LoadError> {
+    let instructions: Vec<Option<Instruction>> = code.iter().map(|line| parse_line(line).ok()).collect();
+    for (line_number, instruction) in instructions.iter().enumerate() {
+        let previous = line_number.checked_sub(1).and_then(|previous| instructions[previous].as_ref());
+        let follows_switch = matches!(previous, Some(Instruction::Switch(_) | Instruction::Case { .. }));
+        let has_cases = matches!(instructions.get(line_number + 1), Some(Some(Instruction::Case { .. } | Instruction::Default(_))));
+        match instruction {
+            Some(Instruction::Switch(_)) if !has_cases => return Err(LoadError::invalid_code(line_number, "switch has no cases!")),
+            Some(Instruction::Case { .. } | Instruction::Default(_)) if !follows_switch => {
+                return Err(LoadError::invalid_code(line_number, format!("{} must follow a switch or one of its cases!", code[line_number])));
+            },
+            Some(Instruction::Case { target, .. } | Instruction::Default(target)) if *target >= code.len() => {
+                return Err(LoadError::invalid_code(line_number, format!("{} points to a line that does not exist!", code[line_number])));
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+/// Finds mistakes in a program that do not stop it from running, but probably do not do what
+/// its author meant.
+///
+/// # Returns
+/// * `Vec<String>` - A message for every mistake found
+pub fn find_warnings(code: &[String]) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    // A program that does not end by quitting, jumping elsewhere, returning from a subroutine or
+    // closing a function quits after its last line
+    if let Some(last_line) = code.last() {
+        if !matches!(parse_line(last_line), Ok(Instruction::Quit(_)) | Ok(Instruction::Goto(_)) | Ok(Instruction::Return(_)) | Ok(Instruction::EndFunc)) {
+            warnings.push(format!("Warning: line {} is the last line but is not a quit statement. \
+                                   The program will quit after running it.", code.len() - 1));
+        }
+    }
+    warnings
+}
+
+/// Finds the dialect a program asks for with a 'dialect <name>' statement on its first line.
+///
+/// # Returns
+/// * `Ok(Some(Dialect))` - The dialect named by the program
+/// * `Ok(None)` - The program does not name a dialect
+/// * `Err(String)` - The program names a dialect that does not exist
+pub fn detect_dialect(code: &[String]) -> Result<Option<Dialect>, String> {
+    match code.first().map(|line| parse_line(line)) {
+        Some(Ok(Instruction::Dialect(name))) => Ok(Some(name.parse::<Dialect>()?)),
+        _ => Ok(None)
+    }
+}
+
+///Uses the load_segment function to load the values of the register segment.
+/// # Arguments
+///  * - `segment` - String slice containing registers in the format 'index value'
+/// # Returns
+/// * Ok(Vec<String>) - The values the registers start with, from M0 upwards.
+/// * Err(LoadError) - The reason the register string could not be parsed.
+fn load_register_segment(segment: &str) -> Result<Vec<String>, LoadError> {
+    if segment.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let var_regex = Regex::new(r#"^(\d+)[ \t]+(.+)"#).unwrap();
+    load_segment(Segment::Register, segment, var_regex)
+}
+
+///Uses the load_segment function to load the entries of the stack segment.
+/// # Arguments
+///  * - `segment` - String slice containing stack entries in the format 'index value'
+/// # Returns
+/// * Ok(Vec<String>) - The values to push onto the stack, in the order they are pushed.
+/// * Err(LoadError) - The reason the stack string could not be parsed.
+fn load_stack_segment(segment: &str) -> Result<Vec<String>, LoadError> {
+    if segment.trim().is_empty() {
+        return Ok(Vec::new());
     }
+    let var_regex = Regex::new(r#"^(\d+)[ \t]+(.+)"#).unwrap();
+    load_segment(Segment::Stack, segment, var_regex)
 }
 
 ///Uses the load_segment function to load code data into memory.
@@ -117,16 +711,16 @@ fn load_segment(segment_error_type: SegmentErrorTypes, variable_string: &str, sp
 ///  * - `segment` - String slice containing code data
 /// # Returns
 /// * Ok(Vec<String>) - An array containing code data for the interpreter to execute.
-/// * Err(u32) - An error code. This happens when there was an error parsing the code string.
-fn load_code_segment(segment: &str) -> Result<Vec<String>, u32> {
-    let var_regex = Regex::new(r#"^(\d+) (.+)"#).unwrap();
-    load_segment(SegmentErrorTypes::Code, segment, var_regex)
+/// * Err(LoadError) - The reason the code string could not be parsed.
+fn load_code_segment(segment: &str) -> Result<Vec<String>, LoadError> {
+    // Line numbers may be separated from their instruction by any mix of spaces and tabs
+    let var_regex = Regex::new(r#"^(\d+)[ \t]+(.+)"#).unwrap();
+    load_segment(Segment::Code, segment, var_regex)
 }
 
 #[cfg(test)]
 mod test {
     use crate::code_loader::load_code_segment;
-    use crate::errors::segment_errors::{CodeErrorCode, ErrorCodes, ErrorTypes, VariableErrorCodes};
     use super::*;
 
     #[test]
@@ -135,22 +729,93 @@ mod test {
         assert_eq!(result.ok().unwrap(), vec![String::from("let M0 = 3")])
     }
 
+    #[test]
+    fn test_loading_code_with_mixed_whitespace() {
+        let result = load_code_segment("0\tlet $a = 3  \r\n1    output $a\t\n2 \t quit\r\n");
+        assert_eq!(result.ok().unwrap(), vec![
+            String::from("let $a = 3"),
+            String::from("output $a"),
+            String::from("quit"),
+        ])
+    }
+
+    #[test]
+    fn test_loading_indented_code_with_blank_lines() {
+        let result = load_code_from_string("\n0 let $a = 3\n\n1 if $a > 0 goto 3\n    2 output $a\n  \t\n\t3 quit\n");
+        assert_eq!(result.ok().unwrap().code, vec![
+            String::from("let $a = 3"),
+            String::from("if $a > 0 goto 3"),
+            String::from("output $a"),
+            String::from("quit"),
+        ]);
+        let result = load_code_from_string("\n  stack\n  0 5\n\ncode\n  0 let $a = pop\n").ok().unwrap();
+        assert_eq!(result.stack, vec![String::from("5")]);
+    }
+
     #[test]
     fn test_missing_space_between_index_and_code() {
         let result = load_code_segment("0let M0 = 3");
-        let expected_result = CodeErrorCode{
-            error: ErrorTypes::MalformedSegment
-        };
-        assert_eq!(result.err().unwrap(), expected_result.value())
+        assert_eq!(result.err().unwrap(), LoadError::MalformedSegment(Segment::Code))
+    }
+
+    #[test]
+    fn test_malformed_code_line() {
+        let result = load_code_segment("0 let M0 = 3\n1quit");
+        assert_eq!(result.err().unwrap(), LoadError::MalformedAssignment {
+            segment: Segment::Code,
+            line: 2,
+            text: String::from("1quit"),
+        })
+    }
+
+    #[test]
+    fn test_code_lines_out_of_order() {
+        let result = load_code_segment("0 let M0 = 3\n\n2 quit");
+        assert_eq!(result.err().unwrap(), LoadError::NotChronological {
+            segment: Segment::Code,
+            line: 3,
+            expected: 1,
+            found: 2,
+        })
     }
 
     #[test]
     fn get_error_from_file_not_found() {
         let result = load_code_from_file("notfound.txt".to_string());
 
-        let error_string = "notfound.txt: No such file or directory (os error 2)";
+        let error = result.err().unwrap();
+        assert_eq!(error.to_string(), "notfound.txt: No such file or directory (os error 2)");
+        assert!(matches!(error, LoadError::InFile { error, .. } if matches!(*error, LoadError::Io { kind: io::ErrorKind::NotFound, .. })));
+    }
+
+    #[test]
+    fn read_program_within_size_limit() {
+        let result = read_program("0 output $a\n1 quit\n".as_bytes(), 19);
+        assert_eq!(result.ok().unwrap(), "0 output $a\n1 quit\n");
+    }
+
+    #[test]
+    fn read_program_over_size_limit() {
+        let result = read_program("0 output $a\n1 quit\n".as_bytes(), 18);
+        assert_eq!(result.err().unwrap(), LoadError::TooLarge { max_size: 18 });
+
+        // A single huge line is cut off at the limit instead of being read whole
+        let garbage = vec![b'x'; 1000];
+        assert_eq!(read_program(garbage.as_slice(), 10).err().unwrap().to_string(), "program is larger than 10 bytes!");
+    }
 
-        assert_eq!(result.err().unwrap(), error_string)
+    #[test]
+    fn read_program_with_invalid_utf8() {
+        let result = read_program(&b"0 output $a\n1 out\xffput\n2 quit\n"[..], 100);
+        let error = result.err().unwrap();
+        assert_eq!(error, LoadError::InvalidUtf8 { offset: 17 });
+        assert_eq!(error.to_string(), "program is not valid UTF-8! (invalid byte at offset 17)");
+    }
+
+    #[test]
+    fn read_program_with_byte_order_mark() {
+        let result = read_program("\u{feff}0 quit\n".as_bytes(), 100);
+        assert_eq!(result.ok().unwrap(), "0 quit\n");
     }
 
     #[test]
@@ -158,7 +823,7 @@ mod test {
 
         let result = load_code_from_file("testfiles/test1.txt".to_string());
 
-        assert_eq!(result.ok().unwrap(), vec![String::from("quit")]);
+        assert_eq!(result.ok().unwrap().code, vec![String::from("quit")]);
     }
 
     #[test]
@@ -181,14 +846,302 @@ mod test {
 
         let result = load_code_from_file("testfiles/test2.txt".to_string());
 
-        assert_eq!(result.ok().unwrap(), code_vec);
+        assert_eq!(result.ok().unwrap().code, code_vec);
     }
 
     #[test]
     fn load_code_with_code_error() {
         let result = load_code_from_file("testfiles/test3.txt".to_string());
 
-        assert_eq!(result.as_ref().err().unwrap(), ERROR_MESSAGES[8]);
+        assert_eq!(result.err().unwrap(), LoadError::MalformedSegment(Segment::Code));
+    }
+
+    #[test]
+    fn strip_comment_lines_and_trailing_comments() {
+        let stripped = strip_comments("# Adds one\n0 let $a = $a + $b # $b holds 1\n  rem the end\n1 quit\nremove");
+        assert_eq!(stripped.unwrap(), "\n0 let $a = $a + $b\n\n1 quit\nremove");
+        assert_eq!(strip_comments("0 quit\n1 rem the end").err().unwrap(),
+                   LoadError::NumberedComment { line: 2, text: String::from("1 rem the end") });
+        assert_eq!(strip_comments("0 # the end").err().unwrap().to_string(),
+                   "Line 1: Comments go on lines of their own, without a line number: 0 # the end");
+    }
+
+    #[test]
+    fn load_code_with_comments() {
+        let program = load_code_from_string("# Swaps two values\n0 let $a = 1\nrem keep a copy\n\
+                                             1 let $b = $a # copy\n2 if $a = $b \\\n# only if equal\n... goto 3\n3 quit\n").unwrap();
+        assert_eq!(program.code, vec!["let $a = 1", "let $b = $a", "if $a = $b goto 3", "quit"]);
+    }
+
+    #[test]
+    fn join_backslash_continuation() {
+        let joined = join_continued_lines("0 let $a = \"hello \\\n      world\"\r\n1 quit\r\n");
+        assert_eq!(joined, "0 let $a = \"hello world\"\n1 quit");
+    }
+
+    #[test]
+    fn join_dot_continuation() {
+        let joined = join_continued_lines("0 if $a < $b\n  ... goto 1\n1 quit");
+        assert_eq!(joined, "0 if $a < $b goto 1\n1 quit");
+    }
+
+    #[test]
+    fn unbalanced_loops() {
+        assert!(load_code_from_string("0 while $a < $b\n1 for $i = 1 to 3\n2 next $i\n3 endwhile\n").is_ok());
+        let load = |file_string: &str| load_code_from_string(file_string).err().unwrap().to_string();
+        assert_eq!(load("0 let $a = 1\n1 while $a < $b\n"), "Line 1: while has no matching endwhile!");
+        assert_eq!(load("0 endwhile\n"), "Line 0: endwhile has no matching while!");
+        assert_eq!(load("0 for $i = 1 to 3\n"), "Line 0: Loop over $i has no matching next!");
+        assert_eq!(load("0 next $i\n"), "Line 0: next $i has no matching for!");
+        assert_eq!(load("0 for $i = 1 to 3\n1 next $j\n"), "Line 1: next $j cannot close the loop over $i!");
+        assert_eq!(load("0 for $i = 1 to 3\n1 while $a < $b\n2 next $i\n3 endwhile\n"),
+                   "Line 2: next $i cannot close the while loop on line 1!");
+        assert_eq!(load("0 while $a < $b\n1 for $i = 1 to 3\n2 endwhile\n3 next $i\n"),
+                   "Line 2: endwhile cannot close the loop over $i!");
+    }
+
+    #[test]
+    fn invalid_switches() {
+        assert!(load_code_from_string("0 switch $x\n1 case 1 goto 3\n2 default goto 3\n3 quit\n").is_ok());
+        let load = |file_string: &str| load_code_from_string(file_string).err().unwrap().to_string();
+        assert_eq!(load("0 switch $x\n1 quit\n"), "Line 0: switch has no cases!");
+        assert_eq!(load("0 quit\n1 case 1 goto 0\n"), "Line 1: case 1 goto 0 must follow a switch or one of its cases!");
+        assert_eq!(load("0 switch $x\n1 default goto 3\n2 case 1 goto 3\n3 quit\n"),
+                   "Line 2: case 1 goto 3 must follow a switch or one of its cases!");
+        assert_eq!(load("0 switch $x\n1 case 1 goto 7\n2 quit\n"), "Line 1: case 1 goto 7 points to a line that does not exist!");
+        // Cases can jump to labels
+        assert_eq!(load_code_from_string("0 switch $x\n1 case 1 goto done\n2 label done\n").unwrap().code[1], "case 1 goto 2");
+    }
+
+    #[test]
+    fn load_file_with_continuations() {
+        let result = load_code_from_file("testfiles/test_continuation.txt".to_string());
+        assert_eq!(result.ok().unwrap().code, vec![
+            String::from("let $msg = \"hello world\""),
+            String::from("output $msg"),
+            String::from("quit"),
+        ]);
+    }
+
+    #[test]
+    fn load_file_with_macros() {
+        let result = load_code_from_file("testfiles/test_macros.txt".to_string());
+        assert_eq!(result.ok().unwrap().code, vec![
+            String::from("let $x = 1"),
+            String::from("let $y = 2"),
+            String::from("let $tmp = $x"),
+            String::from("let $x = $y"),
+            String::from("let $y = $tmp"),
+            String::from("output $x"),
+            String::from("quit"),
+        ]);
+    }
+
+    #[test]
+    fn resolve_jumps_to_labels() {
+        let program = load_code_from_string("0 let $i = 0\n1 label loop_start\n2 let $i = $i + $one\n\
+                                             3 if $i < $n goto loop_start\n4 gosub done\n5 label done\n6 return\n").unwrap();
+        assert_eq!(program.code[3], "if $i < $n goto 1");
+        assert_eq!(program.code[4], "gosub 5");
+        assert_eq!(program.code[5], "label done");
+
+        let program = load_code_from_string("0 label top\n1 if $a < $b goto top else gosub bottom\n2 label bottom\n").unwrap();
+        assert_eq!(program.code[1], "if $a < $b goto 0 else gosub 2");
+    }
+
+    #[test]
+    fn label_errors() {
+        assert_eq!(load_code_from_string("0 label a\n1 label a\n").err().unwrap().to_string(),
+                   "Line 1: Label a is already declared on line 0!");
+        assert_eq!(load_code_from_string("0 goto somewhere\n").err().unwrap().to_string(), "Line 0: Label somewhere does not exist!");
+        assert_eq!(load_code_from_string("0 label quit\n").err().unwrap().to_string(),
+                   "Line 0: quit is a reserved word and cannot be used as a label!");
+        // Strings that mention a jump are left alone
+        assert_eq!(load_code_from_string("0 let $a = \"goto nowhere\"\n").unwrap().code[0], "let $a = \"goto nowhere\"");
+    }
+
+    #[test]
+    fn load_main_program_from_bundle() {
+        let result = load_code_from_file("testfiles/test_bundle.basz".to_string());
+        assert_eq!(result.ok().unwrap().code, vec![
+            String::from("let $msg = \"hello from a bundle\""),
+            String::from("output $msg"),
+            String::from("quit"),
+        ]);
+    }
+
+    #[test]
+    fn load_file_with_imports() {
+        let program = load_code_from_file("testfiles/test_import.txt".to_string()).unwrap();
+        assert_eq!(program.code[4..], [
+            String::from("let $g = call m.gcd $a $b"),
+            String::from("output $g"),
+            String::from("quit"),
+            String::from("func m.gcd $m.a $m.b"),
+            String::from("let $m.zero = 0"),
+            String::from("label m.top"),
+            String::from("if $m.b = $m.zero goto 15"),
+            String::from("let $m.r = $m.a % $m.b"),
+            String::from("let $m.a = $m.b"),
+            String::from("let $m.b = $m.r"),
+            String::from("goto 9"),
+            String::from("label m.done"),
+            String::from("return $m.a"),
+            String::from("endfunc"),
+        ]);
+
+        // The module keeps to its own variables
+        let outcome = crate::runner::run(program.clone().into_program_data()).ok().unwrap();
+        assert_eq!(outcome.data.get_var("g").unwrap(), "6");
+        assert_eq!(outcome.data.get_var("r").unwrap(), "99");
+        assert_eq!(outcome.data.get_var("a").unwrap(), "12");
+        assert_eq!(outcome.data.get_var("m.r").unwrap(), "0");
+
+        let result = load_code_from_string("0 import math_lib as m\n");
+        assert_eq!(result.err().unwrap().to_string(), "Line 0: math_lib.bas cannot be imported, as the program was not loaded from a file!");
+    }
+
+    #[test]
+    fn load_file_with_stack_segment() {
+        let result = load_code_from_file("testfiles/test_stack.txt".to_string()).ok().unwrap();
+        assert_eq!(result.stack, vec![String::from("3"), String::from("hello world"), String::from("7")]);
+        assert_eq!(result.code, vec![
+            String::from("let $a = pop"),
+            String::from("output $a"),
+            String::from("quit"),
+        ]);
+    }
+
+    #[test]
+    fn share_code_between_runs() {
+        let program = Arc::new(load_code_from_file("testfiles/test_stack.txt".to_string()).ok().unwrap());
+        let runs: Vec<ProgramData> = (0..3).map(|_| program.shared_program_data()).collect();
+        assert_eq!(Arc::strong_count(&program), 4);
+
+        for data in runs {
+            let outcome = crate::runner::run(data).ok().unwrap();
+            assert_eq!(outcome.data.get_var("a").unwrap(), "7");
+        }
+        assert_eq!(Arc::strong_count(&program), 1);
+    }
+
+    #[test]
+    fn resolve_aliases_when_loaded() {
+        let mut program = load_code_from_string("0 let $a = 5\n1 show $a\n").unwrap();
+        let mut aliases = crate::aliases::default_aliases();
+        aliases.insert(String::from("show"), String::from("output"));
+        program.set_aliases(&aliases);
+        // The code keeps the program as it was written
+        assert_eq!(program.code[1], "show $a");
+
+        let program = Arc::new(program);
+        let mut data = program.shared_program_data();
+        data.set_index(1);
+        assert_eq!(data.get_code(), Some("output $a"));
+
+        let mut data = program.shared_program_data();
+        data.set_console(Box::new(crate::console::BufferConsole::new("")));
+        assert!(crate::runner::run(data).is_ok());
+    }
+
+    #[test]
+    fn load_file_with_register_segment() {
+        let program = load_code_from_file("testfiles/test_program2.txt".to_string()).ok().unwrap();
+        assert_eq!(program.registers, vec![String::from("0"); 4]);
+        assert_eq!(program.code[2], "let $M2 = $M0 + $M1");
+        assert_eq!(program.code[10], "output $M3");
+
+        let mut data = program.into_program_data();
+        data.set_console(Box::new(crate::console::BufferConsole::new("7\n2")));
+        let outcome = crate::runner::run(data).ok().unwrap();
+        assert_eq!(outcome.data.get_var("M2").unwrap(), "3");
+        assert_eq!(outcome.data.get_var("M3").unwrap(), "0");
+    }
+
+    #[test]
+    fn address_registers_given_later() {
+        let mut program = load_code_from_string("BEGIN_CODE_SEGMENT\n0 let M1 = M01\nEND_CODE_SEGMENT\n").unwrap();
+        assert!(program.registers.is_empty());
+        program.use_registers(vec![String::from("0"), String::from("4")]).unwrap();
+        assert_eq!(program.code, vec![String::from("let $M1 = $M1")]);
+        assert_eq!(program.use_registers(vec![String::from("0")]).err().unwrap(), LoadError::RegistersDeclared);
+
+        let mut program = load_code_from_string("0 let $M1 = 5\n1 output M2\n").unwrap();
+        assert_eq!(program.use_registers(vec![String::from("0"); 2]).err().unwrap().to_string(),
+                   "Line 1: Register M2 does not exist! The registers run from M0 to M1.");
+    }
+
+    #[test]
+    fn leave_strings_as_they_are() {
+        let source = "0 label top\n1 output \"goto top, $input and M1 # not a comment\"\n2 goto top\n";
+        let mut program = load_code_from_string(source).unwrap();
+        program.use_registers(vec![String::from("0")]).unwrap();
+        assert_eq!(program.code, vec![
+            String::from("label top"),
+            String::from("output \"goto top, $input and M1 # not a comment\""),
+            String::from("goto 0"),
+        ]);
+    }
+
+    #[test]
+    fn register_segment_errors() {
+        let result = load_code_from_string("BEGIN_REGISTER_SEGMENT\n0 0\nBEGIN_CODE_SEGMENT\n0 quit\n");
+        assert_eq!(result.err().unwrap(), LoadError::MalformedSegment(Segment::Register));
+        let result = load_code_from_string("BEGIN_REGISTER_SEGMENT\n1 0\nEND_REGISTER_SEGMENT\nBEGIN_CODE_SEGMENT\n0 quit\n");
+        assert_eq!(result.err().unwrap().to_string(), "Line 1: Register declaration not in chronological order! (expected index 0, found 1)");
+        let result = load_code_from_string("BEGIN_REGISTER_SEGMENT\n0 0\nEND_REGISTER_SEGMENT\n");
+        assert_eq!(result.err().unwrap().to_string(), "No code segment found!");
+    }
+
+    #[test]
+    fn stack_segment_without_code_segment() {
+        let result = load_code_from_string("stack\n0 5\n");
+        assert_eq!(result.err().unwrap(), LoadError::NoSegment(Segment::Code));
+    }
+
+    #[test]
+    fn stack_entries_out_of_order() {
+        let result = load_code_from_string("stack\n1 5\ncode\n0 quit\n");
+        assert_eq!(result.err().unwrap().to_string(),
+                   "Line 1: Stack entries are not in chronological order! (expected index 0, found 1)");
+    }
+
+    #[test]
+    fn reject_reserved_variable_names() {
+        let code = vec![String::from("let $a = 5"), String::from("let $input = 5")];
+        assert_eq!(validate_variable_names(&code).err().unwrap().to_string(),
+                   "Line 1: $input is a reserved word and cannot be used as a variable name!");
+    }
+
+    #[test]
+    fn allow_variable_names_containing_keywords() {
+        let code = vec![String::from("let $inputs = 5"), String::from("output $inputs")];
+        assert!(validate_variable_names(&code).is_ok());
+    }
+
+    #[test]
+    fn warn_about_missing_quit() {
+        let code = vec![String::from("let $a = 5"), String::from("output $a")];
+        assert_eq!(find_warnings(&code), vec![
+            "Warning: line 1 is the last line but is not a quit statement. The program will quit after running it."
+        ]);
+
+        let code = vec![String::from("output $a"), String::from("goto 0")];
+        assert!(find_warnings(&code).is_empty());
+        assert!(find_warnings(&[String::from("quit")]).is_empty());
+        assert!(find_warnings(&[]).is_empty());
+    }
+
+    #[test]
+    fn detect_program_dialect() {
+        let code = vec![String::from("dialect v2"), String::from("quit")];
+        assert_eq!(detect_dialect(&code).unwrap(), Some(Dialect::V2));
+
+        let code = vec![String::from("quit"), String::from("dialect v2")];
+        assert_eq!(detect_dialect(&code).unwrap(), None);
+
+        let code = vec![String::from("dialect v9")];
+        assert_eq!(detect_dialect(&code).err().unwrap(), "Unknown dialect: v9 (expected v1, v2 or v3)");
     }
 
     #[test]
@@ -197,6 +1150,6 @@ mod test {
 
         let test: Vec<String> = Vec::new();
 
-        assert_eq!(result.ok().unwrap(), test);
+        assert_eq!(result.ok().unwrap().code, test);
     }
 }
@@ -0,0 +1,540 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::sync::mpsc::Receiver;
+#[cfg(not(target_os = "wasi"))]
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+#[cfg(not(target_os = "wasi"))]
+use std::thread;
+use std::time::Duration;
+
+/// Provides the input and output used by a running program. Consoles must be `Send` and `Sync`
+/// so that a program can be run on (or shared with) another thread.
+pub trait Console: Send + Sync {
+    /// Reads a single line of input without its line terminator. `Ok(None)` is returned once
+    /// the input has been exhausted.
+    fn read_line(&mut self) -> Result<Option<String>, String>;
+
+    /// Reads a single line of input, giving up after `timeout`. `Ok(None)` is returned if no
+    /// line arrived in time or the input has been exhausted.
+    fn read_line_timeout(&mut self, timeout: Duration) -> Result<Option<String>, String>;
+
+    /// Reads all remaining input.
+    fn read_all(&mut self) -> Result<String, String>;
+
+    /// Writes a line of program output.
+    fn write_line(&mut self, line: &str) -> Result<(), String>;
+
+    /// Writes a prompt for the input that is about to be read, without ending the line. Consoles
+    /// that only deal in whole lines write it as a line of its own.
+    fn write_prompt(&mut self, prompt: &str) -> Result<(), String> {
+        self.write_line(prompt)
+    }
+
+    /// Writes out any program output that is still buffered.
+    fn flush(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Checks whether a person is typing the input, who can be asked again when they enter
+    /// something invalid. Input from files, scripts and other programs is not interactive.
+    fn is_interactive(&mut self) -> Result<bool, String> {
+        Ok(false)
+    }
+}
+
+type LineReader = Box<dyn BufRead + Send + Sync>;
+type LineWriter = Box<dyn Write + Send + Sync>;
+type InputLine = Result<Option<String>, String>;
+
+fn read_input_line(input: &mut dyn BufRead) -> Result<Option<String>, String> {
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) => Ok(None),
+        Ok(_) => Ok(Some(line.trim_end_matches(['\r', '\n']).to_string())),
+        Err(_) => Err(String::from("Error reading input!")),
+    }
+}
+
+/// Console that reads from stdin (or a file given with --input) and writes to stdout (or a file
+/// given with --output). Output is buffered, and flushed before any input is read so that
+/// prompts are seen in time.
+pub struct StdConsole {
+    input: Option<LineReader>,
+    output: LineWriter,
+    // Lines read by a background thread. This is only started once a timed read is requested,
+    // after which every read goes through it so that no input is lost or reordered. Receivers
+    // are not Sync, so it is kept behind a mutex (which is never contended, as reads need &mut).
+    lines: Option<Mutex<Receiver<InputLine>>>,
+    // Whether input is read from a terminal, rather than a file or a pipe
+    interactive: bool,
+}
+
+impl Default for StdConsole {
+    fn default() -> Self {
+        StdConsole::new()
+    }
+}
+
+impl StdConsole {
+    pub fn new() -> Self {
+        StdConsole {
+            input: Some(Box::new(BufReader::new(io::stdin()))),
+            output: Box::new(io::BufWriter::new(io::stdout())),
+            lines: None,
+            interactive: io::stdin().is_terminal(),
+        }
+    }
+
+    /// Reads input from `file_path` instead of stdin.
+    pub fn set_input_file(&mut self, file_path: &str) -> Result<(), String> {
+        let file = File::open(file_path).map_err(|err| format!("{}: {}", file_path, err))?;
+        self.input = Some(Box::new(BufReader::new(file)));
+        self.interactive = false;
+        Ok(())
+    }
+
+    /// Writes output to `file_path` instead of stdout. The file is replaced if it already exists.
+    pub fn set_output_file(&mut self, file_path: &str) -> Result<(), String> {
+        let file = File::create(file_path).map_err(|err| format!("{}: {}", file_path, err))?;
+        self.output = Box::new(io::BufWriter::new(file));
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "wasi"))]
+    fn line_receiver(&mut self) -> &Receiver<InputLine> {
+        let input = &mut self.input;
+        let lines = self.lines.get_or_insert_with(|| {
+            let mut input = input.take().unwrap();
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || loop {
+                let line = read_input_line(input.as_mut());
+                let done = !matches!(line, Ok(Some(_)));
+                if sender.send(line).is_err() || done {
+                    break;
+                }
+            });
+            Mutex::new(receiver)
+        });
+        lines.get_mut().unwrap()
+    }
+}
+
+impl Console for StdConsole {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        self.flush()?;
+        match (&mut self.lines, &mut self.input) {
+            (Some(lines), _) => lines.get_mut().unwrap().recv().unwrap_or(Ok(None)),
+            (None, Some(input)) => read_input_line(input.as_mut()),
+            (None, None) => Ok(None),
+        }
+    }
+
+    #[cfg(not(target_os = "wasi"))]
+    fn read_line_timeout(&mut self, timeout: Duration) -> Result<Option<String>, String> {
+        self.flush()?;
+        match self.line_receiver().recv_timeout(timeout) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => Ok(None),
+        }
+    }
+
+    // WASI has no threads to wait for input with, so timed reads wait for as long as it takes
+    #[cfg(target_os = "wasi")]
+    fn read_line_timeout(&mut self, _timeout: Duration) -> Result<Option<String>, String> {
+        self.read_line()
+    }
+
+    fn read_all(&mut self) -> Result<String, String> {
+        self.flush()?;
+        let mut all_input = String::new();
+        match (&self.lines, &mut self.input) {
+            (None, Some(input)) => {
+                input.read_to_string(&mut all_input)
+                    .map_err(|_| String::from("Error reading input!"))?;
+            },
+            _ => {
+                while let Some(line) = self.read_line()? {
+                    all_input.push_str(&line);
+                    all_input.push('\n');
+                }
+            }
+        }
+        Ok(all_input)
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        writeln!(self.output, "{}", line).map_err(|_| String::from("Error writing output!"))
+    }
+
+    fn write_prompt(&mut self, prompt: &str) -> Result<(), String> {
+        write!(self.output, "{}", prompt).map_err(|_| String::from("Error writing output!"))
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.output.flush().map_err(|_| String::from("Error writing output!"))
+    }
+
+    fn is_interactive(&mut self) -> Result<bool, String> {
+        Ok(self.interactive)
+    }
+}
+
+/// Console that answers input requests from a list of values given on the command line instead
+/// of from stdin. Once the list runs out, every request is answered with `default` (if one was
+/// given). Output is passed on to the wrapped console.
+pub struct ScriptedConsole {
+    inputs: VecDeque<String>,
+    default: Option<String>,
+    inner: Box<dyn Console>,
+}
+
+impl ScriptedConsole {
+    pub fn new(inputs: Vec<String>, default: Option<String>, inner: Box<dyn Console>) -> Self {
+        ScriptedConsole {
+            inputs: inputs.into(),
+            default,
+            inner,
+        }
+    }
+}
+
+impl Console for ScriptedConsole {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        Ok(self.inputs.pop_front().or_else(|| self.default.clone()))
+    }
+
+    fn read_line_timeout(&mut self, _: Duration) -> Result<Option<String>, String> {
+        self.read_line()
+    }
+
+    fn read_all(&mut self) -> Result<String, String> {
+        let lines: Vec<String> = self.inputs.drain(..).collect();
+        Ok(lines.into_iter().map(|line| line + "\n").collect())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        self.inner.write_line(line)
+    }
+
+    fn write_prompt(&mut self, prompt: &str) -> Result<(), String> {
+        self.inner.write_prompt(prompt)
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.inner.flush()
+    }
+}
+
+/// Console that records a transcript of everything the program reads and writes. Output lines
+/// are written as '> line' and input lines as '< line', in the order they happened.
+pub struct TranscriptConsole {
+    transcript: LineWriter,
+    inner: Box<dyn Console>,
+}
+
+impl TranscriptConsole {
+    pub fn new(transcript: LineWriter, inner: Box<dyn Console>) -> Self {
+        TranscriptConsole { transcript, inner }
+    }
+
+    /// Records a transcript to `file_path`. The file is replaced if it already exists.
+    pub fn with_file(file_path: &str, inner: Box<dyn Console>) -> Result<Self, String> {
+        let file = File::create(file_path).map_err(|err| format!("{}: {}", file_path, err))?;
+        Ok(TranscriptConsole::new(Box::new(io::LineWriter::new(file)), inner))
+    }
+
+    fn record(&mut self, prefix: &str, line: &str) -> Result<(), String> {
+        writeln!(self.transcript, "{} {}", prefix, line).map_err(|_| String::from("Error writing transcript!"))
+    }
+
+    fn record_input(&mut self, input: Result<Option<String>, String>) -> Result<Option<String>, String> {
+        if let Ok(Some(line)) = &input {
+            self.record("<", line)?;
+        }
+        input
+    }
+}
+
+impl Console for TranscriptConsole {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        let input = self.inner.read_line();
+        self.record_input(input)
+    }
+
+    fn read_line_timeout(&mut self, timeout: Duration) -> Result<Option<String>, String> {
+        let input = self.inner.read_line_timeout(timeout);
+        self.record_input(input)
+    }
+
+    fn read_all(&mut self) -> Result<String, String> {
+        let input = self.inner.read_all()?;
+        for line in input.lines() {
+            self.record("<", line)?;
+        }
+        Ok(input)
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        self.record(">", line)?;
+        self.inner.write_line(line)
+    }
+
+    // The transcript has a line for the prompt, even though the program's output does not
+    fn write_prompt(&mut self, prompt: &str) -> Result<(), String> {
+        self.record(">", prompt)?;
+        self.inner.write_prompt(prompt)
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.inner.flush()
+    }
+
+    fn is_interactive(&mut self) -> Result<bool, String> {
+        self.inner.is_interactive()
+    }
+}
+
+/// Console that stops a program once it has written more than `max_lines` lines or `max_bytes`
+/// bytes of output (each line counting its newline). Input is passed straight through.
+pub struct LimitedConsole {
+    max_lines: Option<usize>,
+    max_bytes: Option<usize>,
+    lines: usize,
+    bytes: usize,
+    inner: Box<dyn Console>,
+}
+
+impl LimitedConsole {
+    pub fn new(max_lines: Option<usize>, max_bytes: Option<usize>, inner: Box<dyn Console>) -> Self {
+        LimitedConsole { max_lines, max_bytes, lines: 0, bytes: 0, inner }
+    }
+}
+
+impl Console for LimitedConsole {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        self.inner.read_line()
+    }
+
+    fn read_line_timeout(&mut self, timeout: Duration) -> Result<Option<String>, String> {
+        self.inner.read_line_timeout(timeout)
+    }
+
+    fn read_all(&mut self) -> Result<String, String> {
+        self.inner.read_all()
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        self.lines += 1;
+        self.bytes += line.len() + 1;
+        if let Some(max_lines) = self.max_lines.filter(|max_lines| self.lines > *max_lines) {
+            return Err(format!("Output limit exceeded: more than {} lines written!", max_lines));
+        }
+        if let Some(max_bytes) = self.max_bytes.filter(|max_bytes| self.bytes > *max_bytes) {
+            return Err(format!("Output limit exceeded: more than {} bytes written!", max_bytes));
+        }
+        self.inner.write_line(line)
+    }
+
+    // A prompt does not end a line, so it only counts towards the bytes written
+    fn write_prompt(&mut self, prompt: &str) -> Result<(), String> {
+        self.bytes += prompt.len();
+        if let Some(max_bytes) = self.max_bytes.filter(|max_bytes| self.bytes > *max_bytes) {
+            return Err(format!("Output limit exceeded: more than {} bytes written!", max_bytes));
+        }
+        self.inner.write_prompt(prompt)
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.inner.flush()
+    }
+
+    fn is_interactive(&mut self) -> Result<bool, String> {
+        self.inner.is_interactive()
+    }
+}
+
+/// Console that reads from a fixed block of text and records everything written to it.
+#[cfg(test)]
+pub struct BufferConsole {
+    input: VecDeque<String>,
+    pub output: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    pub interactive: bool,
+}
+
+#[cfg(test)]
+impl BufferConsole {
+    pub fn new(input: &str) -> Self {
+        BufferConsole {
+            input: input.lines().map(String::from).collect(),
+            output: Default::default(),
+            interactive: false,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Console for BufferConsole {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        Ok(self.input.pop_front())
+    }
+
+    fn read_line_timeout(&mut self, _: Duration) -> Result<Option<String>, String> {
+        self.read_line()
+    }
+
+    fn read_all(&mut self) -> Result<String, String> {
+        let lines: Vec<String> = self.input.drain(..).collect();
+        Ok(lines.into_iter().map(|line| line + "\n").collect())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        self.output.lock().unwrap().push(line.to_string());
+        Ok(())
+    }
+
+    fn is_interactive(&mut self) -> Result<bool, String> {
+        Ok(self.interactive)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use std::sync::{Arc, Mutex};
+    use super::{BufferConsole, Console, LimitedConsole, ScriptedConsole, StdConsole, TranscriptConsole};
+
+    /// Writer that keeps everything written to it in memory
+    #[derive(Clone, Default)]
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn read_from_input_file() {
+        let mut console = StdConsole::new();
+        console.set_input_file("testfiles/input1.txt").unwrap();
+        assert_eq!(console.read_line().unwrap().unwrap(), "3");
+        assert_eq!(console.read_line_timeout(Duration::from_secs(1)).unwrap().unwrap(), "5");
+        assert_eq!(console.read_all().unwrap(), "hello world\n");
+        assert!(console.read_line().unwrap().is_none());
+    }
+
+    #[test]
+    fn missing_input_file() {
+        let result = StdConsole::new().set_input_file("notfound.txt");
+        assert_eq!(result.err().unwrap(), "notfound.txt: No such file or directory (os error 2)");
+    }
+
+    #[test]
+    fn write_to_output_file() {
+        let output_path = std::env::temp_dir().join("cos341basic_output_test.txt");
+        let output_path = output_path.to_str().unwrap();
+        {
+            let mut console = StdConsole::new();
+            console.set_output_file(output_path).unwrap();
+            console.write_line("5").unwrap();
+            console.write_line("meme").unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(output_path).unwrap(), "5\nmeme\n");
+        std::fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn output_is_buffered_until_input_or_flush() {
+        let output_path = std::env::temp_dir().join(format!("cos341basic_flush_test_{}.txt", std::process::id()));
+        let output_path = output_path.to_str().unwrap();
+        let written = || std::fs::read_to_string(output_path).unwrap();
+        let mut console = StdConsole::new();
+        console.set_input_file("testfiles/input1.txt").unwrap();
+        console.set_output_file(output_path).unwrap();
+
+        console.write_line("Enter a number").unwrap();
+        assert_eq!(written(), "");
+        assert_eq!(console.read_line().unwrap().unwrap(), "3");
+        assert_eq!(written(), "Enter a number\n");
+        console.write_prompt("Another ").unwrap();
+        assert_eq!(console.read_line().unwrap().unwrap(), "5");
+        assert_eq!(written(), "Enter a number\nAnother ");
+        console.write_line("done").unwrap();
+        console.flush().unwrap();
+        assert_eq!(written(), "Enter a number\nAnother done\n");
+        std::fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn scripted_inputs_then_default() {
+        let mut console = ScriptedConsole::new(
+            vec![String::from("3"), String::from("hello")],
+            Some(String::from("0")),
+            Box::new(BufferConsole::new(""))
+        );
+        assert_eq!(console.read_line().unwrap().unwrap(), "3");
+        assert_eq!(console.read_line().unwrap().unwrap(), "hello");
+        assert_eq!(console.read_line().unwrap().unwrap(), "0");
+        assert_eq!(console.read_line().unwrap().unwrap(), "0");
+    }
+
+    #[test]
+    fn scripted_inputs_without_default() {
+        let mut console = ScriptedConsole::new(vec![String::from("5")], None, Box::new(BufferConsole::new("")));
+        assert_eq!(console.read_line().unwrap().unwrap(), "5");
+        assert!(console.read_line().unwrap().is_none());
+    }
+
+    #[test]
+    fn scripted_console_passes_output_on() {
+        let inner = BufferConsole::new("");
+        let output = inner.output.clone();
+        let mut console = ScriptedConsole::new(Vec::new(), None, Box::new(inner));
+        console.write_line("meme").unwrap();
+        assert_eq!(*output.lock().unwrap(), vec![String::from("meme")]);
+    }
+
+    #[test]
+    fn transcript_records_input_and_output() {
+        let transcript = SharedWriter::default();
+        let mut console = TranscriptConsole::new(
+            Box::new(transcript.clone()),
+            Box::new(BufferConsole::new("Bob\n"))
+        );
+        console.write_line("Enter your name").unwrap();
+        assert_eq!(console.read_line().unwrap().unwrap(), "Bob");
+        console.write_line("Hello Bob").unwrap();
+        console.write_prompt("Again").unwrap();
+        assert!(console.read_line().unwrap().is_none());
+
+        let recorded = String::from_utf8(transcript.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(recorded, "> Enter your name\n< Bob\n> Hello Bob\n> Again\n");
+    }
+
+    #[test]
+    fn limited_console_stops_after_max_lines() {
+        let inner = BufferConsole::new("");
+        let output = inner.output.clone();
+        let mut console = LimitedConsole::new(Some(2), None, Box::new(inner));
+        console.write_line("one").unwrap();
+        console.write_line("two").unwrap();
+        assert_eq!(console.write_line("three").err().unwrap(), "Output limit exceeded: more than 2 lines written!");
+        assert_eq!(*output.lock().unwrap(), vec![String::from("one"), String::from("two")]);
+    }
+
+    #[test]
+    fn limited_console_stops_after_max_bytes() {
+        let mut console = LimitedConsole::new(None, Some(8), Box::new(BufferConsole::new("")));
+        console.write_line("abc").unwrap();
+        console.write_prompt("abc").unwrap();
+        console.write_prompt("a").unwrap();
+        assert_eq!(console.write_line("").err().unwrap(), "Output limit exceeded: more than 8 bytes written!");
+    }
+}
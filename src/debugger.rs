@@ -0,0 +1,549 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::io::{BufRead, Write};
+use crate::prog_data::{Frame, ProgramData, Value};
+use crate::runner::{Outcome, RuntimeError};
+use crate::states::{get_state, NewState, States};
+
+/// Number of instructions that can be undone with 'back'.
+pub const HISTORY_LIMIT: usize = 1000;
+
+const STOPPED_MESSAGE: &str = "Program was stopped from the debugger!";
+
+const HELP: &str = "Commands:
+  step [n]      (s) Runs the next n instructions (1 by default)
+  back [n]      (b) Undoes the last n instructions (1 by default)
+  next          (n) Runs the next instruction, along with any subroutine it calls
+  finish        (f) Runs until the current subroutine returns
+  continue      (c) Runs until a breakpoint is reached or the program finishes
+  break <line>      Stops before the given line is run
+  delete <line>     Removes a breakpoint
+  print [$var]  (p) Shows a variable, or the whole state of the program
+  set $var <value>  Sets a variable
+  push <value>      Pushes a value onto the stack
+  jump <line>       Moves to the start of the given line
+  quit          (q) Stops the program
+  help          (h) Shows this list";
+
+/// The state of a program before an instruction was run.
+struct Snapshot {
+    index: usize,
+    inline_code: Option<String>,
+    // Variables outside every call, as those inside one are kept with the call
+    vars: HashMap<String, Value>,
+    stack: Vec<Value>,
+    calls: Vec<Frame>,
+}
+
+impl Snapshot {
+    fn of(data: &ProgramData) -> Self {
+        Snapshot {
+            index: data.get_index(),
+            inline_code: data.inline_code().map(String::from),
+            vars: data.global_vars().map(|(name, value)| (name.to_string(), value.clone())).collect(),
+            stack: data.iter_stack().cloned().collect(),
+            calls: data.frames().to_vec(),
+        }
+    }
+}
+
+/// The changes made by a single instruction, which are just enough to undo it.
+struct Delta {
+    index: usize,
+    inline_code: Option<String>,
+    // The value each changed variable outside every call had before the instruction, or None if
+    // it was not set
+    vars: Vec<(String, Option<Value>)>,
+    // Values taken off the top of the stack (top first), and the number of values put in their place
+    popped: Vec<Value>,
+    pushed: usize,
+    // The calls the program was inside, if the instruction made or returned from one or changed
+    // a variable inside one
+    calls: Option<Vec<Frame>>,
+}
+
+impl Delta {
+    fn between(before: Snapshot, after: &ProgramData) -> Self {
+        let after_vars: HashMap<&str, &Value> = after.global_vars().collect();
+        let mut vars: Vec<(String, Option<Value>)> = after_vars.iter()
+            .filter(|(name, value)| before.vars.get(**name) != Some(**value))
+            .map(|(name, _)| (name.to_string(), before.vars.get(*name).cloned()))
+            .collect();
+        vars.extend(before.vars.iter()
+            .filter(|(name, _)| !after_vars.contains_key(name.as_str()))
+            .map(|(name, value)| (name.clone(), Some(value.clone()))));
+
+        // The bottom of the stack that the instruction did not touch is left out of the delta
+        let stack: Vec<&Value> = after.iter_stack().collect();
+        let untouched = before.stack.iter().rev().zip(stack.iter().rev())
+            .take_while(|(before, after)| before == *after)
+            .count();
+        let mut popped = before.stack;
+        popped.truncate(popped.len() - untouched);
+
+        let calls = Some(before.calls).filter(|calls| calls.as_slice() != after.frames());
+        Delta {
+            index: before.index,
+            inline_code: before.inline_code,
+            vars,
+            popped,
+            pushed: stack.len() - untouched,
+            calls,
+        }
+    }
+
+    fn undo(self, data: &mut ProgramData) {
+        for _ in 0..self.pushed {
+            data.pop();
+        }
+        for value in self.popped.into_iter().rev() {
+            data.push(value);
+        }
+        // The calls are set aside while the variables are restored, so that only variables
+        // outside every call are changed
+        let calls = self.calls.unwrap_or_else(|| data.frames().to_vec());
+        data.set_frames(Vec::new());
+        for (name, value) in self.vars {
+            match value {
+                Some(value) => data.set_var(name, value),
+                None => {
+                    data.remove_var(&name);
+                },
+            }
+        }
+        data.set_frames(calls);
+        data.set_index(self.index);
+        if let Some(code) = self.inline_code {
+            data.set_inline_code(code);
+        }
+    }
+}
+
+/// Where a program is after running an instruction.
+enum Step {
+    Paused(ProgramData),
+    Finished(Outcome),
+    Failed(ProgramData, RuntimeError),
+}
+
+/// Runs the current instruction of a program, through every state it takes.
+fn run_instruction(mut data: ProgramData) -> Step {
+    let line = data.get_index();
+    let mut state = get_state(States::ExecuteState);
+    loop {
+        match state.execute(data) {
+            NewState::Continue(next_data, next_state) if next_state.starts_instruction() => return Step::Paused(next_data),
+            NewState::Continue(next_data, next_state) => {
+                data = next_data;
+                state = next_state;
+            },
+            NewState::Finished(data, exit_code) => return Step::Finished(Outcome { exit_code, data }),
+            NewState::Error(data, msg) => {
+                let error = RuntimeError::in_program(&data, line, &msg);
+                return Step::Failed(data, error);
+            },
+        }
+    }
+}
+
+/// Removes the quotes around a value given to 'set' or 'push', if it has them.
+fn unquote(value: &str) -> &str {
+    value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value)
+}
+
+/// Interactive debugger that runs a program one instruction at a time. The last `HISTORY_LIMIT`
+/// instructions are remembered so that they can be stepped back through, which restores the
+/// variables, stack and current line as they were. Input that has been read and output that has
+/// been written are not taken back. An instruction that fails leaves the program as it was
+/// before the instruction ran, so that the failure can be looked into (and the instruction run
+/// again once the state has been fixed).
+pub struct Debugger {
+    commands: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    history: VecDeque<Delta>,
+    breakpoints: BTreeSet<usize>,
+    // The error raised by the instruction the program is stopped at, if it failed
+    error: Option<RuntimeError>,
+}
+
+impl Debugger {
+    /// Creates a debugger that reads its commands from `commands` and writes what it has to say
+    /// to `output`, which should be kept apart from the output of the program (such as stderr).
+    pub fn new(commands: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Debugger {
+            commands,
+            output,
+            history: VecDeque::new(),
+            breakpoints: BTreeSet::new(),
+            error: None,
+        }
+    }
+
+    fn say(&mut self, message: &str) {
+        writeln!(self.output, "{}", message).ok();
+    }
+
+    fn show_position(&mut self, data: &ProgramData) {
+        let position = match data.current_line() {
+            Some(line) => format!("{}: {}", data.get_index(), line),
+            None => String::from("(end of program)"),
+        };
+        self.say(&position);
+    }
+
+    /// Reads the next command, or `None` once the commands have run out.
+    fn read_command(&mut self) -> Option<String> {
+        write!(self.output, "(debug) ").ok();
+        self.output.flush().ok();
+        let mut command = String::new();
+        match self.commands.read_line(&mut command) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(command.trim().to_string()),
+        }
+    }
+
+    /// Runs a single instruction, remembering how to undo it. If the instruction fails, whatever
+    /// it changed before failing is undone straight away.
+    fn step(&mut self, data: ProgramData) -> Step {
+        let before = Snapshot::of(&data);
+        match run_instruction(data) {
+            Step::Paused(data) => {
+                self.history.push_back(Delta::between(before, &data));
+                if self.history.len() > HISTORY_LIMIT {
+                    self.history.pop_front();
+                }
+                Step::Paused(data)
+            },
+            Step::Failed(mut data, error) => {
+                Delta::between(before, &data).undo(&mut data);
+                Step::Failed(data, error)
+            },
+            finished => finished,
+        }
+    }
+
+    /// Changes the state of the program outside of any instruction. The change is remembered like
+    /// an instruction is, so it can be undone with 'back'.
+    fn patch(&mut self, data: &mut ProgramData, change: impl FnOnce(&mut ProgramData)) {
+        let before = Snapshot::of(data);
+        change(data);
+        self.history.push_back(Delta::between(before, data));
+        if self.history.len() > HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+    }
+
+    /// Runs instructions until `done` holds for the program, a breakpoint is reached or the
+    /// program finishes. At least one instruction is run.
+    fn run_until(&mut self, mut data: ProgramData, done: impl Fn(&ProgramData) -> bool) -> Step {
+        loop {
+            data = match self.step(data) {
+                Step::Paused(data) => data,
+                stopped => return stopped,
+            };
+            if data.inline_code().is_none() && self.breakpoints.contains(&data.get_index()) {
+                self.say(&format!("Breakpoint at line {}", data.get_index()));
+                return Step::Paused(data);
+            }
+            if done(&data) {
+                return Step::Paused(data);
+            }
+        }
+    }
+
+    /// Shows where the program has stopped after running instructions, or hands back the outcome
+    /// of a program that finished.
+    fn stop(&mut self, step: Step) -> Result<ProgramData, Box<Outcome>> {
+        let mut data = match step {
+            Step::Paused(data) => {
+                self.error = None;
+                data
+            },
+            Step::Finished(outcome) => return Err(Box::new(outcome)),
+            Step::Failed(data, error) => {
+                self.say(&format!("Error on line {}: {}", error.line, error.message));
+                self.error = Some(error);
+                data
+            },
+        };
+        // The program's output so far is shown before the debugger has its say
+        data.console().flush().ok();
+        self.show_position(&data);
+        Ok(data)
+    }
+
+    /// Runs a program under the debugger until it finishes or is stopped with 'quit'. The
+    /// debugger stops before the first instruction is run.
+    ///
+    /// # Arguments
+    /// * `data` - The program to debug, along with its variables, stack and console
+    ///
+    /// # Returns
+    /// * `Ok(Outcome)` - The exit code and the final program data of a program that finished
+    /// * `Err(RuntimeError)` - The error the program was stopped at, or a message saying it was
+    ///   stopped from the debugger
+    pub fn run(&mut self, data: ProgramData) -> Result<Outcome, RuntimeError> {
+        self.show_position(&data);
+        self.debug(data)
+    }
+
+    /// Runs a program at full speed until it finishes or encounters an error. On an error, the
+    /// debugger is opened at the failing line, with the state of the program as it was before
+    /// the line ran (and the instructions before it available to 'back').
+    ///
+    /// # Arguments
+    /// * `data` - The program to run, along with its variables, stack and console
+    ///
+    /// # Returns
+    /// * `Ok(Outcome)` - The exit code and the final program data of a program that finished
+    /// * `Err(RuntimeError)` - The error the program was stopped at, or a message saying it was
+    ///   stopped from the debugger
+    pub fn run_until_error(&mut self, data: ProgramData) -> Result<Outcome, RuntimeError> {
+        let step = self.run_until(data, |_| false);
+        match self.stop(step) {
+            Ok(data) => self.debug(data),
+            Err(outcome) => Ok(*outcome),
+        }
+    }
+
+    /// Carries out debugger commands until the program finishes or is stopped.
+    fn debug(&mut self, mut data: ProgramData) -> Result<Outcome, RuntimeError> {
+        loop {
+            // Once the commands run out, the program is left to run to the end, unless it is
+            // stopped at an error (which would only happen again)
+            let command = match (self.read_command(), self.error.take()) {
+                (Some(command), error) => {
+                    self.error = error;
+                    command
+                },
+                (None, Some(error)) => return Err(error),
+                (None, None) => String::from("continue"),
+            };
+            let mut words = command.split_whitespace();
+            let name = words.next().unwrap_or("");
+            let argument = words.next();
+            let rest = command[name.len()..].trim();
+            let count = match argument.map(str::parse::<usize>) {
+                None => 1,
+                Some(Ok(count)) => count,
+                Some(Err(_)) if ["step", "s", "back", "b", "break", "delete", "jump"].contains(&name) => {
+                    self.say(&format!("Invalid number: {}", argument.unwrap()));
+                    continue;
+                },
+                Some(Err(_)) => 1,
+            };
+
+            match name {
+                "step" | "s" => {
+                    let mut step = Step::Paused(data);
+                    for _ in 0..count {
+                        step = match step {
+                            Step::Paused(data) => self.step(data),
+                            stopped => stopped,
+                        };
+                    }
+                    data = match self.stop(step) {
+                        Ok(data) => data,
+                        Err(outcome) => return Ok(*outcome),
+                    };
+                },
+                "finish" | "f" if data.call_depth() == 0 => self.say("Not inside a subroutine!"),
+                "continue" | "c" | "next" | "n" | "finish" | "f" => {
+                    let depth = data.call_depth();
+                    let step = match name {
+                        "next" | "n" => self.run_until(data, |data| data.call_depth() <= depth),
+                        "finish" | "f" => self.run_until(data, |data| data.call_depth() < depth),
+                        _ => self.run_until(data, |_| false),
+                    };
+                    data = match self.stop(step) {
+                        Ok(data) => data,
+                        Err(outcome) => return Ok(*outcome),
+                    };
+                },
+                "back" | "b" => {
+                    let steps = count.min(self.history.len());
+                    for delta in self.history.drain(self.history.len() - steps..).rev().collect::<Vec<Delta>>() {
+                        delta.undo(&mut data);
+                    }
+                    if steps < count {
+                        self.say(&format!("Only {} instructions could be stepped back through", steps));
+                    }
+                    self.show_position(&data);
+                },
+                "break" | "delete" | "jump" if argument.is_none() => self.say(&format!("Usage: {} <line>", name)),
+                "break" | "jump" if count >= data.code_size() => self.say(&format!("Line {} is not part of the program!", count)),
+                "break" => {
+                    self.breakpoints.insert(count);
+                    self.say(&format!("Breakpoint set at line {}", count));
+                },
+                "delete" => {
+                    if !self.breakpoints.remove(&count) {
+                        self.say(&format!("There is no breakpoint at line {}", count));
+                    }
+                },
+                "jump" => {
+                    self.patch(&mut data, |data| data.set_index(count));
+                    self.show_position(&data);
+                },
+                "set" => match rest.split_once(char::is_whitespace) {
+                    Some((var, value)) if var.len() > 1 && var.starts_with('$') => {
+                        let (name, value) = (var[1..].to_string(), unquote(value.trim()).to_string());
+                        self.patch(&mut data, |data| data.set_var(name, value));
+                        self.say(&format!("{} = {}", var, data.get_var(&var[1..]).unwrap()));
+                    },
+                    _ => self.say("Usage: set $var <value>"),
+                },
+                "push" if rest.is_empty() => self.say("Usage: push <value>"),
+                "push" => {
+                    let value = unquote(rest).to_string();
+                    self.patch(&mut data, |data| data.push(value));
+                },
+                "print" | "p" => match argument {
+                    Some(var) => match data.get_var(var.trim_start_matches('$')) {
+                        Some(value) => self.say(&format!("{} = {}", var, value)),
+                        None => self.say(&format!("{} is not set", var)),
+                    },
+                    None => self.say(&data.to_string()),
+                },
+                "quit" | "q" => return Err(self.error.take()
+                    .unwrap_or(RuntimeError::in_program(&data, data.get_index(), STOPPED_MESSAGE))),
+                "help" | "h" => self.say(HELP),
+                "" => {},
+                _ => self.say(&format!("Unknown command: {} (type help for a list of commands)", name)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, LinkedList};
+    use std::io::{Cursor, Write};
+    use std::sync::{Arc, Mutex};
+    use crate::prog_data::ProgramData;
+    use super::{Debugger, STOPPED_MESSAGE};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn program(lines: Vec<&str>) -> ProgramData {
+        let mut stack = LinkedList::new();
+        stack.push_front(String::from("3"));
+        ProgramData::new(lines.into_iter().map(String::from).collect(), HashMap::new(), stack, 0)
+    }
+
+    /// Debugs a program with the given commands, returning what the debugger said.
+    fn debug(data: ProgramData, commands: &str) -> (Option<i32>, String) {
+        let output = SharedBuffer::default();
+        let mut debugger = Debugger::new(Box::new(Cursor::new(commands.to_string())), Box::new(output.clone()));
+        let exit_code = debugger.run(data).ok().map(|outcome| outcome.exit_code);
+        let said = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        (exit_code, said.replace("(debug) ", ""))
+    }
+
+    #[test]
+    fn step_and_print() {
+        let data = program(vec!["let $a = 5", "output $a", "quit"]);
+        let (exit_code, said) = debug(data, "print $a\nstep\np $a\nhelp\nfoo\n");
+        assert_eq!(exit_code, Some(0));
+        assert!(said.starts_with("0: let $a = 5\n$a is not set\n1: output $a\n$a = 5\nCommands:\n"));
+        assert!(said.ends_with("Unknown command: foo (type help for a list of commands)\n"));
+    }
+
+    #[test]
+    fn step_back_restores_state() {
+        let data = program(vec!["let $a = 1", "let $a = 2", "let $b = pop", "push $a", "quit"]);
+        let (_, said) = debug(data, "s 4\np\nback 3\np\nb 5\nq\n");
+        assert_eq!(said, "0: let $a = 1\n4: quit\nLine 4: quit\nVariables:\n  $a = 2\n  $b = 3\nStack:\n  2 (top)\n\
+                    1: let $a = 2\nLine 1: let $a = 2\nVariables:\n  $a = 1\nStack:\n  3 (top)\n\
+                    Only 1 instructions could be stepped back through\n0: let $a = 1\n");
+    }
+
+    #[test]
+    fn step_back_through_subroutines() {
+        let data = program(vec!["gosub 2", "quit", "return"]);
+        let (exit_code, said) = debug(data, "s 2\nback\ns\nc\n");
+        assert_eq!(exit_code, Some(0));
+        assert_eq!(said, "0: gosub 2\n1: quit\n2: return\n1: quit\n");
+    }
+
+    #[test]
+    fn step_back_through_functions() {
+        let data = program(vec!["let $x = 1", "let $y = call f 5", "quit", "func f $x", "let $x = $x + $x", "return $x", "endfunc"]);
+        let (_, said) = debug(data, "s 3\np $x\nback 2\np $x\ns 3\np\nq\n");
+        // The parameter hides $x while the function runs, and is gone once the call is undone
+        assert_eq!(said, "0: let $x = 1\n5: return $x\n$x = 10\n1: let $y = call f 5\n$x = 1\n2: quit\n\
+                          Line 2: quit\nVariables:\n  $x = 1\n  $y = 10\nStack:\n  3 (top)\n");
+    }
+
+    #[test]
+    fn continue_to_breakpoint() {
+        let data = program(vec!["let $a = 1", "let $b = 2", "output $b", "quit"]);
+        let (exit_code, said) = debug(data, "break 2\nbreak 9\nc\np $b\ndelete 2\nc\n");
+        assert_eq!(exit_code, Some(0));
+        assert_eq!(said, "0: let $a = 1\nBreakpoint set at line 2\nLine 9 is not part of the program!\n\
+                          Breakpoint at line 2\n2: output $b\n$b = 2\n");
+    }
+
+    #[test]
+    fn next_and_finish_without_subroutines() {
+        let data = program(vec!["let $a = 1", "let $b = 2", "quit"]);
+        let (exit_code, said) = debug(data, "next\nfinish\nn\n");
+        assert_eq!(exit_code, Some(0));
+        assert_eq!(said, "0: let $a = 1\n1: let $b = 2\nNot inside a subroutine!\n2: quit\n");
+    }
+
+    #[test]
+    fn modify_state_from_debugger() {
+        let data = program(vec!["let $a = 1", "let $b = pop", "output $b", "output $a", "quit"]);
+        let (_, said) = debug(data, "set $a 42\npush \"hello world\"\njump 1\ns\np\nback 2\np\nset a\njump 7\nq\n");
+        assert_eq!(said, "0: let $a = 1\n$a = 42\n1: let $b = pop\n2: output $b\n\
+                          Line 2: output $b\nVariables:\n  $a = 42\n  $b = hello world\nStack:\n  3 (top)\n\
+                          0: let $a = 1\nLine 0: let $a = 1\nVariables:\n  $a = 42\nStack:\n  hello world (top)\n  3\n\
+                          Usage: set $var <value>\nLine 7 is not part of the program!\n");
+    }
+
+    #[test]
+    fn quit_from_debugger() {
+        let output = SharedBuffer::default();
+        let mut debugger = Debugger::new(Box::new(Cursor::new("quit\n")), Box::new(output));
+        let error = debugger.run(program(vec!["quit"])).err().unwrap();
+        assert_eq!(error.message, STOPPED_MESSAGE);
+    }
+
+    #[test]
+    fn debug_on_error_opens_at_failing_line() {
+        let data = program(vec!["let $a = pop", "let $b = pop", "output $b", "quit"]);
+        let output = SharedBuffer::default();
+        let commands = "p\npush 4\ns\np $b\nc\n";
+        let mut debugger = Debugger::new(Box::new(Cursor::new(commands)), Box::new(output.clone()));
+        let outcome = debugger.run_until_error(data).ok().unwrap();
+        assert_eq!(outcome.exit_code, 0);
+        let said = String::from_utf8(output.0.lock().unwrap().clone()).unwrap().replace("(debug) ", "");
+        assert_eq!(said, "Error on line 1: Stack is empty!\n1: let $b = pop\n\
+                          Line 1: let $b = pop\nVariables:\n  $a = 3\nStack: (empty)\n2: output $b\n$b = 4\n");
+    }
+
+    #[test]
+    fn quit_at_error_returns_error() {
+        let lines = vec!["let $a = pop", "let $b = pop", "quit"];
+        let mut debugger = Debugger::new(Box::new(Cursor::new("q\n")), Box::new(SharedBuffer::default()));
+        let error = debugger.run_until_error(program(lines.clone())).err().unwrap();
+        assert_eq!(error.line, 1);
+        assert_ne!(error.message, STOPPED_MESSAGE);
+
+        // Running out of commands at an error gives the error back too
+        let mut debugger = Debugger::new(Box::new(Cursor::new("")), Box::new(SharedBuffer::default()));
+        assert_eq!(debugger.run_until_error(program(lines)).err().unwrap().line, 1);
+    }
+}
@@ -0,0 +1,58 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Language versions understood by the interpreter. Programs run as `V1` unless they ask for a
+/// newer dialect, so older programs keep behaving exactly as they always have.
+///
+/// * `V1` - The original language. Comparisons are always made between strings.
+/// * `V2` - Comparisons between two numbers are made numerically.
+/// * `V3` - Dividing with `/` always gives a float, and no longer pushes the remainder onto the
+///   stack. Integers are divided with `//` (in any dialect), and the remainder is found with `%`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Dialect {
+    #[default]
+    V1,
+    V2,
+    V3,
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "v1" => Ok(Dialect::V1),
+            "v2" => Ok(Dialect::V2),
+            "v3" => Ok(Dialect::V3),
+            _ => Err(format!("Unknown dialect: {} (expected v1, v2 or v3)", name)),
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dialect::V1 => write!(f, "v1"),
+            Dialect::V2 => write!(f, "v2"),
+            Dialect::V3 => write!(f, "v3"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Dialect;
+
+    #[test]
+    fn parse_dialects() {
+        assert_eq!("v1".parse::<Dialect>().unwrap(), Dialect::V1);
+        assert_eq!("v2".parse::<Dialect>().unwrap(), Dialect::V2);
+        assert_eq!("v3".parse::<Dialect>().unwrap(), Dialect::V3);
+        assert_eq!("v4".parse::<Dialect>().err().unwrap(), "Unknown dialect: v4 (expected v1, v2 or v3)");
+    }
+
+    #[test]
+    fn default_dialect_is_v1() {
+        assert_eq!(Dialect::default(), Dialect::V1);
+    }
+}
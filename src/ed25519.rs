@@ -0,0 +1,442 @@
+//! Verification of Ed25519 signatures (RFC 8032), so that signed programs can be checked without
+//! pulling in a cryptography crate. Only verification is implemented: programs are signed with
+//! standard tools such as 'openssl pkeyutl'.
+//!
+//! None of this is constant time, which is fine as only public data is ever handled.
+
+const SHA512_ROUND_CONSTANTS: [u64; 80] = [
+    0x428a2f98d728ae22, 0x7137449123ef65cd, 0xb5c0fbcfec4d3b2f, 0xe9b5dba58189dbbc,
+    0x3956c25bf348b538, 0x59f111f1b605d019, 0x923f82a4af194f9b, 0xab1c5ed5da6d8118,
+    0xd807aa98a3030242, 0x12835b0145706fbe, 0x243185be4ee4b28c, 0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f, 0x80deb1fe3b1696b1, 0x9bdc06a725c71235, 0xc19bf174cf692694,
+    0xe49b69c19ef14ad2, 0xefbe4786384f25e3, 0x0fc19dc68b8cd5b5, 0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275, 0x4a7484aa6ea6e483, 0x5cb0a9dcbd41fbd4, 0x76f988da831153b5,
+    0x983e5152ee66dfab, 0xa831c66d2db43210, 0xb00327c898fb213f, 0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2, 0xd5a79147930aa725, 0x06ca6351e003826f, 0x142929670a0e6e70,
+    0x27b70a8546d22ffc, 0x2e1b21385c26c926, 0x4d2c6dfc5ac42aed, 0x53380d139d95b3df,
+    0x650a73548baf63de, 0x766a0abb3c77b2a8, 0x81c2c92e47edaee6, 0x92722c851482353b,
+    0xa2bfe8a14cf10364, 0xa81a664bbc423001, 0xc24b8b70d0f89791, 0xc76c51a30654be30,
+    0xd192e819d6ef5218, 0xd69906245565a910, 0xf40e35855771202a, 0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8, 0x1e376c085141ab53, 0x2748774cdf8eeb99, 0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63, 0x4ed8aa4ae3418acb, 0x5b9cca4f7763e373, 0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc, 0x78a5636f43172f60, 0x84c87814a1f0ab72, 0x8cc702081a6439ec,
+    0x90befffa23631e28, 0xa4506cebde82bde9, 0xbef9a3f7b2c67915, 0xc67178f2e372532b,
+    0xca273eceea26619c, 0xd186b8c721c0c207, 0xeada7dd6cde0eb1e, 0xf57d4f7fee6ed178,
+    0x06f067aa72176fba, 0x0a637dc5a2c898a6, 0x113f9804bef90dae, 0x1b710b35131c471b,
+    0x28db77f523047d84, 0x32caab7b40c72493, 0x3c9ebe0a15c9bebc, 0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6, 0x597f299cfc657e2a, 0x5fcb6fab3ad6faec, 0x6c44198c4a475817,
+];
+
+const SHA512_INITIAL_HASH: [u64; 8] = [
+    0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+    0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+];
+
+/// The order of the base point, 2^252 + 27742317777372353535851937790883648493, little endian
+const GROUP_ORDER: [u64; 4] = [0x5812631a5cf5d3ed, 0x14def9dea2f79cd6, 0, 0x1000000000000000];
+
+/// The encoding of the base point, whose y coordinate is 4/5
+const BASE_POINT: [u8; 32] = [
+    0x58, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+    0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+];
+
+/// Computes the SHA-512 digest of `data`.
+fn sha512(data: &[u8]) -> [u8; 64] {
+    // Pad the message with a 1 bit, zeroes and the message length in bits
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 128 != 112 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u128) * 8).to_be_bytes());
+
+    let mut hash = SHA512_INITIAL_HASH;
+    for block in message.chunks(128) {
+        let mut schedule = [0u64; 80];
+        for (index, word) in block.chunks(8).enumerate() {
+            schedule[index] = u64::from_be_bytes(word.try_into().unwrap());
+        }
+        for index in 16..80 {
+            let s0 = schedule[index - 15].rotate_right(1) ^ schedule[index - 15].rotate_right(8) ^ (schedule[index - 15] >> 7);
+            let s1 = schedule[index - 2].rotate_right(19) ^ schedule[index - 2].rotate_right(61) ^ (schedule[index - 2] >> 6);
+            schedule[index] = schedule[index - 16].wrapping_add(s0).wrapping_add(schedule[index - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = hash;
+        for index in 0..80 {
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let choice = (e & f) ^ (!e & g);
+            let temp1 = h.wrapping_add(s1).wrapping_add(choice).wrapping_add(SHA512_ROUND_CONSTANTS[index]).wrapping_add(schedule[index]);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(majority);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (value, new_value) in hash.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *value = value.wrapping_add(new_value);
+        }
+    }
+
+    let mut digest = [0u8; 64];
+    for (bytes, word) in digest.chunks_mut(8).zip(hash) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+const LIMB_MASK: u64 = (1 << 51) - 1;
+
+/// An element of the field of integers modulo 2^255 - 19, held in five 51 bit limbs. The limbs
+/// are only partially reduced between operations.
+#[derive(Clone, Copy, Debug)]
+struct FieldElement([u64; 5]);
+
+impl FieldElement {
+    const ZERO: FieldElement = FieldElement([0, 0, 0, 0, 0]);
+    const ONE: FieldElement = FieldElement([1, 0, 0, 0, 0]);
+
+    fn from_u64(value: u64) -> FieldElement {
+        FieldElement([value & LIMB_MASK, value >> 51, 0, 0, 0])
+    }
+
+    /// Reads a little endian field element, ignoring the top bit.
+    fn from_bytes(bytes: &[u8; 32]) -> FieldElement {
+        let load = |index: usize| u64::from_le_bytes(bytes[index..index + 8].try_into().unwrap());
+        FieldElement([
+            load(0) & LIMB_MASK,
+            (load(6) >> 3) & LIMB_MASK,
+            (load(12) >> 6) & LIMB_MASK,
+            (load(19) >> 1) & LIMB_MASK,
+            (load(24) >> 12) & LIMB_MASK,
+        ])
+    }
+
+    /// Writes the fully reduced field element in little endian.
+    fn to_bytes(self) -> [u8; 32] {
+        let mut limbs = FieldElement::carry(self.0).0;
+
+        // Subtract the modulus once if the value is not smaller than it
+        let mut overflow = (limbs[0] + 19) >> 51;
+        for limb in &limbs[1..] {
+            overflow = (limb + overflow) >> 51;
+        }
+        limbs[0] += 19 * overflow;
+        for index in 0..4 {
+            limbs[index + 1] += limbs[index] >> 51;
+            limbs[index] &= LIMB_MASK;
+        }
+        limbs[4] &= LIMB_MASK;
+
+        let words = [
+            limbs[0] | (limbs[1] << 51),
+            (limbs[1] >> 13) | (limbs[2] << 38),
+            (limbs[2] >> 26) | (limbs[3] << 25),
+            (limbs[3] >> 39) | (limbs[4] << 12),
+        ];
+        let mut bytes = [0u8; 32];
+        for (chunk, word) in bytes.chunks_mut(8).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Carries the bits above each limb into the next one, wrapping the top limb around as
+    /// 2^255 = 19 (mod p).
+    fn carry(mut limbs: [u64; 5]) -> FieldElement {
+        let carries = limbs.map(|limb| limb >> 51);
+        for limb in limbs.iter_mut() {
+            *limb &= LIMB_MASK;
+        }
+        limbs[0] += carries[4] * 19;
+        for index in 1..5 {
+            limbs[index] += carries[index - 1];
+        }
+        FieldElement(limbs)
+    }
+
+    fn add(self, other: FieldElement) -> FieldElement {
+        let mut limbs = self.0;
+        for (limb, other_limb) in limbs.iter_mut().zip(other.0) {
+            *limb += other_limb;
+        }
+        FieldElement::carry(limbs)
+    }
+
+    fn sub(self, other: FieldElement) -> FieldElement {
+        // Adding 16p first keeps every limb from going negative
+        let mut limbs = [
+            self.0[0] + 0x7ffffffffffed0,
+            self.0[1] + 0x7ffffffffffff0,
+            self.0[2] + 0x7ffffffffffff0,
+            self.0[3] + 0x7ffffffffffff0,
+            self.0[4] + 0x7ffffffffffff0,
+        ];
+        for (limb, other_limb) in limbs.iter_mut().zip(other.0) {
+            *limb -= other_limb;
+        }
+        FieldElement::carry(limbs)
+    }
+
+    fn neg(self) -> FieldElement {
+        FieldElement::ZERO.sub(self)
+    }
+
+    fn mul(self, other: FieldElement) -> FieldElement {
+        let a = self.0;
+        let b = other.0;
+        let product = |x: u64, y: u64| (x as u128) * (y as u128);
+        let b19 = b.map(|limb| limb * 19);
+
+        let mut wide = [
+            product(a[0], b[0]) + product(a[4], b19[1]) + product(a[3], b19[2]) + product(a[2], b19[3]) + product(a[1], b19[4]),
+            product(a[1], b[0]) + product(a[0], b[1]) + product(a[4], b19[2]) + product(a[3], b19[3]) + product(a[2], b19[4]),
+            product(a[2], b[0]) + product(a[1], b[1]) + product(a[0], b[2]) + product(a[4], b19[3]) + product(a[3], b19[4]),
+            product(a[3], b[0]) + product(a[2], b[1]) + product(a[1], b[2]) + product(a[0], b[3]) + product(a[4], b19[4]),
+            product(a[4], b[0]) + product(a[3], b[1]) + product(a[2], b[2]) + product(a[1], b[3]) + product(a[0], b[4]),
+        ];
+        for index in 0..4 {
+            wide[index + 1] += wide[index] >> 51;
+        }
+        let mut limbs = wide.map(|limb| (limb as u64) & LIMB_MASK);
+        limbs[0] += ((wide[4] >> 51) as u64) * 19;
+        limbs[1] += limbs[0] >> 51;
+        limbs[0] &= LIMB_MASK;
+        FieldElement(limbs)
+    }
+
+    fn square(self) -> FieldElement {
+        self.mul(self)
+    }
+
+    /// Raises the field element to the power of the little endian `exponent`.
+    fn pow(self, exponent: &[u8; 32]) -> FieldElement {
+        let mut result = FieldElement::ONE;
+        for bit in (0..256).rev() {
+            result = result.square();
+            if (exponent[bit / 8] >> (bit % 8)) & 1 == 1 {
+                result = result.mul(self);
+            }
+        }
+        result
+    }
+
+    /// Raises the field element to the power of 2^255 - 21, 2^252 - 3 or 2^253 - 5, whose little
+    /// endian encodings are all 0xff apart from the first and last bytes.
+    fn pow_special(self, first: u8, last: u8) -> FieldElement {
+        let mut exponent = [0xff; 32];
+        exponent[0] = first;
+        exponent[31] = last;
+        self.pow(&exponent)
+    }
+
+    fn invert(self) -> FieldElement {
+        // x^(p - 2) = x^-1 (mod p)
+        self.pow_special(0xeb, 0x7f)
+    }
+
+    fn is_zero(self) -> bool {
+        self.to_bytes() == [0; 32]
+    }
+
+    fn is_negative(self) -> bool {
+        self.to_bytes()[0] & 1 == 1
+    }
+
+    fn equals(self, other: FieldElement) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+}
+
+/// The curve constant d = -121665 / 121666.
+fn curve_constant() -> FieldElement {
+    FieldElement::from_u64(121665).neg().mul(FieldElement::from_u64(121666).invert())
+}
+
+/// A point on the Edwards curve, in extended coordinates: x = X / Z, y = Y / Z and x * y = T / Z.
+#[derive(Clone, Copy, Debug)]
+struct Point {
+    x: FieldElement,
+    y: FieldElement,
+    z: FieldElement,
+    t: FieldElement,
+}
+
+impl Point {
+    const IDENTITY: Point = Point {
+        x: FieldElement::ZERO,
+        y: FieldElement::ONE,
+        z: FieldElement::ONE,
+        t: FieldElement::ZERO,
+    };
+
+    /// Reads an encoded point (RFC 8032, section 5.1.3), returning `None` if the encoding is not
+    /// canonical or not on the curve.
+    fn decompress(bytes: &[u8; 32]) -> Option<Point> {
+        let y = FieldElement::from_bytes(bytes);
+        let mut canonical = *bytes;
+        canonical[31] &= 0x7f;
+        if y.to_bytes() != canonical {
+            return None;
+        }
+
+        let y_squared = y.square();
+        let u = y_squared.sub(FieldElement::ONE);
+        let v = curve_constant().mul(y_squared).add(FieldElement::ONE);
+
+        // x = u * v^3 * (u * v^7)^((p - 5) / 8)
+        let v3 = v.square().mul(v);
+        let v7 = v3.square().mul(v);
+        let mut x = u.mul(v3).mul(u.mul(v7).pow_special(0xfd, 0x0f));
+        let check = v.mul(x.square());
+        if check.equals(u.neg()) {
+            // Multiply by sqrt(-1) = 2^((p - 1) / 4)
+            x = x.mul(FieldElement::from_u64(2).pow_special(0xfb, 0x1f));
+        } else if !check.equals(u) {
+            return None;
+        }
+
+        let sign = bytes[31] >> 7 == 1;
+        if sign && x.is_zero() {
+            return None;
+        }
+        if x.is_negative() != sign {
+            x = x.neg();
+        }
+        Some(Point { x, y, z: FieldElement::ONE, t: x.mul(y) })
+    }
+
+    fn compress(&self) -> [u8; 32] {
+        let z_inverse = self.z.invert();
+        let x = self.x.mul(z_inverse);
+        let mut bytes = self.y.mul(z_inverse).to_bytes();
+        if x.is_negative() {
+            bytes[31] |= 0x80;
+        }
+        bytes
+    }
+
+    /// Adds two points (RFC 8032, section 5.1.4). The formula also works for doubling.
+    fn add(&self, other: &Point, d: FieldElement) -> Point {
+        let a = self.y.sub(self.x).mul(other.y.sub(other.x));
+        let b = self.y.add(self.x).mul(other.y.add(other.x));
+        let c = self.t.mul(d.add(d)).mul(other.t);
+        let d = self.z.add(self.z).mul(other.z);
+        let (e, f, g, h) = (b.sub(a), d.sub(c), d.add(c), b.add(a));
+        Point { x: e.mul(f), y: g.mul(h), z: f.mul(g), t: e.mul(h) }
+    }
+
+    /// Multiplies the point by the little endian `scalar`.
+    fn mul(&self, scalar: &[u8; 32], d: FieldElement) -> Point {
+        let mut result = Point::IDENTITY;
+        for bit in (0..256).rev() {
+            result = result.add(&result, d);
+            if (scalar[bit / 8] >> (bit % 8)) & 1 == 1 {
+                result = result.add(self, d);
+            }
+        }
+        result
+    }
+}
+
+/// Compares two little endian 256 bit integers.
+fn is_less(value: &[u64; 4], other: &[u64; 4]) -> bool {
+    value.iter().rev().cmp(other.iter().rev()) == std::cmp::Ordering::Less
+}
+
+/// Reduces a little endian 512 bit integer modulo the group order, one bit at a time.
+fn reduce_scalar(bytes: &[u8; 64]) -> [u8; 32] {
+    let mut remainder = [0u64; 4];
+    for bit in (0..512).rev() {
+        // The remainder stays below 2^253, so shifting it left never overflows
+        for index in (1..4).rev() {
+            remainder[index] = (remainder[index] << 1) | (remainder[index - 1] >> 63);
+        }
+        remainder[0] = (remainder[0] << 1) | ((bytes[bit / 8] >> (bit % 8)) & 1) as u64;
+
+        if !is_less(&remainder, &GROUP_ORDER) {
+            let mut borrow = false;
+            for (limb, order_limb) in remainder.iter_mut().zip(GROUP_ORDER) {
+                let (difference, first_borrow) = limb.overflowing_sub(order_limb);
+                let (difference, second_borrow) = difference.overflowing_sub(borrow as u64);
+                *limb = difference;
+                borrow = first_borrow || second_borrow;
+            }
+        }
+    }
+
+    let mut scalar = [0u8; 32];
+    for (chunk, limb) in scalar.chunks_mut(8).zip(remainder) {
+        chunk.copy_from_slice(&limb.to_le_bytes());
+    }
+    scalar
+}
+
+/// Checks an Ed25519 signature of `message` (RFC 8032, section 5.1.7).
+///
+/// # Returns
+/// * `true` - The signature was made over `message` by the owner of `public_key`
+/// * `false` - The signature, or the public key, is not valid
+pub fn verify(public_key: &[u8; 32], message: &[u8], signature: &[u8; 64]) -> bool {
+    let r_bytes: [u8; 32] = signature[..32].try_into().unwrap();
+    let s_bytes: [u8; 32] = signature[32..].try_into().unwrap();
+    let s_limbs: Vec<u64> = s_bytes.chunks(8).map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap())).collect();
+    if !is_less(&s_limbs.try_into().unwrap(), &GROUP_ORDER) {
+        return false;
+    }
+
+    let (public_point, r_point) = match (Point::decompress(public_key), Point::decompress(&r_bytes)) {
+        (Some(public_point), Some(r_point)) => (public_point, r_point),
+        _ => return false,
+    };
+    let base_point = Point::decompress(&BASE_POINT).unwrap();
+
+    let mut hashed = r_bytes.to_vec();
+    hashed.extend_from_slice(public_key);
+    hashed.extend_from_slice(message);
+    let k = reduce_scalar(&sha512(&hashed));
+
+    // [S]B = R + [k]A
+    let d = curve_constant();
+    base_point.mul(&s_bytes, d).compress() == r_point.add(&public_point.mul(&k, d), d).compress()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{sha512, verify};
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn sha512_test_vectors() {
+        assert_eq!(sha512(b"").to_vec(), from_hex("cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce\
+                                                   47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"));
+        assert_eq!(sha512(b"abc").to_vec(), from_hex("ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                                                      2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"));
+    }
+
+    #[test]
+    fn verify_rfc_8032_signatures() {
+        // Tests 1 and 2 from RFC 8032, section 7.1
+        let key = from_hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+        let signature = from_hex("e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b");
+        assert!(verify(&key.clone().try_into().unwrap(), b"", &signature.clone().try_into().unwrap()));
+        assert!(!verify(&key.try_into().unwrap(), b"tampered", &signature.try_into().unwrap()));
+
+        let key = from_hex("3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c");
+        let signature = from_hex("92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00");
+        assert!(verify(&key.clone().try_into().unwrap(), &[0x72], &signature.clone().try_into().unwrap()));
+        assert!(!verify(&key.try_into().unwrap(), &[0x73], &signature.try_into().unwrap()));
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::{Arc, OnceLock};
+
+use crate::code_loader::{load_code_from_string, Program};
+use crate::errors::LoadError;
+
+/// A program embedded in a Rust binary with `include_basic!`, so that it can be run without
+/// any access to the filesystem. The program is validated the first time it is used, and the
+/// validated program is kept and shared by every later use.
+pub struct CompiledProgram {
+    path: &'static str,
+    source: &'static str,
+    program: OnceLock<Result<Arc<Program>, LoadError>>,
+}
+
+impl CompiledProgram {
+    /// Wraps the source of an embedded program. Use `include_basic!` instead of calling this
+    /// directly.
+    pub const fn new(path: &'static str, source: &'static str) -> Self {
+        CompiledProgram { path, source, program: OnceLock::new() }
+    }
+
+    /// Returns the path the program was embedded from.
+    pub fn path(&self) -> &'static str {
+        self.path
+    }
+
+    /// Returns the source of the program, exactly as it was embedded.
+    pub fn source(&self) -> &'static str {
+        self.source
+    }
+
+    fn validated(&self) -> &Result<Arc<Program>, LoadError> {
+        self.program.get_or_init(|| {
+            load_code_from_string(self.source)
+                .map(Arc::new)
+                .map_err(|err| LoadError::in_file(self.path, err))
+        })
+    }
+
+    /// Returns the validated program, validating it exactly as a program file would be validated
+    /// if this is the first time it is used.
+    ///
+    /// # Returns
+    /// * `Ok(Arc<Program>)` - The stack and code of the program
+    /// * `Err(LoadError)` - The reason the program could not be loaded, naming its path
+    pub fn load(&self) -> Result<Arc<Program>, LoadError> {
+        self.validated().clone()
+    }
+
+    /// Returns the validated program.
+    ///
+    /// # Panics
+    /// If the embedded program is not valid, with the reason it could not be loaded. A program
+    /// that is embedded by mistake therefore fails the first test or run that uses it.
+    pub fn program(&self) -> Arc<Program> {
+        match self.validated() {
+            Ok(program) => program.clone(),
+            Err(err) => panic!("{}", err),
+        }
+    }
+}
+
+/// Embeds a program file in the binary being built. The path is relative to the file the macro
+/// is used in, as with `include_str!`. A missing file fails the build; the program itself is
+/// validated once, the first time it is used.
+///
+/// # Examples
+/// ```ignore
+/// static PROG: CompiledProgram = include_basic!("demo.bas");
+/// let outcome = runner::run(PROG.program().shared_program_data());
+/// ```
+#[macro_export]
+macro_rules! include_basic {
+    ($path:expr) => {
+        $crate::embed::CompiledProgram::new($path, include_str!($path))
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::runner::run;
+    use super::CompiledProgram;
+
+    static STACK_PROGRAM: CompiledProgram = include_basic!("../testfiles/test_stack.txt");
+    static BROKEN_PROGRAM: CompiledProgram = include_basic!("../testfiles/test3.txt");
+
+    #[test]
+    fn run_embedded_program() {
+        let program = STACK_PROGRAM.program();
+        let outcome = run(program.shared_program_data()).ok().unwrap();
+        assert_eq!(outcome.data.get_var("a").unwrap(), "7");
+        assert!(Arc::ptr_eq(&program, &STACK_PROGRAM.load().unwrap()));
+    }
+
+    #[test]
+    fn embedded_program_is_validated_on_load() {
+        assert_eq!(BROKEN_PROGRAM.path(), "../testfiles/test3.txt");
+        assert_eq!(BROKEN_PROGRAM.load().err().unwrap().to_string(), "../testfiles/test3.txt: Malformed code segment found!");
+    }
+
+    #[test]
+    #[should_panic(expected = "../testfiles/test3.txt: Malformed code segment found!")]
+    fn broken_embedded_program_is_reported() {
+        BROKEN_PROGRAM.program();
+    }
+}
@@ -1,77 +1,155 @@
-pub mod segment_errors {
-    use lazy_static::lazy_static;
-    lazy_static! {
-    pub static ref ERROR_MESSAGES: [&'static str; 9] = [
-            "Ok",
-            "No register segment found!",
-            "No code segment found!",
-            "Malformed register assignment found!",
-            "Malformed code assignment found!",
-            "Register declaration not in chronological order!",
-            "Code lines are not in chronological order!",
-            "Malformed register segment found!",
-            "Malformed code segment found!",
-        ];
-    }
+use std::error::Error;
+use std::fmt;
+use std::io;
 
-    pub enum ErrorTypes {
-        NoSegment,
-        AllOk,
-        MalformedAssignment,
-        NotChronological,
-        MalformedSegment
-    }
+/// The segments of a program file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Segment {
+    Register,
+    Stack,
+    Code,
+}
 
-    pub enum SegmentErrorTypes {
-        Variable,
-        Code
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Segment::Register => write!(f, "register"),
+            Segment::Stack => write!(f, "stack"),
+            Segment::Code => write!(f, "code"),
+        }
     }
+}
 
-    pub trait ErrorCodes {
-        fn value(&self) -> u32;
+/// Errors that can occur while loading a segment of a program file. Lines are numbered from 1,
+/// counting from the start of the segment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    /// The segment could not be found
+    NoSegment(Segment),
+    /// The segment does not contain any 'index value' lines
+    MalformedSegment(Segment),
+    /// A line of the segment does not have an 'index value' structure
+    MalformedAssignment { segment: Segment, line: usize, text: String },
+    /// A line of the segment does not have the index that follows the previous line
+    NotChronological { segment: Segment, line: usize, expected: usize, found: usize },
+    /// A comment is given a line number, which would leave a line of the program without an
+    /// instruction
+    NumberedComment { line: usize, text: String },
+    /// The program could not be read
+    Io { kind: io::ErrorKind, message: String },
+    /// The program is larger than a program may be
+    TooLarge { max_size: u64 },
+    /// The program is not valid UTF-8, from the byte at `offset` onwards
+    InvalidUtf8 { offset: usize },
+    /// The bundle holding the program could not be read
+    Bundle(String),
+    /// A line of the code is invalid, such as a jump to a label that does not exist. Lines are
+    /// numbered from 0, as they are in the program.
+    InvalidCode { line: usize, message: String },
+    /// The module imported on a line of the code could not be loaded
+    Import { line: usize, error: Box<LoadError> },
+    /// A module is imported by a program that was not loaded from a file, so there is nowhere to
+    /// find the module
+    NoModuleFiles(String),
+    /// Registers are given to a program that already declares its own
+    RegistersDeclared,
+    /// A data file of a project does not exist
+    MissingDataFile(String),
+    /// The error is in the file at `path`, such as a module or a file of a bundle
+    InFile { path: String, error: Box<LoadError> },
+}
+
+impl LoadError {
+    /// Names the file the error is in.
+    pub fn in_file(path: impl Into<String>, error: LoadError) -> Self {
+        LoadError::InFile { path: path.into(), error: Box::new(error) }
     }
 
-    pub struct VariableErrorCodes{
-        pub(crate) error: ErrorTypes
+    /// Creates an error for an invalid line of the code.
+    pub fn invalid_code(line: usize, message: impl Into<String>) -> Self {
+        LoadError::InvalidCode { line, message: message.into() }
     }
-    pub struct CodeErrorCode{
-        pub(crate) error: ErrorTypes
+}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        LoadError::Io { kind: error.kind(), message: error.to_string() }
     }
+}
 
-    impl ErrorCodes for VariableErrorCodes {
-        fn value(&self) -> u32 {
-
-            // Error codes for this type are all odd numbers except for the AllOk type
-            match (*self).error {
-                ErrorTypes::NoSegment => 1,
-                ErrorTypes::AllOk => 0,
-                ErrorTypes::MalformedAssignment => 3,
-                ErrorTypes::NotChronological => 5,
-                ErrorTypes::MalformedSegment => 7
-            }
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::NoSegment(segment) => write!(f, "No {} segment found!", segment),
+            LoadError::MalformedSegment(segment) => write!(f, "Malformed {} segment found!", segment),
+            LoadError::MalformedAssignment { segment, line, text } =>
+                write!(f, "Line {}: Malformed {} assignment found: {}", line, segment, text),
+            LoadError::NotChronological { segment: Segment::Register, line, expected, found } =>
+                write!(f, "Line {}: Register declaration not in chronological order! (expected index {}, found {})",
+                       line, expected, found),
+            LoadError::NotChronological { segment: Segment::Stack, line, expected, found } =>
+                write!(f, "Line {}: Stack entries are not in chronological order! (expected index {}, found {})",
+                       line, expected, found),
+            LoadError::NotChronological { segment: Segment::Code, line, expected, found } =>
+                write!(f, "Line {}: Code lines are not in chronological order! (expected index {}, found {})",
+                       line, expected, found),
+            LoadError::NumberedComment { line, text } =>
+                write!(f, "Line {}: Comments go on lines of their own, without a line number: {}", line, text),
+            LoadError::Io { message, .. } => write!(f, "{}", message),
+            LoadError::TooLarge { max_size } => write!(f, "program is larger than {} bytes!", max_size),
+            LoadError::InvalidUtf8 { offset } =>
+                write!(f, "program is not valid UTF-8! (invalid byte at offset {})", offset),
+            LoadError::Bundle(message) => write!(f, "{}", message),
+            LoadError::InvalidCode { line, message } => write!(f, "Line {}: {}", line, message),
+            LoadError::Import { line, error } => write!(f, "Line {}: {}", line, error),
+            LoadError::NoModuleFiles(module) =>
+                write!(f, "{} cannot be imported, as the program was not loaded from a file!", module),
+            LoadError::RegistersDeclared => write!(f, "The program already declares its registers!"),
+            LoadError::MissingDataFile(path) => write!(f, "data file {} does not exist!", path),
+            LoadError::InFile { path, error } => write!(f, "{}: {}", path, error),
         }
     }
+}
 
-    impl ErrorCodes for CodeErrorCode {
-        fn value(&self) -> u32 {
-            match (*self).error {
-                ErrorTypes::NoSegment => 2,
-                ErrorTypes::AllOk => 0,
-                ErrorTypes::MalformedAssignment => 4,
-                ErrorTypes::NotChronological => 6,
-                ErrorTypes::MalformedSegment => 8
-            }
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Import { error, .. } | LoadError::InFile { error, .. } => Some(error.as_ref()),
+            _ => None,
         }
     }
+}
 
-    pub fn error(seg: &SegmentErrorTypes, code: ErrorTypes) -> Box<dyn ErrorCodes> {
-        match seg {
-            SegmentErrorTypes::Variable => Box::new(VariableErrorCodes{
-                error: code
-            }),
-            SegmentErrorTypes::Code => Box::new(CodeErrorCode {
-                error:code
-            })
-        }
+#[cfg(test)]
+mod test {
+    use std::error::Error;
+    use super::{LoadError, Segment};
+
+    #[test]
+    fn display_load_errors() {
+        assert_eq!(LoadError::MalformedSegment(Segment::Code).to_string(), "Malformed code segment found!");
+        assert_eq!(LoadError::NoSegment(Segment::Register).to_string(), "No register segment found!");
+
+        let error = LoadError::MalformedAssignment { segment: Segment::Code, line: 3, text: String::from("2quit") };
+        assert_eq!(error.to_string(), "Line 3: Malformed code assignment found: 2quit");
+
+        let error = LoadError::NotChronological { segment: Segment::Code, line: 2, expected: 1, found: 4 };
+        assert_eq!(error.to_string(), "Line 2: Code lines are not in chronological order! (expected index 1, found 4)");
+
+        let error = LoadError::NotChronological { segment: Segment::Stack, line: 1, expected: 0, found: 1 };
+        assert_eq!(error.to_string(), "Line 1: Stack entries are not in chronological order! (expected index 0, found 1)");
+    }
+
+    #[test]
+    fn display_nested_load_errors() {
+        let error = LoadError::in_file("lib.bas", LoadError::invalid_code(2, "Label done does not exist!"));
+        assert_eq!(error.to_string(), "lib.bas: Line 2: Label done does not exist!");
+        assert_eq!(error.source().unwrap().to_string(), "Line 2: Label done does not exist!");
+
+        let error = LoadError::Import { line: 0, error: Box::new(LoadError::in_file("strings.bas", LoadError::InvalidUtf8 { offset: 7 })) };
+        assert_eq!(error.to_string(), "Line 0: strings.bas: program is not valid UTF-8! (invalid byte at offset 7)");
+
+        let error = LoadError::from(std::io::Error::new(std::io::ErrorKind::NotFound, "not found"));
+        assert!(matches!(error, LoadError::Io { kind: std::io::ErrorKind::NotFound, .. }));
     }
 }
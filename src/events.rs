@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::console::Console;
+use crate::json::Json;
+use crate::prog_data::ProgramData;
+use crate::runner::Observer;
+
+/// The stream events are written to, shared between an `EventWriter` and its `EventConsole`.
+struct EventLog<W: Write> {
+    events: W,
+    // The line of the instruction that is running, which output is reported on
+    index: usize,
+}
+
+impl<W: Write> EventLog<W> {
+    /// Writes an event of the given kind, with the line it happened on and the fields that follow.
+    fn write_event(&mut self, event: &str, index: usize, fields: Vec<(&str, Json)>) -> Result<(), String> {
+        let mut object = vec![
+            (String::from("event"), Json::String(event.to_string())),
+            (String::from("index"), Json::Number(index as i64)),
+        ];
+        object.extend(fields.into_iter().map(|(name, value)| (name.to_string(), value)));
+        writeln!(self.events, "{}", Json::Object(object)).map_err(|err| format!("Could not write the events: {}", err))
+    }
+}
+
+/// Writes what a program does as a stream of JSON objects, one per line, when passed to
+/// `runner::run_observed`. Every object has an `event` field naming the kind of event and an
+/// `index` field holding the line it happened on:
+/// * `instruction` - An instruction is about to run, with its `code`
+/// * `write` - The instruction set a variable, with its `name` (without the '$') and `value`
+/// * `output` - The instruction wrote a line of output, with its `value`
+/// * `prompt` - The instruction wrote a prompt before reading input, with its `value`
+/// * `jump` - The program went on at a line other than the next one, with its `target`
+/// * `error` - The instruction failed, with the error `message`
+///
+/// Output only shows up in the stream if the program writes it to the console made by `console`.
+pub struct EventWriter<W: Write + Send + 'static> {
+    log: Arc<Mutex<EventLog<W>>>,
+    // The line the last instruction ran on, which a jump is taken from
+    last_index: Option<usize>,
+    before: BTreeMap<String, String>,
+}
+
+impl<W: Write + Send + 'static> EventWriter<W> {
+    pub fn new(events: W) -> Self {
+        EventWriter {
+            log: Arc::new(Mutex::new(EventLog { events, index: 0 })),
+            last_index: None,
+            before: BTreeMap::new(),
+        }
+    }
+
+    /// Wraps the console of a program, so that the output it writes is reported as events.
+    pub fn console(&self, inner: Box<dyn Console>) -> EventConsole<W> {
+        EventConsole { log: Arc::clone(&self.log), inner }
+    }
+}
+
+impl<W: Write + Send + 'static> Observer for EventWriter<W> {
+    fn before_instruction(&mut self, data: &ProgramData) {
+        let index = data.get_index();
+        let mut log = self.log.lock().unwrap();
+        log.index = index;
+        // A failed write is reported once the instruction has run
+        if let Some(last_index) = self.last_index.filter(|last_index| last_index + 1 != index) {
+            let _ = log.write_event("jump", last_index, vec![("target", Json::Number(index as i64))]);
+        }
+        let code = data.get_code().unwrap_or_default();
+        let _ = log.write_event("instruction", index, vec![("code", Json::String(code.to_string()))]);
+        self.before = data.iter_vars().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+    }
+
+    fn after_instruction(&mut self, data: &ProgramData, error: Option<&str>) -> Result<(), String> {
+        let mut log = self.log.lock().unwrap();
+        let index = log.index;
+        self.last_index = Some(index);
+        let writes: Vec<(String, String)> = data.iter_vars()
+            .filter(|(name, value)| self.before.get(*name).map(String::as_str) != Some(&value.as_text()))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        for (name, value) in writes {
+            log.write_event("write", index, vec![("name", Json::String(name)), ("value", Json::String(value))])?;
+        }
+        if let Some(error) = error {
+            log.write_event("error", index, vec![("message", Json::String(error.to_string()))])?;
+        }
+        log.events.flush().map_err(|err| format!("Could not write the events: {}", err))
+    }
+}
+
+/// Console that reports every line the program writes to an event stream, made by
+/// `EventWriter::console`.
+pub struct EventConsole<W: Write + Send + 'static> {
+    log: Arc<Mutex<EventLog<W>>>,
+    inner: Box<dyn Console>,
+}
+
+impl<W: Write + Send + 'static> Console for EventConsole<W> {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        self.inner.read_line()
+    }
+
+    fn read_line_timeout(&mut self, timeout: Duration) -> Result<Option<String>, String> {
+        self.inner.read_line_timeout(timeout)
+    }
+
+    fn read_all(&mut self) -> Result<String, String> {
+        self.inner.read_all()
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        self.inner.write_line(line)?;
+        let mut log = self.log.lock().unwrap();
+        let index = log.index;
+        log.write_event("output", index, vec![("value", Json::String(line.to_string()))])
+    }
+
+    fn write_prompt(&mut self, prompt: &str) -> Result<(), String> {
+        self.inner.write_prompt(prompt)?;
+        let mut log = self.log.lock().unwrap();
+        let index = log.index;
+        log.write_event("prompt", index, vec![("value", Json::String(prompt.to_string()))])
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.inner.flush()
+    }
+
+    fn is_interactive(&mut self) -> Result<bool, String> {
+        self.inner.is_interactive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, LinkedList};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use crate::console::BufferConsole;
+    use crate::prog_data::ProgramData;
+    use crate::runner::run_observed;
+    use super::EventWriter;
+
+    /// Event stream that can be read once the program has finished.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn events(lines: &[&str], inputs: &[&str]) -> Vec<String> {
+        let mut data = ProgramData::new(lines.iter().map(|line| line.to_string()).collect(),
+                                        HashMap::new(), LinkedList::new(), 0);
+        let buffer = SharedBuffer::default();
+        let mut writer = EventWriter::new(buffer.clone());
+        data.set_console(Box::new(writer.console(Box::new(BufferConsole::new(&inputs.join("\n"))))));
+        let _ = run_observed(data, &mut writer);
+        let events = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        events.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn report_events() {
+        assert_eq!(events(&["let $a = input", "if $a = $a goto 3", "quit", "output $a"], &["7"]), vec![
+            r#"{"event":"instruction","index":0,"code":"let $a = input"}"#,
+            r#"{"event":"write","index":0,"name":"a","value":"7"}"#,
+            r#"{"event":"instruction","index":1,"code":"if $a = $a goto 3"}"#,
+            r#"{"event":"jump","index":1,"target":3}"#,
+            r#"{"event":"instruction","index":3,"code":"output $a"}"#,
+            r#"{"event":"output","index":3,"value":"7"}"#,
+        ]);
+    }
+
+    #[test]
+    fn report_errors() {
+        assert_eq!(events(&["let $a = pop"], &[]), vec![
+            r#"{"event":"instruction","index":0,"code":"let $a = pop"}"#,
+            r#"{"event":"error","index":0,"message":"Stack is empty!"}"#,
+        ]);
+    }
+}
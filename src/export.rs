@@ -0,0 +1,1077 @@
+use std::collections::{HashMap, HashSet};
+use crate::aliases::resolve_alias;
+use crate::builtins::BUILTINS;
+use crate::dialect::Dialect;
+use crate::json::Json;
+use crate::parser::{parse_line, Condition, Expression, Instruction, Operand, Operator, Predicate};
+use crate::states::{find_block_end, is_endwhile, is_for, is_next, is_while};
+
+/// Helpers used by every exported program. They behave like the interpreter does, down to its
+/// error messages, so that an exported program fails in the same places.
+const PYTHON_PRELUDE: &str = r#"import math
+import os
+import random
+import select
+import subprocess
+import sys
+
+variables = {}
+constants = set()
+stack = []
+calls = []
+files = {}
+
+
+def fail(message):
+    print(message + "\nAborting...", file=sys.stderr)
+    sys.exit(255)
+
+
+def get(name):
+    if name not in variables:
+        fail("Variable $" + name + " does not exist!")
+    return variables[name]
+
+
+def constant(name, value):
+    if name in constants:
+        fail("Cannot reassign constant $" + name + "!")
+    variables[name] = value
+    constants.add(name)
+
+
+def unset(name):
+    if name in constants:
+        fail("Cannot unset constant $" + name + "!")
+    get(name)
+    del variables[name]
+
+
+def integer(value):
+    digits = value[1:] if value[:1] in ("+", "-") else value
+    return int(value) if digits.isascii() and digits.isdigit() else None
+
+
+def number(value):
+    # Floats are written with digits, a decimal point and an exponent, not as "inf" or "nan"
+    if integer(value) is not None:
+        return integer(value)
+    digits = value[1:] if value[:1] in ("+", "-") else value
+    if not digits[:1].isdigit():
+        return None
+    try:
+        return float(value)
+    except ValueError:
+        return None
+
+
+def numeric(value, source):
+    if number(value) is None:
+        fail(source + " is not a numeric value!")
+    return number(value)
+
+
+def operands(lhs, rhs):
+    values = (get(lhs), get(rhs))
+    for name, value in zip((lhs, rhs), values):
+        if number(value) is None:
+            fail("$" + name + " is not a numeric value!")
+    return number(values[0]), number(values[1])
+
+
+def negate(name):
+    value = number(get(name))
+    if value is None:
+        fail("$" + name + " is not a numeric value!")
+    return -value
+
+
+def whole_number(value, source):
+    # Loops count, and programs exit, with whole numbers
+    if integer(value) is None:
+        fail(source + (" is not an integer value!" if source.startswith("$") else " is not a whole number!"))
+    return integer(value)
+
+
+def quit(value, source):
+    code = whole_number(value, source)
+    if not 0 <= code <= 255:
+        fail("Exit code " + str(code) + " is not between 0 and 255!")
+    sys.stdout.flush()
+    sys.exit(code)
+
+
+def program_arg(value, source):
+    position = whole_number(value, source)
+    if not 1 <= position < len(sys.argv):
+        fail("Argument " + str(position) + " does not exist!")
+    return sys.argv[position]
+
+
+def loop_step(value, source, name):
+    step = whole_number(value, source)
+    if step == 0:
+        fail("The step of the loop over $" + name + " cannot be 0!")
+    return step
+
+
+def loop_runs(value, end, step):
+    return value <= end if step > 0 else value >= end
+
+
+def truncated_divide(lhs, rhs):
+    if rhs == 0:
+        fail("Division by zero!")
+    # Dividing by a float gives a float, and no remainder
+    if isinstance(lhs, float) or isinstance(rhs, float):
+        return lhs / rhs, None
+    # The quotient is rounded towards zero, and the remainder has the sign of the dividend
+    quotient = abs(lhs) // abs(rhs)
+    if (lhs < 0) != (rhs < 0):
+        quotient = -quotient
+    return quotient, lhs - quotient * rhs
+
+
+def divide(lhs, rhs):
+    # From dialect v3, '/' always gives a float, and no remainder
+    if DIALECT >= 3 and rhs != 0:
+        return lhs / rhs, None
+    return truncated_divide(lhs, rhs)
+
+
+def integer_divide(lhs, rhs):
+    quotient, remainder = truncated_divide(lhs, rhs)
+    return float(math.trunc(quotient)) if remainder is None else quotient
+
+
+def modulo(lhs, rhs):
+    quotient, remainder = truncated_divide(lhs, rhs)
+    # Floats have no remainder from division, but their remainder also has the sign of the dividend
+    return math.fmod(lhs, rhs) if remainder is None else remainder
+
+
+def power(lhs, rhs, lhs_name, rhs_name):
+    if isinstance(lhs, int) and isinstance(rhs, int) and rhs < 0:
+        fail("Cannot raise $" + lhs_name + " to the negative power $" + rhs_name + "!")
+    return lhs ** rhs
+
+
+def integers(lhs, rhs):
+    values = operands(lhs, rhs)
+    for name, value in zip((lhs, rhs), values):
+        if isinstance(value, float):
+            fail("$" + name + " is not an integer value!")
+    return values
+
+
+def shift_by(rhs, lhs_name, rhs_name):
+    if not 0 <= rhs < 128:
+        fail("Cannot shift $" + lhs_name + " by $" + rhs_name + " bits!")
+    return rhs
+
+
+def compare(lhs, condition, rhs):
+    if DIALECT >= 2 and number(lhs) is not None and number(rhs) is not None:
+        lhs, rhs = number(lhs), number(rhs)
+    return {
+        "<": lhs < rhs, "<=": lhs <= rhs, ">": lhs > rhs,
+        ">=": lhs >= rhs, "=": lhs == rhs, "!=": lhs != rhs,
+    }[condition]
+
+
+def equal(lhs, rhs):
+    if DIALECT >= 2 and number(lhs) is not None and number(rhs) is not None:
+        return number(lhs) == number(rhs)
+    return lhs == rhs
+
+
+def check(lhs, condition, rhs, source, names):
+    if not compare(lhs, condition, rhs):
+        # Only the values of variables are shown, as literals are already in the statement
+        shown = ", ".join("$" + name + " is " + text(get(name)) for name in names)
+        fail("Assertion failed: " + source + (" (" + shown + ")" if shown else ""))
+
+
+def text(value):
+    # Maps are written with their keys in order, as the interpreter writes them
+    if isinstance(value, dict):
+        return "{" + ", ".join(key + ": " + text(value[key]) for key in sorted(value)) + "}"
+    return value
+
+
+def get_map(name):
+    value = get(name)
+    if not isinstance(value, dict):
+        fail("$" + name + " is not a map!")
+    return value
+
+
+def set_entry(name, key, value):
+    # The map is replaced rather than changed, as other variables may hold the same map
+    entries = dict(get_map(name))
+    entries[key] = value
+    variables[name] = entries
+
+
+def lookup(name, key):
+    entries = get_map(name)
+    if key not in entries:
+        fail("$" + name + " has no entry \"" + key + "\"!")
+    return entries[key]
+
+
+def shown(name):
+    if name not in variables:
+        fail("Memory index out of bounds!")
+    return text(variables[name])
+
+
+def output(name):
+    print(shown(name))
+
+
+def push(name):
+    if name not in variables:
+        fail("No such variable: $" + name)
+    stack.append(variables[name])
+
+
+def pop():
+    if not stack:
+        fail("Stack is empty!")
+    return stack.pop()
+
+
+def call(site):
+    if len(calls) >= 10000:
+        fail("Too many nested subroutine calls! (more than 10000)")
+    calls.append(site)
+
+
+def return_line():
+    # The program carries on after the gosub that called the subroutine
+    if not calls:
+        fail("Return without a matching gosub!")
+    return calls.pop() + 1
+
+
+def count(name):
+    value = integer(get(name))
+    if value is None:
+        fail("$" + name + " is not a numeric value!")
+    return value
+
+
+def pad(name, width, fill):
+    text, width = get(name), count(width)
+    if fill is not None and len(get(fill)) != 1:
+        fail("$" + fill + " must hold a single character!")
+    padding = (" " if fill is None else get(fill)) * max(abs(width) - len(text), 0)
+    return text + padding if width < 0 else padding + text
+
+
+def repeat(name, times):
+    text, value = get(name), count(times)
+    if value < 0:
+        fail("$" + times + " cannot be negative!")
+    return text * value
+
+
+def run(command):
+    result = subprocess.run(command, shell=True, stdin=subprocess.DEVNULL, stdout=subprocess.PIPE)
+    stdout = result.stdout.decode(errors="replace")
+    stdout = stdout[:-1] if stdout.endswith("\n") else stdout
+    stdout = stdout[:-1] if stdout.endswith("\r") else stdout
+    # Commands killed by a signal have a negative return code
+    return stdout, str(result.returncode if result.returncode >= 0 else -1)
+
+
+def delete(name):
+    # Directories can be deleted once they are empty
+    path = get(name)
+    try:
+        os.rmdir(path) if os.path.isdir(path) else os.remove(path)
+    except OSError as err:
+        fail("Could not delete " + path + ": " + err.strerror)
+
+
+def listdir(name):
+    path = get(name)
+    try:
+        entries = sorted(os.listdir(path))
+    except OSError as err:
+        fail("Could not list " + path + ": " + err.strerror)
+    variables["entries"] = str(len(entries))
+    stack.extend(reversed(entries))
+
+
+def without_terminator(text):
+    text = text[:-1] if text.endswith("\n") else text
+    return text[:-1] if text.endswith("\r") else text
+
+
+def read_file(path):
+    try:
+        with open(path, encoding="utf-8", newline="") as file:
+            return without_terminator(file.read())
+    except OSError as err:
+        fail("Could not read " + path + ": " + err.strerror)
+
+
+def open_file(path):
+    try:
+        file = open(path, encoding="utf-8", newline="")
+    except OSError as err:
+        fail("Could not open " + path + ": " + err.strerror)
+    # Handles start at 1, and follow the highest handle that is open
+    handle = max(files, default=0) + 1
+    files[handle] = file
+    return str(handle)
+
+
+def file_handle(name):
+    handle = integer(text(get(name)))
+    if handle not in files:
+        fail("$" + name + " is not an open file!")
+    return handle
+
+
+def read_file_line(name):
+    line = files[file_handle(name)].readline()
+    variables["eof"] = "0" if line else "1"
+    return without_terminator(line)
+
+
+def close_file(name):
+    files.pop(file_handle(name)).close()
+
+
+def write_file(path, contents, mode):
+    try:
+        with open(path, mode, encoding="utf-8", newline="") as file:
+            file.write(text(contents) + "\n")
+    except OSError as err:
+        fail("Could not write to " + path + ": " + err.strerror)
+
+
+def random_below(name):
+    bound = count(name)
+    if bound <= 0:
+        fail("$" + name + " must be greater than 0!")
+    return str(random.randrange(bound))
+
+
+def seed(name):
+    value = count(name)
+    if not 0 <= value < 2 ** 64:
+        fail("$" + name + " is not a valid seed!")
+    random.seed(value)
+
+
+def sort_stack():
+    # The smallest value ends up on top of the stack
+    if all(integer(value) is not None for value in stack):
+        stack.sort(key=lambda value: (integer(value), value), reverse=True)
+    elif all(number(value) is not None for value in stack):
+        stack.sort(key=lambda value: (float(number(value)), value), reverse=True)
+    else:
+        stack.sort(reverse=True)
+
+
+def read_line(prompt=""):
+    # Reading past the end of the input gives an empty string
+    print(prompt, end="")
+    sys.stdout.flush()
+    return sys.stdin.readline().strip()
+
+
+def read_number(prompt=""):
+    # Someone at a terminal is asked again, but input from anywhere else cannot be fixed
+    while True:
+        print(prompt, end="")
+        sys.stdout.flush()
+        line = sys.stdin.readline()
+        if not line:
+            fail("Input ended before a whole number was entered!")
+        if integer(line.strip()) is not None:
+            return str(integer(line.strip()))
+        if not sys.stdin.isatty():
+            fail("Input \"%s\" is not a whole number!" % line.strip())
+        print("Please enter a whole number")
+
+
+def read_all():
+    sys.stdout.flush()
+    text = sys.stdin.read()
+    text = text[:-1] if text.endswith("\n") else text
+    return text[:-1] if text.endswith("\r") else text
+
+
+def read_line_timeout(seconds, default):
+    # select() only works on the standard input of Unix systems
+    sys.stdout.flush()
+    ready, _, _ = select.select([sys.stdin], [], [], seconds)
+    if not ready:
+        return default, "1"
+    line = sys.stdin.readline()
+    return (line.strip(), "0") if line else (default, "1")
+"#;
+
+/// Quotes `text` as a Python string. JSON strings are valid Python strings.
+fn quote(text: &str) -> String {
+    Json::String(text.to_string()).to_string()
+}
+
+fn condition_symbol(condition: Condition) -> &'static str {
+    match condition {
+        Condition::Less => "<",
+        Condition::LessEqual => "<=",
+        Condition::Greater => ">",
+        Condition::GreaterEqual => ">=",
+        Condition::Equal => "=",
+        Condition::NotEqual => "!=",
+    }
+}
+
+/// The Python expression that gives the value of `operand`.
+/// The way an operand is written in the program, as error messages name it.
+fn operand_source(operand: &Operand) -> String {
+    match operand {
+        Operand::Literal(literal) => literal.clone(),
+        Operand::Variable(var_name) => format!("${}", var_name),
+    }
+}
+
+/// The expression that reads the step of the loop over `var`.
+fn step_expression(var: &str, step: &Option<Operand>) -> String {
+    match step {
+        Some(step) => format!("loop_step({}, {}, {})", operand_expression(step), quote(&operand_source(step)), quote(var)),
+        None => String::from("1"),
+    }
+}
+
+fn operand_expression(operand: &Operand) -> String {
+    match operand {
+        Operand::Literal(literal) => quote(literal),
+        Operand::Variable(var_name) => format!("get({})", quote(var_name)),
+    }
+}
+
+/// The Python expression that evaluates `predicate`. Python gives 'not', 'and' and 'or' the same
+/// precedence and short-circuiting as the interpreter, so the predicate is written out as parsed.
+fn predicate_expression(predicate: &Predicate) -> String {
+    match predicate {
+        Predicate::Compare { lhs, condition, rhs } => {
+            format!("compare({}, {}, {})", operand_expression(lhs), quote(condition_symbol(*condition)), operand_expression(rhs))
+        },
+        Predicate::Not(predicate) => format!("not {}", predicate_expression(predicate)),
+        Predicate::And(lhs, rhs) => format!("{} and {}", predicate_expression(lhs), predicate_expression(rhs)),
+        Predicate::Or(lhs, rhs) => format!("{} or {}", predicate_expression(lhs), predicate_expression(rhs)),
+    }
+}
+
+/// Converts a program to Python, one line of the program at a time.
+struct PythonExporter<'a> {
+    code: &'a [String],
+    // Variables the program declares with 'const', which are checked before they are assigned
+    constants: HashSet<String>,
+    // The instruction on every line, which loops are matched up with
+    instructions: Vec<Instruction>,
+}
+
+/// Finds the names of the constants declared by an instruction, including inside its clauses.
+fn declared_constants(instruction: &Instruction, constants: &mut HashSet<String>) {
+    match instruction {
+        Instruction::Const { name, .. } => {
+            constants.insert(name.clone());
+        },
+        Instruction::If { then, otherwise, .. } => {
+            declared_constants(then, constants);
+            if let Some((otherwise, _)) = otherwise {
+                declared_constants(otherwise, constants);
+            }
+        },
+        _ => {},
+    }
+}
+
+impl PythonExporter<'_> {
+    /// The statements that carry out `instruction`, found on line `index` as `source`. Every
+    /// path through them sets the line to run next, or leaves the program.
+    fn instruction(&self, index: usize, source: &str, instruction: &Instruction) -> Result<Vec<String>, String> {
+        let next = format!("line = {}", index + 1);
+        let set = |name: &str, value: String| format!("variables[{}] = {}", quote(name), value);
+        let statements = match instruction {
+            Instruction::Let { name, value } => match value {
+                Expression::Literal(literal) => vec![set(name, quote(literal)), next],
+                Expression::Variable(rhs) => vec![set(name, format!("get({})", quote(rhs))), next],
+                Expression::Conditional { then, predicate, otherwise } => vec![
+                    set(name, format!("{} if {} else {}", operand_expression(then), predicate_expression(predicate), operand_expression(otherwise))),
+                    next,
+                ],
+                Expression::Concat { lhs, rhs } => vec![set(name, format!("text(get({})) + text(get({}))", quote(lhs), quote(rhs))), next],
+                Expression::Lookup { map, key } => vec![set(name, format!("lookup({}, {})", quote(map), operand_expression(key))), next],
+                Expression::Negate(value) => vec![set(name, format!("str(negate({}))", quote(value))), next],
+                Expression::Operation { lhs, operator, rhs } => {
+                    // Bitwise operators only work on integers
+                    let read = match operator {
+                        Operator::BitAnd | Operator::BitOr | Operator::BitXor | Operator::ShiftLeft | Operator::ShiftRight => "integers",
+                        _ => "operands",
+                    };
+                    let mut statements = vec![format!("lhs, rhs = {}({}, {})", read, quote(lhs), quote(rhs))];
+                    match operator {
+                        Operator::Add => statements.push(set(name, String::from("str(lhs + rhs)"))),
+                        Operator::Subtract => statements.push(set(name, String::from("str(lhs - rhs)"))),
+                        Operator::Multiply => statements.push(set(name, String::from("str(lhs * rhs)"))),
+                        Operator::Divide => {
+                            // The quotient is assigned and the remainder is pushed onto the stack
+                            statements.push(String::from("quotient, remainder = divide(lhs, rhs)"));
+                            statements.push(set(name, String::from("str(quotient)")));
+                            statements.push(String::from("if remainder is not None:"));
+                            statements.push(String::from("    stack.append(str(remainder))"));
+                        },
+                        Operator::IntegerDivide => statements.push(set(name, String::from("str(integer_divide(lhs, rhs))"))),
+                        Operator::Modulo => statements.push(set(name, String::from("str(modulo(lhs, rhs))"))),
+                        Operator::Power => statements.push(set(name, format!("str(power(lhs, rhs, {}, {}))", quote(lhs), quote(rhs)))),
+                        Operator::BitAnd => statements.push(set(name, String::from("str(lhs & rhs)"))),
+                        Operator::BitOr => statements.push(set(name, String::from("str(lhs | rhs)"))),
+                        Operator::BitXor => statements.push(set(name, String::from("str(lhs ^ rhs)"))),
+                        Operator::ShiftLeft => statements.push(set(name, format!("str(lhs << shift_by(rhs, {}, {}))", quote(lhs), quote(rhs)))),
+                        Operator::ShiftRight => statements.push(set(name, format!("str(lhs >> shift_by(rhs, {}, {}))", quote(lhs), quote(rhs)))),
+                    }
+                    statements.push(next);
+                    statements
+                },
+                Expression::Input => vec![set(name, String::from("read_line()")), next],
+                Expression::PromptedInput(prompt) => vec![set(name, format!("read_line({})", quote(prompt))), next],
+                Expression::InputNum(prompt) => {
+                    let prompt = prompt.as_deref().map(quote).unwrap_or_default();
+                    vec![set(name, format!("read_number({})", prompt)), next]
+                },
+                Expression::InputAll => vec![set(name, String::from("read_all()")), next],
+                Expression::TimedInput { timeout, default } => vec![
+                    format!("variables[{}], variables[\"timedout\"] = read_line_timeout({}, {})", quote(name), timeout, quote(default)),
+                    next,
+                ],
+                Expression::Pop => vec![set(name, String::from("pop()")), next],
+                Expression::Pad { value, width, fill } => {
+                    let fill = fill.as_deref().map(quote).unwrap_or_else(|| String::from("None"));
+                    vec![set(name, format!("pad({}, {}, {})", quote(value), quote(width), fill)), next]
+                },
+                Expression::Repeat { value, count } => vec![set(name, format!("repeat({}, {})", quote(value), quote(count))), next],
+                Expression::Reverse(value) => vec![set(name, format!("get({})[::-1]", quote(value))), next],
+                Expression::Exec(command) => {
+                    vec![format!("variables[{}], variables[\"exitcode\"] = run({})", quote(name), operand_expression(command)), next]
+                },
+                Expression::ReadFile(path) => vec![set(name, format!("read_file({})", operand_expression(path))), next],
+                Expression::OpenFile(path) => vec![set(name, format!("open_file({})", operand_expression(path))), next],
+                Expression::Env(var_name) => vec![set(name, format!("os.environ.get({}, \"\")", operand_expression(var_name))), next],
+                Expression::Arg(position) => {
+                    vec![set(name, format!("program_arg({}, {})", operand_expression(position), quote(&operand_source(position)))), next]
+                },
+                Expression::ArgCount => vec![set(name, String::from("str(len(sys.argv) - 1)")), next],
+                Expression::Builtin { function, args } => {
+                    let args: Vec<String> = args.iter()
+                        .map(|arg| format!("{}, {}", operand_expression(arg), quote(&operand_source(arg))))
+                        .collect();
+                    vec![set(name, format!("builtin_{}({})", function, args.join(", "))), next]
+                },
+                Expression::ReadLine(file) => vec![set(name, format!("read_file_line({})", quote(file))), next],
+                Expression::Random(bound) => vec![set(name, format!("random_below({})", quote(bound))), next],
+                Expression::Exists(path) => {
+                    vec![set(name, format!("\"1\" if os.path.exists(get({})) else \"0\"", quote(path))), next]
+                },
+                #[cfg(feature = "net")]
+                Expression::Connect(_) | Expression::NetRecv(_) | Expression::HttpGet(_) => {
+                    return Err(format!("Line {} cannot be exported: networking instructions are not supported", index));
+                },
+                Expression::Call { .. } => {
+                    return Err(format!("Line {} cannot be exported: functions are not supported", index));
+                },
+            },
+            Instruction::If { predicate, then, then_source, otherwise } => {
+                let mut statements = vec![format!("if {}:", predicate_expression(predicate))];
+                for statement in self.instruction(index, then_source, then)? {
+                    statements.push(format!("    {}", statement));
+                }
+                statements.push(String::from("else:"));
+                match otherwise {
+                    Some((otherwise, otherwise_source)) => {
+                        for statement in self.instruction(index, otherwise_source, otherwise)? {
+                            statements.push(format!("    {}", statement));
+                        }
+                    },
+                    None => statements.push(format!("    {}", next)),
+                }
+                statements
+            },
+            Instruction::Goto(target) if *target >= self.code.len() => {
+                vec![String::from("fail(\"Goto statement points to region out of bounds!\")")]
+            },
+            Instruction::Goto(target) => vec![format!("line = {}", target)],
+            Instruction::Gosub(target) if *target >= self.code.len() => {
+                vec![String::from("fail(\"Gosub statement points to region out of bounds!\")")]
+            },
+            Instruction::Gosub(target) => vec![format!("call({})", index), format!("line = {}", target)],
+            Instruction::Return(None) => vec![String::from("line = return_line()")],
+            Instruction::Return(Some(_)) | Instruction::Func { .. } | Instruction::EndFunc => {
+                return Err(format!("Line {} cannot be exported: functions are not supported", index));
+            },
+            Instruction::Local(_) => {
+                return Err(format!("Line {} cannot be exported: local variables are not supported", index));
+            },
+            Instruction::Const { name, value } => vec![format!("constant({}, {})", quote(name), operand_expression(value)), next],
+            Instruction::Unset(name) => vec![format!("unset({})", quote(name)), next],
+            Instruction::Map(name) => vec![set(name, String::from("{}")), next],
+            Instruction::SetEntry { map, key, value } => vec![
+                format!("set_entry({}, {}, {})", quote(map), operand_expression(key), operand_expression(value)),
+                next,
+            ],
+            Instruction::For { var, from, to, step } => {
+                let next = find_block_end(index + 1..self.code.len(), |line| self.instructions.get(line), is_for, is_next)
+                    .ok_or(format!("Line {} cannot be exported: the loop over ${} has no matching next", index, var))?;
+                vec![
+                    format!("start = whole_number({}, {})", operand_expression(from), quote(&operand_source(from))),
+                    format!("end = whole_number({}, {})", operand_expression(to), quote(&operand_source(to))),
+                    format!("step = {}", step_expression(var, step)),
+                    set(var, String::from("str(start)")),
+                    format!("line = {} if loop_runs(start, end, step) else {}", index + 1, next + 1),
+                ]
+            },
+            Instruction::Next(var) => {
+                let start = find_block_end((0..index).rev(), |line| self.instructions.get(line), is_next, is_for);
+                let Some((start, Instruction::For { var: counter, to, step, .. })) = start.map(|start| (start, &self.instructions[start])) else {
+                    return Err(format!("Line {} cannot be exported: next ${} has no matching for", index, var));
+                };
+                if counter != var {
+                    return Err(format!("Line {} cannot be exported: next ${} cannot close the loop over ${}", index, var, counter));
+                }
+                vec![
+                    format!("end = whole_number({}, {})", operand_expression(to), quote(&operand_source(to))),
+                    format!("step = {}", step_expression(var, step)),
+                    format!("counted = whole_number(get({}), {}) + step", quote(var), quote(&format!("${}", var))),
+                    set(var, String::from("str(counted)")),
+                    format!("line = {} if loop_runs(counted, end, step) else {}", start + 1, index + 1),
+                ]
+            },
+            Instruction::While(predicate) => {
+                let end = find_block_end(index + 1..self.code.len(), |line| self.instructions.get(line), is_while, is_endwhile)
+                    .ok_or(format!("Line {} cannot be exported: while has no matching endwhile", index))?;
+                vec![format!("line = {} if {} else {}", index + 1, predicate_expression(predicate), end + 1)]
+            },
+            Instruction::EndWhile => {
+                let start = find_block_end((0..index).rev(), |line| self.instructions.get(line), is_endwhile, is_while)
+                    .ok_or(format!("Line {} cannot be exported: endwhile has no matching while", index))?;
+                vec![format!("line = {}", start)]
+            },
+            Instruction::Switch(var_name) => {
+                let jump = |target: usize| if target < self.code.len() {
+                    format!("line = {}", target)
+                } else {
+                    String::from("fail(\"Goto statement points to region out of bounds!\")")
+                };
+                let mut statements = vec![format!("value = get({})", quote(var_name))];
+                let mut line = index + 1;
+                while let Some(Instruction::Case { value, target }) = self.instructions.get(line) {
+                    let keyword = if line == index + 1 { "if" } else { "elif" };
+                    statements.push(format!("{} equal(value, {}):", keyword, operand_expression(value)));
+                    statements.push(format!("    {}", jump(*target)));
+                    line += 1;
+                }
+                // Without a default, a switch that matches no case carries on after its cases
+                let otherwise = match self.instructions.get(line) {
+                    Some(Instruction::Default(target)) => jump(*target),
+                    _ => format!("line = {}", line),
+                };
+                if line == index + 1 {
+                    statements.push(otherwise);
+                } else {
+                    statements.extend([String::from("else:"), format!("    {}", otherwise)]);
+                }
+                statements
+            },
+            Instruction::Case { .. } | Instruction::Default(_) => {
+                vec![format!("fail({})", quote(&format!("{} can only be reached through its switch!", source)))]
+            },
+            Instruction::Quit(Some(exit_code)) => {
+                vec![format!("quit({}, {})", operand_expression(exit_code), quote(&operand_source(exit_code)))]
+            },
+            Instruction::Quit(None) | Instruction::EndTest => vec![String::from("return")],
+            Instruction::Error(message) => {
+                let message = match message {
+                    Operand::Literal(literal) => quote(literal),
+                    Operand::Variable(var_name) => format!("text(get({}))", quote(var_name)),
+                };
+                vec![format!("fail({} + {})", quote(&format!("Line {}: ", index)), message)]
+            },
+            Instruction::Output(values) => match values.as_slice() {
+                [Operand::Variable(name)] => vec![format!("output({})", quote(name)), next],
+                _ => {
+                    let values: Vec<String> = values.iter().map(|value| match value {
+                        Operand::Literal(literal) => quote(literal),
+                        Operand::Variable(var_name) => format!("shown({})", quote(var_name)),
+                    }).collect();
+                    vec![format!("print({}, sep=\"\")", values.join(", ")), next]
+                },
+            },
+            Instruction::Flush => vec![String::from("sys.stdout.flush()"), next],
+            Instruction::Push(Operand::Variable(name)) => vec![format!("push({})", quote(name)), next],
+            Instruction::Push(Operand::Literal(literal)) => vec![format!("stack.append({})", quote(literal)), next],
+            Instruction::Assert { lhs, condition, rhs } => {
+                let names: Vec<String> = [lhs, rhs].into_iter().filter_map(|operand| match operand {
+                    Operand::Variable(var_name) => Some(quote(var_name)),
+                    Operand::Literal(_) => None,
+                }).collect();
+                vec![
+                    format!("check({}, {}, {}, {}, [{}])", operand_expression(lhs), quote(condition_symbol(*condition)),
+                            operand_expression(rhs), quote(source), names.join(", ")),
+                    next,
+                ]
+            },
+            // Testcase blocks are skipped, as they only run with the 'test' command
+            Instruction::Testcase(name) => match (index + 1..self.code.len()).find(|line| self.code[*line] == "endtest") {
+                Some(end) => vec![format!("line = {}", end + 1)],
+                None => vec![format!("fail({})", quote(&format!("Testcase {} has no matching endtest!", name)))],
+            },
+            Instruction::Dialect(_) if index != 0 => {
+                vec![String::from("fail(\"The dialect can only be declared on the first line!\")")]
+            },
+            Instruction::Dialect(_) | Instruction::Label(_) | Instruction::Import { .. } => vec![next],
+            Instruction::Delete(path) => vec![format!("delete({})", quote(path)), next],
+            Instruction::ListDir(path) => vec![format!("listdir({})", quote(path)), next],
+            Instruction::CloseFile(file) => vec![format!("close_file({})", quote(file)), next],
+            Instruction::WriteFile { path, contents } => vec![
+                format!("write_file({}, {}, \"w\")", operand_expression(path), operand_expression(contents)),
+                next,
+            ],
+            Instruction::AppendFile { path, contents } => vec![
+                format!("write_file({}, {}, \"a\")", operand_expression(path), operand_expression(contents)),
+                next,
+            ],
+            Instruction::Seed(seed) => vec![format!("seed({})", quote(seed)), next],
+            Instruction::Sort => vec![String::from("sort_stack()"), next],
+            #[cfg(feature = "net")]
+            Instruction::NetSend { .. } | Instruction::Close(_) => {
+                return Err(format!("Line {} cannot be exported: networking instructions are not supported", index));
+            },
+        };
+        // A constant of the same name may have been declared by the time the variable is assigned
+        let target = match instruction {
+            Instruction::Let { name, .. } | Instruction::SetEntry { map: name, .. } => Some(name),
+            Instruction::For { var: name, .. } | Instruction::Next(name) => Some(name),
+            _ => None,
+        };
+        match target.filter(|name| self.constants.contains(*name)) {
+            Some(name) => Ok([
+                vec![
+                    format!("if {} in constants:", quote(name)),
+                    format!("    fail({})", quote(&format!("Cannot reassign constant ${}!", name))),
+                ],
+                statements,
+            ].concat()),
+            None => Ok(statements),
+        }
+    }
+}
+
+/// Exports a program as an equivalent Python 3 program, so the same control flow can be seen in
+/// a mainstream language. Each line of the program becomes a block of a loop that runs one line
+/// at a time, commented with the line it came from, and `goto` sets the line the loop runs next.
+/// Variables are kept as strings in a dictionary, as they are by the interpreter.
+///
+/// # Arguments
+/// * `code` - The lines of the program, as loaded
+/// * `dialect` - The dialect the program runs with, which decides how values are compared
+/// * `aliases` - Keyword aliases the program may use
+///
+/// # Returns
+/// * `Ok(String)` - The source of the Python program
+/// * `Err(String)` - A message detailing the first line that cannot be exported
+pub fn to_python(code: &[String], dialect: Dialect, aliases: &HashMap<String, String>) -> Result<String, String> {
+    let lines = code.iter().enumerate()
+        .map(|(index, line)| {
+            let source = resolve_alias(aliases, line);
+            parse_line(&source)
+                .map(|instruction| (source, instruction))
+                .map_err(|err| format!("Line {} cannot be exported: {} ({})", index, line, err))
+        })
+        .collect::<Result<Vec<(String, Instruction)>, String>>()?;
+    let mut constants = HashSet::new();
+    for (_, instruction) in &lines {
+        declared_constants(instruction, &mut constants);
+    }
+    let instructions = lines.iter().map(|(_, instruction)| instruction.clone()).collect();
+    let exporter = PythonExporter { code, constants, instructions };
+    let version = match dialect {
+        Dialect::V1 => 1,
+        Dialect::V2 => 2,
+        Dialect::V3 => 3,
+    };
+    // Each builtin brings the Python function it is exported as
+    let builtins: String = BUILTINS.iter().map(|builtin| format!("\n\n{}", builtin.python)).collect();
+    let mut python = format!("# Exported from a COS341Basic program (dialect v{}). Each block of main()\n\
+                              # runs the line of the program it is commented with.\n{}{}\nDIALECT = {}\n\n\n\
+                              def main():\n    line = 0\n    while True:\n", version, PYTHON_PRELUDE, builtins, version);
+
+    for (index, (line, (source, instruction))) in code.iter().zip(&lines).enumerate() {
+        let keyword = if index == 0 { "if" } else { "elif" };
+        python.push_str(&format!("        {} line == {}:  # {}: {}\n", keyword, index, index, line.replace('\n', " ")));
+        for statement in exporter.instruction(index, source, instruction)? {
+            python.push_str(&format!("            {}\n", statement));
+        }
+    }
+    // Running past the last line is the same as running 'quit'
+    if code.is_empty() {
+        python.push_str("        return\n");
+    } else {
+        python.push_str("        else:\n            return\n");
+    }
+    python.push_str("\n\nmain()\n");
+    Ok(python)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::aliases::default_aliases;
+    use crate::dialect::Dialect;
+    use super::to_python;
+
+    fn export(lines: &[&str]) -> Result<String, String> {
+        let code: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        to_python(&code, Dialect::V2, &default_aliases())
+    }
+
+    #[test]
+    fn export_control_flow() {
+        let python = export(&["let $a = input", "let $b = 10", "if $a < $b goto 4", "print $a", "let $q = $a / $b",
+                              "goto 9"]).unwrap();
+        assert!(python.starts_with("# Exported from a COS341Basic program (dialect v2)."));
+        assert!(python.contains("\nDIALECT = 2\n"));
+        let main = &python[python.find("def main():").unwrap()..];
+        assert_eq!(main, "def main():\n    line = 0\n    while True:\n\
+            \x20       if line == 0:  # 0: let $a = input\n            variables[\"a\"] = read_line()\n            line = 1\n\
+            \x20       elif line == 1:  # 1: let $b = 10\n            variables[\"b\"] = \"10\"\n            line = 2\n\
+            \x20       elif line == 2:  # 2: if $a < $b goto 4\n            if compare(get(\"a\"), \"<\", get(\"b\")):\n                line = 4\n\
+            \x20           else:\n                line = 3\n\
+            \x20       elif line == 3:  # 3: print $a\n            output(\"a\")\n            line = 4\n\
+            \x20       elif line == 4:  # 4: let $q = $a / $b\n            lhs, rhs = operands(\"a\", \"b\")\n\
+            \x20           quotient, remainder = divide(lhs, rhs)\n            variables[\"q\"] = str(quotient)\n\
+            \x20           if remainder is not None:\n                stack.append(str(remainder))\n            line = 5\n\
+            \x20       elif line == 5:  # 5: goto 9\n            fail(\"Goto statement points to region out of bounds!\")\n\
+            \x20       else:\n            return\n\n\nmain()\n");
+    }
+
+    #[test]
+    fn export_integer_division() {
+        let python = export(&["let $q = $a // $b"]).unwrap();
+        assert!(python.contains("lhs, rhs = operands(\"a\", \"b\")\n            variables[\"q\"] = str(integer_divide(lhs, rhs))\n"));
+        let python = to_python(&[String::from("let $q = $a / $b")], Dialect::V3, &default_aliases()).unwrap();
+        assert!(python.contains("\nDIALECT = 3\n"));
+    }
+
+    #[test]
+    fn export_compound_conditions() {
+        let python = export(&["if $a < $b or not $a = $c and $b = $c goto 0"]).unwrap();
+        assert!(python.contains("if compare(get(\"a\"), \"<\", get(\"b\")) or not compare(get(\"a\"), \"=\", get(\"c\")) and compare(get(\"b\"), \"=\", get(\"c\")):\n"));
+    }
+
+    #[test]
+    fn export_else_clauses() {
+        let python = export(&["if $a < $b goto 1 else output $a", "quit"]).unwrap();
+        assert!(python.contains("if compare(get(\"a\"), \"<\", get(\"b\")):\n                line = 1\n\
+                                 \x20           else:\n                output(\"a\")\n                line = 1\n"));
+    }
+
+    #[test]
+    fn export_testcases_and_asserts() {
+        let python = export(&["quit", "testcase sum", "assert $a = $b", "endtest"]).unwrap();
+        assert!(python.contains("elif line == 1:  # 1: testcase sum\n            line = 4\n"));
+        assert!(python.contains("check(get(\"a\"), \"=\", get(\"b\"), \"assert $a = $b\", [\"a\", \"b\"])"));
+        assert!(python.contains("elif line == 3:  # 3: endtest\n            return\n"));
+
+        let python = export(&["if $a < -1 goto 1", "assert $a > -3"]).unwrap();
+        assert!(python.contains("if compare(get(\"a\"), \"<\", \"-1\"):\n"));
+        assert!(python.contains("check(get(\"a\"), \">\", \"-3\", \"assert $a > -3\", [\"a\"])"));
+    }
+
+    #[test]
+    fn export_subroutines() {
+        let python = export(&["gosub 2", "quit", "return", "gosub 4"]).unwrap();
+        assert!(python.contains("if line == 0:  # 0: gosub 2\n            call(0)\n            line = 2\n"));
+        assert!(python.contains("elif line == 2:  # 2: return\n            line = return_line()\n"));
+        assert!(python.contains("elif line == 3:  # 3: gosub 4\n            fail(\"Gosub statement points to region out of bounds!\")\n"));
+    }
+
+    #[test]
+    fn export_modulo_and_power() {
+        let python = export(&["let $r = $a % $b", "let $p = $a ^ $b"]).unwrap();
+        assert!(python.contains("lhs, rhs = operands(\"a\", \"b\")\n            variables[\"r\"] = str(modulo(lhs, rhs))\n"));
+        assert!(python.contains("variables[\"p\"] = str(power(lhs, rhs, \"a\", \"b\"))\n"));
+    }
+
+    #[test]
+    fn export_concatenation() {
+        let python = export(&["let $c = $a . $b"]).unwrap();
+        assert!(python.contains("variables[\"c\"] = text(get(\"a\")) + text(get(\"b\"))\n"));
+    }
+
+    #[test]
+    fn export_push() {
+        let python = export(&["push 5", "push $a"]).unwrap();
+        assert!(python.contains("stack.append(\"5\")\n"));
+        assert!(python.contains("push(\"a\")\n"));
+    }
+
+    #[test]
+    fn export_functions() {
+        assert_eq!(export(&["let $a = call f", "quit", "func f", "return 1", "endfunc"]).err().unwrap(),
+                   "Line 0 cannot be exported: functions are not supported");
+        assert_eq!(export(&["gosub 2", "quit", "return"]).map(|_| ()), Ok(()));
+        assert_eq!(export(&["gosub 2", "quit", "local $i", "return"]).err().unwrap(),
+                   "Line 2 cannot be exported: local variables are not supported");
+    }
+
+    #[test]
+    fn export_constants() {
+        let python = export(&["const $PI = 3", "let $PI = $a", "let $b = $PI"]).unwrap();
+        assert!(python.contains("constant(\"PI\", \"3\")\n"));
+        assert!(python.contains("# 1: let $PI = $a\n            if \"PI\" in constants:\n                \
+                                 fail(\"Cannot reassign constant $PI!\")\n            variables[\"PI\"] = get(\"a\")\n"));
+        assert!(python.contains("# 2: let $b = $PI\n            variables[\"b\"] = get(\"PI\")\n"));
+        assert!(export(&["unset $a"]).unwrap().contains("unset(\"a\")\n"));
+    }
+
+    #[test]
+    fn export_output_of_several_values() {
+        let python = export(&["output \"Result \" $a 5"]).unwrap();
+        assert!(python.contains("print(\"Result \", shown(\"a\"), \"5\", sep=\"\")\n"));
+    }
+
+    #[test]
+    fn export_prompted_input() {
+        let python = export(&["let $a = input \"Name \""]).unwrap();
+        assert!(python.contains("variables[\"a\"] = read_line(\"Name \")\n"));
+    }
+
+    #[test]
+    fn export_number_input() {
+        let python = export(&["let $a = inputnum", "let $b = inputnum \"Age \""]).unwrap();
+        assert!(python.contains("variables[\"a\"] = read_number()\n"));
+        assert!(python.contains("variables[\"b\"] = read_number(\"Age \")\n"));
+    }
+
+    #[test]
+    fn export_maps() {
+        let python = export(&["map $m", "let $m{\"name\"} = $x", "let $a = $m{$k}"]).unwrap();
+        assert!(python.contains("variables[\"m\"] = {}\n"));
+        assert!(python.contains("set_entry(\"m\", \"name\", get(\"x\"))\n"));
+        assert!(python.contains("variables[\"a\"] = lookup(\"m\", get(\"k\"))\n"));
+    }
+
+    #[test]
+    fn export_for_loops() {
+        let python = export(&["for $i = 1 to $n step 2", "output $i", "next $i", "quit"]).unwrap();
+        assert!(python.contains("# 0: for $i = 1 to $n step 2\n            start = whole_number(\"1\", \"1\")\n            \
+                                 end = whole_number(get(\"n\"), \"$n\")\n            step = loop_step(\"2\", \"2\", \"i\")\n            \
+                                 variables[\"i\"] = str(start)\n            line = 1 if loop_runs(start, end, step) else 3\n"));
+        assert!(python.contains("# 2: next $i\n            end = whole_number(get(\"n\"), \"$n\")\n            step = loop_step(\"2\", \"2\", \"i\")\n            \
+                                 counted = whole_number(get(\"i\"), \"$i\") + step\n            variables[\"i\"] = str(counted)\n            \
+                                 line = 1 if loop_runs(counted, end, step) else 3\n"));
+        assert_eq!(export(&["next $i"]).err().unwrap(), "Line 0 cannot be exported: next $i has no matching for");
+    }
+
+    #[test]
+    fn export_while_loops() {
+        let python = export(&["while $a < $b", "let $a += $one", "endwhile", "quit"]).unwrap();
+        assert!(python.contains("# 0: while $a < $b\n            line = 1 if compare(get(\"a\"), \"<\", get(\"b\")) else 3\n"));
+        assert!(python.contains("# 2: endwhile\n            line = 0\n"));
+        assert_eq!(export(&["endwhile"]).err().unwrap(), "Line 0 cannot be exported: endwhile has no matching while");
+    }
+
+    #[test]
+    fn export_quit_with_exit_code() {
+        let python = export(&["quit $code"]).unwrap();
+        assert!(python.contains("# 0: quit $code\n            quit(get(\"code\"), \"$code\")\n"));
+    }
+
+    #[test]
+    fn export_errors() {
+        let python = export(&["quit", "error \"went wrong\"", "error $m"]).unwrap();
+        assert!(python.contains("# 1: error \"went wrong\"\n            fail(\"Line 1: \" + \"went wrong\")\n"));
+        assert!(python.contains("# 2: error $m\n            fail(\"Line 2: \" + text(get(\"m\")))\n"));
+    }
+
+    #[test]
+    fn export_file_reads() {
+        let python = export(&["let $text = readfile $path", "let $f = openfile \"notes\"", "let $line = readline $f", "closefile $f"]).unwrap();
+        assert!(python.contains("variables[\"text\"] = read_file(get(\"path\"))\n"));
+        assert!(python.contains("variables[\"f\"] = open_file(\"notes\")\n"));
+        assert!(python.contains("variables[\"line\"] = read_file_line(\"f\")\n"));
+        assert!(python.contains("close_file(\"f\")\n"));
+    }
+
+    #[test]
+    fn export_file_writes() {
+        let python = export(&["writefile $path $data", "appendfile \"log\" \"done\""]).unwrap();
+        assert!(python.contains("write_file(get(\"path\"), get(\"data\"), \"w\")\n"));
+        assert!(python.contains("write_file(\"log\", \"done\", \"a\")\n"));
+    }
+
+    #[test]
+    fn export_environment_variables() {
+        let python = export(&["let $home = env \"HOME\""]).unwrap();
+        assert!(python.contains("variables[\"home\"] = os.environ.get(\"HOME\", \"\")\n"));
+    }
+
+    #[test]
+    fn export_program_arguments() {
+        let python = export(&["let $x = arg 1", "let $y = arg $i", "let $n = argc"]).unwrap();
+        assert!(python.contains("variables[\"x\"] = program_arg(\"1\", \"1\")\n"));
+        assert!(python.contains("variables[\"y\"] = program_arg(get(\"i\"), \"$i\")\n"));
+        assert!(python.contains("variables[\"n\"] = str(len(sys.argv) - 1)\n"));
+    }
+
+    #[test]
+    fn export_builtins() {
+        let python = export(&["let $a = abs $x", "let $m = max $a 10"]).unwrap();
+        assert!(python.contains("variables[\"a\"] = builtin_abs(get(\"x\"), \"$x\")\n"));
+        assert!(python.contains("variables[\"m\"] = builtin_max(get(\"a\"), \"$a\", \"10\", \"10\")\n"));
+        assert!(python.contains("\n\n\ndef builtin_sqrt(value, source):\n"));
+    }
+
+    #[test]
+    fn export_switches() {
+        let python = export(&["switch $x", "case 1 goto 3", "case $y goto 9", "quit"]).unwrap();
+        assert!(python.contains("# 0: switch $x\n            value = get(\"x\")\n            if equal(value, \"1\"):\n                line = 3\n            \
+                                 elif equal(value, get(\"y\")):\n                fail(\"Goto statement points to region out of bounds!\")\n            \
+                                 else:\n                line = 3\n"));
+        assert!(python.contains("# 1: case 1 goto 3\n            fail(\"case 1 goto 3 can only be reached through its switch!\")\n"));
+        let python = export(&["switch $x", "default goto 2", "quit"]).unwrap();
+        assert!(python.contains("value = get(\"x\")\n            line = 2\n"));
+    }
+
+    #[test]
+    fn export_conditional_assignments() {
+        let python = export(&["let $m = $a if $a > $b or $a = $b else 0"]).unwrap();
+        assert!(python.contains("variables[\"m\"] = get(\"a\") if compare(get(\"a\"), \">\", get(\"b\")) or compare(get(\"a\"), \"=\", get(\"b\")) else \"0\"\n"));
+    }
+
+    #[test]
+    fn export_negation() {
+        let python = export(&["let $b = -$a"]).unwrap();
+        assert!(python.contains("variables[\"b\"] = str(negate(\"a\"))\n"));
+    }
+
+    #[test]
+    fn export_bitwise_operators() {
+        let python = export(&["let $c = $a xor $b", "let $d = $a << $b"]).unwrap();
+        assert!(python.contains("lhs, rhs = integers(\"a\", \"b\")\n            variables[\"c\"] = str(lhs ^ rhs)\n"));
+        assert!(python.contains("variables[\"d\"] = str(lhs << shift_by(rhs, \"a\", \"b\"))\n"));
+    }
+
+    #[test]
+    fn export_invalid_line() {
+        assert_eq!(export(&["quit", "let $a ="]).err().unwrap(),
+                   "Line 1 cannot be exported: let $a = (column 9: expected a value)");
+    }
+}
@@ -0,0 +1,182 @@
+use std::str::FromStr;
+use crate::builtins::BUILTINS;
+use crate::parser::{CLAUSE_KEYWORDS, EXPRESSION_KEYWORDS, INSTRUCTION_KEYWORDS, PREDICATE_KEYWORDS, SYMBOLS};
+
+/// Keywords that are handled by the loader rather than the parser.
+const LOADER_KEYWORDS: [&str; 2] = ["macro", "endmacro"];
+
+/// Editor grammar formats that can be generated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GrammarFormat {
+    TextMate,
+    TreeSitter,
+}
+
+impl FromStr for GrammarFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "textmate" => Ok(GrammarFormat::TextMate),
+            "tree-sitter" => Ok(GrammarFormat::TreeSitter),
+            _ => Err(format!("Unknown grammar format: {} (expected textmate or tree-sitter)", name)),
+        }
+    }
+}
+
+/// Escapes the characters of `text` that have a meaning in a regular expression.
+fn regex_escape(text: &str) -> String {
+    text.chars()
+        .map(|c| if "\\^$.|?*+()[]{}/".contains(c) { format!("\\{}", c) } else { c.to_string() })
+        .collect()
+}
+
+/// Quotes `text` as a JSON (or JavaScript) string.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The words that start an expression, followed by the names of the builtins.
+fn expression_keywords() -> Vec<&'static str> {
+    EXPRESSION_KEYWORDS.iter().copied().chain(BUILTINS.iter().map(|builtin| builtin.name)).collect()
+}
+
+/// Builds a regular expression that matches any of `words` as a whole word.
+fn word_pattern(words: &[&str]) -> String {
+    format!("\\b({})\\b", words.join("|"))
+}
+
+/// Builds a regular expression that matches any of the comparison and arithmetic symbols.
+fn symbol_pattern() -> String {
+    SYMBOLS.iter().map(|symbol| regex_escape(symbol)).collect::<Vec<String>>().join("|")
+}
+
+/// Generates an editor grammar for COS341Basic programs. The keywords and symbols come from the
+/// parser, so the grammar always matches what this build of the interpreter accepts (including
+/// the networking instructions, if it was built with them).
+pub fn generate(format: GrammarFormat) -> String {
+    match format {
+        GrammarFormat::TextMate => textmate_grammar(),
+        GrammarFormat::TreeSitter => tree_sitter_grammar(),
+    }
+}
+
+fn textmate_grammar() -> String {
+    let instruction_keywords = [INSTRUCTION_KEYWORDS, CLAUSE_KEYWORDS, &LOADER_KEYWORDS].concat();
+    let pattern = |scope: &str, regex: &str| {
+        format!("    {{ \"name\": {}, \"match\": {} }}", quote(&format!("{}.cos341basic", scope)), quote(regex))
+    };
+    let patterns = [
+        // Comments come first, so that nothing inside them is highlighted
+        pattern("comment.line.number-sign", "#.*$"),
+        pattern("comment.line.rem", "^\\s*rem\\b.*$"),
+        pattern("constant.numeric.line-number", "^\\s*\\d+"),
+        pattern("keyword.control", &word_pattern(&instruction_keywords)),
+        pattern("support.function", &word_pattern(&expression_keywords())),
+        pattern("variable.other", "\\$\\w+(\\.\\w+)?"),
+        pattern("constant.numeric", "\\b\\d+\\b"),
+        // Continuations come before the symbols, so that '...' is not read as three '.' symbols
+        pattern("punctuation.separator.continuation", "\\\\$|^\\s*\\.\\.\\."),
+        pattern("keyword.operator", &symbol_pattern()),
+        pattern("keyword.operator.logical", &word_pattern(PREDICATE_KEYWORDS)),
+        // Strings may be continued onto the next line, so they are matched with begin and end
+        String::from("    { \"name\": \"string.quoted.double.cos341basic\", \"begin\": \"\\\"\", \"end\": \"\\\"\" }"),
+    ];
+
+    format!("{{\n  \"name\": \"COS341Basic\",\n  \"scopeName\": \"source.cos341basic\",\n  \
+             \"fileTypes\": [\"bas\"],\n  \"patterns\": [\n{}\n  ]\n}}\n", patterns.join(",\n"))
+}
+
+fn tree_sitter_grammar() -> String {
+    let choices = |words: &[&str]| words.iter().map(|word| quote(word)).collect::<Vec<String>>().join(", ");
+    let instruction_keywords = [INSTRUCTION_KEYWORDS, CLAUSE_KEYWORDS, &LOADER_KEYWORDS, PREDICATE_KEYWORDS].concat();
+
+    format!("module.exports = grammar({{
+  name: 'cos341basic',
+
+  extras: $ => [/[ \\t]/, $.comment],
+
+  // Keywords are matched as whole names, so '$letter' and 'letter' are not read as 'let'
+  word: $ => $.name,
+
+  rules: {{
+    program: $ => repeat(choice($.segment_header, $.line, $.continuation, $._newline)),
+
+    // 'stack' and 'code' lines start the optional stack segment and the code segment after it
+    segment_header: $ => seq(choice('stack', 'code'), $._newline),
+
+    line: $ => seq($.line_number, repeat($._token), $._newline),
+
+    // A line starting with '...' continues the line before it
+    continuation: $ => seq('...', repeat($._token), $._newline),
+
+    _token: $ => choice(
+      $.keyword,
+      $.builtin,
+      $.variable,
+      $.number,
+      $.string,
+      $.operator,
+      $.name,
+      $.punctuation,
+    ),
+
+    // A comment runs from '#' to the end of the line, or fills a line starting with 'rem'
+    comment: $ => token(choice(/#[^\\r\\n]*/, /rem([ \\t][^\\r\\n]*)?/)),
+
+    line_number: $ => /\\d+/,
+    keyword: $ => choice({}),
+    builtin: $ => choice({}),
+    operator: $ => choice({}),
+    variable: $ => /\\$\\w+(\\.\\w+)?/,
+    number: $ => /\\d+/,
+    // Strings may be continued onto the next line by ending the line with '\\'
+    string: $ => /\"([^\"\\\\\\r\\n]|\\\\\\r?\\n[ \\t]*|\\\\)*\"/,
+    name: $ => /[A-Za-z_]\\w*/,
+    punctuation: $ => choice('(', ')', '{{', '}}', ',', '\\\\'),
+
+    _newline: $ => /\\r?\\n/,
+  }},
+}});
+", choices(&instruction_keywords), choices(&expression_keywords()), choices(&SYMBOLS))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{generate, regex_escape, GrammarFormat};
+
+    #[test]
+    fn parse_grammar_formats() {
+        assert_eq!("textmate".parse::<GrammarFormat>().unwrap(), GrammarFormat::TextMate);
+        assert_eq!("tree-sitter".parse::<GrammarFormat>().unwrap(), GrammarFormat::TreeSitter);
+        assert_eq!("vim".parse::<GrammarFormat>().err().unwrap(),
+                   "Unknown grammar format: vim (expected textmate or tree-sitter)");
+    }
+
+    #[test]
+    fn escape_regex_symbols() {
+        assert_eq!(regex_escape("<="), "<=");
+        assert_eq!(regex_escape("+"), "\\+");
+        assert_eq!(regex_escape("*"), "\\*");
+    }
+
+    #[test]
+    fn textmate_grammar_lists_keywords() {
+        let grammar = generate(GrammarFormat::TextMate);
+        assert!(grammar.contains(
+            "\"match\": \"\\\\b(let|if|goto|quit|endtest|output|push|assert|testcase|dialect|"));
+        assert!(grammar.contains("\"match\": \"\\\\/\\\\/|<<|>>|<=|>=|!=|<|>|=|\\\\+|-|\\\\*|\\\\/|%|\\\\^|&|\\\\||\\\\.\""));
+        assert!(grammar.contains("else|to|step|as|macro|endmacro)\\\\b"));
+        assert!(grammar.contains("\"match\": \"\\\\b(and|or|not)\\\\b\""));
+        assert!(grammar.contains("|abs|sign|sqrt|min|max)\\\\b"));
+        assert!(grammar.contains("{ \"name\": \"comment.line.number-sign.cos341basic\", \"match\": \"#.*$\" }"));
+    }
+
+    #[test]
+    fn tree_sitter_grammar_lists_keywords() {
+        let grammar = generate(GrammarFormat::TreeSitter);
+        assert!(grammar.contains("builtin: $ => choice(\"input\", \"all\", \"timeout\", \"default\", \"pop\""));
+        assert!(grammar.contains("\"abs\", \"sign\", \"sqrt\", \"min\", \"max\")"));
+        assert!(grammar.contains("operator: $ => choice(\"//\", \"<<\", \">>\", \"<=\", \">=\", \"!=\", \"<\", \">\", \"=\", \"+\", \"-\", \"*\", \"/\", \"%\", \"^\", \"&\", \"|\", \".\")"));
+    }
+}
@@ -0,0 +1,129 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Provides what the 'exec', 'env' and filesystem instructions get from the machine the program
+/// runs on. Paths have already been resolved against the program's base directory, and errors
+/// are returned without the path, which the instruction adds.
+pub trait HostBackend: Send + Sync {
+    /// Runs `command` in the shell, starting from `dir` if one is given, and returns everything
+    /// it wrote to its standard output along with its exit code. Commands killed by a signal
+    /// have the exit code -1.
+    fn exec(&mut self, command: &str, dir: Option<&Path>) -> Result<(String, i128), String>;
+
+    /// Returns the value of the environment variable `name`, or "" if it is not set.
+    fn env(&mut self, name: &str) -> Result<String, String>;
+
+    fn exists(&mut self, path: &Path) -> Result<bool, String>;
+
+    /// Deletes the file at `path`, or the directory if it is empty.
+    fn delete(&mut self, path: &Path) -> Result<(), String>;
+
+    /// Returns the names of the entries of the directory at `path`, sorted.
+    fn list_dir(&mut self, path: &Path) -> Result<Vec<String>, String>;
+
+    fn read_file(&mut self, path: &Path) -> Result<String, String>;
+
+    /// Writes `contents` in place of the contents of the file at `path`, or to its end if
+    /// `append` is set. The file is created if it does not exist.
+    fn write_file(&mut self, path: &Path, contents: &str, append: bool) -> Result<(), String>;
+
+    /// Opens the file at `path` to be read a line at a time.
+    fn open_file(&mut self, path: &Path) -> Result<Box<dyn HostFile>, String>;
+}
+
+/// A file opened with `HostBackend::open_file`.
+pub trait HostFile: Send + Sync {
+    /// Reads the next line of the file, along with its line terminator. An empty string is
+    /// returned once there are no lines left.
+    fn read_line(&mut self) -> Result<String, String>;
+}
+
+impl HostFile for BufReader<File> {
+    fn read_line(&mut self) -> Result<String, String> {
+        let mut line = String::new();
+        BufRead::read_line(self, &mut line).map_err(|err| err.to_string())?;
+        Ok(line)
+    }
+}
+
+/// Runs commands in the shell of the platform, such as `sh -c` on Unix.
+fn shell_command(command: &str) -> Command {
+    #[cfg(windows)]
+    let (shell, flag) = ("cmd", "/C");
+    #[cfg(not(windows))]
+    let (shell, flag) = ("sh", "-c");
+    let mut shell = Command::new(shell);
+    shell.arg(flag).arg(command);
+    shell
+}
+
+/// Host backend that uses the real shell, environment and filesystem.
+#[derive(Default)]
+pub struct OsBackend;
+
+impl OsBackend {
+    pub fn new() -> Self {
+        OsBackend
+    }
+}
+
+impl HostBackend for OsBackend {
+    fn exec(&mut self, command: &str, dir: Option<&Path>) -> Result<(String, i128), String> {
+        // The command gets no input, so it cannot read the input meant for the program
+        let mut shell = shell_command(command);
+        if let Some(dir) = dir {
+            shell.current_dir(dir);
+        }
+        let output = shell
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|err| err.to_string())?;
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        Ok((stdout, output.status.code().unwrap_or(-1) as i128))
+    }
+
+    fn env(&mut self, name: &str) -> Result<String, String> {
+        Ok(std::env::var_os(name).map(|value| value.to_string_lossy().into_owned()).unwrap_or_default())
+    }
+
+    fn exists(&mut self, path: &Path) -> Result<bool, String> {
+        Ok(path.exists())
+    }
+
+    fn delete(&mut self, path: &Path) -> Result<(), String> {
+        let result = match path.is_dir() {
+            true => fs::remove_dir(path),
+            false => fs::remove_file(path),
+        };
+        result.map_err(|err| err.to_string())
+    }
+
+    fn list_dir(&mut self, path: &Path) -> Result<Vec<String>, String> {
+        let mut entries = fs::read_dir(path)
+            .and_then(|entries| entries
+                .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+                .collect::<Result<Vec<String>, _>>())
+            .map_err(|err| err.to_string())?;
+        entries.sort();
+        Ok(entries)
+    }
+
+    fn read_file(&mut self, path: &Path) -> Result<String, String> {
+        fs::read_to_string(path).map_err(|err| err.to_string())
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &str, append: bool) -> Result<(), String> {
+        OpenOptions::new().create(true).write(true).append(append).truncate(!append)
+            .open(path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .map_err(|err| err.to_string())
+    }
+
+    fn open_file(&mut self, path: &Path) -> Result<Box<dyn HostFile>, String> {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
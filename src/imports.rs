@@ -0,0 +1,205 @@
+use std::collections::{BTreeSet, HashMap};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use crate::code_loader::{outside_strings, resolve_labels};
+use crate::errors::LoadError;
+use crate::parser::{parse_line, Expression, Instruction};
+use crate::states::{INTERPRETER_VARIABLES, RESERVED_WORDS};
+
+lazy_static! {
+    static ref JUMP_REGEX : Regex = Regex::new(r"\b(goto|gosub) (\d+)").unwrap();
+    static ref VARIABLE_REGEX : Regex = Regex::new(r"\$(\w+)(\.\w+)?").unwrap();
+}
+
+/// Extension of the module files read by 'import'.
+pub const MODULE_EXTENSION: &str = ".bas";
+
+/// Finds the name of the file that 'import module' reads, relative to the program importing it.
+pub fn module_file(module: &str) -> String {
+    format!("{}{}", module, MODULE_EXTENSION)
+}
+
+/// Replaces the first `name` that follows `keyword` in a line with `alias.name`.
+fn qualify_after(line: &str, keyword: &str, name: &str, alias: &str) -> String {
+    let regex = Regex::new(&format!(r"\b({}\s+){}\b", keyword, regex::escape(name))).unwrap();
+    outside_strings(line, |piece| regex.replace(piece, format!("${{1}}{}.{}", alias, name)).to_string())
+}
+
+/// Puts the functions, labels and variables of an imported module in the namespace `alias`, so
+/// that they cannot clash with those of the program or of other modules. The jumps of the module
+/// are resolved within the module, and point to its lines counted from its first line.
+///
+/// # Arguments
+/// * `module` - The name of the module, which errors are reported with
+/// * `alias` - The name the module is imported as
+/// * `lines` - The lines of the module
+///
+/// # Returns
+/// * `Ok(Vec<String>)` - The lines of the module, in its namespace
+/// * `Err(LoadError)` - The module line that is not part of a function
+fn namespace(module: &str, alias: &str, lines: Vec<String>) -> Result<Vec<String>, LoadError> {
+    let instructions: Vec<Option<Instruction>> = lines.iter().map(|line| parse_line(line).ok()).collect();
+    let mut functions = BTreeSet::new();
+    let mut in_function = false;
+    for (index, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Some(Instruction::Func { name, .. }) => {
+                functions.insert(name.clone());
+                in_function = true;
+            },
+            Some(Instruction::EndFunc) if in_function => in_function = false,
+            Some(Instruction::Import { .. }) => {
+                return Err(LoadError::in_file(module, LoadError::invalid_code(index, "Imports another module! Modules cannot import modules.")));
+            },
+            _ if !in_function => {
+                return Err(LoadError::in_file(module, LoadError::invalid_code(index, "Not part of a function! Imported modules may only define functions.")));
+            },
+            _ => {},
+        }
+    }
+
+    let lines = resolve_labels(lines).map_err(|err| LoadError::in_file(module, err))?;
+    Ok(lines.into_iter().zip(instructions).map(|(line, instruction)| {
+        let line = match instruction {
+            Some(Instruction::Func { name, .. }) => qualify_after(&line, "func", &name, alias),
+            Some(Instruction::Label(name)) => qualify_after(&line, "label", &name, alias),
+            Some(Instruction::Let { value: Expression::Call { function, .. }, .. }) if functions.contains(&function) =>
+                qualify_after(&line, "call", &function, alias),
+            _ => line,
+        };
+        // Variables that are already qualified, and those the interpreter sets, keep their names
+        outside_strings(&line, |piece| VARIABLE_REGEX.replace_all(piece, |var: &Captures| {
+            if var.get(2).is_some() || INTERPRETER_VARIABLES.contains(&&var[1]) {
+                var[0].to_string()
+            } else {
+                format!("${}.{}", alias, &var[1])
+            }
+        }).to_string())
+    }).collect())
+}
+
+/// Adds the modules a program imports with 'import module as alias' to the end of the program.
+/// A module may only hold functions, which the program calls as 'alias.name'. The functions,
+/// labels and variables of the module are put in the namespace of its alias (see `namespace`),
+/// so a module cannot change the variables of the program, or jump to its labels. Jumps in a
+/// module point to lines of the same module, and jumps in the program past its last line still
+/// leave the program, so both are moved to where the lines end up. The 'import' lines stay in the
+/// program, and do nothing when they are run. A module is not imported if the program already
+/// defines functions in its namespace.
+///
+/// # Arguments
+/// * `code` - The lines of the program
+/// * `load_module` - Loads the lines of the module with the given name
+///
+/// # Returns
+/// * `Ok(Vec<String>)` - The program followed by every module it imports
+/// * `Err(LoadError)` - The reason a module could not be imported
+pub fn append_imports(code: Vec<String>, load_module: &dyn Fn(&str) -> Result<Vec<String>, LoadError>) -> Result<Vec<String>, LoadError> {
+    let mut aliases = HashMap::new();
+    let mut modules = Vec::new();
+    for (line_number, line) in code.iter().enumerate() {
+        let Ok(Instruction::Import { module, alias }) = parse_line(line) else {
+            continue;
+        };
+        if RESERVED_WORDS.contains(&alias.as_str()) {
+            return Err(LoadError::invalid_code(line_number, format!("{} is a reserved word and cannot be used as a module name!", alias)));
+        }
+        if let Some(first_line) = aliases.insert(alias.clone(), line_number) {
+            return Err(LoadError::invalid_code(line_number, format!("Module name {} is already used on line {}!", alias, first_line)));
+        }
+        // A program that already holds the functions of the module (such as a minified program,
+        // which is written with its imports added) does not import them again
+        let namespace_prefix = format!("{}.", alias);
+        if code.iter().any(|line| matches!(parse_line(line), Ok(Instruction::Func { name, .. }) if name.starts_with(&namespace_prefix))) {
+            continue;
+        }
+        let lines = load_module(&module).map_err(|error| LoadError::Import { line: line_number, error: Box::new(error) })?;
+        modules.push(namespace(&module, &alias, lines)?);
+    }
+    if modules.is_empty() {
+        return Ok(code);
+    }
+
+    let shift = |line: &str, from: usize, by: usize| outside_strings(line, |piece| JUMP_REGEX.replace_all(piece, |jump: &Captures| {
+        match jump[2].parse::<usize>() {
+            Ok(target) if target >= from => format!("{} {}", &jump[1], target + by),
+            _ => jump[0].to_string(),
+        }
+    }).to_string());
+
+    let module_lines: usize = modules.iter().map(Vec::len).sum();
+    let mut combined: Vec<String> = code.iter().map(|line| shift(line, code.len(), module_lines)).collect();
+    for lines in modules {
+        let offset = combined.len();
+        combined.extend(lines.iter().map(|line| shift(line, 0, offset)));
+    }
+    Ok(combined)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use crate::errors::LoadError;
+    use super::append_imports;
+
+    fn program(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    fn import(code: &[&str], module: &[&str]) -> Result<Vec<String>, LoadError> {
+        let module = program(module);
+        let load_module = move |name: &str| match name {
+            "math_lib" => Ok(module.clone()),
+            _ => Err(LoadError::in_file(format!("{}.bas", name), io::Error::from(io::ErrorKind::NotFound).into())),
+        };
+        append_imports(program(code), &load_module)
+    }
+
+    #[test]
+    fn namespace_imported_modules() {
+        let combined = import(
+            &["import math_lib as m", "let $g = call m.gcd $a $b", "goto 3"],
+            &["func gcd $a $b", "if $b = $zero goto done", "let $a = call gcd $b $a", "output \"call gcd\"", "label done", "return $a", "endfunc"],
+        ).unwrap();
+        assert_eq!(combined, program(&[
+            "import math_lib as m",
+            "let $g = call m.gcd $a $b",
+            "goto 10",
+            "func m.gcd $m.a $m.b",
+            "if $m.b = $m.zero goto 7",
+            "let $m.a = call m.gcd $m.b $m.a",
+            "output \"call gcd\"",
+            "label m.done",
+            "return $m.a",
+            "endfunc",
+        ]));
+    }
+
+    #[test]
+    fn skip_modules_already_added() {
+        let code = ["import math_lib as m", "let $g = call m.gcd $a $b", "func m.gcd $e.a $e.b", "return $e.a", "endfunc"];
+        assert_eq!(import(&code, &["func gcd $a $b", "return $a", "endfunc"]).unwrap(), program(&code));
+    }
+
+    #[test]
+    fn keep_interpreter_variables() {
+        let combined = import(&["import math_lib"], &["func run $cmd", "let $out = exec $cmd", "return $exitcode", "endfunc"]).unwrap();
+        assert_eq!(combined[1..], program(&["func math_lib.run $math_lib.cmd", "let $math_lib.out = exec $math_lib.cmd",
+                                             "return $exitcode", "endfunc"]));
+    }
+
+    #[test]
+    fn reject_invalid_imports() {
+        let error = |code: &[&str], module: &[&str]| import(code, module).err().unwrap().to_string();
+        assert_eq!(error(&["import math_lib as m", "import math_lib as m"], &[]),
+                   "Line 1: Module name m is already used on line 0!");
+        assert_eq!(error(&["import math_lib as input"], &[]),
+                   "Line 0: input is a reserved word and cannot be used as a module name!");
+        assert_eq!(error(&["import strings"], &[]), "Line 0: strings.bas: entity not found");
+        assert!(matches!(import(&["import strings"], &[]), Err(LoadError::Import { line: 0, .. })));
+        assert_eq!(error(&["import math_lib"], &["let $a = 1", "func f", "endfunc"]),
+                   "math_lib: Line 0: Not part of a function! Imported modules may only define functions.");
+        assert_eq!(error(&["import math_lib"], &["func f", "import math_lib", "endfunc"]),
+                   "math_lib: Line 1: Imports another module! Modules cannot import modules.");
+    }
+}
@@ -0,0 +1,233 @@
+use std::fs;
+use std::path::Path;
+
+use crate::ed25519;
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const INITIAL_HASH: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Computes the SHA-256 digest of `data`, as a lowercase hex string.
+pub fn sha256_hex(data: &[u8]) -> String {
+    // Pad the message with a 1 bit, zeroes and the message length in bits
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    let mut hash = INITIAL_HASH;
+    for block in message.chunks(64) {
+        let mut schedule = [0u32; 64];
+        for (index, word) in block.chunks(4).enumerate() {
+            schedule[index] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for index in 16..64 {
+            let s0 = schedule[index - 15].rotate_right(7) ^ schedule[index - 15].rotate_right(18) ^ (schedule[index - 15] >> 3);
+            let s1 = schedule[index - 2].rotate_right(17) ^ schedule[index - 2].rotate_right(19) ^ (schedule[index - 2] >> 10);
+            schedule[index] = schedule[index - 16].wrapping_add(s0).wrapping_add(schedule[index - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = hash;
+        for index in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let temp1 = h.wrapping_add(s1).wrapping_add(choice).wrapping_add(ROUND_CONSTANTS[index]).wrapping_add(schedule[index]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(majority);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (value, new_value) in hash.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *value = value.wrapping_add(new_value);
+        }
+    }
+
+    hash.iter().map(|word| format!("{:08x}", word)).collect()
+}
+
+/// Finds the checksum for the file `file_name` in the contents of a checksum file. Each line of a
+/// checksum file has the form '<sha256>  <file name>', as written by 'sha256sum'. A checksum file
+/// holding a single checksum may leave out the file name.
+fn find_checksum(checksums: &str, file_name: &str) -> Option<String> {
+    let entries: Vec<(&str, Option<&str>)> = checksums.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut parts = line.trim().splitn(2, char::is_whitespace);
+            let checksum = parts.next().unwrap_or("");
+            // sha256sum marks files that were read in binary mode with '*'
+            let name = parts.next().map(|name| name.trim().trim_start_matches('*'));
+            (checksum, name)
+        })
+        .collect();
+
+    match entries.as_slice() {
+        [(checksum, None)] => Some(checksum.to_lowercase()),
+        _ => entries.iter()
+            .find(|(_, name)| name.map(|name| Path::new(name).file_name() == Path::new(file_name).file_name()) == Some(true))
+            .map(|(checksum, _)| checksum.to_lowercase()),
+    }
+}
+
+/// Checks that the program file at `file_path` matches the checksum recorded for it in the
+/// checksum file at `checksum_path`, so that tampered programs are never run.
+///
+/// # Returns
+/// * `Ok(())` - The program file matches its checksum
+/// * `Err(String)` - A message detailing why the program file could not be verified
+pub fn verify_file(file_path: &str, checksum_path: &str) -> Result<(), String> {
+    let checksums = fs::read_to_string(checksum_path).map_err(|err| format!("{}: {}", checksum_path, err))?;
+    let expected = find_checksum(&checksums, file_path)
+        .ok_or(format!("{}: no checksum found for {}", checksum_path, file_path))?;
+    let contents = fs::read(file_path).map_err(|err| format!("{}: {}", file_path, err))?;
+
+    if sha256_hex(&contents) != expected {
+        return Err(format!("{}: checksum does not match! The program may have been tampered with.", file_path));
+    }
+    Ok(())
+}
+
+/// The DER header of an Ed25519 public key in a SubjectPublicKeyInfo structure, which is what
+/// 'openssl pkey -pubout' writes. The 32 bytes of the key follow it.
+const ED25519_KEY_HEADER: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+
+/// Decodes standard base64, ignoring any whitespace.
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = text.bytes().filter(|byte| !byte.is_ascii_whitespace()).collect();
+    let digits = digits.strip_suffix(b"==").or(digits.strip_suffix(b"=")).unwrap_or(&digits);
+
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for digit in digits {
+        let value = match digit {
+            b'A'..=b'Z' => digit - b'A',
+            b'a'..=b'z' => digit - b'a' + 26,
+            b'0'..=b'9' => digit - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+/// Reads an Ed25519 public key from a PEM file, as written by 'openssl pkey -pubout'. The base64
+/// of the bare 32 byte key is accepted as well.
+fn read_public_key(key_path: &str) -> Result<[u8; 32], String> {
+    let contents = fs::read_to_string(key_path).map_err(|err| format!("{}: {}", key_path, err))?;
+    let encoded: String = contents.lines().filter(|line| !line.starts_with("-----")).collect();
+    let key = decode_base64(&encoded).ok_or(format!("{}: public key is not valid base64!", key_path))?;
+
+    let key = key.strip_prefix(&ED25519_KEY_HEADER[..]).unwrap_or(&key);
+    key.try_into().map_err(|_| format!("{}: not an Ed25519 public key!", key_path))
+}
+
+/// Reads a detached signature, which is either the raw 64 bytes written by 'openssl pkeyutl -sign'
+/// or their base64.
+fn read_signature(signature_path: &str) -> Result<[u8; 64], String> {
+    let contents = fs::read(signature_path).map_err(|err| format!("{}: {}", signature_path, err))?;
+    if let Ok(signature) = contents.as_slice().try_into() {
+        return Ok(signature);
+    }
+
+    std::str::from_utf8(&contents).ok()
+        .and_then(decode_base64)
+        .and_then(|signature| signature.try_into().ok())
+        .ok_or(format!("{}: not an Ed25519 signature!", signature_path))
+}
+
+/// Checks that the program file (or bundle) at `file_path` was signed by the owner of the public
+/// key at `key_path`, using the detached Ed25519 signature at `signature_path`. Unlike a checksum,
+/// the signature cannot be remade by whoever changed the program, so tampered programs are never
+/// run.
+///
+/// # Returns
+/// * `Ok(())` - The signature of the program file is valid
+/// * `Err(String)` - A message detailing why the program file could not be verified
+pub fn verify_signature(file_path: &str, key_path: &str, signature_path: &str) -> Result<(), String> {
+    let public_key = read_public_key(key_path)?;
+    let signature = read_signature(signature_path)?;
+    let contents = fs::read(file_path).map_err(|err| format!("{}: {}", file_path, err))?;
+
+    if !ed25519::verify(&public_key, &contents, &signature) {
+        return Err(format!("{}: signature does not match! The program may have been tampered with.", file_path));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_base64, find_checksum, read_public_key, sha256_hex, verify_file, verify_signature};
+
+    #[test]
+    fn sha256_test_vectors() {
+        assert_eq!(sha256_hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+                   "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+    }
+
+    #[test]
+    fn find_checksum_by_file_name() {
+        let checksums = "1111  other.txt\nABCD *programs/main.txt\n";
+        assert_eq!(find_checksum(checksums, "main.txt").unwrap(), "abcd");
+        assert_eq!(find_checksum("abcd\n", "main.txt").unwrap(), "abcd");
+        assert!(find_checksum(checksums, "missing.txt").is_none());
+    }
+
+    #[test]
+    fn verify_program_file() {
+        assert!(verify_file("testfiles/test1.txt", "testfiles/test1.txt.sha256").is_ok());
+        assert_eq!(verify_file("testfiles/test2.txt", "testfiles/test1.txt.sha256").err().unwrap(),
+                   "testfiles/test1.txt.sha256: no checksum found for testfiles/test2.txt");
+    }
+
+    #[test]
+    fn decode_base64_with_padding() {
+        assert_eq!(decode_base64("TWFu").unwrap(), b"Man");
+        assert_eq!(decode_base64("TWE=\n").unwrap(), b"Ma");
+        assert_eq!(decode_base64("TQ==").unwrap(), b"M");
+        assert!(decode_base64("T*Q=").is_none());
+    }
+
+    #[test]
+    fn read_pem_public_key() {
+        let key = read_public_key("testfiles/test1.txt.pub").unwrap();
+        assert_eq!(key[..4], [0xea, 0x7f, 0xa2, 0xb9]);
+    }
+
+    #[test]
+    fn verify_signed_program_file() {
+        assert!(verify_signature("testfiles/test1.txt", "testfiles/test1.txt.pub", "testfiles/test1.txt.sig").is_ok());
+        assert_eq!(verify_signature("testfiles/test2.txt", "testfiles/test1.txt.pub", "testfiles/test1.txt.sig").err().unwrap(),
+                   "testfiles/test2.txt: signature does not match! The program may have been tampered with.");
+    }
+}
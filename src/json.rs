@@ -0,0 +1,295 @@
+use std::fmt;
+
+/// A JSON value. Objects keep their keys in the order they were written. Numbers written without
+/// a decimal point or exponent are integers, and other numbers are floats.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    /// An integer too large for `Number`, such as a big integer variable in a snapshot
+    BigNumber(i128),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Returns the value of `key`, if this is an object that has it.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Json>> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&Vec<(String, Json)>> {
+        match self {
+            Json::Object(fields) => Some(fields),
+            _ => None,
+        }
+    }
+}
+
+/// Writes `text` as a quoted JSON string.
+fn write_string(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::BigNumber(value) => write!(f, "{}", value),
+            // JSON has no infinity or NaN
+            Json::Float(value) if !value.is_finite() => write!(f, "null"),
+            // Debug formatting keeps the decimal point, so the number reads back as a float
+            Json::Float(value) => write!(f, "{:?}", value),
+            Json::String(value) => write_string(f, value),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (index, (name, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    fn error<T>(&mut self, message: &str) -> Result<T, String> {
+        match self.chars.peek() {
+            Some((offset, _)) => Err(format!("Invalid JSON at offset {}: {}", offset, message)),
+            None => Err(format!("Invalid JSON at end of input: {}", message)),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next_if(|(_, c)| *c == expected) {
+            Some(_) => Ok(()),
+            None => self.error(&format!("expected '{}'", expected)),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+        for expected in keyword.chars() {
+            if self.chars.next_if(|(_, c)| *c == expected).is_none() {
+                return self.error(&format!("expected '{}'", keyword));
+            }
+        }
+        Ok(value)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(text),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, '"')) => text.push('"'),
+                    Some((_, '\\')) => text.push('\\'),
+                    Some((_, '/')) => text.push('/'),
+                    Some((_, 'n')) => text.push('\n'),
+                    Some((_, 'r')) => text.push('\r'),
+                    Some((_, 't')) => text.push('\t'),
+                    Some((_, 'b')) => text.push('\u{8}'),
+                    Some((_, 'f')) => text.push('\u{c}'),
+                    Some((_, 'u')) => {
+                        let code: String = (0..4).filter_map(|_| self.chars.next().map(|(_, c)| c)).collect();
+                        let c = u32::from_str_radix(&code, 16).ok().and_then(char::from_u32);
+                        match c {
+                            Some(c) => text.push(c),
+                            None => return self.error("invalid unicode escape"),
+                        }
+                    },
+                    _ => return self.error("invalid escape"),
+                },
+                Some((_, c)) => text.push(c),
+                None => return self.error("unterminated string"),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.chars.peek().map(|(offset, _)| *offset).unwrap_or_default();
+        let mut number = String::new();
+        while let Some((_, c)) = self.chars.next_if(|(_, c)| c.is_ascii_digit() || "-+.eE".contains(*c)) {
+            number.push(c);
+        }
+        if let Ok(value) = number.parse::<i64>() {
+            return Ok(Json::Number(value));
+        }
+        if let Ok(value) = number.parse::<i128>() {
+            return Ok(Json::BigNumber(value));
+        }
+        number.parse::<f64>().ok()
+            .filter(|value| value.is_finite())
+            .map(Json::Float)
+            .ok_or(format!("Invalid JSON at offset {}: expected a number", start))
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.peek().map(|(_, c)| *c) {
+            Some('n') => self.keyword("null", Json::Null),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('[') => {
+                self.expect('[')?;
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if self.chars.next_if(|(_, c)| *c == ']').is_some() {
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.skip_whitespace();
+                    if self.chars.next_if(|(_, c)| *c == ',').is_none() {
+                        self.expect(']')?;
+                        return Ok(Json::Array(values));
+                    }
+                }
+            },
+            Some('{') => {
+                self.expect('{')?;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.chars.next_if(|(_, c)| *c == '}').is_some() {
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let name = self.string()?;
+                    self.expect(':')?;
+                    fields.push((name, self.value()?));
+                    self.skip_whitespace();
+                    if self.chars.next_if(|(_, c)| *c == ',').is_none() {
+                        self.expect('}')?;
+                        return Ok(Json::Object(fields));
+                    }
+                }
+            },
+            Some(c) if c.is_ascii_digit() || c == '-' => self.number(),
+            _ => self.error("expected a value"),
+        }
+    }
+}
+
+/// Parses a JSON document.
+///
+/// # Returns
+/// * `Ok(Json)` - The value held by the document
+/// * `Err(String)` - A message detailing where the document is invalid
+pub fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = Parser { chars: text.char_indices().peekable() };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.chars.peek() {
+        None => Ok(value),
+        Some(_) => parser.error("expected end of input"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, Json};
+
+    #[test]
+    fn write_json() {
+        let value = Json::Object(vec![
+            (String::from("name"), Json::String(String::from("say \"hi\"\n"))),
+            (String::from("lines"), Json::Array(vec![Json::Number(1), Json::Number(-2)])),
+            (String::from("done"), Json::Bool(false)),
+            (String::from("next"), Json::Null),
+        ]);
+        assert_eq!(value.to_string(), r#"{"name":"say \"hi\"\n","lines":[1,-2],"done":false,"next":null}"#);
+    }
+
+    #[test]
+    fn parse_json() {
+        let value = parse(r#" { "name" : "tab\there A", "lines": [1, -2], "empty": {}, "done": true } "#).unwrap();
+        assert_eq!(value.get("name").unwrap().as_str().unwrap(), "tab\there A");
+        assert_eq!(value.get("lines").unwrap().as_array().unwrap(), &vec![Json::Number(1), Json::Number(-2)]);
+        assert_eq!(value.get("empty").unwrap(), &Json::Object(Vec::new()));
+        assert_eq!(value.get("done").unwrap(), &Json::Bool(true));
+        assert!(value.get("missing").is_none());
+    }
+
+    #[test]
+    fn parse_json_numbers() {
+        let value = parse("[7, -0.5, 2.0, 1e-7, 170141183460469231731687303715884105727]").unwrap();
+        assert_eq!(value.as_array().unwrap(), &vec![
+            Json::Number(7), Json::Float(-0.5), Json::Float(2.0), Json::Float(1e-7), Json::BigNumber(i128::MAX),
+        ]);
+        assert_eq!(value.to_string(), "[7,-0.5,2.0,1e-7,170141183460469231731687303715884105727]");
+    }
+
+    #[test]
+    fn parse_invalid_json() {
+        assert_eq!(parse("[1, 2").err().unwrap(), "Invalid JSON at end of input: expected ']'");
+        assert_eq!(parse("{\"a\": 1.5.2}").err().unwrap(), "Invalid JSON at offset 6: expected a number");
+        assert_eq!(parse("[1] x").err().unwrap(), "Invalid JSON at offset 4: expected end of input");
+    }
+}
@@ -0,0 +1,40 @@
+//! A very simple interpreter that runs a minimal version of BASIC, for the teaching and
+//! learning of Compiler Construction. The `COS341Basic` binary is built on this library.
+
+extern crate lazy_static;
+
+pub mod aliases;
+pub mod async_runner;
+pub mod builtins;
+pub mod bundle;
+pub mod classic;
+pub mod code_loader;
+pub mod console;
+pub mod debugger;
+pub mod dialect;
+pub mod ed25519;
+pub mod embed;
+pub mod errors;
+pub mod events;
+pub mod export;
+pub mod grammar;
+pub mod host;
+pub mod imports;
+pub mod integrity;
+pub mod json;
+pub mod macros;
+pub mod manifest;
+pub mod minify;
+#[cfg(feature = "net")]
+pub mod net;
+pub mod parser;
+pub mod states;
+pub mod storage;
+pub mod prog_data;
+pub mod renumber;
+pub mod replay;
+pub mod runner;
+pub mod session;
+pub mod stats;
+pub mod testcases;
+pub mod trace;
@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use crate::code_loader::outside_strings;
+use crate::errors::LoadError;
+
+lazy_static! {
+    static ref MACRO_REGEX : Regex = Regex::new(r"^macro (\w+)\(([\w, ]*)\)$").unwrap();
+    static ref CALL_REGEX : Regex = Regex::new(r"^(\w+)\((.*)\)$").unwrap();
+    static ref JUMP_REGEX : Regex = Regex::new(r"\b(goto|gosub) (\d+)").unwrap();
+    static ref VARIABLE_REGEX : Regex = Regex::new(r"\$(\w+)").unwrap();
+}
+
+/// A macro defined in a program.
+struct Macro {
+    params: Vec<String>,
+    // First and last line of the macro body in the original program
+    body_start: usize,
+    body_end: usize,
+}
+
+/// The start of the body a line of an expanded program was copied from, and where that copy of
+/// the body starts in the expanded program.
+type BodyCopy = (usize, usize);
+
+/// Splits a comma separated list, ignoring an empty list.
+fn split_list(list: &str) -> Vec<String> {
+    if list.trim().is_empty() {
+        return Vec::new();
+    }
+    list.split(',').map(|item| item.trim().to_string()).collect()
+}
+
+/// Finds every 'macro name(params)' ... 'endmacro' block in a program.
+fn find_macros(code: &[String]) -> Result<HashMap<String, Macro>, LoadError> {
+    let mut macros = HashMap::new();
+    let mut index = 0;
+
+    while index < code.len() {
+        if let Some(captures) = MACRO_REGEX.captures(&code[index]) {
+            let name = captures[1].to_string();
+            let body_end = (index + 1..code.len())
+                .find(|line| code[*line] == "endmacro")
+                .ok_or_else(|| LoadError::invalid_code(index, format!("macro {} has no matching endmacro!", name)))?;
+            if macros.contains_key(&name) {
+                return Err(LoadError::invalid_code(index, format!("macro {} is already defined!", name)));
+            }
+
+            macros.insert(name, Macro {
+                params: split_list(&captures[2]),
+                body_start: index + 1,
+                body_end: body_end - 1,
+            });
+            index = body_end;
+        } else if code[index] == "endmacro" {
+            return Err(LoadError::invalid_code(index, "endmacro without a matching macro!"));
+        }
+        index += 1;
+    }
+    Ok(macros)
+}
+
+/// Expands every macro call in a program. A call such as 'swap($x, $y)' is replaced by the body
+/// of the macro with '$a' and '$b' (the parameters of 'macro swap(a, b)') substituted by the
+/// arguments. Macro definitions are removed from the program, so every line is renumbered and
+/// jump targets are updated to match. Jumps inside a macro body to lines of the same body stay
+/// inside the copy of the body they belong to.
+///
+/// # Returns
+/// * `Ok(Vec<String>)` - The program with every macro expanded
+/// * `Err(LoadError)` - The line of the program holding a macro that could not be expanded
+pub fn expand_macros(code: Vec<String>) -> Result<Vec<String>, LoadError> {
+    let macros = find_macros(&code)?;
+    if macros.is_empty() {
+        return Ok(code);
+    }
+
+    // Lines that belong to a definition (including the macro and endmacro lines)
+    let in_definition = |index: usize| macros.values()
+        .any(|definition| index + 1 >= definition.body_start && index <= definition.body_end + 1);
+
+    // Each emitted line, along with the line it came from in the original program and the start
+    // of the definition body it was copied from (and where that copy starts in the expanded
+    // program) if it came from a macro
+    let mut expanded: Vec<(usize, String, Option<BodyCopy>)> = Vec::new();
+    let mut new_index = HashMap::new();
+
+    for (index, line) in code.iter().enumerate() {
+        if in_definition(index) {
+            continue;
+        }
+        new_index.insert(index, expanded.len());
+
+        let call = CALL_REGEX.captures(line);
+        match call.as_ref().and_then(|captures| macros.get(&captures[1])) {
+            Some(definition) => {
+                let captures = call.as_ref().unwrap();
+                let args = split_list(&captures[2]);
+                if args.len() != definition.params.len() {
+                    return Err(LoadError::invalid_code(index, format!("macro {} expects {} arguments but was given {}!",
+                                                                      &captures[1], definition.params.len(), args.len())));
+                }
+                let substitutions: HashMap<&String, &String> = definition.params.iter().zip(args.iter()).collect();
+
+                let copy_start = expanded.len();
+                for (offset, body_line) in code[definition.body_start..=definition.body_end].iter().enumerate() {
+                    if let Some(inner) = CALL_REGEX.captures(body_line).filter(|inner| macros.contains_key(&inner[1])) {
+                        return Err(LoadError::invalid_code(index, format!("macro {} cannot use macro {}!", &captures[1], &inner[1])));
+                    }
+                    let substituted = outside_strings(body_line, |piece| VARIABLE_REGEX.replace_all(piece, |var: &Captures| {
+                        match substitutions.get(&var[1].to_string()) {
+                            Some(arg) => arg.to_string(),
+                            None => var[0].to_string(),
+                        }
+                    }).to_string());
+                    expanded.push((definition.body_start + offset, substituted, Some((definition.body_start, copy_start))));
+                }
+            },
+            None => expanded.push((index, line.clone(), None)),
+        }
+    }
+
+    // Jumps past the end of the program stay past the end
+    let old_len = code.len();
+    let new_len = expanded.len();
+    let mut result = Vec::new();
+    for (index, line, origin) in expanded {
+        let mut error = None;
+        let remapped = outside_strings(&line, |piece| JUMP_REGEX.replace_all(piece, |jump: &Captures| {
+            let target = jump[2].parse::<usize>().unwrap();
+            let body = origin.and_then(|(body_start, copy_start)| macros.values()
+                .find(|definition| definition.body_start == body_start)
+                .map(|definition| (definition, copy_start)));
+
+            let new_target = match body {
+                Some((definition, copy_start)) if target >= definition.body_start && target <= definition.body_end =>
+                    copy_start + target - definition.body_start,
+                _ if target >= old_len => new_len + target - old_len,
+                _ => match new_index.get(&target) {
+                    Some(new_target) => *new_target,
+                    None => {
+                        error = Some(format!("{} {} points into a macro definition!", &jump[1], target));
+                        target
+                    }
+                }
+            };
+            format!("{} {}", &jump[1], new_target)
+        }).to_string());
+
+        if let Some(error) = error {
+            return Err(LoadError::invalid_code(index, error));
+        }
+        result.push(remapped);
+    }
+    Ok(result)
+}
+
+/// Adds the macros defined by modules to the end of a program, so that the program can call
+/// them. A module may only hold macro definitions. Jumps in a module point to lines of the same
+/// module, and jumps in the program past its last line still leave the program, so both are
+/// moved to where the lines end up.
+///
+/// # Arguments
+/// * `code` - The lines of the program
+/// * `modules` - The name of each module, along with its lines
+///
+/// # Returns
+/// * `Ok(Vec<String>)` - The program followed by every module, ready for `expand_macros`
+/// * `Err(LoadError)` - The module line that is not part of a macro definition
+pub fn append_modules(code: Vec<String>, modules: &[(String, Vec<String>)]) -> Result<Vec<String>, LoadError> {
+    if modules.is_empty() {
+        return Ok(code);
+    }
+    let shift = |line: &str, from: usize, by: usize| outside_strings(line, |piece| JUMP_REGEX.replace_all(piece, |jump: &Captures| {
+        match jump[2].parse::<usize>() {
+            Ok(target) if target >= from => format!("{} {}", &jump[1], target + by),
+            _ => jump[0].to_string(),
+        }
+    }).to_string());
+
+    let module_lines: usize = modules.iter().map(|(_, lines)| lines.len()).sum();
+    let mut combined: Vec<String> = code.iter().map(|line| shift(line, code.len(), module_lines)).collect();
+    for (name, lines) in modules {
+        let mut in_macro = false;
+        for (index, line) in lines.iter().enumerate() {
+            if MACRO_REGEX.is_match(line) {
+                in_macro = true;
+            } else if line == "endmacro" {
+                in_macro = false;
+            } else if !in_macro {
+                return Err(LoadError::in_file(name, LoadError::invalid_code(index, "Not part of a macro! Modules may only define macros.")));
+            }
+        }
+        let offset = combined.len();
+        combined.extend(lines.iter().map(|line| shift(line, 0, offset)));
+    }
+    Ok(combined)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::errors::LoadError;
+    use super::{append_modules, expand_macros};
+
+    fn program(lines: Vec<&str>) -> Vec<String> {
+        lines.into_iter().map(String::from).collect()
+    }
+
+    #[test]
+    fn program_without_macros_is_unchanged() {
+        let code = program(vec!["let $a = 1", "goto 0"]);
+        assert_eq!(expand_macros(code.clone()).unwrap(), code);
+    }
+
+    #[test]
+    fn expand_macro_call() {
+        let code = program(vec![
+            "macro swap(a, b)",
+            "let $tmp = $a",
+            "let $a = $b",
+            "let $b = $tmp",
+            "endmacro",
+            "let $x = 1",
+            "let $y = 2",
+            "swap($x, $y)",
+            "output $x",
+            "goto 5",
+        ]);
+        assert_eq!(expand_macros(code).unwrap(), program(vec![
+            "let $x = 1",
+            "let $y = 2",
+            "let $tmp = $x",
+            "let $x = $y",
+            "let $y = $tmp",
+            "output $x",
+            "goto 0",
+        ]));
+    }
+
+    #[test]
+    fn jumps_inside_macro_stay_local() {
+        let code = program(vec![
+            "macro max(a, b, m)",
+            "let $m = $a",
+            "if $a > $b goto 3",
+            "let $m = $b",
+            "endmacro",
+            "max($x, $y, $big)",
+            "max($big, $z, $big)",
+            "if $big > $x goto 6",
+            "quit",
+        ]);
+        assert_eq!(expand_macros(code).unwrap(), program(vec![
+            "let $big = $x",
+            "if $x > $y goto 2",
+            "let $big = $y",
+            "let $big = $big",
+            "if $big > $z goto 5",
+            "let $big = $z",
+            "if $big > $x goto 3",
+            "quit",
+        ]));
+    }
+
+    #[test]
+    fn macro_called_with_wrong_argument_count() {
+        let code = program(vec!["macro twice(a)", "output $a", "output $a", "endmacro", "twice($x, $y)"]);
+        assert_eq!(expand_macros(code).err().unwrap().to_string(), "Line 4: macro twice expects 1 arguments but was given 2!");
+    }
+
+    #[test]
+    fn macro_without_endmacro() {
+        let code = program(vec!["macro twice(a)", "output $a"]);
+        assert_eq!(expand_macros(code).err().unwrap(), LoadError::invalid_code(0, "macro twice has no matching endmacro!"));
+    }
+
+    #[test]
+    fn jump_into_macro_definition() {
+        let code = program(vec!["macro twice(a)", "output $a", "endmacro", "goto 1"]);
+        assert_eq!(expand_macros(code).err().unwrap().to_string(), "Line 3: goto 1 points into a macro definition!");
+    }
+
+    #[test]
+    fn call_macros_from_modules() {
+        let code = program(vec!["let $x = 1", "twice($x)", "if $x < $x goto 3", "output $x"]);
+        let module = program(vec!["macro twice(a)", "let $a = $a + $a", "if $a > $a goto 1", "endmacro"]);
+        let combined = append_modules(code, &[(String::from("lib.bas"), module)]).unwrap();
+        assert_eq!(combined[2], "if $x < $x goto 3");
+        assert_eq!(combined[6], "if $a > $a goto 5");
+        assert_eq!(expand_macros(combined).unwrap(), program(vec![
+            "let $x = 1",
+            "let $x = $x + $x",
+            "if $x > $x goto 1",
+            "if $x < $x goto 4",
+            "output $x",
+        ]));
+
+        let code = program(vec!["goto 1"]);
+        let combined = append_modules(code, &[(String::from("lib.bas"), program(vec!["macro nothing()", "endmacro"]))]).unwrap();
+        assert_eq!(expand_macros(combined).unwrap(), program(vec!["goto 1"]));
+
+        let result = append_modules(program(vec!["quit"]), &[(String::from("lib.bas"), program(vec!["output $x"]))]);
+        assert_eq!(result.err().unwrap().to_string(), "lib.bas: Line 0: Not part of a macro! Modules may only define macros.");
+    }
+}
@@ -1,40 +1,454 @@
-extern crate lazy_static;
+mod cli;
 
-mod code_loader;
-mod errors;
-mod states;
-mod prog_data;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::process;
+use std::sync::Arc;
+use cos341basic::{aliases, classic, code_loader, export, grammar, integrity, minify, renumber, runner, session, testcases, trace};
+#[cfg(feature = "net")]
+use cos341basic::net;
+use cos341basic::code_loader::Program;
+use cos341basic::debugger::Debugger;
+use cos341basic::events::EventWriter;
+use cos341basic::manifest::Manifest;
+use cos341basic::console::{Console, LimitedConsole, ScriptedConsole, StdConsole, TranscriptConsole};
+use cos341basic::prog_data::ProgramData;
+use cos341basic::host::OsBackend;
+use cos341basic::replay::{Recording, Replay};
+use cos341basic::stats::StatsCollector;
+use cos341basic::trace::TraceWriter;
+use crate::cli::{Command, Options};
 
-use std::collections::{HashMap, LinkedList};
-use std::process::exit;
-use crate::prog_data::ProgramData;
-use crate::states::{States, get_state};
+/// Exits the interpreter with `code`. WASI exit statuses are unsigned, so negative codes are
+/// wrapped the way a Unix shell would report them (-1 becomes 255).
+fn exit(code: i32) -> ! {
+    #[cfg(target_os = "wasi")]
+    let code = code as u8 as i32;
+    process::exit(code)
+}
+
+/// Builds the console that a program will use for input and output, based on the options
+/// given on the command line. Input is recorded to `recording` or replayed from `replay`.
+fn build_console(options: &Options, recording: Option<&Recording>, replay: Option<&Replay>) -> Result<Box<dyn Console>, String> {
+    let mut std_console = StdConsole::new();
+    if let Some(input_file) = &options.input_file {
+        std_console.set_input_file(input_file)?;
+    }
+    if let Some(output_file) = &options.output_file {
+        std_console.set_output_file(output_file)?;
+    }
+
+    let mut console: Box<dyn Console> = Box::new(std_console);
+    if options.inputs.is_some() || options.input_default.is_some() {
+        console = Box::new(ScriptedConsole::new(
+            options.inputs.clone().unwrap_or_default(),
+            options.input_default.clone(),
+            console
+        ));
+    }
+    if let Some(recording) = recording {
+        console = recording.console(console);
+    }
+    if let Some(replay) = replay {
+        console = replay.console(console);
+    }
+    if let Some(transcript_file) = &options.transcript_file {
+        console = Box::new(TranscriptConsole::with_file(transcript_file, console)?);
+    }
+    if options.max_output_lines.is_some() || options.max_output_bytes.is_some() {
+        console = Box::new(LimitedConsole::new(options.max_output_lines, options.max_output_bytes, console));
+    }
+    Ok(console)
+}
+
+/// Creates the program data for a freshly loaded program, wired up according to the options
+/// given on the command line.
+fn new_program_data(program: &Arc<Program>, options: &Options) -> Result<ProgramData, String> {
+    // A dialect given on the command line takes precedence over the program's own header
+    let dialect = match options.dialect {
+        Some(dialect) => dialect,
+        None => code_loader::detect_dialect(&program.code)?.unwrap_or_default(),
+    };
+
+    let recording = options.record_file.as_ref()
+        .map(|record_file| Recording::create(record_file, &program.code, options.seed, &options.program_args))
+        .transpose()?;
+    let replay = options.replay_file.as_ref()
+        .map(|replay_file| Replay::open(replay_file, &program.code))
+        .transpose()?;
+
+    let mut prog_data = program.shared_program_data();
+    prog_data.set_console(build_console(options, recording.as_ref(), replay.as_ref())?);
+    prog_data.set_dialect(dialect);
+    prog_data.set_exec_allowed(options.allow_exec);
+    prog_data.set_fs_allowed(options.allow_fs);
+    prog_data.set_env_allowed(options.allow_env);
+    // A replayed run gets the arguments, command output, environment and files of the run it
+    // was recorded from
+    let args = replay.as_ref().and_then(Replay::args).unwrap_or(&options.program_args);
+    prog_data.set_args(args.to_vec());
+    match (&recording, &replay) {
+        (_, Some(replay)) => prog_data.set_host_backend(replay.host_backend()),
+        (Some(recording), None) => prog_data.set_host_backend(recording.host_backend(Box::new(OsBackend::new()))),
+        (None, None) => {},
+    }
+    if let Some(project_dir) = &options.project_dir {
+        prog_data.set_base_dir(project_dir.clone());
+    }
+    // A replayed run gets the random numbers of the run it was recorded from
+    let seed = match (&recording, &replay) {
+        (_, Some(replay)) => replay.seed(),
+        (Some(recording), None) => Some(recording.seed()),
+        (None, None) => options.seed,
+    };
+    if let Some(seed) = seed {
+        prog_data.seed_rng(seed);
+    }
+
+    #[cfg(feature = "net")]
+    if options.allow_net {
+        let backend: Box<dyn net::NetBackend> = match (&recording, &replay) {
+            (_, Some(replay)) => replay.net_backend(),
+            (Some(recording), None) => recording.net_backend(Box::new(net::TcpBackend::new())),
+            (None, None) => Box::new(net::TcpBackend::new()),
+        };
+        prog_data.set_net_backend(backend);
+    }
+
+    Ok(prog_data)
+}
+
+/// Applies the manifest of a project to the options. The program only gets the capabilities the
+/// manifest asks for, and only if they were also allowed on the command line. The limits of the
+/// manifest apply unless the command line gives its own.
+fn apply_manifest(manifest: &Manifest, options: &mut Options) -> Result<(), String> {
+    let capabilities = [
+        (manifest.fs, options.allow_fs, "file access", "--allow-fs"),
+        (manifest.net, options.allow_net, "network access", "--allow-net"),
+        (manifest.exec, options.allow_exec, "to run commands", "--allow-exec"),
+        (manifest.env, options.allow_env, "environment access", "--allow-env"),
+    ];
+    for (needed, allowed, capability, flag) in capabilities {
+        if needed && !allowed {
+            return Err(format!("The project needs {}! Run with {} to allow it.", capability, flag));
+        }
+    }
+    options.allow_fs = manifest.fs;
+    options.allow_net = manifest.net;
+    options.allow_exec = manifest.exec;
+    options.allow_env = manifest.env;
+    options.max_output_lines = options.max_output_lines.or(manifest.max_output_lines);
+    options.max_output_bytes = options.max_output_bytes.or(manifest.max_output_bytes);
+    options.project_dir = Some(manifest.dir.clone());
+    Ok(())
+}
+
+/// Runs a program, telling the observers asked for on the command line about every instruction.
+/// With --stats or --stats-json, the stats of the program are reported once it has ended.
+fn run_observed(mut prog_data: ProgramData, options: &Options) -> Result<runner::Outcome, runner::RuntimeError> {
+    let mut trace = match &options.trace_file {
+        Some(trace_file) => match File::create(trace_file) {
+            Ok(file) => Some(TraceWriter::new(BufWriter::new(file))),
+            Err(err) => {
+                eprintln!("{}: {}", trace_file, err);
+                exit(-1);
+            }
+        },
+        None => None,
+    };
+    let mut events = match &options.events_file {
+        Some(events_file) => match File::create(events_file) {
+            Ok(file) => Some(EventWriter::new(BufWriter::new(file))),
+            Err(err) => {
+                eprintln!("{}: {}", events_file, err);
+                exit(-1);
+            }
+        },
+        None => None,
+    };
+    if let Some(events) = &events {
+        prog_data.wrap_console(|console| Box::new(events.console(console)));
+    }
+    let mut stats = (options.show_stats || options.stats_file.is_some()).then(StatsCollector::new);
+
+    let mut observers: Vec<&mut dyn runner::Observer> = Vec::new();
+    if let Some(trace) = &mut trace {
+        observers.push(trace);
+    }
+    if let Some(events) = &mut events {
+        observers.push(events);
+    }
+    if let Some(stats) = &mut stats {
+        observers.push(stats);
+    }
+    let result = runner::run_observed(prog_data, &mut observers);
+
+    if let Some(stats) = stats.map(StatsCollector::finish) {
+        if options.show_stats {
+            eprintln!("{}", stats);
+        }
+        if let Some(stats_file) = &options.stats_file {
+            if let Err(err) = fs::write(stats_file, stats.to_json() + "\n") {
+                eprintln!("{}: {}", stats_file, err);
+            }
+        }
+    }
+    result
+}
+
+/// Executes a program until it quits or encounters an error. With --debug, the program is run
+/// under the debugger, which reads its commands from stdin and writes to stderr. With
+/// --debug-on-error, the debugger is only opened if the program encounters an error. With
+/// --dump-vars, the final variables and stack are written to stderr if the program finishes.
+fn run_program(prog_data: ProgramData, options: &Options) -> ! {
+    let debugger = || Debugger::new(Box::new(BufReader::new(io::stdin())), Box::new(io::stderr()));
+    let result = if options.debug {
+        debugger().run(prog_data)
+    } else if options.debug_on_error {
+        debugger().run_until_error(prog_data)
+    } else if options.trace_file.is_some() || options.events_file.is_some() || options.show_stats
+            || options.stats_file.is_some() {
+        run_observed(prog_data, options)
+    } else {
+        runner::run(prog_data)
+    };
+    match result {
+        Ok(outcome) => {
+            if options.dump_vars {
+                eprint!("{}", outcome.data.variable_table());
+            }
+            exit(outcome.exit_code)
+        },
+        Err(error) => {
+            eprintln!("{}", error);
+            exit(-1);
+        }
+    }
+}
+
+/// Runs every testcase block in a program and reports the result of each one.
+fn run_tests(program: &Arc<Program>, options: &Options) -> ! {
+    // Every testcase runs the same code, so it is shared rather than copied for each one
+    let results = testcases::run_testcases(|| new_program_data(program, options));
+    match results {
+        Ok(results) => {
+            let failed = results.iter().filter(|result| result.error.is_some()).count();
+            for result in &results {
+                match &result.error {
+                    None => println!("PASS {}", result.name),
+                    Some(error) => println!("FAIL {}: {}", result.name, error),
+                }
+            }
+            println!("{} passed, {} failed", results.len() - failed, failed);
+            exit(if failed == 0 { 0 } else { 1 });
+        },
+        Err(error_msg) => {
+            eprintln!("{}", error_msg);
+            exit(-1);
+        }
+    }
+}
+
+/// Runs a program against a scripted session, and reports whether it followed the session.
+fn run_expect(program: &Arc<Program>, options: &Options, session_file: &str) -> ! {
+    let result = session::load_session(session_file)
+        .and_then(|steps| Ok((steps, new_program_data(program, options)?)))
+        .map(|(steps, prog_data)| session::run_session(prog_data, steps));
+    match result {
+        Ok(Ok(steps)) => {
+            println!("PASS {} ({} steps)", session_file, steps);
+            exit(0);
+        },
+        Ok(Err(diff)) => {
+            println!("FAIL {}\n{}", session_file, diff);
+            exit(1);
+        },
+        Err(error_msg) => {
+            eprintln!("{}", error_msg);
+            exit(-1);
+        }
+    }
+}
+
+/// Compares two execution traces, and reports the first point at which they diverge.
+fn run_trace_diff(first: &str, second: &str) -> ! {
+    let traces = trace::load_trace(first).and_then(|a| Ok((a, trace::load_trace(second)?)));
+    match traces.map(|(a, b)| trace::diff_traces((first, second), &a, &b)) {
+        Ok(Ok(steps)) => {
+            println!("Traces are identical ({} steps)", steps);
+            exit(0);
+        },
+        Ok(Err(report)) => {
+            println!("{}", report);
+            exit(1);
+        },
+        Err(error_msg) => {
+            eprintln!("{}", error_msg);
+            exit(-1);
+        }
+    }
+}
+
+/// Converts a classic BASIC program to COS341Basic, writing the converted program to stdout.
+fn run_convert(file_path: &str) -> ! {
+    let source = match fs::read_to_string(file_path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("{}: {}", file_path, err);
+            exit(-1);
+        }
+    };
+    match classic::convert_classic(&source) {
+        Ok(converted) => {
+            print!("{}", converted);
+            exit(0);
+        },
+        Err(errors) => {
+            eprintln!("{}\n{} could not be converted", errors, file_path);
+            exit(1);
+        }
+    }
+}
+
+/// Renumbers the lines of a program, writing the renumbered program to stdout and the number
+/// each line was given to stderr.
+fn run_renumber(file_path: &str, stride: usize) -> ! {
+    let source = match fs::read_to_string(file_path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("{}: {}", file_path, err);
+            exit(-1);
+        }
+    };
+    match renumber::renumber(&source, stride) {
+        Ok(renumbered) => {
+            print!("{}", renumbered.source);
+            eprintln!("{}", renumbered.mapping_table());
+            exit(0);
+        },
+        Err(errors) => {
+            eprintln!("{}\n{} could not be renumbered", errors, file_path);
+            exit(1);
+        }
+    }
+}
+
+/// Exports a loaded program as Python, writing the exported program to stdout.
+fn run_export(program: &Program, options: &Options) -> ! {
+    let dialect = match options.dialect {
+        Some(dialect) => Ok(dialect),
+        None => code_loader::detect_dialect(&program.code).map(Option::unwrap_or_default),
+    };
+    let aliases = match &options.alias_file {
+        Some(alias_file) => aliases::load_aliases(alias_file),
+        None => Ok(aliases::default_aliases()),
+    };
+    let exported = dialect.and_then(|dialect| aliases.and_then(|aliases| export::to_python(&program.code, dialect, &aliases)));
+    match exported {
+        Ok(python) => {
+            print!("{}", python);
+            exit(0);
+        },
+        Err(error_msg) => {
+            eprintln!("{}\nAborting...", error_msg);
+            exit(1);
+        }
+    }
+}
 
 fn main() {
-    let program_file = std::env::args().nth(1);
-    match program_file {
+    let mut options = match cli::parse_args(std::env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(error_msg) => {
+            eprintln!("{}", error_msg);
+            exit(-1);
+        }
+    };
+
+    #[cfg(not(feature = "net"))]
+    if options.allow_net {
+        eprintln!("This interpreter was built without networking support! Aborting...");
+        exit(-1);
+    }
+
+    if let Command::Grammar(format) = options.command {
+        print!("{}", grammar::generate(format));
+        exit(0);
+    }
+    if let Command::TraceDiff(first, second) = &options.command {
+        run_trace_diff(first, second);
+    }
+
+    match options.program_file.clone() {
         Some(data) => {
-            let program = code_loader::load_code_from_file(data);
+            if options.command == Command::Convert {
+                run_convert(&data);
+            }
+            if options.command == Command::Renumber {
+                run_renumber(&data, options.stride.unwrap_or(1));
+            }
+
+            // Tampered programs are rejected before any of their code is loaded
+            if let Some(key_file) = &options.verify_key {
+                let signature_file = options.signature_file.clone().unwrap_or(format!("{}.sig", data));
+                if let Err(error_msg) = integrity::verify_signature(&data, key_file, &signature_file) {
+                    eprintln!("{}\nAborting...", error_msg);
+                    exit(-1);
+                }
+            }
+            if let Some(checksum_file) = &options.checksum_file {
+                if let Err(error_msg) = integrity::verify_file(&data, checksum_file) {
+                    eprintln!("{}\nAborting...", error_msg);
+                    exit(-1);
+                }
+            }
+
+            // A project directory is loaded through its manifest, which also decides what the
+            // program may use
+            let program = match Manifest::is_project(&data) {
+                true => Manifest::open(&data).and_then(|manifest| {
+                    apply_manifest(&manifest, &mut options)?;
+                    manifest.load_program().map_err(|err| err.to_string())
+                }),
+                false => code_loader::load_code_from_file(data).map_err(|err| err.to_string()),
+            };
+            // Programs written for the register model can be given their registers on the command line
+            let program = program.and_then(|mut program| {
+                if let Some(registers) = options.registers {
+                    program.use_registers(vec![String::from("0"); registers]).map_err(|err| err.to_string())?;
+                }
+                // Aliases are resolved once, as the program is loaded
+                if let Some(alias_file) = &options.alias_file {
+                    program.set_aliases(&aliases::load_aliases(alias_file)?);
+                }
+                Ok(program)
+            });
             match program {
-                Ok(code_data) => {
-
-                    let mut prog_data = ProgramData::new(
-                        code_data,
-                        HashMap::new(),
-                        LinkedList::new(),
-                        0
-                    );
-
-                    let mut state = get_state(States::ExecuteState).execute(prog_data);
-                    loop {
-                        if state.as_ref().is_err() {
-                            eprintln!("{}", state.err().unwrap());
+                Ok(program) => {
+                    let program = Arc::new(program);
+                    if options.show_warnings {
+                        for warning in code_loader::find_warnings(&program.code) {
+                            eprintln!("{}", warning);
+                        }
+                    }
+
+                    match &options.command {
+                        Command::Test => run_tests(&program, &options),
+                        Command::Expect(session_file) => run_expect(&program, &options, session_file),
+                        Command::Export => run_export(&program, &options),
+                        Command::Minify => {
+                            print!("{}", minify::minify(&program));
+                            exit(0);
+                        },
+                        _ => {}
+                    }
+
+                    match new_program_data(&program, &options) {
+                        Ok(prog_data) => run_program(prog_data, &options),
+                        Err(error_msg) => {
+                            eprintln!("{}", error_msg);
                             exit(-1);
                         }
-                        let result = state.ok().unwrap();
-                        let state_function = result.1;
-                        prog_data = result.0;
-                        state = state_function.execute(prog_data);
                     }
                 },
                 Err(error_msg) => {
@@ -49,4 +463,3 @@ fn main() {
         }
     };
 }
-
@@ -0,0 +1,262 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::code_loader::{load_code_importing, read_program_file, Program};
+use crate::errors::LoadError;
+use crate::imports::module_file;
+
+/// Name of the manifest file at the root of a project directory.
+pub const MANIFEST_FILE: &str = "program.toml";
+
+/// A project: a directory holding a program made of several files, described by its manifest.
+/// Manifests are written in a small subset of TOML:
+/// ```toml
+/// entry = "main.bas"
+/// modules = ["lib/strings.bas"]
+/// data = ["words.txt"]
+///
+/// [capabilities]
+/// fs = true
+///
+/// [limits]
+/// max_output_lines = 100
+/// ```
+/// Paths are relative to the project directory.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Manifest {
+    /// The directory of the project
+    pub dir: PathBuf,
+    /// The program file that is run
+    pub entry: String,
+    /// Files holding macros the program calls
+    pub modules: Vec<String>,
+    /// Files the program reads, which must exist before it is run
+    pub data: Vec<String>,
+    /// Whether the program needs the filesystem, the network, to run commands or the environment
+    pub fs: bool,
+    pub net: bool,
+    pub exec: bool,
+    pub env: bool,
+    /// Limits on the output of the program
+    pub max_output_lines: Option<usize>,
+    pub max_output_bytes: Option<usize>,
+}
+
+/// A value in a manifest.
+#[derive(Debug, PartialEq, Eq)]
+enum Value {
+    String(String),
+    Integer(u64),
+    Bool(bool),
+    Array(Vec<String>),
+}
+
+/// Reads a quoted string from the start of `text`, returning it and the text after it.
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let mut value = String::new();
+    let mut chars = text.strip_prefix('"').ok_or("expected a string")?.char_indices();
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &text[pos + 2..])),
+            '\\' => match chars.next() {
+                Some((_, '"')) => value.push('"'),
+                Some((_, '\\')) => value.push('\\'),
+                _ => return Err(String::from("unsupported escape in string")),
+            },
+            c => value.push(c),
+        }
+    }
+    Err(String::from("unterminated string"))
+}
+
+/// Reads the value of a 'key = value' line.
+fn parse_value(text: &str) -> Result<Value, String> {
+    let text = text.trim();
+    match text {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {},
+    }
+    if text.starts_with('"') {
+        let (value, rest) = parse_string(text)?;
+        return match rest.trim() {
+            "" => Ok(Value::String(value)),
+            _ => Err(String::from("unexpected text after string")),
+        };
+    }
+    if let Some(mut rest) = text.strip_prefix('[') {
+        let mut values = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return match after.trim() {
+                    "" => Ok(Value::Array(values)),
+                    _ => Err(String::from("unexpected text after list")),
+                };
+            }
+            let (value, after) = parse_string(rest)?;
+            values.push(value);
+            rest = after.trim_start();
+            rest = rest.strip_prefix(',').unwrap_or(rest);
+        }
+    }
+    text.parse::<u64>().map(Value::Integer).map_err(|_| format!("invalid value {}", text))
+}
+
+/// Removes a comment from the end of a line, leaving '#' inside strings alone.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (pos, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..pos],
+            _ => {},
+        }
+    }
+    line
+}
+
+impl Manifest {
+    /// Reads a manifest.
+    ///
+    /// # Arguments
+    /// * `text` - The contents of the manifest
+    /// * `dir` - The directory of the project
+    ///
+    /// # Returns
+    /// * `Ok(Manifest)` - The project the manifest describes
+    /// * `Err(String)` - A message detailing the first line of the manifest that is not valid
+    pub fn parse(text: &str, dir: &Path) -> Result<Self, String> {
+        let mut manifest = Manifest { dir: dir.to_path_buf(), ..Manifest::default() };
+        let mut section = String::new();
+        let mut entry = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: &str| format!("line {}: {}", number + 1, message);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                if !["capabilities", "limits"].contains(&name.trim()) {
+                    return Err(error(&format!("unknown section [{}]", name.trim())));
+                }
+                section = name.trim().to_string();
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(|| error("expected 'key = value'"))?;
+            let key = key.trim();
+            let value = parse_value(value).map_err(|message| error(&message))?;
+            let wrong_type = |expected: &str| error(&format!("{} must be {}", key, expected));
+            match (section.as_str(), key, value) {
+                ("", "entry", Value::String(path)) => entry = Some(path),
+                ("", "entry", _) => return Err(wrong_type("a string")),
+                ("", "modules", Value::Array(paths)) => manifest.modules = paths,
+                ("", "data", Value::Array(paths)) => manifest.data = paths,
+                ("", "modules" | "data", _) => return Err(wrong_type("a list of strings")),
+                ("capabilities", "fs", Value::Bool(needed)) => manifest.fs = needed,
+                ("capabilities", "net", Value::Bool(needed)) => manifest.net = needed,
+                ("capabilities", "exec", Value::Bool(needed)) => manifest.exec = needed,
+                ("capabilities", "env", Value::Bool(needed)) => manifest.env = needed,
+                ("capabilities", "fs" | "net" | "exec" | "env", _) => return Err(wrong_type("true or false")),
+                ("limits", "max_output_lines", Value::Integer(limit)) => manifest.max_output_lines = Some(limit as usize),
+                ("limits", "max_output_bytes", Value::Integer(limit)) => manifest.max_output_bytes = Some(limit as usize),
+                ("limits", "max_output_lines" | "max_output_bytes", _) => return Err(wrong_type("a whole number")),
+                ("", key, _) => return Err(error(&format!("unknown key {}", key))),
+                (section, key, _) => return Err(error(&format!("unknown key {} in [{}]", key, section))),
+            }
+        }
+
+        manifest.entry = entry.ok_or("no entry file given")?;
+        Ok(manifest)
+    }
+
+    /// Opens the manifest of the project in `dir`.
+    pub fn open(dir: &str) -> Result<Self, String> {
+        let path = Path::new(dir).join(MANIFEST_FILE);
+        let text = fs::read_to_string(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Manifest::parse(&text, Path::new(dir)).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// Checks whether `path` is the directory of a project.
+    pub fn is_project(path: &str) -> bool {
+        Path::new(path).join(MANIFEST_FILE).is_file()
+    }
+
+    /// Loads the entry program of the project, along with the macros of its modules and the modules
+    /// it imports. Every data file must exist.
+    pub fn load_program(&self) -> Result<Program, LoadError> {
+        let read = |path: &str| read_program_file(&self.dir.join(path).to_string_lossy());
+        for path in &self.data {
+            if !self.dir.join(path).is_file() {
+                return Err(LoadError::in_file(self.dir.join(MANIFEST_FILE).display().to_string(), LoadError::MissingDataFile(path.clone())));
+            }
+        }
+        let modules = self.modules.iter()
+            .map(|path| read(path).map(|contents| (path.clone(), contents)))
+            .collect::<Result<Vec<(String, String)>, LoadError>>()?;
+        // Imported modules are found next to the entry program
+        let import = |module: &str| read(&Path::new(&self.entry).with_file_name(module_file(module)).to_string_lossy());
+        load_code_importing(&read(&self.entry)?, &modules, &import)
+            .map_err(|err| LoadError::in_file(&self.entry, err))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use super::Manifest;
+
+    #[test]
+    fn parse_manifest() {
+        let manifest = Manifest::parse("# A project\nentry = \"main.bas\"\nmodules = [\"lib/a.bas\", \"lib/#b.bas\"]\n\
+                                        data = []\n\n[capabilities]\nfs = true  # reads words.txt\n\n\
+                                        [limits]\nmax_output_lines = 10\n", Path::new("project")).unwrap();
+        assert_eq!(manifest, Manifest {
+            dir: Path::new("project").to_path_buf(),
+            entry: String::from("main.bas"),
+            modules: vec![String::from("lib/a.bas"), String::from("lib/#b.bas")],
+            data: Vec::new(),
+            fs: true,
+            max_output_lines: Some(10),
+            ..Manifest::default()
+        });
+    }
+
+    #[test]
+    fn reject_invalid_manifests() {
+        let parse = |text: &str| Manifest::parse(text, Path::new(".")).err().unwrap();
+        assert_eq!(parse("modules = []\n"), "no entry file given");
+        assert_eq!(parse("entry = main.bas\n"), "line 1: invalid value main.bas");
+        assert_eq!(parse("entry = \"main.bas\"\nmain = \"x\"\n"), "line 2: unknown key main");
+        assert_eq!(parse("entry = \"main.bas\"\n[limits]\nfs = true\n"), "line 3: unknown key fs in [limits]");
+        assert_eq!(parse("[network]\n"), "line 1: unknown section [network]");
+        assert_eq!(parse("[capabilities]\nnet = 1\n"), "line 2: net must be true or false");
+        assert_eq!(parse("data = [\"a.txt\"\n"), "line 1: expected a string");
+    }
+
+    #[test]
+    fn load_project() {
+        let manifest = Manifest::open("testfiles/project").unwrap();
+        assert_eq!(manifest.entry, "main.bas");
+        let program = manifest.load_program().unwrap();
+        assert_eq!(program.code, vec![
+            String::from("let $a = \"hello\""),
+            String::from("let $b = \"world\""),
+            String::from("let $tmp = $a"),
+            String::from("let $a = $b"),
+            String::from("let $b = $tmp"),
+            String::from("output $a"),
+            String::from("quit"),
+        ]);
+
+        let manifest = Manifest { data: vec![String::from("missing.txt")], ..manifest };
+        assert_eq!(manifest.load_program().err().unwrap().to_string(),
+                   format!("{}: data file missing.txt does not exist!", Path::new("testfiles/project/program.toml").display()));
+        assert!(!Manifest::is_project("testfiles"));
+        assert!(Manifest::is_project("testfiles/project"));
+    }
+}
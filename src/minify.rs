@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use crate::code_loader::Program;
+use crate::states::{INTERPRETER_VARIABLES, RESERVED_WORDS};
+
+/// Gives the variables of a program short names, in the order they first appear.
+struct Renamer {
+    names: HashMap<String, String>,
+    next: usize,
+    // Registers keep their names, as the register segment gives them their values
+    registers: usize,
+}
+
+impl Renamer {
+    /// Finds the short name of a variable, giving it the next unused one if it does not have one.
+    fn rename(&mut self, name: &str) -> String {
+        // The variables the interpreter sets keep their names, as renaming them would stop the
+        // program from seeing the values the interpreter gives them
+        if INTERPRETER_VARIABLES.contains(&name) || self.is_register(name) {
+            return name.to_string();
+        }
+        if let Some(short_name) = self.names.get(name) {
+            return short_name.clone();
+        }
+        let short_name = loop {
+            let candidate = short_name(self.next);
+            self.next += 1;
+            if !RESERVED_WORDS.contains(&candidate.as_str()) && !INTERPRETER_VARIABLES.contains(&candidate.as_str()) {
+                break candidate;
+            }
+        };
+        self.names.insert(name.to_string(), short_name.clone());
+        short_name
+    }
+
+    fn is_register(&self, name: &str) -> bool {
+        name.strip_prefix('M')
+            .filter(|index| !index.starts_with('0') || *index == "0")
+            .and_then(|index| index.parse::<usize>().ok())
+            .is_some_and(|index| index < self.registers)
+    }
+}
+
+/// Finds the short name with the given index: 'a' to 'z', then 'aa', 'ab' and so on.
+fn short_name(index: usize) -> String {
+    let mut name = Vec::new();
+    let mut index = index + 1;
+    while index > 0 {
+        index -= 1;
+        name.push(b'a' + (index % 26) as u8);
+        index /= 26;
+    }
+    name.iter().rev().map(|c| *c as char).collect()
+}
+
+/// Minifies a single line of code, renaming its variables and separating its tokens by a single
+/// space. Strings are copied as they are.
+fn minify_line(line: &str, renamer: &mut Renamer) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut minified = String::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        if c.is_whitespace() {
+            let end = (pos..chars.len()).find(|i| !chars[*i].is_whitespace()).unwrap_or(chars.len());
+            if !minified.is_empty() && end < chars.len() {
+                minified.push(' ');
+            }
+            pos = end;
+        } else if c == '"' {
+            let end = (pos + 1..chars.len()).find(|i| chars[*i] == '"').map(|end| end + 1).unwrap_or(chars.len());
+            minified.extend(&chars[pos..end]);
+            pos = end;
+        } else if c == '$' {
+            let end = (pos + 1..chars.len()).find(|i| !chars[*i].is_ascii_alphanumeric() && chars[*i] != '_')
+                .unwrap_or(chars.len());
+            let name: String = chars[pos + 1..end].iter().collect();
+            minified.push('$');
+            if !name.is_empty() {
+                minified.push_str(&renamer.rename(&name));
+            }
+            pos = end;
+        } else {
+            minified.push(c);
+            pos += 1;
+        }
+    }
+    minified
+}
+
+/// Minifies a loaded program, so that it can be handed out without giving away readable source.
+/// Every variable is renamed to a short name (except those the interpreter sets itself, such as
+/// `$exitcode`), and the tokens of each line are separated by a single space. Continued lines are
+/// joined and macros are expanded, as they are when the program is loaded, so the minified
+/// program runs exactly like the original. A program with registers keeps its register segment,
+/// and its registers keep their names.
+///
+/// # Arguments
+/// * `program` - The program to minify
+///
+/// # Returns
+/// * `String` - The minified program file, including its stack segment if it has one
+pub fn minify(program: &Program) -> String {
+    let mut renamer = Renamer { names: HashMap::new(), next: 0, registers: program.registers.len() };
+    let mut minified = String::new();
+    if !program.registers.is_empty() {
+        minified.push_str("BEGIN_REGISTER_SEGMENT\n");
+        for (index, value) in program.registers.iter().enumerate() {
+            minified.push_str(&format!("{} {}\n", index, value));
+        }
+        minified.push_str("END_REGISTER_SEGMENT\nBEGIN_CODE_SEGMENT\n");
+    } else if !program.stack.is_empty() {
+        minified.push_str("stack\n");
+        for (index, value) in program.stack.iter().enumerate() {
+            minified.push_str(&format!("{} {}\n", index, value));
+        }
+        minified.push_str("code\n");
+    }
+    for (index, line) in program.code.iter().enumerate() {
+        minified.push_str(&format!("{} {}\n", index, minify_line(line, &mut renamer)));
+    }
+    if !program.registers.is_empty() {
+        minified.push_str("END_CODE_SEGMENT\n");
+    }
+    minified
+}
+
+#[cfg(test)]
+mod test {
+    use crate::code_loader::{load_code_from_file, load_code_from_string};
+    use super::{minify, short_name};
+
+    #[test]
+    fn generate_short_names() {
+        assert_eq!(short_name(0), "a");
+        assert_eq!(short_name(25), "z");
+        assert_eq!(short_name(26), "aa");
+        assert_eq!(short_name(27), "ab");
+        assert_eq!(short_name(26 + 26 * 26), "aaa");
+    }
+
+    #[test]
+    fn minify_program() {
+        let program = load_code_from_string("0 let $total   =\t\"hello  world\"\n\
+                                             1 let $count = exec \"ls\"\n\
+                                             2 if $count >= $exitcode \\\n   output $total\n\
+                                             3 output $count\n").unwrap();
+        assert_eq!(minify(&program), "0 let $a = \"hello  world\"\n\
+                                      1 let $b = exec \"ls\"\n\
+                                      2 if $b >= $exitcode output $a\n\
+                                      3 output $b\n");
+    }
+
+    #[test]
+    fn minify_program_with_stack_segment() {
+        let program = load_code_from_string("stack\n0 5\n1 hello there\ncode\n0 let $value = pop\n1 output $value\n").unwrap();
+        assert_eq!(minify(&program), "stack\n0 5\n1 hello there\ncode\n0 let $a = pop\n1 output $a\n");
+    }
+
+    #[test]
+    fn minify_program_with_registers() {
+        let program = load_code_from_string("BEGIN_REGISTER_SEGMENT\n0 5\n1 0\nEND_REGISTER_SEGMENT\nBEGIN_CODE_SEGMENT\n\
+                                             0 let $total = M0 + M1\n1 output $total\nEND_CODE_SEGMENT\n").unwrap();
+        let minified = minify(&program);
+        assert_eq!(minified, "BEGIN_REGISTER_SEGMENT\n0 5\n1 0\nEND_REGISTER_SEGMENT\nBEGIN_CODE_SEGMENT\n\
+                              0 let $a = $M0 + $M1\n1 output $a\nEND_CODE_SEGMENT\n");
+        assert_eq!(load_code_from_string(&minified).unwrap().registers, program.registers);
+    }
+
+    #[test]
+    fn skip_reserved_names() {
+        let code: String = (0..260).map(|index| format!("{} let $v{} = 1\n", index, index)).collect();
+        let minified = minify(&load_code_from_string(&code).unwrap());
+        assert!(minified.contains("let $ie = 1"));
+        assert!(!minified.contains("let $if = 1"));
+    }
+
+    #[test]
+    fn minify_imports() {
+        let program = load_code_from_file(String::from("testfiles/test_import.txt")).unwrap();
+        let minified = minify(&program);
+        assert!(minified.contains("4 let $d = call m.gcd $a $b\n"));
+        assert!(minified.contains("7 func m.gcd $e.a $e.b\n"));
+        // The module has already been added, so loading the minified program does not import it again
+        assert_eq!(load_code_from_string(&minified).unwrap().code.len(), program.code.len());
+    }
+}
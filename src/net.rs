@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Largest response body (in bytes) that 'httpget' will accept
+pub const HTTP_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// Time allowed for each connect, read and write performed by 'httpget'
+pub const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Provides the socket operations used by the networking instructions. Sockets are referred
+/// to by numeric handles so that they can be stored in program variables.
+pub trait NetBackend: Send + Sync {
+    /// Opens a connection to `address` (in the form 'host:port') and returns its handle.
+    fn connect(&mut self, address: &str) -> Result<usize, String>;
+
+    /// Sends a line of data over the socket referenced by `handle`.
+    fn send(&mut self, handle: usize, data: &str) -> Result<(), String>;
+
+    /// Receives a line of data from the socket referenced by `handle`. An empty string is
+    /// returned once the peer has closed the connection.
+    fn recv(&mut self, handle: usize) -> Result<String, String>;
+
+    /// Closes the socket referenced by `handle`.
+    fn close(&mut self, handle: usize) -> Result<(), String>;
+
+    /// Fetches `url` with an HTTP GET request and returns the response body.
+    fn http_get(&mut self, url: &str) -> Result<String, String>;
+}
+
+/// Splits an 'http://host[:port]/path' url into its address and path parts.
+fn split_url(url: &str) -> Result<(String, String, String), String> {
+    let rest = url.strip_prefix("http://")
+        .ok_or(format!("Unsupported url: {} (only http:// urls can be fetched)", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/"),
+    };
+    if authority.is_empty() {
+        return Err(format!("Invalid url: {}", url));
+    }
+    let host = authority.split(':').next().unwrap().to_string();
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    Ok((host, address, path.to_string()))
+}
+
+/// Network backend that talks to real TCP sockets.
+pub struct TcpBackend {
+    sockets: HashMap<usize, BufReader<TcpStream>>,
+    next_handle: usize,
+}
+
+impl Default for TcpBackend {
+    fn default() -> Self {
+        TcpBackend::new()
+    }
+}
+
+impl TcpBackend {
+    pub fn new() -> Self {
+        TcpBackend {
+            sockets: HashMap::new(),
+            next_handle: 0,
+        }
+    }
+
+    fn socket(&mut self, handle: usize) -> Result<&mut BufReader<TcpStream>, String> {
+        self.sockets.get_mut(&handle).ok_or(format!("Socket {} is not open!", handle))
+    }
+}
+
+impl NetBackend for TcpBackend {
+    fn connect(&mut self, address: &str) -> Result<usize, String> {
+        let stream = TcpStream::connect(address)
+            .map_err(|err| format!("Could not connect to {}: {}", address, err))?;
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.sockets.insert(handle, BufReader::new(stream));
+        Ok(handle)
+    }
+
+    fn send(&mut self, handle: usize, data: &str) -> Result<(), String> {
+        let stream = self.socket(handle)?.get_mut();
+        writeln!(stream, "{}", data).map_err(|err| format!("Could not send data: {}", err))
+    }
+
+    fn recv(&mut self, handle: usize) -> Result<String, String> {
+        let mut line = String::new();
+        self.socket(handle)?
+            .read_line(&mut line)
+            .map_err(|err| format!("Could not receive data: {}", err))?;
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    fn close(&mut self, handle: usize) -> Result<(), String> {
+        match self.sockets.remove(&handle) {
+            Some(_) => Ok(()),
+            None => Err(format!("Socket {} is not open!", handle)),
+        }
+    }
+
+    fn http_get(&mut self, url: &str) -> Result<String, String> {
+        let (host, address, path) = split_url(url)?;
+        let fetch_error = |err: std::io::Error| format!("Could not fetch {}: {}", url, err);
+
+        let socket_address = address.to_socket_addrs().map_err(fetch_error)?
+            .next()
+            .ok_or(format!("Could not resolve {}", address))?;
+        let mut stream = TcpStream::connect_timeout(&socket_address, HTTP_TIMEOUT).map_err(fetch_error)?;
+        stream.set_read_timeout(Some(HTTP_TIMEOUT)).map_err(fetch_error)?;
+        stream.set_write_timeout(Some(HTTP_TIMEOUT)).map_err(fetch_error)?;
+        write!(stream, "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host)
+            .map_err(fetch_error)?;
+
+        let mut reader = BufReader::new(stream);
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line).map_err(fetch_error)?;
+        let status = status_line.split_whitespace().nth(1).unwrap_or("").to_string();
+        if !status.starts_with('2') {
+            return Err(format!("Could not fetch {}: server responded with '{}'", url, status_line.trim()));
+        }
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).map_err(fetch_error)? == 0 || header.trim().is_empty() {
+                break;
+            }
+        }
+
+        // Read one byte past the limit so that oversized responses can be detected
+        let mut body = Vec::new();
+        reader.take(HTTP_MAX_BODY_SIZE as u64 + 1).read_to_end(&mut body).map_err(fetch_error)?;
+        if body.len() > HTTP_MAX_BODY_SIZE {
+            return Err(format!("Could not fetch {}: response is larger than {} bytes", url, HTTP_MAX_BODY_SIZE));
+        }
+        String::from_utf8(body).map_err(|_| format!("Could not fetch {}: response is not valid text", url))
+    }
+}
+
+/// Network backend that never touches the network. Lines queued in `responses` are handed out
+/// by `recv` and everything passed to `send` is recorded in `sent`.
+#[cfg(test)]
+pub struct MockBackend {
+    pub sent: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    connections: Vec<String>,
+    responses: std::collections::VecDeque<String>,
+    open: Vec<usize>,
+}
+
+#[cfg(test)]
+impl MockBackend {
+    pub fn new(responses: Vec<&str>) -> Self {
+        MockBackend {
+            connections: Vec::new(),
+            sent: Default::default(),
+            responses: responses.into_iter().map(String::from).collect(),
+            open: Vec::new(),
+        }
+    }
+
+    fn check_open(&self, handle: usize) -> Result<(), String> {
+        if self.open.contains(&handle) {
+            Ok(())
+        } else {
+            Err(format!("Socket {} is not open!", handle))
+        }
+    }
+}
+
+#[cfg(test)]
+impl NetBackend for MockBackend {
+    fn connect(&mut self, address: &str) -> Result<usize, String> {
+        self.connections.push(address.to_string());
+        let handle = self.connections.len() - 1;
+        self.open.push(handle);
+        Ok(handle)
+    }
+
+    fn send(&mut self, handle: usize, data: &str) -> Result<(), String> {
+        self.check_open(handle)?;
+        self.sent.lock().unwrap().push(data.to_string());
+        Ok(())
+    }
+
+    fn recv(&mut self, handle: usize) -> Result<String, String> {
+        self.check_open(handle)?;
+        Ok(self.responses.pop_front().unwrap_or_default())
+    }
+
+    fn close(&mut self, handle: usize) -> Result<(), String> {
+        self.check_open(handle)?;
+        self.open.retain(|open| *open != handle);
+        Ok(())
+    }
+
+    fn http_get(&mut self, url: &str) -> Result<String, String> {
+        self.connections.push(url.to_string());
+        Ok(self.responses.pop_front().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use super::{HTTP_MAX_BODY_SIZE, NetBackend, TcpBackend};
+
+    /// Serves a single HTTP response and returns the url that it can be fetched from
+    fn serve_once(response: Vec<u8>) -> (String, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/data.txt", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let _ = reader.get_mut().write_all(&response);
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn tcp_backend_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        // Echo a single line back to the client
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            reader.get_mut().write_all(line.as_bytes()).unwrap();
+        });
+
+        let mut backend = TcpBackend::new();
+        let handle = backend.connect(&address).unwrap();
+        backend.send(handle, "hello").unwrap();
+        assert_eq!(backend.recv(handle).unwrap(), "hello");
+        backend.close(handle).unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn tcp_backend_closed_socket() {
+        let mut backend = TcpBackend::new();
+        assert_eq!(backend.close(3).err().unwrap(), "Socket 3 is not open!");
+    }
+
+    #[test]
+    fn http_get_body() {
+        let (url, server) = serve_once(b"HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\n1 2 3\n".to_vec());
+        assert_eq!(TcpBackend::new().http_get(&url).unwrap(), "1 2 3\n");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn http_get_error_status() {
+        let (url, server) = serve_once(b"HTTP/1.0 404 Not Found\r\n\r\n".to_vec());
+        let result = TcpBackend::new().http_get(&url);
+        assert_eq!(result.err().unwrap(), format!("Could not fetch {}: server responded with 'HTTP/1.0 404 Not Found'", url));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn http_get_body_too_large() {
+        let mut response = b"HTTP/1.0 200 OK\r\n\r\n".to_vec();
+        response.extend(vec![b'a'; HTTP_MAX_BODY_SIZE + 1]);
+        let (url, server) = serve_once(response);
+        let result = TcpBackend::new().http_get(&url);
+        assert_eq!(result.err().unwrap(), format!("Could not fetch {}: response is larger than {} bytes", url, HTTP_MAX_BODY_SIZE));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn http_get_unsupported_url() {
+        let result = TcpBackend::new().http_get("https://example.com");
+        assert_eq!(result.err().unwrap(), "Unsupported url: https://example.com (only http:// urls can be fetched)");
+    }
+}
@@ -0,0 +1,1516 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use crate::aliases::resolve_alias;
+use crate::builtins::find_builtin;
+
+/*
+Grammar of a single instruction. Tokens are separated by whitespace, and a column is reported
+with every syntax error.
+
+instruction := "let" variable "=" expression
+             | "let" variable ("+=" | "-=" | "*=" | "/=" | "//=") variable
+             | "let" variable "{" operand "}" "=" operand
+             | "const" variable "=" operand
+             | "unset" variable
+             | "if" predicate instruction ["else" instruction]
+             | "goto" number
+             | "gosub" number | "return" [operand]
+             | "func" word variable* | "endfunc"
+             | "local" variable
+             | "for" variable "=" operand "to" operand ["step" operand]
+             | "next" variable
+             | "while" predicate | "endwhile"
+             | "switch" variable
+             | "case" operand "goto" number
+             | "default" "goto" number
+             | "label" word
+             | "import" word ["as" word]
+             | "map" variable
+             | "quit" [operand] | "endtest"
+             | "error" operand
+             | "output" operand operand*
+             | "flush"
+             | "push" operand
+             | "assert" operand condition operand
+             | "testcase" word
+             | "dialect" word
+             | "delete" variable
+             | "listdir" variable
+             | "closefile" variable
+             | ("writefile" | "appendfile") (string | variable) operand
+             | "seed" variable
+             | "sort"
+             | "netsend" variable variable          (net feature)
+             | "close" variable                     (net feature)
+expression  := literal
+             | variable
+             | variable operator variable
+             | ("-" | "neg") variable
+             | variable "." variable
+             | variable "{" operand "}"
+             | "input" [string] | "input" "all"
+             | "input" "timeout" number "default" literal
+             | "inputnum" [string]
+             | "pop"
+             | "pad" variable variable [variable]
+             | "repeat" variable variable
+             | "reverse" variable
+             | "exec" (string | variable)
+             | "exists" variable
+             | "random" variable
+             | ("readfile" | "openfile") (string | variable)
+             | "readline" variable
+             | "env" (string | variable)
+             | "arg" (number | variable) | "argc"
+             | "call" word operand*
+             | builtin operand*                  (as many as the builtin takes, see builtins.rs)
+             | operand "if" predicate "else" operand
+             | ("connect" | "netrecv" | "httpget") variable   (net feature)
+predicate   := conjunction ("or" conjunction)*
+conjunction := negation ("and" negation)*
+negation    := "not" negation | operand condition operand
+operand     := literal | variable
+literal     := number | string
+number      := ["-"] digits ["." digits]
+condition   := "<" | "<=" | ">" | ">=" | "=" | "!="
+operator    := "+" | "-" | "*" | "/" | "//" | "%" | "^" | "&" | "|" | "xor" | "<<" | ">>"
+variable    := "$" word
+word        := name ["." name]      (a name qualified by the module it belongs to)
+string      := '"' any characters except '"' '"'
+ */
+
+/// Comparisons used by 'if' and 'assert' statements.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+/// Condition of an 'if' statement: comparisons combined with 'and', 'or' and 'not'. 'not' binds
+/// tightest, then 'and', then 'or'.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Predicate {
+    Compare { lhs: Operand, condition: Condition, rhs: Operand },
+    Not(Box<Predicate>),
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+}
+
+/// Arithmetic operators.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    /// Division of integers, rounded towards zero
+    IntegerDivide,
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+/// A value given either as a literal or as a variable holding it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Literal(String),
+    Variable(String),
+}
+
+/// Right hand side of a 'let' statement. Variables are named without their leading '$'.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expression {
+    Literal(String),
+    Variable(String),
+    Operation { lhs: String, operator: Operator, rhs: String },
+    /// The value of the variable with its sign flipped
+    Negate(String),
+    /// The text of `lhs` followed by the text of `rhs`
+    Concat { lhs: String, rhs: String },
+    /// The value stored under `key` in the map held by `map`
+    Lookup { map: String, key: Operand },
+    Input,
+    /// Writes the prompt without ending the line, then reads a line of input
+    PromptedInput(String),
+    /// Reads a line of input holding a whole number, after writing the prompt if there is one
+    InputNum(Option<String>),
+    InputAll,
+    TimedInput { timeout: u64, default: String },
+    Pop,
+    /// Pads `value` with spaces, or the single character in `fill`, to `width` characters
+    Pad { value: String, width: String, fill: Option<String> },
+    Repeat { value: String, count: String },
+    Reverse(String),
+    /// Runs a shell command, whose standard output is assigned
+    Exec(Operand),
+    /// "1" if the path held by the variable exists, otherwise "0"
+    Exists(String),
+    /// A random number from 0 up to, but not including, the value of the variable
+    Random(String),
+    /// The contents of the file at the path, without their final line terminator
+    ReadFile(Operand),
+    /// Opens the file at the path for reading line by line, giving the handle it is read with
+    OpenFile(Operand),
+    /// The next line of the file whose handle the variable holds
+    ReadLine(String),
+    /// The value of the environment variable with the given name, or "" if it is not set
+    Env(Operand),
+    /// The argument given to the program on the command line at the (1-based) position
+    Arg(Operand),
+    /// The number of arguments given to the program on the command line
+    ArgCount,
+    /// The result of the builtin named `function` (see `builtins::BUILTINS`)
+    Builtin { function: String, args: Vec<Operand> },
+    /// The value returned by the function named `function`, called with the given arguments
+    Call { function: String, args: Vec<Operand> },
+    /// `then` if the predicate holds, otherwise `otherwise`
+    Conditional { then: Operand, predicate: Predicate, otherwise: Operand },
+    #[cfg(feature = "net")]
+    Connect(String),
+    #[cfg(feature = "net")]
+    NetRecv(String),
+    #[cfg(feature = "net")]
+    HttpGet(String),
+}
+
+/// A single parsed instruction. Variables are named without their leading '$'.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Let { name: String, value: Expression },
+    /// Sets a variable that cannot be assigned again
+    Const { name: String, value: Operand },
+    /// Removes a variable, so that it no longer exists
+    Unset(String),
+    /// Runs `then` if the predicate holds, and `otherwise` (if there is an 'else' clause) if it
+    /// does not. `then_source` and the text in `otherwise` are the instructions as they were written.
+    If { predicate: Predicate, then: Box<Instruction>, then_source: String, otherwise: Option<(Box<Instruction>, String)> },
+    Goto(usize),
+    /// Jumps to a subroutine, which carries on after this line once it returns
+    Gosub(usize),
+    /// Returns from a subroutine, or from a function with the value it gives back
+    Return(Option<Operand>),
+    /// Starts the body of a function, which runs up to the matching 'endfunc'. The parameters
+    /// are set as local variables of each call.
+    Func { name: String, params: Vec<String> },
+    EndFunc,
+    /// Declares a variable local to the innermost subroutine or function call
+    Local(String),
+    /// Starts a loop that counts `var` from `from` to `to` (inclusive) in steps of `step`, or
+    /// of 1 if no step is given. The body runs up to the matching 'next'.
+    For { var: String, from: Operand, to: Operand, step: Option<Operand> },
+    /// Steps the counter of the loop over the variable, and goes back to the start of the loop
+    /// unless it has gone past its end
+    Next(String),
+    /// Starts a loop whose body, up to the matching 'endwhile', runs for as long as the
+    /// predicate holds
+    While(Predicate),
+    EndWhile,
+    /// Jumps to the target of the first of the 'case' lines that follow whose value equals that
+    /// of the variable, or to that of the 'default' line after them if none does. Without a
+    /// 'default' line the program carries on after the cases.
+    Switch(String),
+    /// A value of the 'switch' above it, and the line it jumps to
+    Case { value: Operand, target: usize },
+    /// The line a 'switch' jumps to when none of its cases match
+    Default(usize),
+    /// Names the line it is on, so that jumps can point to it by name. Jumps to labels are
+    /// resolved when the program is loaded.
+    Label(String),
+    /// Makes the functions of the module file `<module>.bas` callable as `alias.name`. Imports
+    /// are resolved when the program is loaded.
+    Import { module: String, alias: String },
+    /// Sets the variable to an empty map
+    Map(String),
+    /// Stores `value` under `key` in the map held by `map`
+    SetEntry { map: String, key: Operand, value: Operand },
+    /// Ends the program, with the exit code given or 0
+    Quit(Option<Operand>),
+    EndTest,
+    /// Stops the program with an error, whose message is the value given
+    Error(Operand),
+    /// Writes the values one after another, on a single line
+    Output(Vec<Operand>),
+    /// Writes out the output the console has buffered
+    Flush,
+    /// Pushes a literal, or the value of a variable, onto the stack
+    Push(Operand),
+    Assert { lhs: Operand, condition: Condition, rhs: Operand },
+    Testcase(String),
+    Dialect(String),
+    Delete(String),
+    /// Pushes the names of the entries of a directory onto the stack
+    ListDir(String),
+    /// Closes the file whose handle the variable holds
+    CloseFile(String),
+    /// Replaces the contents of the file at `path` with `contents`, followed by a line terminator
+    WriteFile { path: Operand, contents: Operand },
+    /// Adds `contents`, followed by a line terminator, to the end of the file at `path`
+    AppendFile { path: Operand, contents: Operand },
+    /// Restarts the program's random numbers from the value of the variable
+    Seed(String),
+    /// Sorts the stack, so that its smallest value is on top
+    Sort,
+    #[cfg(feature = "net")]
+    NetSend { socket: String, message: String },
+    #[cfg(feature = "net")]
+    Close(String),
+}
+
+/// A syntax error, along with the column (counting from 1) it was found at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TokenKind {
+    Word(String),
+    Variable(String),
+    Number(String),
+    Str(String),
+    Symbol(&'static str),
+}
+
+struct Token {
+    kind: TokenKind,
+    column: usize,
+}
+
+/// Keywords that start an instruction. 'default' also starts the default case of a 'switch',
+/// but is listed with the expression keywords, where it appeared first.
+pub const INSTRUCTION_KEYWORDS: &[&str] = &[
+    "let", "if", "goto", "quit", "endtest", "output", "push", "assert", "testcase", "dialect",
+    "delete", "listdir", "seed", "sort", "flush", "gosub", "return", "label", "map",
+    "func", "endfunc", "local", "const", "unset", "for", "next",
+    "while", "endwhile", "switch", "case", "error", "closefile",
+    "writefile", "appendfile", "import",
+    #[cfg(feature = "net")] "netsend",
+    #[cfg(feature = "net")] "close",
+];
+
+/// Keywords that start a clause inside an instruction.
+pub const CLAUSE_KEYWORDS: &[&str] = &["else", "to", "step", "as"];
+
+/// Keywords that combine the comparisons of an 'if' statement.
+pub const PREDICATE_KEYWORDS: &[&str] = &["and", "or", "not"];
+
+/// Keywords that appear in the expression of a 'let' statement.
+pub const EXPRESSION_KEYWORDS: &[&str] = &[
+    "input", "all", "timeout", "default", "pop", "pad", "repeat", "reverse", "exec", "exists", "random", "xor", "call",
+    "inputnum", "neg", "readfile", "openfile", "readline", "env", "arg", "argc",
+    #[cfg(feature = "net")] "connect",
+    #[cfg(feature = "net")] "netrecv",
+    #[cfg(feature = "net")] "httpget",
+];
+
+/// Brackets around the key of a map entry.
+pub const BRACKETS: [&str; 2] = ["{", "}"];
+
+/// Comparison, arithmetic and concatenation symbols, longest first so that '<=' and '<<' are not read as '<'.
+pub const SYMBOLS: [&str; 18] = ["//", "<<", ">>", "<=", ">=", "!=", "<", ">", "=", "+", "-", "*", "/", "%", "^", "&", "|", "."];
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Splits a line into tokens.
+fn tokenize(line: &str) -> Result<Vec<Token>, ParseError> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let column = pos + 1;
+        if c.is_whitespace() {
+            pos += 1;
+            continue;
+        }
+
+        let take_word = |start: usize| -> (String, usize) {
+            let end = (start..chars.len()).find(|i| !is_word_char(chars[*i])).unwrap_or(chars.len());
+            (chars[start..end].iter().collect(), end)
+        };
+        // A name may be qualified by the module it belongs to, as in 'm.gcd' or '$m.count'
+        let take_name = |start: usize| -> (String, usize) {
+            let (name, end) = take_word(start);
+            if chars.get(end) == Some(&'.') && chars.get(end + 1).is_some_and(|c| c.is_ascii_alphabetic() || *c == '_') {
+                let (qualified, qualified_end) = take_word(end + 1);
+                return (format!("{}.{}", name, qualified), qualified_end);
+            }
+            (name, end)
+        };
+
+        // A '-' right before a digit starts a negative number, unless it follows a value and
+        // so can only be subtracting
+        let negative = c == '-' && chars.get(pos + 1).is_some_and(char::is_ascii_digit)
+            && !matches!(tokens.last(), Some(Token { kind: TokenKind::Variable(_) | TokenKind::Number(_) | TokenKind::Str(_), .. }));
+
+        let kind = if c == '$' {
+            let (name, end) = take_name(pos + 1);
+            if name.is_empty() {
+                return Err(ParseError { column, message: String::from("expected a variable name after '$'") });
+            }
+            pos = end;
+            TokenKind::Variable(name)
+        } else if c.is_ascii_digit() || negative {
+            let (mut number, mut end) = take_word(if negative { pos + 1 } else { pos });
+            // A decimal point followed by more digits makes the number a float
+            if number.chars().all(|c| c.is_ascii_digit()) && chars.get(end) == Some(&'.')
+                && chars.get(end + 1).is_some_and(char::is_ascii_digit) {
+                let (fraction, fraction_end) = take_word(end + 1);
+                number = format!("{}.{}", number, fraction);
+                end = fraction_end;
+            }
+            if !number.chars().all(|c| c.is_ascii_digit() || c == '.') {
+                return Err(ParseError { column, message: format!("invalid number '{}'", number) });
+            }
+            pos = end;
+            TokenKind::Number(if negative { format!("-{}", number) } else { number })
+        } else if is_word_char(c) {
+            let (word, end) = take_name(pos);
+            pos = end;
+            TokenKind::Word(word)
+        } else if c == '"' {
+            let end = (pos + 1..chars.len()).find(|i| chars[*i] == '"')
+                .ok_or(ParseError { column, message: String::from("unterminated string") })?;
+            let text: String = chars[pos + 1..end].iter().collect();
+            pos = end + 1;
+            TokenKind::Str(text)
+        } else {
+            let rest: String = chars[pos..].iter().collect();
+            let symbol = SYMBOLS.iter().chain(BRACKETS.iter()).find(|symbol| rest.starts_with(*symbol))
+                .ok_or(ParseError { column, message: format!("unexpected character '{}'", c) })?;
+            pos += symbol.len();
+            TokenKind::Symbol(symbol)
+        };
+        tokens.push(Token { kind, column });
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    line: Vec<char>,
+    tokens: Vec<Token>,
+    pos: usize,
+    // Column just past the end of the line, reported when a token is missing
+    end_column: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|token| &token.kind)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.pos).map(|token| token.column).unwrap_or(self.end_column)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, ParseError> {
+        Err(ParseError { column: self.column(), message: message.to_string() })
+    }
+
+    fn next(&mut self) {
+        self.pos += 1;
+    }
+
+    /// Takes the next token if `extract` accepts it, otherwise reports that `what` was expected.
+    fn take<T>(&mut self, what: &str, extract: impl Fn(&TokenKind) -> Option<T>) -> Result<T, ParseError> {
+        match self.peek().and_then(extract) {
+            Some(value) => {
+                self.next();
+                Ok(value)
+            },
+            None => self.error(&format!("expected {}", what)),
+        }
+    }
+
+    /// Takes the next token if it is the word `keyword`.
+    fn accept(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Some(TokenKind::Word(word)) if word == keyword);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    fn word(&mut self) -> Result<String, ParseError> {
+        self.take("a name", |token| match token {
+            TokenKind::Word(word) => Some(word.clone()),
+            _ => None,
+        })
+    }
+
+    /// Reads the name of a module, which cannot itself be qualified.
+    fn module_name(&mut self) -> Result<String, ParseError> {
+        self.take("a module name", |token| match token {
+            TokenKind::Word(word) if !word.contains('.') => Some(word.clone()),
+            _ => None,
+        })
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        self.take(&format!("'{}'", keyword), |token| match token {
+            TokenKind::Word(word) if word == keyword => Some(()),
+            _ => None,
+        })
+    }
+
+    fn symbol(&mut self, symbol: &str) -> Result<(), ParseError> {
+        self.take(&format!("'{}'", symbol), |token| match token {
+            TokenKind::Symbol(found) if *found == symbol => Some(()),
+            _ => None,
+        })
+    }
+
+    fn variable(&mut self) -> Result<String, ParseError> {
+        self.take("a variable", |token| match token {
+            TokenKind::Variable(name) => Some(name.clone()),
+            _ => None,
+        })
+    }
+
+    fn number(&mut self, what: &str) -> Result<String, ParseError> {
+        self.take(what, |token| match token {
+            TokenKind::Number(number) if !number.contains('.') => Some(number.clone()),
+            _ => None,
+        })
+    }
+
+    fn line_number(&mut self) -> Result<usize, ParseError> {
+        let column = self.column();
+        let number = self.number("a line number")?;
+        if number.starts_with('-') {
+            return Err(ParseError { column, message: String::from("line number cannot be negative") });
+        }
+        number.parse::<usize>()
+            .map_err(|_| ParseError { column, message: String::from("line number is too large") })
+    }
+
+    /// Reads a name (such as the path of a file), given as a string or a variable holding it.
+    fn name(&mut self, what: &str) -> Result<Operand, ParseError> {
+        match self.peek() {
+            Some(TokenKind::Variable(_)) => Ok(Operand::Variable(self.variable()?)),
+            Some(TokenKind::Str(_)) => Ok(Operand::Literal(self.literal()?)),
+            _ => self.error(&format!("expected {}", what)),
+        }
+    }
+
+    fn literal(&mut self) -> Result<String, ParseError> {
+        self.take("a number or a string", |token| match token {
+            TokenKind::Number(value) | TokenKind::Str(value) => Some(value.clone()),
+            _ => None,
+        })
+    }
+
+    fn operand(&mut self, what: &str) -> Result<Operand, ParseError> {
+        match self.peek() {
+            Some(TokenKind::Variable(_)) => Ok(Operand::Variable(self.variable()?)),
+            Some(TokenKind::Number(_)) | Some(TokenKind::Str(_)) => Ok(Operand::Literal(self.literal()?)),
+            _ => self.error(&format!("expected {}", what)),
+        }
+    }
+
+    /// Reads the operands up to the end of the line or the first token that is not an operand.
+    fn operands(&mut self) -> Result<Vec<Operand>, ParseError> {
+        let mut operands = Vec::new();
+        while self.at_operand() {
+            operands.push(self.operand("a value")?);
+        }
+        Ok(operands)
+    }
+
+    fn at_operand(&self) -> bool {
+        matches!(self.peek(), Some(TokenKind::Variable(_)) | Some(TokenKind::Number(_)) | Some(TokenKind::Str(_)))
+    }
+
+    /// Reads the key of a map entry, between braces.
+    fn key(&mut self) -> Result<Operand, ParseError> {
+        self.symbol("{")?;
+        let key = self.operand("a key")?;
+        self.symbol("}")?;
+        Ok(key)
+    }
+
+    fn condition(&mut self) -> Result<Condition, ParseError> {
+        let condition = match self.peek() {
+            Some(TokenKind::Symbol("<")) => Condition::Less,
+            Some(TokenKind::Symbol("<=")) => Condition::LessEqual,
+            Some(TokenKind::Symbol(">")) => Condition::Greater,
+            Some(TokenKind::Symbol(">=")) => Condition::GreaterEqual,
+            Some(TokenKind::Symbol("=")) => Condition::Equal,
+            Some(TokenKind::Symbol("!=")) => Condition::NotEqual,
+            _ => return self.error("expected a comparison"),
+        };
+        self.next();
+        Ok(condition)
+    }
+
+    fn predicate(&mut self) -> Result<Predicate, ParseError> {
+        let mut predicate = self.conjunction()?;
+        while self.accept("or") {
+            predicate = Predicate::Or(Box::new(predicate), Box::new(self.conjunction()?));
+        }
+        Ok(predicate)
+    }
+
+    fn conjunction(&mut self) -> Result<Predicate, ParseError> {
+        let mut predicate = self.negation()?;
+        while self.accept("and") {
+            predicate = Predicate::And(Box::new(predicate), Box::new(self.negation()?));
+        }
+        Ok(predicate)
+    }
+
+    fn negation(&mut self) -> Result<Predicate, ParseError> {
+        if self.accept("not") {
+            return Ok(Predicate::Not(Box::new(self.negation()?)));
+        }
+        let lhs = self.operand("a variable or a value")?;
+        let condition = self.condition()?;
+        Ok(Predicate::Compare { lhs, condition, rhs: self.operand("a variable or a value")? })
+    }
+
+    fn operator(&mut self) -> Option<Operator> {
+        let operator = match self.peek() {
+            Some(TokenKind::Symbol("+")) => Operator::Add,
+            Some(TokenKind::Symbol("-")) => Operator::Subtract,
+            Some(TokenKind::Symbol("*")) => Operator::Multiply,
+            Some(TokenKind::Symbol("/")) => Operator::Divide,
+            Some(TokenKind::Symbol("//")) => Operator::IntegerDivide,
+            Some(TokenKind::Symbol("%")) => Operator::Modulo,
+            Some(TokenKind::Symbol("^")) => Operator::Power,
+            Some(TokenKind::Symbol("&")) => Operator::BitAnd,
+            Some(TokenKind::Symbol("|")) => Operator::BitOr,
+            Some(TokenKind::Word(word)) if word == "xor" => Operator::BitXor,
+            Some(TokenKind::Symbol("<<")) => Operator::ShiftLeft,
+            Some(TokenKind::Symbol(">>")) => Operator::ShiftRight,
+            _ => return None,
+        };
+        self.next();
+        Some(operator)
+    }
+
+    /// Reads the operator of a compound assignment, written right before its '='.
+    fn compound_operator(&mut self) -> Option<Operator> {
+        let (operator, width) = match self.peek() {
+            Some(TokenKind::Symbol("+")) => (Operator::Add, 1),
+            Some(TokenKind::Symbol("-")) => (Operator::Subtract, 1),
+            Some(TokenKind::Symbol("*")) => (Operator::Multiply, 1),
+            Some(TokenKind::Symbol("/")) => (Operator::Divide, 1),
+            Some(TokenKind::Symbol("//")) => (Operator::IntegerDivide, 2),
+            _ => return None,
+        };
+        match self.tokens.get(self.pos + 1) {
+            Some(Token { kind: TokenKind::Symbol("="), column }) if *column == self.column() + width => {
+                self.pos += 2;
+                Some(operator)
+            },
+            _ => None,
+        }
+    }
+
+    fn end(&self) -> Result<(), ParseError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => self.error("expected end of line"),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expression, ParseError> {
+        match self.peek() {
+            Some(TokenKind::Number(_)) | Some(TokenKind::Str(_)) => Ok(Expression::Literal(self.literal()?)),
+            Some(TokenKind::Symbol("-")) => {
+                self.next();
+                Ok(Expression::Negate(self.variable()?))
+            },
+            Some(TokenKind::Variable(_)) => {
+                let lhs = self.variable()?;
+                if matches!(self.peek(), Some(TokenKind::Symbol("{"))) {
+                    return Ok(Expression::Lookup { map: lhs, key: self.key()? });
+                }
+                if matches!(self.peek(), Some(TokenKind::Symbol("."))) {
+                    self.next();
+                    return Ok(Expression::Concat { lhs, rhs: self.variable()? });
+                }
+                match self.operator() {
+                    Some(operator) => Ok(Expression::Operation { lhs, operator, rhs: self.variable()? }),
+                    None => Ok(Expression::Variable(lhs)),
+                }
+            },
+            Some(TokenKind::Word(word)) => match word.as_str() {
+                "input" => {
+                    self.next();
+                    match self.peek() {
+                        Some(TokenKind::Word(word)) if word == "all" => {
+                            self.next();
+                            Ok(Expression::InputAll)
+                        },
+                        Some(TokenKind::Word(word)) if word == "timeout" => {
+                            self.next();
+                            let column = self.column();
+                            let timeout = self.number("a timeout in seconds")?;
+                            if timeout.starts_with('-') {
+                                return Err(ParseError { column, message: String::from("timeout cannot be negative") });
+                            }
+                            let timeout = timeout.parse::<u64>()
+                                .map_err(|_| ParseError { column, message: String::from("timeout is too large") })?;
+                            self.keyword("default")?;
+                            Ok(Expression::TimedInput { timeout, default: self.literal()? })
+                        },
+                        Some(TokenKind::Str(prompt)) => {
+                            let prompt = prompt.clone();
+                            self.next();
+                            Ok(Expression::PromptedInput(prompt))
+                        },
+                        _ => Ok(Expression::Input),
+                    }
+                },
+                "inputnum" => {
+                    self.next();
+                    match self.peek() {
+                        Some(TokenKind::Str(prompt)) => {
+                            let prompt = prompt.clone();
+                            self.next();
+                            Ok(Expression::InputNum(Some(prompt)))
+                        },
+                        _ => Ok(Expression::InputNum(None)),
+                    }
+                },
+                "neg" => {
+                    self.next();
+                    Ok(Expression::Negate(self.variable()?))
+                },
+                "pop" => {
+                    self.next();
+                    Ok(Expression::Pop)
+                },
+                "pad" => {
+                    self.next();
+                    let value = self.variable()?;
+                    let width = self.variable()?;
+                    let fill = match self.peek() {
+                        Some(TokenKind::Variable(_)) => Some(self.variable()?),
+                        _ => None,
+                    };
+                    Ok(Expression::Pad { value, width, fill })
+                },
+                "repeat" => {
+                    self.next();
+                    let value = self.variable()?;
+                    Ok(Expression::Repeat { value, count: self.variable()? })
+                },
+                "reverse" => {
+                    self.next();
+                    Ok(Expression::Reverse(self.variable()?))
+                },
+                "exec" => {
+                    self.next();
+                    match self.peek() {
+                        Some(TokenKind::Variable(_)) => Ok(Expression::Exec(Operand::Variable(self.variable()?))),
+                        Some(TokenKind::Str(_)) => Ok(Expression::Exec(Operand::Literal(self.literal()?))),
+                        _ => self.error("expected a command"),
+                    }
+                },
+                "exists" => {
+                    self.next();
+                    Ok(Expression::Exists(self.variable()?))
+                },
+                "readfile" => {
+                    self.next();
+                    Ok(Expression::ReadFile(self.name("a path")?))
+                },
+                "openfile" => {
+                    self.next();
+                    Ok(Expression::OpenFile(self.name("a path")?))
+                },
+                "readline" => {
+                    self.next();
+                    Ok(Expression::ReadLine(self.variable()?))
+                },
+                "env" => {
+                    self.next();
+                    Ok(Expression::Env(self.name("the name of an environment variable")?))
+                },
+                "arg" => {
+                    self.next();
+                    match self.peek() {
+                        Some(TokenKind::Variable(_)) => Ok(Expression::Arg(Operand::Variable(self.variable()?))),
+                        _ => Ok(Expression::Arg(Operand::Literal(self.number("an argument number")?))),
+                    }
+                },
+                "argc" => {
+                    self.next();
+                    Ok(Expression::ArgCount)
+                },
+                "random" => {
+                    self.next();
+                    Ok(Expression::Random(self.variable()?))
+                },
+                "call" => {
+                    self.next();
+                    let function = self.word()?;
+                    Ok(Expression::Call { function, args: self.operands()? })
+                },
+                #[cfg(feature = "net")]
+                "connect" => {
+                    self.next();
+                    Ok(Expression::Connect(self.variable()?))
+                },
+                #[cfg(feature = "net")]
+                "netrecv" => {
+                    self.next();
+                    Ok(Expression::NetRecv(self.variable()?))
+                },
+                #[cfg(feature = "net")]
+                "httpget" => {
+                    self.next();
+                    Ok(Expression::HttpGet(self.variable()?))
+                },
+                function if find_builtin(function).is_some() => {
+                    let function = function.to_string();
+                    self.next();
+                    let args = (0..find_builtin(&function).unwrap().arity)
+                        .map(|_| self.operand("a value"))
+                        .collect::<Result<Vec<Operand>, ParseError>>()?;
+                    Ok(Expression::Builtin { function, args })
+                },
+                _ => self.error("expected a value"),
+            },
+            _ => self.error("expected a value"),
+        }
+    }
+
+    /// Reads an instruction and the text it was written as, which runs up to the end of the line
+    /// or the 'else' that follows it.
+    fn clause(&mut self) -> Result<(Box<Instruction>, String), ParseError> {
+        let start = self.column() - 1;
+        let instruction = Box::new(self.instruction()?);
+        let end = self.column() - 1;
+        let source: String = self.line.get(start..end).unwrap_or_default().iter().collect();
+        Ok((instruction, source.trim_end().to_string()))
+    }
+
+    /// Reads the rest of a conditional expression ('$a if $a > $b else $b') if the value of an
+    /// assignment is followed by 'if'. Only a literal or a single variable can be chosen.
+    fn conditional(&mut self, value: Expression) -> Result<Expression, ParseError> {
+        let then = match &value {
+            Expression::Literal(literal) => Operand::Literal(literal.clone()),
+            Expression::Variable(var_name) => Operand::Variable(var_name.clone()),
+            _ => return Ok(value),
+        };
+        if !self.accept("if") {
+            return Ok(value);
+        }
+        let predicate = self.predicate()?;
+        self.keyword("else")?;
+        Ok(Expression::Conditional { then, predicate, otherwise: self.operand("a value")? })
+    }
+
+    /// Reads an instruction, leaving anything after it (such as an 'else' clause) to the caller.
+    fn instruction(&mut self) -> Result<Instruction, ParseError> {
+        let column = self.column();
+        let keyword = self.word().map_err(|_| ParseError {
+            column,
+            message: String::from("expected an instruction"),
+        })?;
+
+        let instruction = match keyword.as_str() {
+            "let" => {
+                let name = self.variable()?;
+                if matches!(self.peek(), Some(TokenKind::Symbol("{"))) {
+                    let key = self.key()?;
+                    self.symbol("=")?;
+                    Instruction::SetEntry { map: name, key, value: self.operand("a value")? }
+                } else if let Some(operator) = self.compound_operator() {
+                    // 'let $a += $b' is short for 'let $a = $a + $b'
+                    let rhs = self.variable()?;
+                    Instruction::Let { name: name.clone(), value: Expression::Operation { lhs: name, operator, rhs } }
+                } else {
+                    self.symbol("=")?;
+                    let value = self.expression()?;
+                    Instruction::Let { name, value: self.conditional(value)? }
+                }
+            },
+            "const" => {
+                let name = self.variable()?;
+                self.symbol("=")?;
+                Instruction::Const { name, value: self.operand("a variable or a value")? }
+            },
+            "unset" => Instruction::Unset(self.variable()?),
+            "if" => {
+                let predicate = self.predicate()?;
+                let (then, then_source) = self.clause()?;
+                let otherwise = if self.accept("else") { Some(self.clause()?) } else { None };
+                Instruction::If { predicate, then, then_source, otherwise }
+            },
+            "goto" => Instruction::Goto(self.line_number()?),
+            "gosub" => Instruction::Gosub(self.line_number()?),
+            "return" if self.at_operand() => Instruction::Return(Some(self.operand("a value")?)),
+            "return" => Instruction::Return(None),
+            "for" => {
+                let var = self.variable()?;
+                self.symbol("=")?;
+                let from = self.operand("a start value")?;
+                self.keyword("to")?;
+                let to = self.operand("an end value")?;
+                let step = if self.accept("step") { Some(self.operand("a step")?) } else { None };
+                Instruction::For { var, from, to, step }
+            },
+            "next" => Instruction::Next(self.variable()?),
+            "while" => Instruction::While(self.predicate()?),
+            "endwhile" => Instruction::EndWhile,
+            "switch" => Instruction::Switch(self.variable()?),
+            "case" => {
+                let value = self.operand("a value")?;
+                self.keyword("goto")?;
+                Instruction::Case { value, target: self.line_number()? }
+            },
+            "default" => {
+                self.keyword("goto")?;
+                Instruction::Default(self.line_number()?)
+            },
+            "func" => {
+                let name = self.word()?;
+                let mut params = Vec::new();
+                while matches!(self.peek(), Some(TokenKind::Variable(_))) {
+                    params.push(self.variable()?);
+                }
+                Instruction::Func { name, params }
+            },
+            "endfunc" => Instruction::EndFunc,
+            "local" => Instruction::Local(self.variable()?),
+            "label" => Instruction::Label(self.word()?),
+            "import" => {
+                let module = self.module_name()?;
+                let alias = if self.accept("as") { self.module_name()? } else { module.clone() };
+                Instruction::Import { module, alias }
+            },
+            "map" => Instruction::Map(self.variable()?),
+            "quit" if self.at_operand() => Instruction::Quit(Some(self.operand("an exit code")?)),
+            "quit" => Instruction::Quit(None),
+            "endtest" => Instruction::EndTest,
+            "error" => Instruction::Error(self.operand("a message")?),
+            "output" => {
+                let mut values = vec![self.operand("a variable or a value")?];
+                values.extend(self.operands()?);
+                Instruction::Output(values)
+            },
+            "flush" => Instruction::Flush,
+            "push" => Instruction::Push(self.operand("a variable or a value")?),
+            "assert" => {
+                let lhs = self.operand("a variable or a value")?;
+                let condition = self.condition()?;
+                Instruction::Assert { lhs, condition, rhs: self.operand("a variable or a value")? }
+            },
+            "testcase" => Instruction::Testcase(self.word()?),
+            "dialect" => Instruction::Dialect(self.word()?),
+            "delete" => Instruction::Delete(self.variable()?),
+            "listdir" => Instruction::ListDir(self.variable()?),
+            "closefile" => Instruction::CloseFile(self.variable()?),
+            "writefile" => Instruction::WriteFile { path: self.name("a path")?, contents: self.operand("the contents")? },
+            "appendfile" => Instruction::AppendFile { path: self.name("a path")?, contents: self.operand("the contents")? },
+            "seed" => Instruction::Seed(self.variable()?),
+            "sort" => Instruction::Sort,
+            #[cfg(feature = "net")]
+            "netsend" => {
+                let socket = self.variable()?;
+                Instruction::NetSend { socket, message: self.variable()? }
+            },
+            #[cfg(feature = "net")]
+            "close" => Instruction::Close(self.variable()?),
+            _ => return Err(ParseError { column, message: format!("unknown instruction '{}'", keyword) }),
+        };
+        Ok(instruction)
+    }
+}
+
+/// Parses a single line of code into an instruction.
+///
+/// # Arguments
+/// * `line` - The line to parse, without its line number
+///
+/// # Returns
+/// * `Ok(Instruction)` - The instruction on the line
+/// * `Err(ParseError)` - The first syntax error on the line, and where it was found
+pub fn parse_line(line: &str) -> Result<Instruction, ParseError> {
+    let mut parser = Parser {
+        line: line.chars().collect(),
+        tokens: tokenize(line)?,
+        pos: 0,
+        end_column: line.chars().count() + 1,
+    };
+    let instruction = parser.instruction()?;
+    parser.end()?;
+    Ok(instruction)
+}
+
+/// The lines of a program, each parsed into its instruction once, when the program is loaded.
+/// Programs that share their code also share its parsed instructions, so a line is never parsed
+/// again however often it runs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParsedCode {
+    // The keyword aliases the lines were parsed with
+    aliases: HashMap<String, String>,
+    // The lines, with any keyword alias replaced by the keyword it stands for
+    lines: Vec<String>,
+    instructions: Vec<Result<Instruction, ParseError>>,
+}
+
+impl ParsedCode {
+    /// Parses every line of a program, after replacing any keyword alias at the start of the
+    /// line. Lines with syntax errors keep their error, which is reported if the line runs.
+    pub fn parse<'a>(code: impl IntoIterator<Item = &'a str>, aliases: &HashMap<String, String>) -> Self {
+        let lines: Vec<String> = code.into_iter().map(|line| resolve_alias(aliases, line)).collect();
+        let instructions = lines.iter().map(|line| parse_line(line)).collect();
+        ParsedCode { aliases: aliases.clone(), lines, instructions }
+    }
+
+    /// Wraps a single line that has already been parsed, such as the tail of an 'if' statement.
+    pub fn from_instruction(line: &str, instruction: &Instruction) -> Self {
+        ParsedCode { aliases: HashMap::new(), lines: vec![line.to_string()], instructions: vec![Ok(instruction.clone())] }
+    }
+
+    /// Returns the keyword aliases the lines were parsed with.
+    pub fn aliases(&self) -> &HashMap<String, String> {
+        &self.aliases
+    }
+
+    /// Returns the line at `index`, with its keyword alias replaced.
+    pub fn line(&self, index: usize) -> Option<&str> {
+        self.lines.get(index).map(String::as_str)
+    }
+
+    /// Returns the instruction of every line, in order.
+    pub fn instructions(&self) -> &[Result<Instruction, ParseError>] {
+        &self.instructions
+    }
+
+    /// Returns the instruction on the line at `index`, or the syntax error found on it.
+    pub fn instruction(&self, index: usize) -> Option<&Result<Instruction, ParseError>> {
+        self.instructions.get(index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_line, Condition, Expression, Instruction, Operand, Operator, ParseError, Predicate, INSTRUCTION_KEYWORDS};
+
+    fn error(column: usize, message: &str) -> ParseError {
+        ParseError { column, message: message.to_string() }
+    }
+
+    #[test]
+    fn parse_assignments() {
+        assert_eq!(parse_line("let $a = 5").unwrap(),
+                   Instruction::Let { name: String::from("a"), value: Expression::Literal(String::from("5")) });
+        assert_eq!(parse_line("let $a = 3.14").unwrap(),
+                   Instruction::Let { name: String::from("a"), value: Expression::Literal(String::from("3.14")) });
+        assert_eq!(parse_line("let $a = -5").unwrap(),
+                   Instruction::Let { name: String::from("a"), value: Expression::Literal(String::from("-5")) });
+        assert_eq!(parse_line("let $a = -0.25").unwrap(),
+                   Instruction::Let { name: String::from("a"), value: Expression::Literal(String::from("-0.25")) });
+        assert_eq!(parse_line("let $a = \"hello world\"").unwrap(),
+                   Instruction::Let { name: String::from("a"), value: Expression::Literal(String::from("hello world")) });
+        // Strings hold any character but '"', so digits, punctuation and '$' are only text
+        assert_eq!(parse_line("let $a = \"Total: $5, 100% (#1)\"").unwrap(),
+                   Instruction::Let { name: String::from("a"), value: Expression::Literal(String::from("Total: $5, 100% (#1)")) });
+        assert_eq!(parse_line("let $a = $b * $c").unwrap(), Instruction::Let {
+            name: String::from("a"),
+            value: Expression::Operation { lhs: String::from("b"), operator: Operator::Multiply, rhs: String::from("c") },
+        });
+        assert_eq!(parse_line("let $a = $b // $c").unwrap(), Instruction::Let {
+            name: String::from("a"),
+            value: Expression::Operation { lhs: String::from("b"), operator: Operator::IntegerDivide, rhs: String::from("c") },
+        });
+        assert_eq!(parse_line("let $a = $b % $c").unwrap(), Instruction::Let {
+            name: String::from("a"),
+            value: Expression::Operation { lhs: String::from("b"), operator: Operator::Modulo, rhs: String::from("c") },
+        });
+        assert_eq!(parse_line("let $a = $b ^ $c").unwrap(), Instruction::Let {
+            name: String::from("a"),
+            value: Expression::Operation { lhs: String::from("b"), operator: Operator::Power, rhs: String::from("c") },
+        });
+        assert_eq!(parse_line("let $a = $b . $c").unwrap(), Instruction::Let {
+            name: String::from("a"),
+            value: Expression::Concat { lhs: String::from("b"), rhs: String::from("c") },
+        });
+        assert_eq!(parse_line("let $a = $b.$c").unwrap(), Instruction::Let {
+            name: String::from("a"),
+            value: Expression::Concat { lhs: String::from("b"), rhs: String::from("c") },
+        });
+        assert_eq!(parse_line("let $a = $m{\"key\"}").unwrap(), Instruction::Let {
+            name: String::from("a"),
+            value: Expression::Lookup { map: String::from("m"), key: Operand::Literal(String::from("key")) },
+        });
+        assert_eq!(parse_line("let $m{$k} = 5").unwrap(), Instruction::SetEntry {
+            map: String::from("m"),
+            key: Operand::Variable(String::from("k")),
+            value: Operand::Literal(String::from("5")),
+        });
+        assert_eq!(parse_line("map $m").unwrap(), Instruction::Map(String::from("m")));
+        assert_eq!(parse_line("push 5").unwrap(), Instruction::Push(Operand::Literal(String::from("5"))));
+        assert_eq!(parse_line("push $a").unwrap(), Instruction::Push(Operand::Variable(String::from("a"))));
+        assert_eq!(parse_line("let $m{} = 5").err().unwrap(), error(8, "expected a key"));
+        assert_eq!(parse_line("let $m{\"key\" = 5").err().unwrap(), error(14, "expected '}'"));
+        assert_eq!(parse_line("let $a = $b xor $c").unwrap(), Instruction::Let {
+            name: String::from("a"),
+            value: Expression::Operation { lhs: String::from("b"), operator: Operator::BitXor, rhs: String::from("c") },
+        });
+        assert_eq!(parse_line("let $a = $b << $c").unwrap(), Instruction::Let {
+            name: String::from("a"),
+            value: Expression::Operation { lhs: String::from("b"), operator: Operator::ShiftLeft, rhs: String::from("c") },
+        });
+        assert_eq!(parse_line("let $a = input all").unwrap(),
+                   Instruction::Let { name: String::from("a"), value: Expression::InputAll });
+        assert_eq!(parse_line("let $a = input timeout 5 default \"none\"").unwrap(), Instruction::Let {
+            name: String::from("a"),
+            value: Expression::TimedInput { timeout: 5, default: String::from("none") },
+        });
+    }
+
+    #[test]
+    fn parse_for_loops() {
+        assert_eq!(parse_line("for $i = 0 to $n").unwrap(), Instruction::For {
+            var: String::from("i"),
+            from: Operand::Literal(String::from("0")),
+            to: Operand::Variable(String::from("n")),
+            step: None,
+        });
+        assert_eq!(parse_line("for $i = 10 to 0 step -2").unwrap(), Instruction::For {
+            var: String::from("i"),
+            from: Operand::Literal(String::from("10")),
+            to: Operand::Literal(String::from("0")),
+            step: Some(Operand::Literal(String::from("-2"))),
+        });
+        assert_eq!(parse_line("next $i").unwrap(), Instruction::Next(String::from("i")));
+        assert_eq!(parse_line("for $i = 0 $n").err().unwrap(), error(12, "expected 'to'"));
+        assert_eq!(parse_line("for $i = 0 to 5 step").err().unwrap(), error(21, "expected a step"));
+    }
+
+    #[test]
+    fn parse_while_loops() {
+        assert_eq!(parse_line("while $a < $b and not $a = $c").unwrap(), Instruction::While(Predicate::And(
+            Box::new(Predicate::Compare { lhs: Operand::Variable(String::from("a")), condition: Condition::Less, rhs: Operand::Variable(String::from("b")) }),
+            Box::new(Predicate::Not(Box::new(Predicate::Compare {
+                lhs: Operand::Variable(String::from("a")), condition: Condition::Equal, rhs: Operand::Variable(String::from("c")),
+            }))),
+        )));
+        assert_eq!(parse_line("endwhile").unwrap(), Instruction::EndWhile);
+        assert_eq!(parse_line("while $a <").err().unwrap(), error(11, "expected a variable or a value"));
+        assert_eq!(parse_line("endwhile $a").err().unwrap(), error(10, "expected end of line"));
+    }
+
+    #[test]
+    fn parse_quit_with_exit_code() {
+        assert_eq!(parse_line("quit").unwrap(), Instruction::Quit(None));
+        assert_eq!(parse_line("quit 3").unwrap(), Instruction::Quit(Some(Operand::Literal(String::from("3")))));
+        assert_eq!(parse_line("quit $code").unwrap(), Instruction::Quit(Some(Operand::Variable(String::from("code")))));
+        assert_eq!(parse_line("quit 3 4").err().unwrap(), error(8, "expected end of line"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse_line("error \"something went wrong\"").unwrap(),
+                   Instruction::Error(Operand::Literal(String::from("something went wrong"))));
+        assert_eq!(parse_line("error $message").unwrap(), Instruction::Error(Operand::Variable(String::from("message"))));
+        assert_eq!(parse_line("error").err().unwrap(), error(6, "expected a message"));
+    }
+
+    #[test]
+    fn parse_file_reads() {
+        let let_line = |name: &str, value: Expression| Instruction::Let { name: String::from(name), value };
+        assert_eq!(parse_line("let $text = readfile $path").unwrap(),
+                   let_line("text", Expression::ReadFile(Operand::Variable(String::from("path")))));
+        assert_eq!(parse_line("let $f = openfile \"notes\"").unwrap(),
+                   let_line("f", Expression::OpenFile(Operand::Literal(String::from("notes")))));
+        assert_eq!(parse_line("let $x = readfile \"data.txt\"").unwrap(),
+                   let_line("x", Expression::ReadFile(Operand::Literal(String::from("data.txt")))));
+        assert_eq!(parse_line("let $f = openfile \"/tmp/x\"").unwrap(),
+                   let_line("f", Expression::OpenFile(Operand::Literal(String::from("/tmp/x")))));
+        assert_eq!(parse_line("let $line = readline $f").unwrap(), let_line("line", Expression::ReadLine(String::from("f"))));
+        assert_eq!(parse_line("closefile $f").unwrap(), Instruction::CloseFile(String::from("f")));
+        assert_eq!(parse_line("let $text = readfile 5").err().unwrap(), error(22, "expected a path"));
+        assert_eq!(parse_line("let $line = readline").err().unwrap(), error(21, "expected a variable"));
+    }
+
+    #[test]
+    fn parse_file_writes() {
+        assert_eq!(parse_line("writefile \"results\" $data").unwrap(), Instruction::WriteFile {
+            path: Operand::Literal(String::from("results")),
+            contents: Operand::Variable(String::from("data")),
+        });
+        assert_eq!(parse_line("appendfile $path \"done\"").unwrap(), Instruction::AppendFile {
+            path: Operand::Variable(String::from("path")),
+            contents: Operand::Literal(String::from("done")),
+        });
+        assert_eq!(parse_line("appendfile \"logs/run-1.txt\" \"step 2: done\"").unwrap(), Instruction::AppendFile {
+            path: Operand::Literal(String::from("logs/run-1.txt")),
+            contents: Operand::Literal(String::from("step 2: done")),
+        });
+        assert_eq!(parse_line("writefile $path").err().unwrap(), error(16, "expected the contents"));
+        assert_eq!(parse_line("appendfile 3 $data").err().unwrap(), error(12, "expected a path"));
+    }
+
+    #[test]
+    fn parse_environment_variables() {
+        assert_eq!(parse_line("let $home = env \"HOME\"").unwrap(), Instruction::Let {
+            name: String::from("home"),
+            value: Expression::Env(Operand::Literal(String::from("HOME"))),
+        });
+        assert_eq!(parse_line("let $value = env $name").unwrap(), Instruction::Let {
+            name: String::from("value"),
+            value: Expression::Env(Operand::Variable(String::from("name"))),
+        });
+        assert_eq!(parse_line("let $home = env").err().unwrap(), error(16, "expected the name of an environment variable"));
+    }
+
+    #[test]
+    fn parse_program_arguments() {
+        assert_eq!(parse_line("let $x = arg 1").unwrap(), Instruction::Let {
+            name: String::from("x"),
+            value: Expression::Arg(Operand::Literal(String::from("1"))),
+        });
+        assert_eq!(parse_line("let $x = arg $i").unwrap(), Instruction::Let {
+            name: String::from("x"),
+            value: Expression::Arg(Operand::Variable(String::from("i"))),
+        });
+        assert_eq!(parse_line("let $n = argc").unwrap(), Instruction::Let { name: String::from("n"), value: Expression::ArgCount });
+        assert_eq!(parse_line("let $x = arg \"first\"").err().unwrap(), error(14, "expected an argument number"));
+        assert_eq!(parse_line("let $x = arg 1.5").err().unwrap(), error(14, "expected an argument number"));
+    }
+
+    #[test]
+    fn parse_builtins() {
+        assert_eq!(parse_line("let $x = abs $y").unwrap(), Instruction::Let {
+            name: String::from("x"),
+            value: Expression::Builtin { function: String::from("abs"), args: vec![Operand::Variable(String::from("y"))] },
+        });
+        assert_eq!(parse_line("let $x = max $y 10").unwrap(), Instruction::Let {
+            name: String::from("x"),
+            value: Expression::Builtin {
+                function: String::from("max"),
+                args: vec![Operand::Variable(String::from("y")), Operand::Literal(String::from("10"))],
+            },
+        });
+        assert_eq!(parse_line("let $x = min $y").err().unwrap(), error(16, "expected a value"));
+        assert_eq!(parse_line("let $x = sqrt $y $z").err().unwrap(), error(18, "expected end of line"));
+    }
+
+    #[test]
+    fn parse_switches() {
+        assert_eq!(parse_line("switch $x").unwrap(), Instruction::Switch(String::from("x")));
+        assert_eq!(parse_line("case 1 goto 10").unwrap(), Instruction::Case { value: Operand::Literal(String::from("1")), target: 10 });
+        assert_eq!(parse_line("case $y goto 12").unwrap(), Instruction::Case { value: Operand::Variable(String::from("y")), target: 12 });
+        assert_eq!(parse_line("default goto 20").unwrap(), Instruction::Default(20));
+        assert_eq!(parse_line("case 1 10").err().unwrap(), error(8, "expected 'goto'"));
+        assert_eq!(parse_line("default 20").err().unwrap(), error(9, "expected 'goto'"));
+        assert_eq!(parse_line("switch 3").err().unwrap(), error(8, "expected a variable"));
+    }
+
+    #[test]
+    fn parse_conditional_assignments() {
+        assert_eq!(parse_line("let $m = $a if $a > $b else $b").unwrap(), Instruction::Let {
+            name: String::from("m"),
+            value: Expression::Conditional {
+                then: Operand::Variable(String::from("a")),
+                predicate: Predicate::Compare { lhs: Operand::Variable(String::from("a")), condition: Condition::Greater, rhs: Operand::Variable(String::from("b")) },
+                otherwise: Operand::Variable(String::from("b")),
+            },
+        });
+        assert_eq!(parse_line("let $s = \"big\" if not $a < $b else -1").unwrap(), Instruction::Let {
+            name: String::from("s"),
+            value: Expression::Conditional {
+                then: Operand::Literal(String::from("big")),
+                predicate: Predicate::Not(Box::new(Predicate::Compare {
+                    lhs: Operand::Variable(String::from("a")), condition: Condition::Less, rhs: Operand::Variable(String::from("b")),
+                })),
+                otherwise: Operand::Literal(String::from("-1")),
+            },
+        });
+        assert_eq!(parse_line("let $m = $a if $a > $b").err().unwrap(), error(23, "expected 'else'"));
+        assert_eq!(parse_line("let $m = $a if $a > $b else").err().unwrap(), error(28, "expected a value"));
+        assert_eq!(parse_line("let $m = $a + $b if $a > $b else $b").err().unwrap(), error(18, "expected end of line"));
+    }
+
+    #[test]
+    fn parse_compound_assignments() {
+        let operation = |operator| Instruction::Let {
+            name: String::from("a"),
+            value: Expression::Operation { lhs: String::from("a"), operator, rhs: String::from("b") },
+        };
+        assert_eq!(parse_line("let $a += $b").unwrap(), operation(Operator::Add));
+        assert_eq!(parse_line("let $a -= $b").unwrap(), operation(Operator::Subtract));
+        assert_eq!(parse_line("let $a *= $b").unwrap(), operation(Operator::Multiply));
+        assert_eq!(parse_line("let $a /= $b").unwrap(), operation(Operator::Divide));
+        assert_eq!(parse_line("let $a //= $b").unwrap(), operation(Operator::IntegerDivide));
+        assert_eq!(parse_line("let $a + = $b").err().unwrap(), error(8, "expected '='"));
+        assert_eq!(parse_line("let $a += 1").err().unwrap(), error(11, "expected a variable"));
+    }
+
+    #[test]
+    fn parse_negation() {
+        assert_eq!(parse_line("let $b = -$a").unwrap(),
+                   Instruction::Let { name: String::from("b"), value: Expression::Negate(String::from("a")) });
+        assert_eq!(parse_line("let $b = neg $a").unwrap(),
+                   Instruction::Let { name: String::from("b"), value: Expression::Negate(String::from("a")) });
+        assert_eq!(parse_line("let $b = - $a").unwrap(),
+                   Instruction::Let { name: String::from("b"), value: Expression::Negate(String::from("a")) });
+        assert_eq!(parse_line("let $b = -\"a\"").err().unwrap(), error(11, "expected a variable"));
+    }
+
+    #[test]
+    fn parse_negative_numbers() {
+        assert_eq!(parse_line("push -3").unwrap(), Instruction::Push(Operand::Literal(String::from("-3"))));
+        assert_eq!(parse_line("let $a = call f -1 $b").unwrap(), Instruction::Let {
+            name: String::from("a"),
+            value: Expression::Call {
+                function: String::from("f"),
+                args: vec![Operand::Literal(String::from("-1")), Operand::Variable(String::from("b"))],
+            },
+        });
+        assert_eq!(parse_line("let $m{-1} = -2").unwrap(), Instruction::SetEntry {
+            map: String::from("m"),
+            key: Operand::Literal(String::from("-1")),
+            value: Operand::Literal(String::from("-2")),
+        });
+        assert_eq!(parse_line("if $a < -1 goto 5").unwrap(), Instruction::If {
+            predicate: Predicate::Compare {
+                lhs: Operand::Variable(String::from("a")), condition: Condition::Less, rhs: Operand::Literal(String::from("-1")),
+            },
+            then: Box::new(Instruction::Goto(5)),
+            then_source: String::from("goto 5"),
+            otherwise: None,
+        });
+        assert_eq!(parse_line("assert -2.5 != \"x\"").unwrap(), Instruction::Assert {
+            lhs: Operand::Literal(String::from("-2.5")), condition: Condition::NotEqual, rhs: Operand::Literal(String::from("x")),
+        });
+        // After a value, '-' subtracts
+        assert_eq!(parse_line("let $a = $b -5").err().unwrap(), error(14, "expected a variable"));
+        assert_eq!(parse_line("goto -1").err().unwrap(), error(6, "line number cannot be negative"));
+        assert_eq!(parse_line("let $a = input timeout -5 default \"x\"").err().unwrap(), error(24, "timeout cannot be negative"));
+    }
+
+    #[test]
+    fn parse_constants() {
+        assert_eq!(parse_line("const $PI = 3").unwrap(),
+                   Instruction::Const { name: String::from("PI"), value: Operand::Literal(String::from("3")) });
+        assert_eq!(parse_line("const $name = $a").unwrap(),
+                   Instruction::Const { name: String::from("name"), value: Operand::Variable(String::from("a")) });
+        assert_eq!(parse_line("unset $a").unwrap(), Instruction::Unset(String::from("a")));
+        assert_eq!(parse_line("const $PI = $a + $b").err().unwrap(), error(16, "expected end of line"));
+    }
+
+    #[test]
+    fn parse_prompted_input() {
+        assert_eq!(parse_line("let $name = input \"Enter your name\"").unwrap(),
+                   Instruction::Let { name: String::from("name"), value: Expression::PromptedInput(String::from("Enter your name")) });
+        assert_eq!(parse_line("let $name = input $prompt").err().unwrap(), error(19, "expected end of line"));
+    }
+
+    #[test]
+    fn parse_number_input() {
+        assert_eq!(parse_line("let $n = inputnum").unwrap(),
+                   Instruction::Let { name: String::from("n"), value: Expression::InputNum(None) });
+        assert_eq!(parse_line("let $n = inputnum \"Age\"").unwrap(),
+                   Instruction::Let { name: String::from("n"), value: Expression::InputNum(Some(String::from("Age"))) });
+    }
+
+    #[test]
+    fn parse_string_builtins() {
+        assert_eq!(parse_line("let $a = pad $b $w").unwrap(), Instruction::Let {
+            name: String::from("a"),
+            value: Expression::Pad { value: String::from("b"), width: String::from("w"), fill: None },
+        });
+        assert_eq!(parse_line("let $a = pad $b $w $c").unwrap(), Instruction::Let {
+            name: String::from("a"),
+            value: Expression::Pad { value: String::from("b"), width: String::from("w"), fill: Some(String::from("c")) },
+        });
+        assert_eq!(parse_line("let $a = repeat $b $n").unwrap(), Instruction::Let {
+            name: String::from("a"),
+            value: Expression::Repeat { value: String::from("b"), count: String::from("n") },
+        });
+        assert_eq!(parse_line("let $a = reverse $b").unwrap(),
+                   Instruction::Let { name: String::from("a"), value: Expression::Reverse(String::from("b")) });
+        assert_eq!(parse_line("let $a = repeat $b 3").err().unwrap(), error(20, "expected a variable"));
+    }
+
+    #[test]
+    fn parse_exec() {
+        assert_eq!(parse_line("let $a = exec \"date\"").unwrap(),
+                   Instruction::Let { name: String::from("a"), value: Expression::Exec(Operand::Literal(String::from("date"))) });
+        assert_eq!(parse_line("let $a = exec $cmd").unwrap(),
+                   Instruction::Let { name: String::from("a"), value: Expression::Exec(Operand::Variable(String::from("cmd"))) });
+        assert_eq!(parse_line("let $x = exec \"ls -l /tmp | wc -l\"").unwrap(),
+                   Instruction::Let { name: String::from("x"), value: Expression::Exec(Operand::Literal(String::from("ls -l /tmp | wc -l"))) });
+        assert_eq!(parse_line("let $a = exec 5").err().unwrap(), error(15, "expected a command"));
+    }
+
+    #[test]
+    fn parse_filesystem_queries() {
+        assert_eq!(parse_line("let $a = exists $path").unwrap(),
+                   Instruction::Let { name: String::from("a"), value: Expression::Exists(String::from("path")) });
+        assert_eq!(parse_line("delete $path").unwrap(), Instruction::Delete(String::from("path")));
+        assert_eq!(parse_line("listdir $path").unwrap(), Instruction::ListDir(String::from("path")));
+        assert_eq!(parse_line("listdir \"dir\"").err().unwrap(), error(9, "expected a variable"));
+    }
+
+    #[test]
+    fn parse_random_numbers() {
+        assert_eq!(parse_line("let $a = random $n").unwrap(),
+                   Instruction::Let { name: String::from("a"), value: Expression::Random(String::from("n")) });
+        assert_eq!(parse_line("seed $n").unwrap(), Instruction::Seed(String::from("n")));
+        assert_eq!(parse_line("seed 42").err().unwrap(), error(6, "expected a variable"));
+    }
+
+    #[test]
+    fn parse_functions() {
+        assert_eq!(parse_line("func add $x $y").unwrap(),
+                   Instruction::Func { name: String::from("add"), params: vec![String::from("x"), String::from("y")] });
+        assert_eq!(parse_line("func main").unwrap(), Instruction::Func { name: String::from("main"), params: Vec::new() });
+        assert_eq!(parse_line("func $x").err().unwrap(), error(6, "expected a name"));
+        assert_eq!(parse_line("func add $x 2").err().unwrap(), error(13, "expected end of line"));
+        assert_eq!(parse_line("endfunc").unwrap(), Instruction::EndFunc);
+        assert_eq!(parse_line("local $i").unwrap(), Instruction::Local(String::from("i")));
+        assert_eq!(parse_line("let $z = call add $a 2").unwrap(), Instruction::Let {
+            name: String::from("z"),
+            value: Expression::Call {
+                function: String::from("add"),
+                args: vec![Operand::Variable(String::from("a")), Operand::Literal(String::from("2"))],
+            },
+        });
+        assert_eq!(parse_line("return $v").unwrap(), Instruction::Return(Some(Operand::Variable(String::from("v")))));
+        assert_eq!(parse_line("return \"done\"").unwrap(), Instruction::Return(Some(Operand::Literal(String::from("done")))));
+    }
+
+    #[test]
+    fn parse_imports() {
+        assert_eq!(parse_line("import math_lib as m").unwrap(),
+                   Instruction::Import { module: String::from("math_lib"), alias: String::from("m") });
+        assert_eq!(parse_line("import strings").unwrap(),
+                   Instruction::Import { module: String::from("strings"), alias: String::from("strings") });
+        assert_eq!(parse_line("let $g = call m.gcd $m.last 2").unwrap(), Instruction::Let {
+            name: String::from("g"),
+            value: Expression::Call {
+                function: String::from("m.gcd"),
+                args: vec![Operand::Variable(String::from("m.last")), Operand::Literal(String::from("2"))],
+            },
+        });
+        // A '.' before a variable still joins two values
+        assert_eq!(parse_line("let $c = $a.$b").unwrap(), Instruction::Let {
+            name: String::from("c"),
+            value: Expression::Concat { lhs: String::from("a"), rhs: String::from("b") },
+        });
+        assert_eq!(parse_line("import lib.math").err().unwrap(), error(8, "expected a module name"));
+        assert_eq!(parse_line("import math as").err().unwrap(), error(15, "expected a module name"));
+    }
+
+    #[test]
+    fn parse_branches() {
+        let compare = |lhs: &str, condition: Condition, rhs: &str| {
+            Predicate::Compare { lhs: Operand::Variable(lhs.to_string()), condition, rhs: Operand::Variable(rhs.to_string()) }
+        };
+        assert_eq!(parse_line("if $a <= $b goto 12").unwrap(), Instruction::If {
+            predicate: compare("a", Condition::LessEqual, "b"),
+            then: Box::new(Instruction::Goto(12)),
+            then_source: String::from("goto 12"),
+            otherwise: None,
+        });
+        assert_eq!(parse_line("if $a = $b  let $c = 1").unwrap(), Instruction::If {
+            predicate: compare("a", Condition::Equal, "b"),
+            then: Box::new(Instruction::Let { name: String::from("c"), value: Expression::Literal(String::from("1")) }),
+            then_source: String::from("let $c = 1"),
+            otherwise: None,
+        });
+        // 'not' binds tighter than 'and', which binds tighter than 'or'
+        assert_eq!(parse_line("if $a < $b or not $c = $d and $e != $f goto 7").unwrap(), Instruction::If {
+            predicate: Predicate::Or(
+                Box::new(compare("a", Condition::Less, "b")),
+                Box::new(Predicate::And(
+                    Box::new(Predicate::Not(Box::new(compare("c", Condition::Equal, "d")))),
+                    Box::new(compare("e", Condition::NotEqual, "f")),
+                )),
+            ),
+            then: Box::new(Instruction::Goto(7)),
+            then_source: String::from("goto 7"),
+            otherwise: None,
+        });
+        assert_eq!(parse_line("if $a < $b and goto 7").err().unwrap(), error(16, "expected a variable or a value"));
+        assert_eq!(parse_line("if $a < $b output $a  else if $a = $b goto 3 else goto 9").unwrap(), Instruction::If {
+            predicate: compare("a", Condition::Less, "b"),
+            then: Box::new(Instruction::Output(vec![Operand::Variable(String::from("a"))])),
+            then_source: String::from("output $a"),
+            otherwise: Some((Box::new(Instruction::If {
+                predicate: compare("a", Condition::Equal, "b"),
+                then: Box::new(Instruction::Goto(3)),
+                then_source: String::from("goto 3"),
+                otherwise: Some((Box::new(Instruction::Goto(9)), String::from("goto 9"))),
+            }), String::from("if $a = $b goto 3 else goto 9"))),
+        });
+        assert_eq!(parse_line("if $a < $b goto 3 else").err().unwrap(), error(23, "expected an instruction"));
+        assert_eq!(parse_line("if $a < $b goto 3 output $a").err().unwrap(), error(19, "expected end of line"));
+        assert_eq!(parse_line("goto 3").unwrap(), Instruction::Goto(3));
+        assert_eq!(parse_line("gosub 7").unwrap(), Instruction::Gosub(7));
+        assert_eq!(parse_line("return").unwrap(), Instruction::Return(None));
+        assert_eq!(parse_line("label loop_start").unwrap(), Instruction::Label(String::from("loop_start")));
+        assert_eq!(parse_line("sort").unwrap(), Instruction::Sort);
+        assert_eq!(parse_line("flush").unwrap(), Instruction::Flush);
+    }
+
+    #[test]
+    fn keywords_inside_variable_names() {
+        assert_eq!(parse_line("output $letter").unwrap(), Instruction::Output(vec![Operand::Variable(String::from("letter"))]));
+    }
+
+    #[test]
+    fn parse_output_of_several_values() {
+        assert_eq!(parse_line("output \"Result: \" $a \" of \" $b 10").unwrap(), Instruction::Output(vec![
+            Operand::Literal(String::from("Result: ")),
+            Operand::Variable(String::from("a")),
+            Operand::Literal(String::from(" of ")),
+            Operand::Variable(String::from("b")),
+            Operand::Literal(String::from("10")),
+        ]));
+        assert_eq!(parse_line("output").err().unwrap(), error(7, "expected a variable or a value"));
+    }
+
+    #[test]
+    fn report_error_columns() {
+        assert_eq!(parse_line("goto e").err().unwrap(), error(6, "expected a line number"));
+        assert_eq!(parse_line("if $a < $b").err().unwrap(), error(11, "expected an instruction"));
+        assert_eq!(parse_line("if $a < $b jump 4").err().unwrap(), error(12, "unknown instruction 'jump'"));
+        assert_eq!(parse_line("let $a = 5 $b").err().unwrap(), error(12, "expected end of line"));
+        assert_eq!(parse_line("let $a = \"hi 2").err().unwrap(), error(10, "unterminated string"));
+        assert_eq!(parse_line("output $").err().unwrap(), error(8, "expected a variable name after '$'"));
+        assert_eq!(parse_line("go to 0").err().unwrap(), error(1, "unknown instruction 'go'"));
+        assert_eq!(parse_line("let $a = 3.").err().unwrap(), error(11, "expected end of line"));
+        assert_eq!(parse_line("goto 1.5").err().unwrap(), error(6, "expected a line number"));
+    }
+
+    #[test]
+    fn every_instruction_keyword_is_parsed() {
+        for keyword in INSTRUCTION_KEYWORDS {
+            if let Err(err) = parse_line(keyword) {
+                assert!(!err.message.starts_with("unknown instruction"), "{} is not parsed", keyword);
+            }
+        }
+    }
+}
@@ -1,26 +1,355 @@
-use std::collections::{HashMap, LinkedList};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, LinkedList};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use crate::aliases::default_aliases;
+use crate::console::{Console, StdConsole};
+use crate::dialect::Dialect;
+use crate::host::{HostBackend, HostFile, OsBackend};
+use crate::json::{self, Json};
+use crate::parser::{Instruction, ParseError, ParsedCode};
+#[cfg(feature = "net")]
+use crate::net::NetBackend;
+use crate::storage::{CodeStore, StackStore, VariableStore};
 
+/// A value held by a variable or on the stack. Values keep their type while the program runs, so
+/// arithmetic and comparisons work on numbers rather than their text. Values read as text (such
+/// as literals, input and the output of commands) are given a type by `Value::from_text`.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Int(i128),
+    Float(f64),
+    Str(String),
+    /// A truth value, written as 1 or 0 as the interpreter has always written them
+    Bool(bool),
+    /// Values looked up by key, created by 'map'. Keys are kept in order, so a map is always
+    /// written out the same way.
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// Finds the type of a value read as text: a number written the way the interpreter writes
+    /// numbers (such as `42`, `-7` or `3.14`) is an `Int` or a `Float`, and anything else is a
+    /// `Str`. Text such as `007` or `2.50` stays a string, so that it is written out exactly as it
+    /// was read.
+    pub fn from_text(text: &str) -> Self {
+        if let Ok(number) = text.parse::<i128>() {
+            if number.to_string() == text {
+                return Value::Int(number);
+            }
+        }
+        match parse_float(text) {
+            Some(number) if format!("{:?}", number) == text => Value::Float(number),
+            _ => Value::Str(text.to_string()),
+        }
+    }
+
+    /// Converts the value to an integer, if it holds one or is text that reads as one.
+    pub fn as_int(&self) -> Option<i128> {
+        match self {
+            Value::Int(number) => Some(*number),
+            Value::Bool(truth) => Some(*truth as i128),
+            Value::Str(text) => text.parse::<i128>().ok(),
+            Value::Float(_) | Value::Map(_) => None,
+        }
+    }
+
+    /// Converts the value to a float, if it holds a number or is text that reads as one.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Int(number) => Some(*number as f64),
+            Value::Float(number) => Some(*number),
+            Value::Bool(truth) => Some(*truth as u8 as f64),
+            Value::Str(text) => parse_float(text),
+            Value::Map(_) => None,
+        }
+    }
+
+    /// Returns the text of the value, as it is written out.
+    pub fn as_text(&self) -> Cow<'_, str> {
+        match self {
+            Value::Str(text) => Cow::Borrowed(text),
+            value => Cow::Owned(value.to_string()),
+        }
+    }
+
+    /// Names the type of the value, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "an integer",
+            Value::Float(_) => "a float",
+            Value::Str(_) => "a string",
+            Value::Bool(_) => "a boolean",
+            Value::Map(_) => "a map",
+        }
+    }
+}
+
+/// Reads a float written with digits, an optional sign, decimal point and exponent, such as
+/// `3.14`, `-0.5` or `1e-7`. Words such as `inf` and `NaN` are not numbers in a program.
+fn parse_float(text: &str) -> Option<f64> {
+    fn unsigned(text: &str) -> &str {
+        text.strip_prefix(['+', '-']).unwrap_or(text)
+    }
+    fn digits(text: &str) -> bool {
+        !text.is_empty() && text.chars().all(|c| c.is_ascii_digit())
+    }
+
+    let (mantissa, exponent) = match unsigned(text).split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(unsigned(exponent))),
+        None => (unsigned(text), None),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, "0"));
+    if !digits(whole) || !digits(fraction) || !exponent.is_none_or(digits) {
+        return None;
+    }
+    text.parse::<f64>().ok().filter(|number| number.is_finite())
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(number) => write!(f, "{}", number),
+            // Floats always have a decimal point (or an exponent), so they do not read as integers
+            Value::Float(number) => write!(f, "{:?}", number),
+            Value::Str(text) => write!(f, "{}", text),
+            Value::Bool(truth) => write!(f, "{}", *truth as u8),
+            Value::Map(entries) => {
+                let entries: Vec<String> = entries.iter().map(|(key, value)| format!("{}: {}", key, value)).collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            },
+        }
+    }
+}
+
+/// Values are equal if they have the same type and value, or are written the same way.
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(lhs), Value::Int(rhs)) => lhs == rhs,
+            (Value::Float(lhs), Value::Float(rhs)) => lhs == rhs,
+            (Value::Bool(lhs), Value::Bool(rhs)) => lhs == rhs,
+            (Value::Map(lhs), Value::Map(rhs)) => lhs == rhs,
+            (lhs, rhs) => lhs.as_text() == rhs.as_text(),
+        }
+    }
+}
+
+impl PartialEq<str> for Value {
+    fn eq(&self, other: &str) -> bool {
+        self.as_text() == other
+    }
+}
+
+impl PartialEq<&str> for Value {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_text() == *other
+    }
+}
+
+impl PartialEq<String> for Value {
+    fn eq(&self, other: &String) -> bool {
+        self.as_text() == other.as_str()
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        Value::from_text(&text)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Self {
+        Value::from_text(text)
+    }
+}
+
+impl From<i128> for Value {
+    fn from(number: i128) -> Self {
+        Value::Int(number)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(truth: bool) -> Self {
+        Value::Bool(truth)
+    }
+}
+
+/// A subroutine or function call the program is inside.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Frame {
+    /// The line the call was made from, which the program carries on after once it returns
+    pub call_site: usize,
+    /// The variable the value returned by a function is assigned to, or None for a subroutine
+    pub result: Option<String>,
+    /// Variables that only exist while the call runs, such as the parameters of a function
+    pub locals: HashMap<String, Value>,
+}
+
+/// The line of code being run and its parsed instruction. They are shared with the program data
+/// rather than borrowed from it, so that an instruction can change the program data as it runs.
+#[derive(Clone, Debug)]
+pub struct CodeLine {
+    code: Arc<ParsedCode>,
+    // Always the index of a line of the code
+    index: usize,
+}
+
+impl CodeLine {
+    /// Returns the line, with any keyword alias replaced by the keyword it stands for.
+    pub fn source(&self) -> &str {
+        self.code.line(self.index).unwrap_or_default()
+    }
+
+    /// Returns the instruction on the line, or the syntax error found on it.
+    pub fn instruction(&self) -> &Result<Instruction, ParseError> {
+        &self.code.instructions()[self.index]
+    }
+}
+
+/// The state of a running program. It is `Send` and `Sync`, so a program can be moved onto
+/// another thread or kept in shared state (such as a web server's sessions) behind a lock.
 pub struct ProgramData {
-    code: Vec<String>,
-    vars: HashMap<String, String>,
-    stack: LinkedList<String>,
+    code: Box<dyn CodeStore>,
+    vars: Box<dyn VariableStore>,
+    stack: Box<dyn StackStore>,
     index: usize,
+    // The instructions of the code, which programs sharing their code also share
+    parsed: Arc<ParsedCode>,
+    // Instruction run in place of the current line, such as the tail of an 'if' statement
+    inline_code: Option<Arc<ParsedCode>>,
+    // Subroutine and function calls the program is inside, outermost first
+    calls: Vec<Frame>,
+    // Variables declared with 'const', which cannot be assigned again
+    constants: BTreeSet<String>,
+    console: Box<dyn Console>,
+    dialect: Dialect,
+    exec_allowed: bool,
+    fs_allowed: bool,
+    env_allowed: bool,
+    // The arguments given to the program on the command line, after the program file
+    args: Vec<String>,
+    // Directory that relative paths used by the program start from, if not the current one
+    base_dir: Option<PathBuf>,
+    // Files opened with 'openfile', keyed by their handles
+    files: BTreeMap<usize, Box<dyn HostFile>>,
+    // What 'exec', 'env' and the filesystem instructions get from the machine
+    host: Box<dyn HostBackend>,
+    // Source of the numbers given out by 'random'
+    rng: StdRng,
+    #[cfg(feature = "net")]
+    net: Option<Box<dyn NetBackend>>,
 }
 
 impl ProgramData {
 
+    /// Creates program data from the text of its variables and stack, which are given their types
+    /// by `Value::from_text`.
     pub fn new(code: Vec<String>,
                vars: HashMap<String, String>,
                stack: LinkedList<String>,
                index: usize) -> Self {
-        ProgramData{code, vars, stack, index}
+        let vars: HashMap<String, Value> = vars.into_iter().map(|(name, value)| (name, Value::from(value))).collect();
+        let stack: LinkedList<Value> = stack.into_iter().map(Value::from).collect();
+        ProgramData::with_storage(Box::new(code), Box::new(vars), Box::new(stack), index)
+    }
+
+    /// Creates program data that keeps its code, variables and stack in the given stores, for
+    /// example to record how a program uses its memory.
+    pub fn with_storage(code: Box<dyn CodeStore>,
+                        vars: Box<dyn VariableStore>,
+                        stack: Box<dyn StackStore>,
+                        index: usize) -> Self {
+        let parsed = code.parsed().unwrap_or_else(|| {
+            let lines = (0..code.len()).filter_map(|index| code.get(index)).map(String::as_str);
+            Arc::new(ParsedCode::parse(lines, &default_aliases()))
+        });
+        ProgramData{
+            code,
+            parsed,
+            vars,
+            stack,
+            index,
+            inline_code: None,
+            calls: Vec::new(),
+            constants: BTreeSet::new(),
+            console: Box::new(StdConsole::new()),
+            dialect: Dialect::default(),
+            exec_allowed: false,
+            fs_allowed: false,
+            env_allowed: false,
+            args: Vec::new(),
+            base_dir: None,
+            files: BTreeMap::new(),
+            host: Box::new(OsBackend::new()),
+            rng: StdRng::from_entropy(),
+            #[cfg(feature = "net")]
+            net: None,
+        }
+    }
+
+    /// Returns the instruction at the current index, with any keyword alias replaced by the
+    /// keyword it stands for.
+    pub fn get_code(&self) -> Option<&str> {
+        match &self.inline_code {
+            Some(inline_code) => inline_code.line(0),
+            None => self.parsed.line(self.index),
+        }
     }
 
-    pub fn get_code(&self) -> Option<String> {
-        self.code.get(self.index).cloned()
+    /// Returns the line at the current index together with its parsed instruction, which can be
+    /// kept while the program data changes.
+    pub fn code_line(&self) -> Option<CodeLine> {
+        let (code, index) = match &self.inline_code {
+            Some(inline_code) => (inline_code, 0),
+            None => (&self.parsed, self.index),
+        };
+        code.line(index)?;
+        Some(CodeLine { code: code.clone(), index })
+    }
+
+    /// Runs `code` in place of the current line. The current line is used again once the index
+    /// changes.
+    pub fn set_inline_code(&mut self, code: String) {
+        self.inline_code = Some(Arc::new(ParsedCode::parse([code.as_str()], self.parsed.aliases())));
+    }
+
+    /// Runs an instruction that has already been parsed in place of the current line, such as the
+    /// tail of an 'if' statement.
+    pub(crate) fn set_inline_instruction(&mut self, code: &str, instruction: &Instruction) {
+        self.inline_code = Some(Arc::new(ParsedCode::from_instruction(code, instruction)));
+    }
+
+    /// Returns the instruction being run in place of the current line, if there is one.
+    pub fn inline_code(&self) -> Option<&str> {
+        self.inline_code.as_ref().and_then(|inline_code| inline_code.line(0))
+    }
+
+    /// Returns the parsed instruction at the current index, or the syntax error found on it.
+    /// Every line is parsed once, when the program is loaded.
+    pub fn instruction(&self) -> Option<&Result<Instruction, ParseError>> {
+        match &self.inline_code {
+            Some(inline_code) => inline_code.instruction(0),
+            None => self.parsed.instruction(self.index),
+        }
+    }
+
+    /// Returns the parsed instruction on the line at `index`, or the syntax error found on it.
+    pub fn instruction_at(&self, index: usize) -> Option<&Result<Instruction, ParseError>> {
+        self.parsed.instruction(index)
+    }
+
+    pub fn get_code_at(&self, index: usize) -> Option<&String> {
+        self.code.get(index)
     }
 
     pub fn set_index(&mut self, new_index: usize) {
+        self.inline_code = None;
         self.index = new_index;
     }
 
@@ -29,34 +358,800 @@ impl ProgramData {
     }
 
     pub fn next_line(&mut self) {
+        self.inline_code = None;
         self.index += 1;
     }
 
-    pub fn push(&mut self, data: String) {
-        self.stack.push_front(data);
+    pub fn push(&mut self, data: impl Into<Value>) {
+        self.stack.push(data.into());
+    }
+
+    pub fn pop(&mut self) -> Option<Value> {
+        self.stack.pop()
+    }
+
+    /// Sorts the stack so that its smallest value is on top, and values are popped in order.
+    /// Values are compared as numbers if they all are numbers (exactly, if they are all
+    /// integers), otherwise as strings. Equal numbers, such as 2 and 2.0, are ordered by their text.
+    pub fn sort_stack(&mut self) {
+        let mut values: Vec<Value> = std::iter::from_fn(|| self.stack.pop()).collect();
+        let integers: Option<Vec<i128>> = values.iter().map(Value::as_int).collect();
+        let floats: Option<Vec<f64>> = values.iter().map(Value::as_float).collect();
+        match (integers, floats) {
+            (Some(integers), _) => {
+                let mut sorted: Vec<(i128, String, Value)> = integers.into_iter().zip(values)
+                    .map(|(number, value)| (number, value.to_string(), value))
+                    .collect();
+                sorted.sort_by(|lhs, rhs| (lhs.0, &lhs.1).cmp(&(rhs.0, &rhs.1)));
+                values = sorted.into_iter().map(|(_, _, value)| value).collect();
+            },
+            (None, Some(floats)) => {
+                let mut sorted: Vec<(f64, String, Value)> = floats.into_iter().zip(values)
+                    .map(|(number, value)| (number, value.to_string(), value))
+                    .collect();
+                sorted.sort_by(|lhs, rhs| lhs.0.total_cmp(&rhs.0).then_with(|| lhs.1.cmp(&rhs.1)));
+                values = sorted.into_iter().map(|(_, _, value)| value).collect();
+            },
+            (None, None) => values.sort_by_key(|value| value.to_string()),
+        }
+        for value in values.into_iter().rev() {
+            self.stack.push(value);
+        }
+    }
+
+    /// Looks up a variable. Variables local to the calls the program is inside are found first,
+    /// starting with the innermost call, so they hide variables of the same name outside it.
+    pub fn get_var(&self, key: &str) -> Option<&Value> {
+        self.calls.iter().rev().find_map(|frame| frame.locals.get(key)).or_else(|| self.vars.get(key))
+    }
+
+    /// Sets a variable, changing the local variable that `get_var` would find if there is one.
+    pub fn set_var(&mut self, key: String, value: impl Into<Value>) {
+        match self.calls.iter_mut().rev().find(|frame| frame.locals.contains_key(&key)) {
+            Some(frame) => {
+                frame.locals.insert(key, value.into());
+            },
+            None => self.vars.set(key, value.into()),
+        }
+    }
+
+    /// Records that the subroutine call on line `call_site` has been made.
+    pub fn push_call(&mut self, call_site: usize) {
+        self.calls.push(Frame { call_site, ..Frame::default() });
+    }
+
+    /// Returns from the innermost subroutine call, giving the line the call was made from.
+    pub fn pop_call(&mut self) -> Option<usize> {
+        self.calls.pop().map(|frame| frame.call_site)
+    }
+
+    /// Records that a call has been made, along with the variables local to it.
+    pub fn push_frame(&mut self, frame: Frame) {
+        self.calls.push(frame);
+    }
+
+    /// Returns from the innermost call, giving it back along with its local variables.
+    pub fn pop_frame(&mut self) -> Option<Frame> {
+        self.calls.pop()
+    }
+
+    /// Makes a variable local to the innermost call, starting out as an empty string, so that it
+    /// hides any variable of the same name outside the call until the call returns.
+    ///
+    /// # Returns
+    /// * `true` - The variable was declared
+    /// * `false` - The program is not inside a call
+    pub fn declare_local(&mut self, key: String) -> bool {
+        match self.calls.last_mut() {
+            Some(frame) => {
+                frame.locals.insert(key, Value::from(""));
+                true
+            },
+            None => false,
+        }
     }
 
-    pub fn pop(&mut self) -> Option<String> {
-        self.stack.pop_front()
+    /// Returns the innermost call the program is inside.
+    pub fn current_frame(&self) -> Option<&Frame> {
+        self.calls.last()
     }
 
-    pub fn get_var(&self, key: &String) -> Option<&String> {
-        self.vars.get(&*key)
+    /// Returns the calls the program is inside, outermost first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.calls
     }
 
-    pub fn set_var(&mut self, key: String, value: String) {
-        self.vars.insert(key, value);
+    /// Replaces the calls the program is inside, given outermost first as they are returned by
+    /// `frames`.
+    pub fn set_frames(&mut self, frames: Vec<Frame>) {
+        self.calls = frames;
+    }
+
+    /// Returns the number of subroutine calls the program is inside.
+    pub fn call_depth(&self) -> usize {
+        self.calls.len()
+    }
+
+    /// Returns the lines of the subroutine calls the program is inside, innermost first.
+    pub fn call_chain(&self) -> Vec<usize> {
+        self.calls.iter().rev().map(|frame| frame.call_site).collect()
+    }
+
+    /// Removes a variable, returning its value if it was set. A local variable is removed in
+    /// preference to one outside the call, and a constant stops being one once it is removed.
+    pub fn remove_var(&mut self, key: &str) -> Option<Value> {
+        match self.calls.iter_mut().rev().find(|frame| frame.locals.contains_key(key)) {
+            Some(frame) => frame.locals.remove(key),
+            None => {
+                self.constants.remove(key);
+                self.vars.remove(key)
+            },
+        }
+    }
+
+    /// Sets a variable outside every call that cannot be assigned again.
+    ///
+    /// # Returns
+    /// * `true` - The constant was declared
+    /// * `false` - A constant of the same name has already been declared
+    pub fn declare_constant(&mut self, key: String, value: impl Into<Value>) -> bool {
+        if self.constants.contains(&key) {
+            return false;
+        }
+        self.vars.set(key.clone(), value.into());
+        self.constants.insert(key);
+        true
+    }
+
+    /// Checks whether assigning the variable would change a constant. A local variable of the same
+    /// name hides the constant, so it can be assigned.
+    pub fn is_constant(&self, key: &str) -> bool {
+        self.constants.contains(key) && !self.calls.iter().any(|frame| frame.locals.contains_key(key))
     }
 
     pub fn set_var_to_var(&mut self, lhs_key: String, rhs_key: String) {
-        self.vars.insert(lhs_key, (*self.get_var(&rhs_key).unwrap()).parse().unwrap());
+        let value = self.get_var(&rhs_key).unwrap().clone();
+        self.set_var(lhs_key, value);
+    }
+
+    pub fn contains_var(&self, key: &str) -> bool {
+        self.calls.iter().any(|frame| frame.locals.contains_key(key)) || self.vars.contains(key)
     }
 
-    pub fn contains_var(&self, key: &String) -> bool {
-        self.vars.contains_key(&*key)
+    /// Returns the number of variables that are set.
+    pub fn var_count(&self) -> usize {
+        match self.calls.iter().all(|frame| frame.locals.is_empty()) {
+            true => self.vars.count(),
+            false => self.iter_vars().count(),
+        }
+    }
+
+    /// Returns the number of values on the stack.
+    pub fn stack_depth(&self) -> usize {
+        self.stack.depth()
     }
 
     pub fn code_size(&self) -> usize {
         self.code.len()
     }
-}
\ No newline at end of file
+
+    /// Returns true once execution has moved past the last line of the program.
+    pub fn at_end(&self) -> bool {
+        self.inline_code.is_none() && self.index >= self.code.len()
+    }
+
+    pub fn get_dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    /// Replaces the keyword aliases of the program, parsing its code again with them.
+    pub fn set_aliases(&mut self, aliases: HashMap<String, String>) {
+        let lines = (0..self.code.len()).filter_map(|index| self.code.get(index)).map(String::as_str);
+        self.parsed = Arc::new(ParsedCode::parse(lines, &aliases));
+    }
+
+    /// Replaces the console that the program reads input from and writes output to.
+    pub fn set_console(&mut self, console: Box<dyn Console>) {
+        self.console = console;
+    }
+
+    /// Replaces the console of the program with one built around it, such as a console that
+    /// records what the program writes.
+    pub fn wrap_console(&mut self, wrap: impl FnOnce(Box<dyn Console>) -> Box<dyn Console>) {
+        let console = std::mem::replace(&mut self.console, Box::new(StdConsole::new()));
+        self.console = wrap(console);
+    }
+
+    pub fn console(&mut self) -> &mut dyn Console {
+        self.console.as_mut()
+    }
+
+    /// Enables the 'exec' instruction, which lets the program run shell commands.
+    pub fn set_exec_allowed(&mut self, allowed: bool) {
+        self.exec_allowed = allowed;
+    }
+
+    pub fn exec_allowed(&self) -> bool {
+        self.exec_allowed
+    }
+
+    /// Restarts the program's random numbers from `seed`, so that the same numbers follow every time.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Returns a random number from 0 up to, but not including, `bound`, which must be positive.
+    pub fn random_below(&mut self, bound: i128) -> i128 {
+        self.rng.gen_range(0..bound)
+    }
+
+    /// Enables the instructions that use the filesystem.
+    pub fn set_fs_allowed(&mut self, allowed: bool) {
+        self.fs_allowed = allowed;
+    }
+
+    pub fn fs_allowed(&self) -> bool {
+        self.fs_allowed
+    }
+
+    /// Enables the 'env' expression, which lets the program read environment variables.
+    pub fn set_env_allowed(&mut self, allowed: bool) {
+        self.env_allowed = allowed;
+    }
+
+    pub fn env_allowed(&self) -> bool {
+        self.env_allowed
+    }
+
+    /// Gives the program the arguments read by 'arg' and counted by 'argc'.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Makes the relative paths the program uses (for files, and for commands it runs) start from
+    /// `dir` instead of the current directory, such as the directory of a project.
+    pub fn set_base_dir(&mut self, dir: PathBuf) {
+        self.base_dir = Some(dir);
+    }
+
+    pub fn base_dir(&self) -> Option<&Path> {
+        self.base_dir.as_deref()
+    }
+
+    /// Finds the file a path used by the program refers to.
+    pub fn resolve_path(&self, path: &str) -> PathBuf {
+        match &self.base_dir {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        }
+    }
+
+    /// Keeps a file the program has opened, returning the handle it is read with. Handles start
+    /// at 1, and follow the highest handle that is open.
+    pub fn add_file(&mut self, file: Box<dyn HostFile>) -> usize {
+        let handle = self.files.keys().next_back().map_or(1, |handle| handle + 1);
+        self.files.insert(handle, file);
+        handle
+    }
+
+    /// Returns the open file with the given handle.
+    pub fn file(&mut self, handle: usize) -> Option<&mut (dyn HostFile + 'static)> {
+        self.files.get_mut(&handle).map(|file| file.as_mut())
+    }
+
+    /// Closes the file with the given handle, returning false if it was not open.
+    pub fn close_file(&mut self, handle: usize) -> bool {
+        self.files.remove(&handle).is_some()
+    }
+
+    /// Makes the program run commands, read the environment and use files through `backend`
+    /// instead of the machine it runs on.
+    pub fn set_host_backend(&mut self, backend: Box<dyn HostBackend>) {
+        self.host = backend;
+    }
+
+    pub fn host_backend(&mut self) -> &mut dyn HostBackend {
+        self.host.as_mut()
+    }
+
+    /// Enables the networking instructions. Programs can only use the network once a backend has been set.
+    #[cfg(feature = "net")]
+    pub fn set_net_backend(&mut self, backend: Box<dyn NetBackend>) {
+        self.net = Some(backend);
+    }
+
+    #[cfg(feature = "net")]
+    pub fn net_backend(&mut self) -> Option<&mut (dyn NetBackend + 'static)> {
+        self.net.as_deref_mut()
+    }
+
+    /// Returns every variable (named without its leading '$') and its value, sorted by name.
+    /// Variables hidden by local variables of the same name are left out.
+    pub fn iter_vars(&self) -> impl Iterator<Item = (&str, &Value)> {
+        let mut vars: BTreeMap<&str, &Value> = self.vars.iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        for frame in &self.calls {
+            vars.extend(frame.locals.iter().map(|(name, value)| (name.as_str(), value)));
+        }
+        vars.into_iter()
+    }
+
+    /// Returns every variable outside the calls the program is inside (named without its
+    /// leading '$') and its value, in no particular order.
+    pub fn global_vars(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.vars.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// Returns every value on the stack, starting with the top of the stack.
+    pub fn iter_stack(&self) -> impl Iterator<Item = &Value> {
+        self.stack.iter()
+    }
+
+    /// Returns the instruction that will run next, as it was written, or `None` once the program
+    /// has finished.
+    pub fn current_line(&self) -> Option<&str> {
+        self.inline_code().or(self.code.get(self.index).map(String::as_str))
+    }
+
+    /// Returns every line of the program, in order.
+    pub fn code_lines(&self) -> impl Iterator<Item = &str> {
+        (0..self.code.len()).filter_map(|index| self.code.get(index)).map(String::as_str)
+    }
+
+    /// Describes the variables and stack of a program, with one variable per line and the values
+    /// lined up. Line breaks in values are shown as '\n', so that each variable stays on its line.
+    pub fn variable_table(&self) -> String {
+        let vars: Vec<(&str, &Value)> = self.iter_vars().collect();
+        let width = vars.iter().map(|(name, _)| name.chars().count()).max().unwrap_or(0);
+        let shown = |value: &Value| value.as_text().replace('\r', "\\r").replace('\n', "\\n");
+
+        let mut table = String::new();
+        if vars.is_empty() {
+            table.push_str("Variables: (none)\n");
+        } else {
+            table.push_str("Variables:\n");
+            for (name, value) in vars {
+                table.push_str(&format!("  ${:<width$} = {}\n", name, shown(value)));
+            }
+        }
+        if self.stack_depth() == 0 {
+            table.push_str("Stack: (empty)\n");
+        } else {
+            table.push_str("Stack:\n");
+            for (depth, value) in self.iter_stack().enumerate() {
+                table.push_str(&format!("  {}{}\n", shown(value), if depth == 0 { " (top)" } else { "" }));
+            }
+        }
+        table
+    }
+
+    /// Writes a snapshot of the program as JSON: its code, variables, stack (top first), calls
+    /// (innermost first), current line and dialect. The console, aliases and network backend are
+    /// not part of the snapshot. Values keep their types as JSON numbers, booleans, strings and
+    /// objects. A subroutine call is written as the line it was made from, and a function call
+    /// as an object that also holds its result variable and local variables.
+    pub fn to_json(&self) -> String {
+        let strings = |values: Vec<&str>| Json::Array(values.into_iter().map(|value| Json::String(value.to_string())).collect());
+        let vars_to_json = |vars: Vec<(&String, &Value)>| {
+            let mut vars: Vec<(String, Json)> = vars.into_iter()
+                .map(|(name, value)| (name.clone(), value_to_json(value)))
+                .collect();
+            vars.sort_by(|(lhs, _), (rhs, _)| lhs.cmp(rhs));
+            Json::Object(vars)
+        };
+        let vars = vars_to_json(self.vars.iter().collect());
+        let calls = self.calls.iter().rev().map(|frame| match frame.result.is_none() && frame.locals.is_empty() {
+            true => Json::Number(frame.call_site as i64),
+            false => Json::Object(vec![
+                (String::from("line"), Json::Number(frame.call_site as i64)),
+                (String::from("result"), frame.result.clone().map(Json::String).unwrap_or(Json::Null)),
+                (String::from("locals"), vars_to_json(frame.locals.iter().collect())),
+            ]),
+        }).collect();
+
+        Json::Object(vec![
+            (String::from("index"), Json::Number(self.index as i64)),
+            (String::from("inline_code"), self.inline_code().map(|code| Json::String(code.to_string())).unwrap_or(Json::Null)),
+            (String::from("dialect"), Json::String(self.dialect.to_string())),
+            (String::from("code"), strings(self.code_lines().collect())),
+            (String::from("vars"), vars),
+            (String::from("stack"), Json::Array(self.iter_stack().map(value_to_json).collect())),
+            (String::from("calls"), Json::Array(calls)),
+            (String::from("constants"), strings(self.constants.iter().map(String::as_str).collect())),
+        ]).to_string()
+    }
+
+    /// Restores a program from a snapshot written by `to_json`. The program uses the standard
+    /// console, default aliases and no network backend.
+    ///
+    /// # Returns
+    /// * `Ok(ProgramData)` - The restored program
+    /// * `Err(String)` - A message detailing why the snapshot could not be read
+    pub fn from_json(snapshot: &str) -> Result<Self, String> {
+        let snapshot = json::parse(snapshot)?;
+        let field = |name: &str| snapshot.get(name).ok_or(format!("Snapshot has no {} field!", name));
+        let strings = |name: &str| -> Result<Vec<String>, String> {
+            field(name)?.as_array()
+                .ok_or(format!("Snapshot field {} is not an array!", name))?
+                .iter()
+                .map(|value| value.as_str().map(String::from).ok_or(format!("Snapshot field {} must only hold strings!", name)))
+                .collect()
+        };
+
+        let index = field("index")?.as_i64().filter(|index| *index >= 0)
+            .ok_or(String::from("Snapshot field index is not a line number!"))?;
+        let vars_from_json = |vars: &Json, name: &str| -> Result<HashMap<String, Value>, String> {
+            vars.as_object()
+                .ok_or(format!("Snapshot field {} is not an object!", name))?
+                .iter()
+                .map(|(var, value)| value_from_json(value)
+                    .map(|value| (var.clone(), value))
+                    .ok_or(format!("Snapshot field {} must only hold numbers, booleans, strings and maps!", name)))
+                .collect()
+        };
+        let vars = vars_from_json(field("vars")?, "vars")?;
+        // The stack is written top first, which is the front of the list
+        let stack = field("stack")?.as_array()
+            .ok_or(String::from("Snapshot field stack is not an array!"))?
+            .iter()
+            .map(|value| value_from_json(value).ok_or(String::from("Snapshot field stack must only hold numbers, booleans, strings and maps!")))
+            .collect::<Result<LinkedList<Value>, String>>()?;
+
+        let line_number = |line: Option<&Json>| line.and_then(Json::as_i64).filter(|line| *line >= 0).map(|line| line as usize)
+            .ok_or(String::from("Snapshot field calls must only hold line numbers and calls!"));
+        let frame_from_json = |call: &Json| -> Result<Frame, String> {
+            if call.as_object().is_none() {
+                return line_number(Some(call)).map(|call_site| Frame { call_site, ..Frame::default() });
+            }
+            let result = match call.get("result") {
+                Some(Json::Null) | None => None,
+                Some(result) => Some(result.as_str().map(String::from)
+                    .ok_or(String::from("Snapshot field result is not a string!"))?),
+            };
+            let locals = match call.get("locals") {
+                Some(locals) => vars_from_json(locals, "locals")?,
+                None => HashMap::new(),
+            };
+            Ok(Frame { call_site: line_number(call.get("line"))?, result, locals })
+        };
+        // Snapshots written before subroutines existed have no calls. They are written innermost
+        // first, which is the end of the list of frames
+        let mut calls = match snapshot.get("calls") {
+            Some(calls) => calls.as_array()
+                .ok_or(String::from("Snapshot field calls is not an array!"))?
+                .iter()
+                .map(frame_from_json)
+                .collect::<Result<Vec<Frame>, String>>()?,
+            None => Vec::new(),
+        };
+        calls.reverse();
+
+        let mut data = ProgramData::with_storage(Box::new(strings("code")?), Box::new(vars), Box::new(stack), index as usize);
+        data.set_frames(calls);
+        // Neither do snapshots written before constants existed
+        if snapshot.get("constants").is_some() {
+            data.constants = strings("constants")?.into_iter().collect();
+        }
+        if let Some(inline_code) = field("inline_code")?.as_str() {
+            data.set_inline_code(inline_code.to_string());
+        }
+        data.dialect = field("dialect")?.as_str()
+            .ok_or(String::from("Snapshot field dialect is not a string!"))?
+            .parse::<Dialect>()?;
+        Ok(data)
+    }
+}
+
+/// Writes a value into a snapshot. Maps are written as objects, and everything else as text.
+fn value_to_json(value: &Value) -> Json {
+    match value {
+        Value::Int(number) => i64::try_from(*number).map(Json::Number).unwrap_or(Json::BigNumber(*number)),
+        Value::Float(number) => Json::Float(*number),
+        Value::Str(text) => Json::String(text.clone()),
+        Value::Bool(truth) => Json::Bool(*truth),
+        Value::Map(entries) => Json::Object(entries.iter().map(|(key, value)| (key.clone(), value_to_json(value))).collect()),
+    }
+}
+
+/// Reads a value written into a snapshot by `value_to_json`.
+fn value_from_json(json: &Json) -> Option<Value> {
+    match json {
+        Json::Number(number) => Some(Value::Int(*number as i128)),
+        Json::BigNumber(number) => Some(Value::Int(*number)),
+        Json::Float(number) => Some(Value::Float(*number)),
+        Json::String(text) => Some(Value::Str(text.clone())),
+        Json::Bool(truth) => Some(Value::Bool(*truth)),
+        Json::Object(entries) => entries.iter()
+            .map(|(key, value)| value_from_json(value).map(|value| (key.clone(), value)))
+            .collect::<Option<BTreeMap<String, Value>>>()
+            .map(Value::Map),
+        _ => None,
+    }
+}
+
+// Fails to compile if a field stops ProgramData from being sent to or shared with other threads
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<ProgramData>();
+};
+
+impl fmt::Debug for ProgramData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgramData")
+            .field("index", &self.index)
+            .field("inline_code", &self.inline_code())
+            .field("dialect", &self.dialect)
+            .field("code_size", &self.code.len())
+            .field("vars", &self.iter_vars().collect::<Vec<(&str, &Value)>>())
+            .field("stack", &self.iter_stack().collect::<Vec<&Value>>())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Shows the current line, the variables and the stack of a program, for example:
+///
+/// ```text
+/// Line 1: output $a
+/// Variables:
+///   $a = 5
+/// Stack:
+///   7 (top)
+///   3
+/// ```
+impl fmt::Display for ProgramData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get_code() {
+            Some(line) => writeln!(f, "Line {}: {}", self.index, line)?,
+            None => writeln!(f, "Line {}: (end of program)", self.index)?,
+        }
+
+        let vars: Vec<(&str, &Value)> = self.iter_vars().collect();
+        if vars.is_empty() {
+            writeln!(f, "Variables: (none)")?;
+        } else {
+            writeln!(f, "Variables:")?;
+            for (name, value) in vars {
+                writeln!(f, "  ${} = {}", name, value)?;
+            }
+        }
+
+        let mut stack = self.iter_stack().peekable();
+        if stack.peek().is_none() {
+            write!(f, "Stack: (empty)")
+        } else {
+            write!(f, "Stack:")?;
+            for (depth, value) in stack.enumerate() {
+                write!(f, "\n  {}{}", value, if depth == 0 { " (top)" } else { "" })?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashMap, LinkedList};
+    use std::sync::Arc;
+    use crate::code_loader::load_code_from_string;
+    use crate::runner::run;
+    use super::{Frame, ProgramData, Value};
+
+    fn program() -> ProgramData {
+        ProgramData::new(
+            vec![String::from("let $a = 5"), String::from("output $a")],
+            HashMap::from([(String::from("b"), String::from("hello")), (String::from("a"), String::from("5"))]),
+            LinkedList::from([String::from("7"), String::from("3")]),
+            1
+        )
+    }
+
+    #[test]
+    fn display_program_data() {
+        assert_eq!(program().to_string(),
+                   "Line 1: output $a\nVariables:\n  $a = 5\n  $b = hello\nStack:\n  7 (top)\n  3");
+
+        let data = ProgramData::new(Vec::new(), HashMap::new(), LinkedList::new(), 0);
+        assert_eq!(data.to_string(), "Line 0: (end of program)\nVariables: (none)\nStack: (empty)");
+    }
+
+    #[test]
+    fn variable_table_is_aligned() {
+        let mut data = program();
+        data.set_var(String::from("total"), String::from("a\nb"));
+        assert_eq!(data.variable_table(), "Variables:\n  $a     = 5\n  $b     = hello\n  $total = a\\nb\n\
+                                           Stack:\n  7 (top)\n  3\n");
+
+        let data = ProgramData::new(Vec::new(), HashMap::new(), LinkedList::new(), 0);
+        assert_eq!(data.variable_table(), "Variables: (none)\nStack: (empty)\n");
+    }
+
+    #[test]
+    fn typed_values() {
+        assert_eq!(Value::from_text("42"), Value::Int(42));
+        assert!(matches!(Value::from_text("-7"), Value::Int(-7)));
+        assert!(matches!(Value::from_text("007"), Value::Str(_)));
+        assert!(matches!(Value::from_text("hello"), Value::Str(_)));
+        assert_eq!(Value::from_text("007").as_int(), Some(7));
+        assert_eq!(Value::from_text("hello").as_int(), None);
+        assert_eq!(Value::Bool(true).to_string(), "1");
+        assert_eq!(Value::Bool(false).as_int(), Some(0));
+        assert_eq!(Value::Int(5), "5");
+        assert_eq!(Value::Str(String::from("hi")).type_name(), "a string");
+
+        assert!(matches!(Value::from_text("3.14"), Value::Float(_)));
+        assert!(matches!(Value::from_text("-1e-7"), Value::Float(_)));
+        assert!(matches!(Value::from_text("2.50"), Value::Str(_)));
+        assert!(matches!(Value::from_text("inf"), Value::Str(_)));
+        assert_eq!(Value::from_text("2.50").as_float(), Some(2.5));
+        assert_eq!(Value::Float(2.0).to_string(), "2.0");
+        assert_eq!(Value::Float(0.1 + 0.2).to_string(), "0.30000000000000004");
+
+        let map = Value::Map(BTreeMap::from([(String::from("b"), Value::Int(2)), (String::from("a"), Value::from("x"))]));
+        assert_eq!(map.to_string(), "{a: x, b: 2}");
+        assert_eq!(map.as_int(), None);
+        assert_eq!(map.type_name(), "a map");
+    }
+
+    #[test]
+    fn debug_program_data() {
+        assert_eq!(format!("{:?}", program()),
+                   "ProgramData { index: 1, inline_code: None, dialect: V1, code_size: 2, \
+                    vars: [(\"a\", Int(5)), (\"b\", Str(\"hello\"))], stack: [Int(7), Int(3)], .. }");
+    }
+
+    #[test]
+    fn inspect_program_data() {
+        let mut data = program();
+        assert_eq!(data.iter_vars().collect::<Vec<(&str, &Value)>>(), vec![("a", &Value::Int(5)), ("b", &Value::from("hello"))]);
+        assert_eq!(data.iter_stack().collect::<Vec<&Value>>(), vec![&Value::Int(7), &Value::Int(3)]);
+        assert_eq!(data.code_lines().collect::<Vec<&str>>(), vec!["let $a = 5", "output $a"]);
+        assert_eq!(data.current_line(), Some("output $a"));
+
+        data.set_inline_code(String::from("goto 0"));
+        assert_eq!(data.current_line(), Some("goto 0"));
+        data.next_line();
+        assert_eq!(data.current_line(), None);
+    }
+
+    #[test]
+    fn run_program_on_another_thread() {
+        let data = ProgramData::new(
+            vec![String::from("let $a = 5"), String::from("push $a"), String::from("quit")],
+            HashMap::new(), LinkedList::new(), 0
+        );
+        let outcome = std::thread::spawn(move || crate::runner::run(data)).join().unwrap().ok().unwrap();
+        assert_eq!(outcome.data.iter_stack().collect::<Vec<&Value>>(), vec![&Value::Int(5)]);
+    }
+
+    #[test]
+    fn share_program_data_between_threads() {
+        let data = std::sync::Arc::new(program());
+        let threads: Vec<_> = (0..2).map(|_| {
+            let data = data.clone();
+            std::thread::spawn(move || data.to_string())
+        }).collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), program().to_string());
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let json = program().to_json();
+        assert_eq!(json, r#"{"index":1,"inline_code":null,"dialect":"v1","code":["let $a = 5","output $a"],"vars":{"a":5,"b":"hello"},"stack":[7,3],"calls":[],"constants":[]}"#);
+
+        let mut data = ProgramData::from_json(&json).unwrap();
+        assert_eq!(data.to_json(), json);
+        assert_eq!(data.pop().unwrap(), "7");
+        assert_eq!(data.get_code().unwrap(), "output $a");
+
+        data.set_var(String::from("m"), Value::Map(BTreeMap::from([(String::from("k"), Value::Int(1))])));
+        let json = data.to_json();
+        assert!(json.contains(r#""m":{"k":1}"#));
+        let data = ProgramData::from_json(&json).unwrap();
+        assert!(matches!(data.get_var("m"), Some(Value::Map(entries)) if entries["k"] == Value::Int(1)));
+    }
+
+    #[test]
+    fn snapshot_keeps_value_types() {
+        let mut data = program();
+        let values = [
+            ("int", Value::Int(-3)),
+            ("big", Value::Int(i128::MAX)),
+            ("float", Value::Float(2.0)),
+            ("bool", Value::Bool(true)),
+            ("digits", Value::Str(String::from("42"))),
+            ("map", Value::Map(BTreeMap::from([(String::from("k"), Value::Float(0.5))]))),
+        ];
+        for (name, value) in values.clone() {
+            data.set_var(String::from(name), value);
+        }
+        data.push(Value::Bool(false));
+
+        let json = data.to_json();
+        assert!(json.contains(r#""bool":true,"digits":"42","float":2.0,"int":-3,"map":{"k":0.5}"#));
+        let mut data = ProgramData::from_json(&json).unwrap();
+        for (name, value) in values {
+            let restored = data.get_var(name).unwrap();
+            assert_eq!(restored.type_name(), value.type_name(), "type of ${}", name);
+            assert_eq!(restored.to_string(), value.to_string());
+        }
+        assert!(matches!(data.pop(), Some(Value::Bool(false))));
+    }
+
+    #[test]
+    fn local_variables_of_calls() {
+        let mut data = program();
+        assert!(!data.declare_local(String::from("a")));
+        data.push_call(0);
+        data.push_frame(Frame { call_site: 1, result: Some(String::from("r")), locals: HashMap::from([(String::from("a"), Value::Int(9))]) });
+        assert_eq!(data.get_var("a").unwrap(), "9");
+        data.set_var(String::from("a"), Value::Int(10));
+        data.set_var(String::from("c"), "global");
+        assert_eq!(data.iter_vars().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<String>>(),
+                   vec!["a=10", "b=hello", "c=global"]);
+
+        let json = data.to_json();
+        assert!(json.contains(r#""vars":{"a":5,"b":"hello","c":"global"}"#));
+        assert!(json.ends_with(r#""calls":[{"line":1,"result":"r","locals":{"a":10}},0],"constants":[]}"#));
+        let mut data = ProgramData::from_json(&json).unwrap();
+        assert_eq!(data.call_chain(), vec![1, 0]);
+
+        assert_eq!(data.pop_frame().unwrap().locals["a"], Value::Int(10));
+        assert_eq!(data.get_var("a").unwrap(), "5");
+        assert_eq!(data.pop_call(), Some(0));
+    }
+
+    #[test]
+    fn constants() {
+        let mut data = program();
+        assert!(data.declare_constant(String::from("pi"), Value::Int(3)));
+        assert!(!data.declare_constant(String::from("pi"), Value::Int(4)));
+        assert!(data.is_constant("pi"));
+        assert!(!data.is_constant("a"));
+
+        let json = data.to_json();
+        assert!(json.ends_with(r#""constants":["pi"]}"#));
+        let mut data = ProgramData::from_json(&json).unwrap();
+        assert!(data.is_constant("pi"));
+
+        // A local variable hides the constant, and removing the constant frees its name
+        data.push_call(0);
+        data.declare_local(String::from("pi"));
+        assert!(!data.is_constant("pi"));
+        data.pop_call();
+        assert_eq!(data.remove_var("pi"), Some(Value::Int(3)));
+        assert!(!data.is_constant("pi"));
+    }
+
+    #[test]
+    fn snapshot_with_missing_field() {
+        let result = ProgramData::from_json(r#"{"index":0,"code":[]}"#);
+        assert_eq!(result.err().unwrap(), "Snapshot has no vars field!");
+    }
+
+    #[test]
+    fn parse_each_line_once() {
+        let program = Arc::new(load_code_from_string("0 let $i = $i + $one\n1 if $i < $n goto 0\n").unwrap());
+        let mut first = program.shared_program_data();
+        let second = program.shared_program_data();
+        // Runs of a shared program use the instructions parsed when it was loaded
+        assert!(Arc::ptr_eq(&first.parsed, &second.parsed));
+
+        for (name, value) in [("i", 0), ("one", 1), ("n", 9)] {
+            first.set_var(name.to_string(), Value::Int(value));
+        }
+        let data = run(first).unwrap().data;
+        assert_eq!(data.get_var("i").unwrap(), "9");
+        assert!(Arc::ptr_eq(&data.parsed, &second.parsed));
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use crate::code_loader::{is_comment_line, outside_strings};
+
+lazy_static! {
+    static ref LINE_REGEX : Regex = Regex::new(r"^([ \t]*)(\d+)([ \t]+.*)$").unwrap();
+    static ref JUMP_REGEX : Regex = Regex::new(r"\b(goto|gosub) (\d+)").unwrap();
+}
+
+/// A program whose lines have been renumbered.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Renumbered {
+    /// The renumbered program file
+    pub source: String,
+    /// The old and new number of every line, in the order of the new numbers
+    pub mapping: Vec<(usize, usize)>,
+}
+
+impl Renumbered {
+    /// Writes the mapping as a table of 'old -> new' lines.
+    pub fn mapping_table(&self) -> String {
+        let width = self.mapping.iter().map(|(old, _)| old.to_string().len()).max().unwrap_or(0);
+        let mut table = String::from("Line numbers (old -> new):");
+        for (old, new) in &self.mapping {
+            table.push_str(&format!("\n  {:>width$} -> {}", old, new));
+        }
+        table
+    }
+}
+
+/// A numbered line of a program, along with the comment lines before it and continuation lines
+/// that belong to it.
+struct Entry {
+    comments: Vec<String>,
+    number: usize,
+    // The whitespace the line is indented by
+    indent: String,
+    // The rest of the first line (starting with the whitespace after the number), followed by
+    // any lines it continues on
+    rest: String,
+    continuations: Vec<String>,
+}
+
+/// Renumbers the lines of a program file. Lines are put in the order of their numbers (so a line
+/// numbered 15 can be inserted between lines 10 and 20 anywhere in the file), numbered from 0 in
+/// steps of `stride`, and the target of every 'goto' and 'gosub' is changed to the new number of
+/// the line it pointed to. A stack segment is left as it is, continued lines stay with the line
+/// they continue, and comment lines stay with the line that follows them.
+///
+/// The interpreter only runs programs numbered 0, 1, 2, ..., so a stride other than 1 is only
+/// useful to leave room for lines inserted while editing.
+///
+/// # Arguments
+/// * `source` - The contents of the program file
+/// * `stride` - How far apart the new line numbers are
+///
+/// # Returns
+/// * `Ok(Renumbered)` - The renumbered program, and the number every line was given
+/// * `Err(String)` - A message detailing why the program could not be renumbered
+pub fn renumber(source: &str, stride: usize) -> Result<Renumbered, String> {
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+    let lines: Vec<&str> = source.lines().map(str::trim_end).collect();
+
+    // The stack segment is copied over, up to and including its 'code' line
+    let code_start = match lines.iter().position(|line| !line.is_empty()) {
+        Some(first) if lines[first].trim() == "stack" => lines.iter().position(|line| line.trim() == "code")
+            .map(|code| code + 1)
+            .ok_or(String::from("The stack segment has no code line after it"))?,
+        _ => 0,
+    };
+
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut comments = Vec::new();
+    for (index, line) in lines.iter().enumerate().skip(code_start) {
+        if line.is_empty() {
+            continue;
+        }
+        let continues = comments.is_empty() && entries.last().is_some_and(|entry| {
+            entry.continuations.last().unwrap_or(&entry.rest).ends_with('\\')
+        });
+        if !continues && is_comment_line(line) {
+            comments.push(line.to_string());
+            continue;
+        }
+        if continues || line.trim_start().starts_with("...") {
+            let entry = entries.last_mut().ok_or(format!("Line {} continues a line that does not exist: {}", index + 1, line))?;
+            entry.continuations.push(line.to_string());
+            continue;
+        }
+        let captures = LINE_REGEX.captures(line).ok_or(format!("Line {} has no line number: {}", index + 1, line))?;
+        let number = captures[2].parse::<usize>().map_err(|_| format!("Line {} has an invalid line number: {}", index + 1, line))?;
+        let comments = std::mem::take(&mut comments);
+        entries.push(Entry {
+            comments,
+            number,
+            indent: captures[1].to_string(),
+            rest: captures[3].to_string(),
+            continuations: Vec::new(),
+        });
+    }
+
+    entries.sort_by_key(|entry| entry.number);
+    let mut new_numbers = HashMap::new();
+    for (position, entry) in entries.iter().enumerate() {
+        if new_numbers.insert(entry.number, position * stride).is_some() {
+            return Err(format!("Line number {} is used more than once", entry.number));
+        }
+    }
+
+    let remap = |text: &str, number: usize| -> Result<String, String> {
+        let mut missing = None;
+        let remapped = outside_strings(text, |piece| JUMP_REGEX.replace_all(piece, |jump: &Captures| {
+            let target = jump[2].parse::<usize>().ok();
+            match target.and_then(|target| new_numbers.get(&target)) {
+                Some(new_target) => format!("{} {}", &jump[1], new_target),
+                None => {
+                    missing.get_or_insert_with(|| format!("{} {}", &jump[1], &jump[2]));
+                    jump[0].to_string()
+                }
+            }
+        }).to_string());
+        match missing {
+            Some(jump) => Err(format!("Line {}: {} points to a line that does not exist", number, jump)),
+            None => Ok(remapped),
+        }
+    };
+
+    let mut renumbered: Vec<String> = lines[..code_start].iter().map(|line| line.to_string()).collect();
+    let mut mapping = Vec::new();
+    for entry in &entries {
+        renumbered.extend(entry.comments.iter().cloned());
+        let new_number = new_numbers[&entry.number];
+        renumbered.push(format!("{}{}{}", entry.indent, new_number, remap(&entry.rest, entry.number)?));
+        for continuation in &entry.continuations {
+            renumbered.push(remap(continuation, entry.number)?);
+        }
+        mapping.push((entry.number, new_number));
+    }
+    renumbered.extend(comments);
+
+    let mut source = renumbered.join("\n");
+    source.push('\n');
+    Ok(Renumbered { source, mapping })
+}
+
+#[cfg(test)]
+mod test {
+    use super::renumber;
+
+    #[test]
+    fn renumber_inserted_lines() {
+        let renumbered = renumber("0 let $a = 1\n1 if $a < $b goto 3\n2 output $a\n1 let $b = 2\n3 goto 0\n", 1);
+        assert_eq!(renumbered.err().unwrap(), "Line number 1 is used more than once");
+
+        let renumbered = renumber("0 let $a = 1\n10 if $a < $b goto 30\n20\toutput $a\n5 let $b = 2\n30 goto 0\n", 1).unwrap();
+        assert_eq!(renumbered.source, "0 let $a = 1\n1 let $b = 2\n2 if $a < $b goto 4\n3\toutput $a\n4 goto 0\n");
+        assert_eq!(renumbered.mapping, vec![(0, 0), (5, 1), (10, 2), (20, 3), (30, 4)]);
+        assert_eq!(renumbered.mapping_table(), "Line numbers (old -> new):\n   0 -> 0\n   5 -> 1\n  10 -> 2\n  20 -> 3\n  30 -> 4");
+    }
+
+    #[test]
+    fn renumber_with_stride() {
+        let renumbered = renumber("stack\n0 5\ncode\n0 let $a = pop\n1 if $a < $a \\\n  goto 2\n2 gosub 3\n3 return\n", 10).unwrap();
+        assert_eq!(renumbered.source, "stack\n0 5\ncode\n0 let $a = pop\n10 if $a < $a \\\n  goto 20\n20 gosub 30\n30 return\n");
+    }
+
+    #[test]
+    fn renumber_indented_lines() {
+        let renumbered = renumber("0 let $a = 1\n\n10 if $a < 3 \\\n  goto 20\n  20 output $a\n", 1).unwrap();
+        assert_eq!(renumbered.source, "0 let $a = 1\n1 if $a < 3 \\\n  goto 2\n  2 output $a\n");
+    }
+
+    #[test]
+    fn renumber_with_comments() {
+        let renumbered = renumber("# Counts down\n10 let $a = 3 # start\n# The loop\nrem ends at 0\n5 goto 10\n# end\n", 1).unwrap();
+        assert_eq!(renumbered.source, "# The loop\nrem ends at 0\n0 goto 1\n# Counts down\n1 let $a = 3 # start\n# end\n");
+    }
+
+    #[test]
+    fn renumber_around_strings() {
+        let renumbered = renumber("10 output \"goto 10\"\n20 goto 10\n", 1).unwrap();
+        assert_eq!(renumbered.source, "0 output \"goto 10\"\n1 goto 0\n");
+    }
+
+    #[test]
+    fn renumber_invalid_programs() {
+        assert_eq!(renumber("0 goto 4\n1 quit\n", 1).err().unwrap(), "Line 0: goto 4 points to a line that does not exist");
+        assert_eq!(renumber("0 quit\nquit\n", 1).err().unwrap(), "Line 2 has no line number: quit");
+        assert_eq!(renumber("stack\n0 5\n", 1).err().unwrap(), "The stack segment has no code line after it");
+    }
+}
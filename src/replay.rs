@@ -0,0 +1,636 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::console::Console;
+use crate::host::{HostBackend, HostFile};
+use crate::integrity::sha256_hex;
+use crate::json::{self, Json};
+#[cfg(feature = "net")]
+use crate::net::NetBackend;
+
+/// Version of the replay format written by this interpreter.
+const REPLAY_VERSION: i64 = 1;
+
+/// Identifies the program a replay belongs to, so that it is never replayed against another one.
+fn program_checksum(code: &[String]) -> String {
+    sha256_hex(code.join("\n").as_bytes())
+}
+
+/// Writes the result of everything a program receives from outside the interpreter to a replay
+/// file, one JSON object per line. Each line is written as soon as it is known, so the replay
+/// is complete up to the point where a run crashed.
+struct Recorder {
+    writer: Box<dyn Write + Send + Sync>,
+}
+
+impl Recorder {
+    fn record(&mut self, event: &str, result: &Result<Json, String>) -> Result<(), String> {
+        let mut fields = vec![(String::from("event"), Json::String(event.to_string()))];
+        match result {
+            Ok(value) => fields.push((String::from("value"), value.clone())),
+            Err(error) => fields.push((String::from("error"), Json::String(error.clone()))),
+        }
+        writeln!(self.writer, "{}", Json::Object(fields)).map_err(|_| String::from("Error writing replay!"))
+    }
+
+    /// Records `result` as the result of `event`, using `value` to store it, and hands it back.
+    fn record_result<T>(&mut self, event: &str, result: Result<T, String>, value: impl FnOnce(&T) -> Json) -> Result<T, String> {
+        let recorded = result.as_ref().map(value).map_err(String::clone);
+        self.record(event, &recorded)?;
+        result
+    }
+}
+
+/// Hands out the results stored in a replay file, checking that they are asked for in the order
+/// they were recorded.
+struct Replayer {
+    // Each entry is kept with the line of the replay file it came from
+    entries: VecDeque<(usize, Json)>,
+}
+
+impl Replayer {
+    /// Takes the next entry of the replay, which must have been recorded for `event`.
+    ///
+    /// # Returns
+    /// * `Ok(Result<Json, String>)` - The recorded result of the event
+    /// * `Err(String)` - A message detailing how the program strayed from the replay
+    fn next(&mut self, event: &str) -> Result<Result<Json, String>, String> {
+        let (line, entry) = self.entries.pop_front()
+            .ok_or(String::from("Replay ended before the program did!"))?;
+        let recorded = entry.get("event").and_then(Json::as_str).unwrap_or_default();
+        if recorded != event {
+            return Err(format!("Replay does not match the program: expected {} but line {} of the replay is {}",
+                               event, line, recorded));
+        }
+        match (entry.get("value"), entry.get("error").and_then(Json::as_str)) {
+            (_, Some(error)) => Ok(Err(error.to_string())),
+            (Some(value), None) => Ok(Ok(value.clone())),
+            (None, None) => Err(format!("Line {} of the replay has no value!", line)),
+        }
+    }
+}
+
+/// Reads a line of input out of a recorded value, where `null` stands for no line.
+fn as_line(value: Json) -> Result<Option<String>, String> {
+    match value {
+        Json::String(line) => Ok(Some(line)),
+        Json::Null => Ok(None),
+        _ => Err(String::from("Replay entry holds an invalid line of input!")),
+    }
+}
+
+fn as_text(value: Json) -> Result<String, String> {
+    as_line(value)?.ok_or(String::from("Replay entry holds an invalid line of input!"))
+}
+
+fn as_bool(value: Json) -> Result<bool, String> {
+    match value {
+        Json::Bool(value) => Ok(value),
+        _ => Err(String::from("Replay entry holds an invalid answer!")),
+    }
+}
+
+fn as_texts(value: Json) -> Result<Vec<String>, String> {
+    value.as_array()
+        .and_then(|values| values.iter().map(|value| value.as_str().map(String::from)).collect())
+        .ok_or(String::from("Replay entry holds an invalid list!"))
+}
+
+fn texts(values: &[String]) -> Json {
+    Json::Array(values.iter().cloned().map(Json::String).collect())
+}
+
+/// Records a run of a program into a replay file, so that `Replay` can reproduce it exactly.
+pub struct Recording {
+    log: Arc<Mutex<Recorder>>,
+    seed: u64,
+}
+
+impl Recording {
+    /// Creates the replay file at `file_path` for the program with the given code, run with the
+    /// arguments `args`. The file is replaced if it already exists. The program's random numbers
+    /// start from `seed`, or from a seed picked at random if none is given.
+    pub fn create(file_path: &str, code: &[String], seed: Option<u64>, args: &[String]) -> Result<Self, String> {
+        let file = File::create(file_path).map_err(|err| format!("{}: {}", file_path, err))?;
+        Recording::new(Box::new(io::LineWriter::new(file)), code, seed, args)
+    }
+
+    fn new(mut writer: Box<dyn Write + Send + Sync>, code: &[String], seed: Option<u64>, args: &[String]) -> Result<Self, String> {
+        // The seed is kept as a string, as JSON numbers are read back as i64
+        let seed = seed.unwrap_or_else(rand::random::<u64>);
+        let header = Json::Object(vec![
+            (String::from("replay"), Json::Number(REPLAY_VERSION)),
+            (String::from("program"), Json::String(program_checksum(code))),
+            (String::from("seed"), Json::String(seed.to_string())),
+            (String::from("args"), texts(args)),
+        ]);
+        writeln!(writer, "{}", header).map_err(|_| String::from("Error writing replay!"))?;
+        Ok(Recording { log: Arc::new(Mutex::new(Recorder { writer })), seed })
+    }
+
+    /// The seed the recorded program's random numbers must start from, so that they can be
+    /// given out again when the run is replayed.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Wraps `inner` so that every line of input it gives the program is recorded.
+    pub fn console(&self, inner: Box<dyn Console>) -> Box<dyn Console> {
+        Box::new(RecordingConsole { log: self.log.clone(), inner })
+    }
+
+    /// Wraps `inner` so that the result of every network operation is recorded.
+    #[cfg(feature = "net")]
+    pub fn net_backend(&self, inner: Box<dyn NetBackend>) -> Box<dyn NetBackend> {
+        Box::new(RecordingBackend { log: self.log.clone(), inner })
+    }
+
+    /// Wraps `inner` so that the result of every command, environment variable and file
+    /// operation is recorded.
+    pub fn host_backend(&self, inner: Box<dyn HostBackend>) -> Box<dyn HostBackend> {
+        Box::new(RecordingHost { log: self.log.clone(), inner })
+    }
+}
+
+/// Reproduces a run recorded with `Recording`: every line of input (and every result of a
+/// command, environment variable, file or network operation) comes from the replay file instead
+/// of the outside world, so the program runs exactly as it did when it was recorded.
+pub struct Replay {
+    log: Arc<Mutex<Replayer>>,
+    seed: Option<u64>,
+    args: Option<Vec<String>>,
+}
+
+impl Replay {
+    /// Opens the replay file at `file_path`, which must have been recorded for the program with
+    /// the given code.
+    pub fn open(file_path: &str, code: &[String]) -> Result<Self, String> {
+        let contents = fs::read_to_string(file_path).map_err(|err| format!("{}: {}", file_path, err))?;
+        Replay::parse(&contents, code).map_err(|err| format!("{}: {}", file_path, err))
+    }
+
+    fn parse(contents: &str, code: &[String]) -> Result<Self, String> {
+        let mut lines = contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+        let header = lines.next().map(|(_, line)| json::parse(line)).transpose()?;
+        let version = header.as_ref().and_then(|header| header.get("replay")).and_then(Json::as_i64);
+        if version != Some(REPLAY_VERSION) {
+            return Err(String::from("not a replay file!"));
+        }
+        let checksum = header.as_ref().and_then(|header| header.get("program")).and_then(Json::as_str);
+        if checksum != Some(program_checksum(code).as_str()) {
+            return Err(String::from("the replay was recorded with a different program!"));
+        }
+        let seed = header.as_ref().and_then(|header| header.get("seed")).and_then(Json::as_str)
+            .map(|seed| seed.parse::<u64>().map_err(|_| String::from("the replay holds an invalid seed!")))
+            .transpose()?;
+        let args = header.as_ref().and_then(|header| header.get("args")).cloned()
+            .map(|args| as_texts(args).map_err(|_| String::from("the replay holds invalid arguments!")))
+            .transpose()?;
+
+        let entries = lines
+            .map(|(index, line)| json::parse(line)
+                .map(|entry| (index + 1, entry))
+                .map_err(|err| format!("line {}: {}", index + 1, err)))
+            .collect::<Result<VecDeque<(usize, Json)>, String>>()?;
+        Ok(Replay { log: Arc::new(Mutex::new(Replayer { entries })), seed, args })
+    }
+
+    /// The seed the recorded program's random numbers started from. Replays recorded before
+    /// seeds were recorded have none.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// The arguments the recorded program was run with. Replays recorded before arguments were
+    /// recorded have none.
+    pub fn args(&self) -> Option<&[String]> {
+        self.args.as_deref()
+    }
+
+    /// Gives the program its recorded input. Output is still written to `inner`.
+    pub fn console(&self, inner: Box<dyn Console>) -> Box<dyn Console> {
+        Box::new(ReplayConsole { log: self.log.clone(), inner })
+    }
+
+    /// Gives the program its recorded network results, without touching the network.
+    #[cfg(feature = "net")]
+    pub fn net_backend(&self) -> Box<dyn NetBackend> {
+        Box::new(ReplayBackend { log: self.log.clone() })
+    }
+
+    /// Gives the program its recorded command output, environment variables and files, without
+    /// running commands or touching the filesystem.
+    pub fn host_backend(&self) -> Box<dyn HostBackend> {
+        Box::new(ReplayHost { log: self.log.clone() })
+    }
+}
+
+struct RecordingConsole {
+    log: Arc<Mutex<Recorder>>,
+    inner: Box<dyn Console>,
+}
+
+impl RecordingConsole {
+    fn record_line(&mut self, input: Result<Option<String>, String>) -> Result<Option<String>, String> {
+        let value = input.clone().map(|line| line.map_or(Json::Null, Json::String));
+        self.log.lock().unwrap().record("input", &value)?;
+        input
+    }
+}
+
+impl Console for RecordingConsole {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        let input = self.inner.read_line();
+        self.record_line(input)
+    }
+
+    fn read_line_timeout(&mut self, timeout: Duration) -> Result<Option<String>, String> {
+        // A timed out read is recorded as no line, and replayed without waiting
+        let input = self.inner.read_line_timeout(timeout);
+        self.record_line(input)
+    }
+
+    fn read_all(&mut self) -> Result<String, String> {
+        let input = self.inner.read_all();
+        self.log.lock().unwrap().record("input_all", &input.clone().map(Json::String))?;
+        input
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        self.inner.write_line(line)
+    }
+
+    fn write_prompt(&mut self, prompt: &str) -> Result<(), String> {
+        self.inner.write_prompt(prompt)
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.inner.flush()
+    }
+
+    // Whether the program could ask again for input decides which input it reads, so it is
+    // recorded along with the input
+    fn is_interactive(&mut self) -> Result<bool, String> {
+        let interactive = self.inner.is_interactive();
+        self.log.lock().unwrap().record("interactive", &interactive.clone().map(Json::Bool))?;
+        interactive
+    }
+}
+
+struct ReplayConsole {
+    log: Arc<Mutex<Replayer>>,
+    inner: Box<dyn Console>,
+}
+
+impl Console for ReplayConsole {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        self.log.lock().unwrap().next("input")?.and_then(as_line)
+    }
+
+    fn read_line_timeout(&mut self, _: Duration) -> Result<Option<String>, String> {
+        self.read_line()
+    }
+
+    fn read_all(&mut self) -> Result<String, String> {
+        self.log.lock().unwrap().next("input_all")?.and_then(as_text)
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        self.inner.write_line(line)
+    }
+
+    fn write_prompt(&mut self, prompt: &str) -> Result<(), String> {
+        self.inner.write_prompt(prompt)
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.inner.flush()
+    }
+
+    fn is_interactive(&mut self) -> Result<bool, String> {
+        self.log.lock().unwrap().next("interactive")?.and_then(as_bool)
+    }
+}
+
+struct RecordingHost {
+    log: Arc<Mutex<Recorder>>,
+    inner: Box<dyn HostBackend>,
+}
+
+impl HostBackend for RecordingHost {
+    fn exec(&mut self, command: &str, dir: Option<&Path>) -> Result<(String, i128), String> {
+        let result = self.inner.exec(command, dir);
+        self.log.lock().unwrap().record_result("exec", result, |(output, exit_code)| Json::Object(vec![
+            (String::from("output"), Json::String(output.clone())),
+            (String::from("exitcode"), Json::Number(*exit_code as i64)),
+        ]))
+    }
+
+    fn env(&mut self, name: &str) -> Result<String, String> {
+        let result = self.inner.env(name);
+        self.log.lock().unwrap().record_result("env", result, |value| Json::String(value.clone()))
+    }
+
+    fn exists(&mut self, path: &Path) -> Result<bool, String> {
+        let result = self.inner.exists(path);
+        self.log.lock().unwrap().record_result("exists", result, |exists| Json::Bool(*exists))
+    }
+
+    fn delete(&mut self, path: &Path) -> Result<(), String> {
+        let result = self.inner.delete(path);
+        self.log.lock().unwrap().record_result("delete", result, |_| Json::Null)
+    }
+
+    fn list_dir(&mut self, path: &Path) -> Result<Vec<String>, String> {
+        let result = self.inner.list_dir(path);
+        self.log.lock().unwrap().record_result("listdir", result, |entries| texts(entries))
+    }
+
+    fn read_file(&mut self, path: &Path) -> Result<String, String> {
+        let result = self.inner.read_file(path);
+        self.log.lock().unwrap().record_result("readfile", result, |contents| Json::String(contents.clone()))
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &str, append: bool) -> Result<(), String> {
+        let result = self.inner.write_file(path, contents, append);
+        self.log.lock().unwrap().record_result("writefile", result, |_| Json::Null)
+    }
+
+    fn open_file(&mut self, path: &Path) -> Result<Box<dyn HostFile>, String> {
+        let result = self.inner.open_file(path);
+        let inner = self.log.lock().unwrap().record_result("openfile", result, |_| Json::Null)?;
+        Ok(Box::new(RecordingFile { log: self.log.clone(), inner }))
+    }
+}
+
+struct RecordingFile {
+    log: Arc<Mutex<Recorder>>,
+    inner: Box<dyn HostFile>,
+}
+
+impl HostFile for RecordingFile {
+    fn read_line(&mut self) -> Result<String, String> {
+        let result = self.inner.read_line();
+        self.log.lock().unwrap().record_result("readline", result, |line| Json::String(line.clone()))
+    }
+}
+
+struct ReplayHost {
+    log: Arc<Mutex<Replayer>>,
+}
+
+impl HostBackend for ReplayHost {
+    fn exec(&mut self, _: &str, _: Option<&Path>) -> Result<(String, i128), String> {
+        let result = self.log.lock().unwrap().next("exec")??;
+        let output = result.get("output").and_then(Json::as_str);
+        let exit_code = result.get("exitcode").and_then(Json::as_i64);
+        match (output, exit_code) {
+            (Some(output), Some(exit_code)) => Ok((output.to_string(), exit_code as i128)),
+            _ => Err(String::from("Replay entry holds an invalid command result!")),
+        }
+    }
+
+    fn env(&mut self, _: &str) -> Result<String, String> {
+        self.log.lock().unwrap().next("env")?.and_then(as_text)
+    }
+
+    fn exists(&mut self, _: &Path) -> Result<bool, String> {
+        self.log.lock().unwrap().next("exists")?.and_then(as_bool)
+    }
+
+    fn delete(&mut self, _: &Path) -> Result<(), String> {
+        self.log.lock().unwrap().next("delete")?.map(|_| ())
+    }
+
+    fn list_dir(&mut self, _: &Path) -> Result<Vec<String>, String> {
+        self.log.lock().unwrap().next("listdir")?.and_then(as_texts)
+    }
+
+    fn read_file(&mut self, _: &Path) -> Result<String, String> {
+        self.log.lock().unwrap().next("readfile")?.and_then(as_text)
+    }
+
+    fn write_file(&mut self, _: &Path, _: &str, _: bool) -> Result<(), String> {
+        self.log.lock().unwrap().next("writefile")?.map(|_| ())
+    }
+
+    fn open_file(&mut self, _: &Path) -> Result<Box<dyn HostFile>, String> {
+        self.log.lock().unwrap().next("openfile")??;
+        Ok(Box::new(ReplayFile { log: self.log.clone() }))
+    }
+}
+
+struct ReplayFile {
+    log: Arc<Mutex<Replayer>>,
+}
+
+impl HostFile for ReplayFile {
+    fn read_line(&mut self) -> Result<String, String> {
+        self.log.lock().unwrap().next("readline")?.and_then(as_text)
+    }
+}
+
+#[cfg(feature = "net")]
+struct RecordingBackend {
+    log: Arc<Mutex<Recorder>>,
+    inner: Box<dyn NetBackend>,
+}
+
+#[cfg(feature = "net")]
+impl NetBackend for RecordingBackend {
+    fn connect(&mut self, address: &str) -> Result<usize, String> {
+        let result = self.inner.connect(address);
+        self.log.lock().unwrap().record_result("connect", result, |handle| Json::Number(*handle as i64))
+    }
+
+    fn send(&mut self, handle: usize, data: &str) -> Result<(), String> {
+        let result = self.inner.send(handle, data);
+        self.log.lock().unwrap().record_result("send", result, |_| Json::Null)
+    }
+
+    fn recv(&mut self, handle: usize) -> Result<String, String> {
+        let result = self.inner.recv(handle);
+        self.log.lock().unwrap().record_result("recv", result, |data| Json::String(data.clone()))
+    }
+
+    fn close(&mut self, handle: usize) -> Result<(), String> {
+        let result = self.inner.close(handle);
+        self.log.lock().unwrap().record_result("close", result, |_| Json::Null)
+    }
+
+    fn http_get(&mut self, url: &str) -> Result<String, String> {
+        let result = self.inner.http_get(url);
+        self.log.lock().unwrap().record_result("httpget", result, |body| Json::String(body.clone()))
+    }
+}
+
+#[cfg(feature = "net")]
+struct ReplayBackend {
+    log: Arc<Mutex<Replayer>>,
+}
+
+#[cfg(feature = "net")]
+impl NetBackend for ReplayBackend {
+    fn connect(&mut self, _: &str) -> Result<usize, String> {
+        let handle = self.log.lock().unwrap().next("connect")??;
+        handle.as_i64().map(|handle| handle as usize).ok_or(String::from("Replay entry holds an invalid socket!"))
+    }
+
+    fn send(&mut self, _: usize, _: &str) -> Result<(), String> {
+        self.log.lock().unwrap().next("send")?.map(|_| ())
+    }
+
+    fn recv(&mut self, _: usize) -> Result<String, String> {
+        self.log.lock().unwrap().next("recv")?.and_then(as_text)
+    }
+
+    fn close(&mut self, _: usize) -> Result<(), String> {
+        self.log.lock().unwrap().next("close")?.map(|_| ())
+    }
+
+    fn http_get(&mut self, _: &str) -> Result<String, String> {
+        self.log.lock().unwrap().next("httpget")?.and_then(as_text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, LinkedList};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use crate::console::{ScriptedConsole, StdConsole};
+    use crate::host::OsBackend;
+    use crate::prog_data::ProgramData;
+    use crate::runner::run;
+    use super::{Recording, Replay};
+
+    /// Replay file contents that can be checked once the recording is done.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn code() -> Vec<String> {
+        vec!["let $a = input", "let $b = input", "let $c = $a + $b", "quit"].into_iter().map(String::from).collect()
+    }
+
+    fn record(inputs: Vec<&str>) -> String {
+        let buffer = SharedBuffer::default();
+        let recording = Recording::new(Box::new(buffer.clone()), &code(), None, &[]).unwrap();
+        let mut data = ProgramData::new(code(), HashMap::new(), LinkedList::new(), 0);
+        let inputs = inputs.into_iter().map(String::from).collect();
+        data.set_console(recording.console(Box::new(ScriptedConsole::new(inputs, None, Box::new(StdConsole::new())))));
+        run(data).ok().unwrap();
+        let contents = buffer.0.lock().unwrap().clone();
+        String::from_utf8(contents).unwrap()
+    }
+
+    fn replay(contents: &str, code: Vec<String>) -> Result<String, String> {
+        let replay = Replay::parse(contents, &code)?;
+        let mut data = ProgramData::new(code, HashMap::new(), LinkedList::new(), 0);
+        data.set_console(replay.console(Box::new(StdConsole::new())));
+        run(data).map(|outcome| outcome.data.get_var("c").map(|value| value.to_string()).unwrap_or_default()).map_err(|err| err.message)
+    }
+
+    #[test]
+    fn record_run() {
+        let contents = record(vec!["3", "4"]);
+        let lines: Vec<&str> = contents.lines().collect();
+        assert!(lines[0].starts_with("{\"replay\":1,\"program\":\""));
+        assert_eq!(&lines[1..], [r#"{"event":"input","value":"3"}"#, r#"{"event":"input","value":"4"}"#]);
+    }
+
+    #[test]
+    fn replay_recorded_run() {
+        let contents = record(vec!["3", "4"]);
+        assert_eq!(replay(&contents, code()).unwrap(), "7");
+    }
+
+    #[test]
+    fn replay_recorded_seed() {
+        let buffer = SharedBuffer::default();
+        let recording = Recording::new(Box::new(buffer.clone()), &code(), None, &[]).unwrap();
+        let contents = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(Replay::parse(&contents, &code()).unwrap().seed(), Some(recording.seed()));
+
+        let contents = contents.replace(&format!("\"seed\":\"{}\"", recording.seed()), "\"seed\":\"x\"");
+        assert_eq!(Replay::parse(&contents, &code()).err().unwrap(), "the replay holds an invalid seed!");
+    }
+
+    #[test]
+    fn replay_number_input() {
+        let code = vec![String::from("let $c = inputnum")];
+        let buffer = SharedBuffer::default();
+        let recording = Recording::new(Box::new(buffer.clone()), &code, None, &[]).unwrap();
+        let mut data = ProgramData::new(code.clone(), HashMap::new(), LinkedList::new(), 0);
+        let inputs = vec![String::from("x")];
+        data.set_console(recording.console(Box::new(ScriptedConsole::new(inputs, None, Box::new(StdConsole::new())))));
+        assert!(run(data).is_err());
+
+        let contents = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(&lines[1..], [r#"{"event":"input","value":"x"}"#, r#"{"event":"interactive","value":false}"#]);
+        assert_eq!(replay(&contents, code).err().unwrap(), "Input \"x\" is not a whole number!");
+    }
+
+    #[test]
+    fn replay_host_results() {
+        let dir = std::env::temp_dir().join(format!("cos341basic_replay_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lines.txt"), "first\nsecond\n").unwrap();
+        let code: Vec<String> = vec!["let $a = exec \"echo $0\"", "let $b = readfile \"lines.txt\"",
+                                     "let $f = openfile \"lines.txt\"", "let $c = readline $f", "let $d = env \"HOME\"",
+                                     "let $p = \"lines.txt\"", "let $e = exists $p", "let $n = argc"]
+            .into_iter().map(String::from).collect();
+        let program = |host: Box<dyn crate::host::HostBackend>, args: &[String]| {
+            let mut data = ProgramData::new(code.clone(), HashMap::new(), LinkedList::new(), 0);
+            data.set_exec_allowed(true);
+            data.set_fs_allowed(true);
+            data.set_env_allowed(true);
+            data.set_base_dir(dir.clone());
+            data.set_args(args.to_vec());
+            data.set_host_backend(host);
+            let data = run(data).map_err(|err| err.message).unwrap().data;
+            ["a", "b", "c", "d", "e", "exitcode", "n"].map(|name| data.get_var(name).unwrap().to_string())
+        };
+
+        let buffer = SharedBuffer::default();
+        let args = vec![String::from("x")];
+        let recording = Recording::new(Box::new(buffer.clone()), &code, None, &args).unwrap();
+        let recorded = program(recording.host_backend(Box::new(OsBackend::new())), &args);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // The files are gone, so everything has to come from the replay
+        let contents = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let replay = Replay::parse(&contents, &code).unwrap();
+        assert_eq!(replay.args().unwrap(), args);
+        let replayed = program(replay.host_backend(), replay.args().unwrap());
+        assert_eq!(replayed, recorded);
+        assert_eq!(&replayed[1..3], ["first\nsecond", "first"]);
+    }
+
+    #[test]
+    fn replay_that_does_not_match() {
+        let contents = record(vec!["3", "4"]);
+        let mut other = code();
+        other[1] = String::from("let $b = input all");
+        assert_eq!(replay(&contents, other).err().unwrap(), "the replay was recorded with a different program!");
+
+        let header = contents.lines().next().unwrap();
+        let contents = format!("{}\n{}\n", header, r#"{"event":"input","value":"3"}"#);
+        assert_eq!(replay(&contents, code()).err().unwrap(), "Replay ended before the program did!");
+        assert_eq!(replay("{}", code()).err().unwrap(), "not a replay file!");
+    }
+}
@@ -0,0 +1,388 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::console::Console;
+use crate::prog_data::ProgramData;
+use crate::states::{get_state, NewState, States};
+
+/// How often a program waiting for input checks whether it has been cancelled.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+const CANCELLED_MESSAGE: &str = "Program was cancelled!";
+
+/// How a program finished.
+pub struct Outcome {
+    /// The exit code the program finished with
+    pub exit_code: i32,
+    /// The program data as it was when the program finished
+    pub data: ProgramData,
+}
+
+/// An error that stopped a program while it was running.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuntimeError {
+    /// The line that was being executed when the error occurred
+    pub line: usize,
+    /// A message detailing the error
+    pub message: String,
+    /// The lines of the subroutine calls that led to the failing line, innermost first
+    pub called_from: Vec<usize>,
+}
+
+impl RuntimeError {
+    /// Creates the error that `data` failed with while running `line`, remembering the chain of
+    /// subroutine calls that led to it.
+    pub fn in_program(data: &ProgramData, line: usize, message: &str) -> Self {
+        RuntimeError {
+            line,
+            message: message.trim_end_matches("\nAborting...").to_string(),
+            called_from: data.call_chain(),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        // Errors inside subroutines show how the program got there
+        if !self.called_from.is_empty() {
+            write!(f, "\nat line {}", self.line)?;
+            for call_site in &self.called_from {
+                write!(f, ", called from {}", call_site)?;
+            }
+        }
+        write!(f, "\nAborting...")
+    }
+}
+
+impl Error for RuntimeError {}
+
+/// Runs a program from its current line until it finishes or encounters an error.
+///
+/// # Arguments
+/// * `data` - The program to run, along with its variables, stack and console
+///
+/// # Returns
+/// * `Ok(Outcome)` - The exit code and the final program data of a program that finished
+/// * `Err(RuntimeError)` - The error that stopped the program
+pub fn run(data: ProgramData) -> Result<Outcome, RuntimeError> {
+    run_until_cancelled(data, &AtomicBool::new(false))
+}
+
+/// Runs a program like `run`, but stops it with an error before the next instruction once
+/// `cancelled` is set.
+fn run_until_cancelled(mut data: ProgramData, cancelled: &AtomicBool) -> Result<Outcome, RuntimeError> {
+    let mut state = get_state(States::ExecuteState);
+    loop {
+        let line = data.get_index();
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(RuntimeError::in_program(&data, line, CANCELLED_MESSAGE));
+        }
+        match state.execute(data) {
+            NewState::Continue(next_data, next_state) => {
+                data = next_data;
+                state = next_state;
+            },
+            NewState::Finished(data, exit_code) => return Ok(Outcome { exit_code, data }),
+            NewState::Error(data, msg) => return Err(RuntimeError::in_program(&data, line, &msg)),
+        }
+    }
+}
+
+/// Watches the instructions of a program run with `run_observed`, for example to trace or
+/// profile it. The implicit 'quit' at the end of a program is not an instruction, so it is
+/// not observed.
+pub trait Observer {
+    /// Called before each instruction is run.
+    fn before_instruction(&mut self, _data: &ProgramData) {}
+
+    /// Called once an instruction has run, with the program data as the instruction left it and
+    /// the error it failed with, if it did. An error returned here stops the program.
+    fn after_instruction(&mut self, _data: &ProgramData, _error: Option<&str>) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl Observer for Vec<&mut dyn Observer> {
+    fn before_instruction(&mut self, data: &ProgramData) {
+        for observer in self.iter_mut() {
+            observer.before_instruction(data);
+        }
+    }
+
+    fn after_instruction(&mut self, data: &ProgramData, error: Option<&str>) -> Result<(), String> {
+        self.iter_mut().try_for_each(|observer| observer.after_instruction(data, error))
+    }
+}
+
+/// Runs a program like `run`, telling `observer` about every instruction it runs.
+///
+/// # Arguments
+/// * `data` - The program to run, along with its variables, stack and console
+/// * `observer` - What to tell about each instruction
+///
+/// # Returns
+/// * `Ok(Outcome)` - The exit code and the final program data of a program that finished
+/// * `Err(RuntimeError)` - The error that stopped the program, or that the observer returned
+pub fn run_observed(mut data: ProgramData, observer: &mut dyn Observer) -> Result<Outcome, RuntimeError> {
+    let mut state = get_state(States::ExecuteState);
+    loop {
+        let line = data.get_index();
+        let observed = !data.at_end();
+        if observed {
+            observer.before_instruction(&data);
+        }
+
+        // Runs the states of the instruction until the next one starts
+        let mut result = state.execute(data);
+        while let NewState::Continue(next_data, next_state) = result {
+            if next_state.starts_instruction() {
+                result = NewState::Continue(next_data, next_state);
+                break;
+            }
+            result = next_state.execute(next_data);
+        }
+
+        let observe = |observer: &mut dyn Observer, data: &ProgramData, error: Option<&str>| match observed {
+            true => observer.after_instruction(data, error).map_err(|message| RuntimeError::in_program(data, line, &message)),
+            false => Ok(()),
+        };
+        match result {
+            NewState::Continue(next_data, next_state) => {
+                observe(observer, &next_data, None)?;
+                data = next_data;
+                state = next_state;
+            },
+            NewState::Finished(data, exit_code) => {
+                observe(observer, &data, None)?;
+                return Ok(Outcome { exit_code, data });
+            },
+            NewState::Error(data, msg) => {
+                let error = RuntimeError::in_program(&data, line, &msg);
+                observe(observer, &data, Some(&error.message))?;
+                return Err(error);
+            },
+        }
+    }
+}
+
+/// Something that happened in a program started with `spawn`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RunEvent {
+    /// The program wrote a line of output
+    Output(String),
+    /// The program finished with the given exit code
+    Finished(i32),
+    /// The program was stopped by an error (or by being cancelled)
+    Error(RuntimeError),
+}
+
+/// Console of a spawned program, which reads input sent to its handle and reports output as
+/// events.
+struct ChannelConsole {
+    input: Mutex<Receiver<String>>,
+    events: Sender<RunEvent>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ChannelConsole {
+    /// Waits for a line of input until `deadline` (or forever, if there is none), checking
+    /// regularly whether the program has been cancelled.
+    fn receive(&mut self, deadline: Option<Instant>) -> Result<Option<String>, String> {
+        loop {
+            if self.cancelled.load(Ordering::Relaxed) {
+                return Err(String::from(CANCELLED_MESSAGE));
+            }
+            let wait = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()).min(CANCEL_POLL_INTERVAL),
+                None => CANCEL_POLL_INTERVAL,
+            };
+            match self.input.get_mut().unwrap().recv_timeout(wait) {
+                Ok(line) => return Ok(Some(line)),
+                Err(RecvTimeoutError::Disconnected) => return Ok(None),
+                Err(RecvTimeoutError::Timeout) if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+                    return Ok(None)
+                },
+                Err(RecvTimeoutError::Timeout) => continue,
+            }
+        }
+    }
+}
+
+impl Console for ChannelConsole {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        self.receive(None)
+    }
+
+    fn read_line_timeout(&mut self, timeout: Duration) -> Result<Option<String>, String> {
+        self.receive(Some(Instant::now() + timeout))
+    }
+
+    fn read_all(&mut self) -> Result<String, String> {
+        let mut all_input = String::new();
+        while let Some(line) = self.read_line()? {
+            all_input.push_str(&line);
+            all_input.push('\n');
+        }
+        Ok(all_input)
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        // Output is dropped if nobody is listening for it any more
+        let _ = self.events.send(RunEvent::Output(line.to_string()));
+        Ok(())
+    }
+}
+
+/// Handle to a program running on its own thread, started with `spawn`.
+pub struct RunHandle {
+    input: Option<Sender<String>>,
+    events: Receiver<RunEvent>,
+    cancelled: Arc<AtomicBool>,
+    thread: JoinHandle<Result<Outcome, RuntimeError>>,
+}
+
+impl RunHandle {
+    /// Sends a line of input to the program.
+    pub fn send_input(&self, line: &str) -> Result<(), String> {
+        self.input.as_ref()
+            .ok_or(String::from("Input has been closed!"))?
+            .send(line.to_string())
+            .map_err(|_| String::from("Program is no longer running!"))
+    }
+
+    /// Closes the program's input. Once the lines already sent have been read, the program sees
+    /// the end of its input.
+    pub fn close_input(&mut self) {
+        self.input = None;
+    }
+
+    /// Returns the events of the program: every line of output it writes, followed by how it
+    /// finished.
+    pub fn events(&self) -> &Receiver<RunEvent> {
+        &self.events
+    }
+
+    /// Asks the program to stop. It stops before its next instruction, or straight away if it
+    /// is waiting for input.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Waits for the program to finish.
+    pub fn join(self) -> Result<Outcome, RuntimeError> {
+        self.thread.join().unwrap_or_else(|_| Err(RuntimeError {
+            line: 0,
+            message: String::from("Interpreter thread panicked!"),
+            called_from: Vec::new(),
+        }))
+    }
+}
+
+/// Runs a program on a new thread, so that the caller (such as the UI thread of a GUI) is never
+/// blocked by it. The program's console is replaced: input is sent through the returned handle,
+/// and output arrives as events on it. Threads are not available on WASI, where `run` or
+/// `async_runner::run_async` should be used instead.
+pub fn spawn(mut data: ProgramData) -> RunHandle {
+    let (input_sender, input_receiver) = mpsc::channel();
+    let (event_sender, event_receiver) = mpsc::channel();
+    let cancelled = Arc::new(AtomicBool::new(false));
+
+    data.set_console(Box::new(ChannelConsole {
+        input: Mutex::new(input_receiver),
+        events: event_sender.clone(),
+        cancelled: cancelled.clone(),
+    }));
+
+    let thread_cancelled = cancelled.clone();
+    let thread = thread::spawn(move || {
+        let result = run_until_cancelled(data, &thread_cancelled);
+        let event = match &result {
+            Ok(outcome) => RunEvent::Finished(outcome.exit_code),
+            Err(error) => RunEvent::Error(error.clone()),
+        };
+        let _ = event_sender.send(event);
+        result
+    });
+
+    RunHandle { input: Some(input_sender), events: event_receiver, cancelled, thread }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, LinkedList};
+    use crate::console::BufferConsole;
+    use crate::prog_data::ProgramData;
+    use super::{run, spawn, RunEvent, RuntimeError};
+
+    fn program(lines: Vec<&str>) -> ProgramData {
+        ProgramData::new(lines.into_iter().map(String::from).collect(), HashMap::new(), LinkedList::new(), 0)
+    }
+
+    #[test]
+    fn run_program_to_completion() {
+        let mut data = program(vec!["let $a = 5", "let $b = 7", "let $c = $a + $b", "output $c", "quit"]);
+        let console = BufferConsole::new("");
+        let output = console.output.clone();
+        data.set_console(Box::new(console));
+
+        let outcome = run(data).ok().unwrap();
+        assert_eq!(outcome.exit_code, 0);
+        assert_eq!(outcome.data.get_var(&String::from("c")).unwrap(), "12");
+        assert_eq!(*output.lock().unwrap(), vec![String::from("12")]);
+    }
+
+    #[test]
+    fn run_past_end_of_program() {
+        let outcome = run(program(vec!["let $a = 5"])).ok().unwrap();
+        assert_eq!(outcome.exit_code, 0);
+        assert_eq!(outcome.data.get_var(&String::from("a")).unwrap(), "5");
+    }
+
+    #[test]
+    fn run_program_with_runtime_error() {
+        let result = run(program(vec!["let $a = 5", "let $b = $c", "quit"]));
+        let error = result.err().unwrap();
+        assert_eq!(error, RuntimeError { line: 1, message: String::from("Variable $c does not exist!"), called_from: Vec::new() });
+        assert_eq!(error.to_string(), "Variable $c does not exist!\nAborting...");
+    }
+
+    #[test]
+    fn runtime_error_shows_call_chain() {
+        let mut data = program(vec!["let $a = pop"]);
+        data.push_call(3);
+        data.push_call(17);
+        let error = run(data).err().unwrap();
+        assert_eq!(error.called_from, vec![17, 3]);
+        assert_eq!(error.to_string(), "Stack is empty!\nat line 0, called from 17, called from 3\nAborting...");
+    }
+
+    #[test]
+    fn spawn_program_with_channel_io() {
+        let mut handle = spawn(program(vec!["let $a = input", "output $a", "let $b = input", "output $b", "quit"]));
+        handle.send_input("3").unwrap();
+        assert_eq!(handle.events().recv().unwrap(), RunEvent::Output(String::from("3")));
+        handle.close_input();
+        assert_eq!(handle.events().recv().unwrap(), RunEvent::Output(String::new()));
+        assert_eq!(handle.events().recv().unwrap(), RunEvent::Finished(0));
+        assert_eq!(handle.join().ok().unwrap().exit_code, 0);
+    }
+
+    #[test]
+    fn cancel_spawned_program() {
+        // The program waits for input that never arrives, until it is cancelled
+        let handle = spawn(program(vec!["let $a = input", "quit"]));
+        handle.cancel();
+        let error = RuntimeError { line: 0, message: String::from("Program was cancelled!"), called_from: Vec::new() };
+        assert_eq!(handle.events().recv().unwrap(), RunEvent::Error(error.clone()));
+        assert_eq!(handle.join().err().unwrap(), error);
+
+        let handle = spawn(program(vec!["goto 0"]));
+        handle.cancel();
+        assert_eq!(handle.join().err().unwrap().message, "Program was cancelled!");
+    }
+}
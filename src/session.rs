@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::console::Console;
+use crate::prog_data::ProgramData;
+use crate::runner::run;
+
+/// Number of matching steps shown before the first mismatch of a session.
+const CONTEXT_STEPS: usize = 3;
+
+/// A single step of a scripted session: a line the program is expected to write, or a line of
+/// input to give it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SessionStep {
+    Output(String),
+    Input(String),
+}
+
+impl fmt::Display for SessionStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionStep::Output(line) => write!(f, "> {}", line),
+            SessionStep::Input(line) => write!(f, "< {}", line),
+        }
+    }
+}
+
+/// Parses a scripted session. Each line of a session has the form '> line' for a line the
+/// program is expected to write, or '< line' for a line of input given to it, which is the
+/// format of the transcripts written with --transcript. Blank lines are ignored.
+///
+/// # Returns
+/// * `Ok(Vec<SessionStep>)` - The steps of the session, in order
+/// * `Err(String)` - A message detailing the first line that is neither output nor input
+pub fn parse_session(session: &str) -> Result<Vec<SessionStep>, String> {
+    let mut steps = Vec::new();
+    for (index, line) in session.lines().enumerate() {
+        let line = line.trim_end();
+        let step = match (line.strip_prefix('>'), line.strip_prefix('<')) {
+            _ if line.is_empty() => continue,
+            (Some(output), _) => SessionStep::Output(output.strip_prefix(' ').unwrap_or(output).to_string()),
+            (_, Some(input)) => SessionStep::Input(input.strip_prefix(' ').unwrap_or(input).to_string()),
+            _ => return Err(format!("Line {} of the session is neither '> output' nor '< input': {}", index + 1, line)),
+        };
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+/// Loads a scripted session from the file at `file_path`.
+pub fn load_session(file_path: &str) -> Result<Vec<SessionStep>, String> {
+    let session = fs::read_to_string(file_path).map_err(|err| format!("{}: {}", file_path, err))?;
+    parse_session(&session).map_err(|err| format!("{}: {}", file_path, err))
+}
+
+/// Progress of a program through its session.
+struct SessionState {
+    expected: VecDeque<SessionStep>,
+    matched: Vec<SessionStep>,
+    mismatch: Option<String>,
+}
+
+impl SessionState {
+    /// Takes the next step of the session if `matches` accepts it.
+    fn next_if(&mut self, matches: impl FnOnce(&SessionStep) -> bool) -> Option<SessionStep> {
+        if !self.expected.front().is_some_and(matches) {
+            return None;
+        }
+        let step = self.expected.pop_front()?;
+        self.matched.push(step.clone());
+        Some(step)
+    }
+
+    /// Records that the program did `actual` where the session expected something else, as a
+    /// diff of the last few matching steps followed by the expected and actual step.
+    fn mismatch(&mut self, actual: &str) -> String {
+        let expected = match self.expected.front() {
+            Some(step) => step.to_string(),
+            None => String::from("(end of session)"),
+        };
+        let mut report = format!("Session mismatch after {} matching steps:\n", self.matched.len());
+        for step in &self.matched[self.matched.len().saturating_sub(CONTEXT_STEPS)..] {
+            report.push_str(&format!("  {}\n", step));
+        }
+        report.push_str(&format!("- {}\n+ {}", expected, actual));
+
+        self.mismatch.get_or_insert(report);
+        String::from("Session mismatch!")
+    }
+}
+
+/// Console that gives a program the inputs of its session, and stops it as soon as it reads or
+/// writes something the session does not expect.
+struct SessionConsole {
+    state: Arc<Mutex<SessionState>>,
+}
+
+impl Console for SessionConsole {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        let mut state = self.state.lock().unwrap();
+        match state.next_if(|step| matches!(step, SessionStep::Input(_))) {
+            Some(SessionStep::Input(line)) => Ok(Some(line)),
+            _ => Err(state.mismatch("< (input requested)")),
+        }
+    }
+
+    fn read_line_timeout(&mut self, _: Duration) -> Result<Option<String>, String> {
+        self.read_line()
+    }
+
+    fn read_all(&mut self) -> Result<String, String> {
+        let mut state = self.state.lock().unwrap();
+        let mut all_input = String::new();
+        while let Some(SessionStep::Input(line)) = state.next_if(|step| matches!(step, SessionStep::Input(_))) {
+            all_input.push_str(&line);
+            all_input.push('\n');
+        }
+        Ok(all_input)
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        match state.next_if(|step| *step == SessionStep::Output(line.to_string())) {
+            Some(_) => Ok(()),
+            None => Err(state.mismatch(&format!("> {}", line))),
+        }
+    }
+}
+
+/// Runs a program against a scripted session. The program is given the inputs of the session
+/// when it asks for them, and must write exactly the expected output in between. The program's
+/// console is replaced, so nothing is read from stdin or written to stdout.
+///
+/// # Arguments
+/// * `data` - The program to run
+/// * `steps` - The session the program must follow
+///
+/// # Returns
+/// * `Ok(usize)` - The number of steps in the session, all of which the program followed
+/// * `Err(String)` - A diff showing where the program first strayed from the session
+pub fn run_session(mut data: ProgramData, steps: Vec<SessionStep>) -> Result<usize, String> {
+    let state = Arc::new(Mutex::new(SessionState {
+        expected: steps.into(),
+        matched: Vec::new(),
+        mismatch: None,
+    }));
+    data.set_console(Box::new(SessionConsole { state: state.clone() }));
+    let result = run(data);
+
+    let mut state = state.lock().unwrap();
+    if let Some(report) = state.mismatch.take() {
+        return Err(report);
+    }
+    match result {
+        Err(error) => {
+            state.mismatch(&format!("(error) {}", error.message));
+        },
+        Ok(_) if !state.expected.is_empty() => {
+            state.mismatch("(program finished)");
+        },
+        Ok(_) => return Ok(state.matched.len()),
+    }
+    Err(state.mismatch.take().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, LinkedList};
+    use crate::prog_data::ProgramData;
+    use super::{load_session, parse_session, run_session, SessionStep};
+
+    const SQUARE: [&str; 6] = ["let $p = \"Enter a number\"", "output $p", "let $n = input", "let $sq = $n * $n",
+                               "output $sq", "quit"];
+
+    fn program(lines: &[&str]) -> ProgramData {
+        ProgramData::new(lines.iter().map(|line| line.to_string()).collect(), HashMap::new(), LinkedList::new(), 0)
+    }
+
+    #[test]
+    fn parse_session_steps() {
+        let steps = parse_session("> Enter a number\n< 5\n\n>\n> 25  \n").unwrap();
+        assert_eq!(steps, vec![
+            SessionStep::Output(String::from("Enter a number")),
+            SessionStep::Input(String::from("5")),
+            SessionStep::Output(String::new()),
+            SessionStep::Output(String::from("25")),
+        ]);
+        assert_eq!(parse_session("> hi\n5\n").err().unwrap(),
+                   "Line 2 of the session is neither '> output' nor '< input': 5");
+    }
+
+    #[test]
+    fn load_session_file() {
+        assert_eq!(load_session("testfiles/test_session.txt").unwrap().len(), 3);
+        assert!(load_session("testfiles/missing.txt").err().unwrap().starts_with("testfiles/missing.txt: "));
+    }
+
+    #[test]
+    fn run_matching_session() {
+        let steps = parse_session("> Enter a number\n< 5\n> 25\n").unwrap();
+        assert_eq!(run_session(program(&SQUARE), steps).unwrap(), 3);
+    }
+
+    #[test]
+    fn run_session_with_wrong_output() {
+        let steps = parse_session("> Enter a number\n< 5\n> 24\n").unwrap();
+        assert_eq!(run_session(program(&SQUARE), steps).err().unwrap(),
+                   "Session mismatch after 2 matching steps:\n  > Enter a number\n  < 5\n- > 24\n+ > 25");
+    }
+
+    #[test]
+    fn run_session_with_missing_input() {
+        let steps = parse_session("> Enter a number\n> 25\n").unwrap();
+        assert_eq!(run_session(program(&SQUARE), steps).err().unwrap(),
+                   "Session mismatch after 1 matching steps:\n  > Enter a number\n- > 25\n+ < (input requested)");
+    }
+
+    #[test]
+    fn run_session_that_is_not_finished() {
+        let steps = parse_session("> Enter a number\n< 5\n> 25\n> bye\n").unwrap();
+        assert_eq!(run_session(program(&SQUARE), steps).err().unwrap(),
+                   "Session mismatch after 3 matching steps:\n  > Enter a number\n  < 5\n  > 25\n- > bye\n+ (program finished)");
+    }
+}
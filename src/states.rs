@@ -1,72 +1,38 @@
-use std::process::exit;
-use std::io;
-use lazy_static::lazy_static;
-use regex::{Captures, Regex};
 use num_integer::div_rem;
-use rand::Rng;
-use std::{thread, time::Duration};
-use crate::prog_data::ProgramData;
-
-type NewState = Result<(ProgramData, Box<dyn StateMachine>),String>;
-
-/*
-This code exists to provide a means to test that
-input, output and exit states work as intended
- */
-#[cfg(test)]
-static mut IO_BUFFER: String = String::new();
-#[cfg(test)]
-static mut IS_EXIT: bool = false;
-
-#[cfg(test)]
-fn do_exit() {
-    unsafe {
-        IS_EXIT = true
-    }
-}
-
-#[cfg(not(test))]
-fn do_exit() {
-    exit(0);
-}
-
-#[cfg(test)]
-fn get_input() -> String {
-    let mut ret_str = String::new();
-    unsafe {
-        ret_str = IO_BUFFER.clone();
-    }
-    ret_str
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::Duration;
+use crate::builtins::{find_builtin, Argument};
+use crate::dialect::Dialect;
+use crate::parser::{Condition, Expression, Instruction, Operand, Operator, Predicate};
+use crate::prog_data::{CodeLine, Frame, ProgramData, Value};
+
+/// What happens after a state has executed.
+pub enum NewState {
+    /// Execution carries on with the given program data and state
+    Continue(ProgramData, Box<dyn StateMachine>),
+    /// The program finished with the given exit code
+    Finished(ProgramData, i32),
+    /// The program stopped because of a runtime error. The program data is as it was when the
+    /// error occurred, so that it can still be inspected.
+    Error(ProgramData, String),
 }
 
-#[cfg(not(test))]
-fn get_input() -> String {
-    let mut input = String::new();
-    let input_result = io::stdin().read_line(&mut input);
-    match input_result {
-        Ok(_) => {},
-        Err(_) => {
-            eprintln!("Error reading input!\nAborting...");
-            exit(-1);
-        }
-    }
-    input.trim().to_string()
-}
+/// Result of the work done by a state on the program data before it is turned into a
+/// `NewState`. States build this so that errors can be passed on with `?`.
+type Transition = Result<Box<dyn StateMachine>, String>;
 
-#[cfg(test)]
-fn write_output(out_string: String) {
-    unsafe {
-        IO_BUFFER = out_string;
+/// Turns the result of a state's work into the state that follows it.
+fn transition(data: ProgramData, result: Transition) -> NewState {
+    match result {
+        Ok(state) => NewState::Continue(data, state),
+        Err(msg) => NewState::Error(data, msg),
     }
 }
 
-#[cfg(not(test))]
-fn write_output(out_string: String) {
-    println!("{}", out_string);
-}
-
 /// This trait is used to interpret code data and to be returned by other states.
-pub trait StateMachine {
+pub trait StateMachine: Send + Sync {
     /// Interprets code data referenced by a state offset.
     /// A new state and offset is returned if the execution was successful, otherwise an error message is returned.
     /// # Arguments
@@ -75,104 +41,156 @@ pub trait StateMachine {
     /// * `state` - Offset of the code_list parameter. Tells the function which statement to execute
     ///
     /// # Returns
-    /// * `Ok((usize, Box<dyn StateMachine>))` - A tuple containing the new state and offset. The offset may
-    ///                                          be the next instruction to execute or an offset specified by a 'goto' command
-    /// * `Err(String)` - An error message detailing why the execution failed
+    /// * `NewState::Continue(ProgramData, Box<dyn StateMachine>)` - The program data and the next state to
+    ///   execute. The offset may be the next instruction to execute or an offset specified by a 'goto' command
+    /// * `NewState::Finished(ProgramData, i32)` - The program has finished with the given exit code
+    /// * `NewState::Error(ProgramData, String)` - The program data and an error message detailing
+    ///   why the execution failed
     fn execute(&self, data: ProgramData) -> NewState;
+
+    /// Returns true for the state that starts running an instruction. An instruction has been
+    /// run completely once execution reaches this state again, however many states it took.
+    fn starts_instruction(&self) -> bool {
+        false
+    }
 }
 
-/// Decodes a line of code using a given regex. If the decoding was successful, the passed in
-/// executor function is executed, otherwise the given error message is returned. If no code string
-/// is passed, this function returns the quit state.
+/// Parses the current line of code. If the parsing was successful, the passed in executor
+/// function is executed with the parsed instruction, otherwise the given error message is
+/// returned along with the position of the syntax error.
 ///
 /// # Arguments
-/// * `code` - code that the function needs to decode
-/// * `regular_expression` - Regex used to decode the code
-/// * `executor` - Function to execute if the decoding was successful
-/// * `error_msg` - Error to return if the decoding failed
+/// * `data` - Program data holding the code that the function needs to parse
+/// * `executor` - Function to execute if the parsing was successful
+/// * `error_msg` - Error to return if the parsing failed
 ///
 /// # Returns
-/// * `Ok((usize, Box<dyn StateMachine>))` - A tuple containing the new state and offset. The offset may
-///                                          be the next instruction to execute or an offset specified by a 'goto' command
-/// * `Err(String)` - An error message detailing why the execution failed
+/// * `NewState` - The state returned by the executor, or an error if the parsing failed
 fn decode_and_execute<T>(
-    data: ProgramData,
-    regular_expression: &Regex,
+    mut data: ProgramData,
     mut executor: T,
     error_msg: &str
-) -> NewState where T: FnMut(ProgramData, &String, Captures) -> NewState {
-    let code = data.get_code();
-    match code {
-        Some(value) => {
-            if regular_expression.is_match(&value) {
-                executor(data, &value, regular_expression.captures(&value).unwrap())
-            } else {
-                Err(format!("{}: {}\nAborting...", error_msg, value))
-            }
-        },
-        None => Ok((data, get_state(States::QuitState)))
-    }
+) -> NewState where T: FnMut(&mut ProgramData, &str, &Instruction) -> Transition {
+    let result = current_line(&data).and_then(|line| match line.instruction() {
+        Ok(instruction) => executor(&mut data, line.source(), instruction),
+        Err(err) => Err(format!("{}: {} ({})\nAborting...", error_msg, line.source(), err)),
+    });
+    transition(data, result)
+}
+
+/// Returns the line an instruction state has been asked to run. The end of the program is
+/// handled by the execute state, so the other states always have a line to run.
+fn current_line(data: &ProgramData) -> Result<CodeLine, String> {
+    data.code_line().ok_or(format!("Line {} has no instruction to run!\nAborting...", data.get_index()))
+}
+
+/// Error returned by a state that has been given an instruction it does not handle.
+fn invalid(error_msg: &str, value: &str) -> Transition {
+    Err(format!("{}: {}\nAborting...", error_msg, value))
 }
 
 #[derive(Copy, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum States {
     AssignState,
     ExecuteState,
     GotoState,
+    SubroutineState,
     IfState,
     QuitState,
     OutputState,
     MathState,
+    StringState,
+    ExecState,
+    FsState,
+    RandomState,
+    SortState,
     PushState,
+    AssertState,
+    TestcaseState,
+    DialectState,
+    LabelState,
+    MapState,
+    FunctionState,
+    LoopState,
+    SwitchState,
+    ErrorState,
+    EnvState,
+    BuiltinState,
+    #[cfg(feature = "net")]
+    NetState,
 }
 
 struct EndState {} // Tell the interpreter to quit
-struct AssignState {} // Assigns data to registers and gets user input
+struct AssignState {} // Assigns data to registers, declares constants, removes variables and gets user input
 struct ExecuteState {} // Starting point for code execution
 struct IfState{} // Handles conditional branching statements
 struct GotoState{} // Handles unconditional jump statements
+struct SubroutineState{} // Calls subroutines, returns from them and declares their local variables
 struct OutputState{} // Outputs data to the user
 struct MathState {} // Handle arithmetic statements
+struct StringState {} // Handles the string builtins
+struct ExecState {} // Runs shell commands
+struct FsState {} // Looks at and changes the filesystem
+struct RandomState {} // Gives out random numbers
+struct SortState {} // Sorts the stack
 struct PushState{} // Push data onto the stack
-
-/*
-Array of state types and conditions used by the execute state to
-determine which state to transition to.
- */
-lazy_static! {
-    static ref TRANSITION_FUNCTIONS: [(Regex, States); 6] = [
-        (Regex::new(r"let").unwrap(), States::AssignState),
-        (Regex::new(r"if").unwrap(), States::IfState), //must go before 'goto'
-        (Regex::new(r"goto").unwrap(), States::GotoState),
-        (Regex::new(r"quit").unwrap(), States::QuitState),
-        (Regex::new(r"output").unwrap(), States::OutputState),
-        (Regex::new(r"push").unwrap(), States::PushState),
-    ];
-}
-
-/*
-Build regexes ahead of time to improve performance
- */
-lazy_static! {
-    static ref ASSIGN_REGEXES : [Regex; 5] = [
-        Regex::new(r#"let \$(\w+) = (0+|([1-9]\d*)|"[a-zA-Z ]*")"#).unwrap(),
-        Regex::new(r"let \$(\w+) = \$(\w+)").unwrap(),
-        Regex::new(r"let \$(\w+) = input").unwrap(),
-        Regex::new(r"let \$(\w+) = \$(\w+) ([+\-*/]) \$(\w+)").unwrap(),
-        Regex::new(r"let \$(\w+) = pop").unwrap(),
-    ];
-
-    static ref IF_REGEX : Regex = Regex::new(r"if \$(\w+) (<=?|>=?|=|!=) \$(\w+) goto (\d+)").unwrap();
-
-    static ref EXECUTE_REGEX : Regex = Regex::new("(.*)").unwrap();
-
-    static ref PUSH_REGEX : Regex = Regex::new(r"push \$(\w+)").unwrap();
-
-    static ref GOTO_REGEX : Regex = Regex::new(r"goto (\d+)").unwrap();
-
-    static ref OUTPUT_REGEX : Regex = Regex::new(r"output \$(\w+)").unwrap();
-
-    static ref MATH_REGEX : Regex = Regex::new(r"\$(\w+) = \$(\w+) ([+\-*/]) \$(\w+)").unwrap();
+struct AssertState{} // Checks that a condition holds
+struct TestcaseState{} // Skips over testcase blocks during normal execution
+struct DialectState{} // Checks the dialect header, which is read when the program is loaded
+struct LabelState{} // Passes over labels and imports, which are resolved when the program is loaded
+struct MapState{} // Creates maps, and stores and looks up their entries
+struct FunctionState{} // Calls functions and passes over their bodies
+struct LoopState{} // Counts for loops, checks while loops and jumps around their bodies
+struct SwitchState{} // Jumps to the case of a switch that matches its value
+struct ErrorState{} // Stops the program with an error of its own
+struct EnvState{} // Reads environment variables and the arguments of the program
+struct BuiltinState{} // Calls the builtins, such as 'abs' and 'max'
+#[cfg(feature = "net")]
+struct NetState{} // Handles socket instructions
+
+/// Keywords of the language, which cannot be used as variable names.
+pub const RESERVED_WORDS: [&str; 70] = [
+    "let", "const", "unset", "if", "goto", "gosub", "return", "label", "quit", "output", "flush", "push", "pop", "input", "inputnum", "neg", "all", "timeout", "default",
+    "pad", "repeat", "reverse", "exec", "exists", "delete", "listdir", "random", "seed", "sort", "xor", "and", "or", "not", "else", "map",
+    "func", "endfunc", "call", "local", "for", "to", "step", "next", "while", "endwhile", "switch", "case", "error",
+    "readfile", "openfile", "readline", "closefile", "writefile", "appendfile", "env", "arg", "argc", "import", "as",
+    "assert", "testcase", "endtest", "dialect", "connect", "netsend", "netrecv", "close", "httpget",
+    "macro", "endmacro",
+];
+
+/// Variables the interpreter sets itself, such as the exit code of a command run by 'exec'.
+pub const INTERPRETER_VARIABLES: [&str; 4] = ["timedout", "exitcode", "entries", "eof"];
+
+/// Finds the state that handles an instruction, based on the first word of the instruction.
+/// Keywords that only appear later in a line (such as the 'goto' of an 'if' statement, or a
+/// variable named '$letter') do not change where the instruction is sent.
+fn dispatch(keyword: &str) -> Option<States> {
+    match keyword {
+        "let" | "const" | "unset" => Some(States::AssignState),
+        "if" => Some(States::IfState),
+        "goto" => Some(States::GotoState),
+        "gosub" | "return" | "local" => Some(States::SubroutineState),
+        "quit" => Some(States::QuitState),
+        "endtest" => Some(States::QuitState), // only reached while running tests
+        "output" | "flush" => Some(States::OutputState),
+        "push" => Some(States::PushState),
+        "assert" => Some(States::AssertState),
+        "error" => Some(States::ErrorState),
+        "testcase" => Some(States::TestcaseState),
+        "dialect" => Some(States::DialectState),
+        "label" | "import" => Some(States::LabelState),
+        "map" => Some(States::MapState),
+        "func" | "endfunc" => Some(States::FunctionState),
+        "for" | "next" | "while" | "endwhile" => Some(States::LoopState),
+        "switch" | "case" | "default" => Some(States::SwitchState),
+        "delete" | "listdir" | "closefile" | "writefile" | "appendfile" => Some(States::FsState),
+        "seed" => Some(States::RandomState),
+        "sort" => Some(States::SortState),
+        #[cfg(feature = "net")]
+        "netsend" | "close" => Some(States::NetState),
+        _ => None
+    }
 }
 
 /// Returns the desired state based on the provided state type
@@ -182,7 +200,7 @@ lazy_static! {
 /// Box<dyn StateMachine> - A state that implements the 'StateMachine' trait.
 ///
 /// # Examples
-/// ```
+/// ```ignore
 /// let state = get_state(States::EndState);
 /// state.execute(vec![], vec![], 0);
 /// ```
@@ -190,39 +208,91 @@ pub fn get_state(state_type: States) -> Box<dyn StateMachine> {
     match state_type {
         States::AssignState => Box::new(AssignState{}),
         States::GotoState => Box::new(GotoState{}),
+        States::SubroutineState => Box::new(SubroutineState{}),
         States::IfState => Box::new(IfState{}),
         States::QuitState => Box::new(EndState{}),
         States::OutputState => Box::new(OutputState{}),
         States::ExecuteState => Box::new(ExecuteState{}),
         States::MathState => Box::new(MathState{}),
+        States::StringState => Box::new(StringState{}),
+        States::ExecState => Box::new(ExecState{}),
+        States::FsState => Box::new(FsState{}),
+        States::RandomState => Box::new(RandomState{}),
+        States::SortState => Box::new(SortState{}),
         States::PushState => Box::new(PushState{}),
+        States::AssertState => Box::new(AssertState{}),
+        States::TestcaseState => Box::new(TestcaseState{}),
+        States::DialectState => Box::new(DialectState{}),
+        States::LabelState => Box::new(LabelState{}),
+        States::MapState => Box::new(MapState{}),
+        States::FunctionState => Box::new(FunctionState{}),
+        States::LoopState => Box::new(LoopState{}),
+        States::SwitchState => Box::new(SwitchState{}),
+        States::ErrorState => Box::new(ErrorState{}),
+        States::EnvState => Box::new(EnvState{}),
+        States::BuiltinState => Box::new(BuiltinState{}),
+        #[cfg(feature = "net")]
+        States::NetState => Box::new(NetState{}),
     }
 }
 
 impl StateMachine for ExecuteState {
     fn execute(&self, data: ProgramData) -> NewState {
-        let code = data.get_code();
-        decode_and_execute(
-          data,
-          &EXECUTE_REGEX,
-          |data, value, _| -> NewState
-              {
-                  //Find the correct state to move to
-                  for new_state in TRANSITION_FUNCTIONS.iter() {
-                      if new_state.0.is_match(value){
-                          return Ok((data, get_state(new_state.1)));
-                      }
-                  }
-                  return Err(format!("Unknown instruction: {}\nAborting...", value));
-              },
-            "Unknown instruction")
+        // Running past the last line of the program is the same as running 'quit'
+        if data.at_end() {
+            return NewState::Continue(data, get_state(States::QuitState));
+        }
+
+        let value = data.get_code().unwrap_or_default();
+        //Find the correct state to move to
+        let keyword = value.split_whitespace().next().unwrap_or("");
+        match dispatch(keyword) {
+            Some(new_state) => NewState::Continue(data, get_state(new_state)),
+            None => {
+                let error_msg = format!("Unknown instruction: {}\nAborting...", value);
+                NewState::Error(data, error_msg)
+            }
+        }
+    }
+
+    fn starts_instruction(&self) -> bool {
+        true
+    }
+}
+
+/// Largest exit code a program can quit with, as most systems only keep the lowest byte.
+const MAX_EXIT_CODE: i128 = 255;
+
+/// Finds the exit code of the 'quit' being run. Running past the last line of the program, or
+/// reaching 'endtest', quits with 0.
+fn exit_code(data: &mut ProgramData) -> Result<i32, String> {
+    let Some(line) = data.code_line() else {
+        return Ok(0);
+    };
+    match line.instruction() {
+        Ok(Instruction::Quit(Some(operand))) => {
+            let exit_code = whole_number(data, operand)?;
+            if !(0..=MAX_EXIT_CODE).contains(&exit_code) {
+                return Err(format!("Exit code {} is not between 0 and {}!\nAborting...", exit_code, MAX_EXIT_CODE));
+            }
+            Ok(exit_code as i32)
+        },
+        Ok(_) => Ok(0),
+        Err(err) => Err(format!("Invalid quit statement: {} ({})\nAborting...", line.source(), err)),
     }
 }
 
 impl StateMachine for EndState {
-    fn execute(&self, _: ProgramData) -> NewState {
-        do_exit();
-        Err(format!("Exit"))
+    fn execute(&self, mut data: ProgramData) -> NewState {
+        let exit_code = match exit_code(&mut data) {
+            Ok(exit_code) => exit_code,
+            Err(err) => return NewState::Error(data, err),
+        };
+        // Output still buffered by the console is written before the program is done
+        match data.console().flush() {
+            Ok(()) => NewState::Finished(data, exit_code),
+            Err(err) => NewState::Error(data, format!("{}\nAborting...", err)),
+        }
     }
 }
 
@@ -230,16 +300,25 @@ impl StateMachine for PushState {
     fn execute(&self, data: ProgramData) -> NewState {
         decode_and_execute(
             data,
-            &PUSH_REGEX,
-            |mut data, _, capture| -> NewState
+            |data, value, instruction| -> Transition
                 {
-                    let var_name = capture[1].to_string();
-                    let var_data = data.get_var(&var_name);
+                    let Instruction::Push(operand) = instruction else {
+                        return invalid("Invalid push statement", value);
+                    };
+                    let var_name = match operand {
+                        Operand::Literal(literal) => {
+                            data.push(Value::from_text(literal));
+                            data.next_line();
+                            return Ok(get_state(States::ExecuteState));
+                        },
+                        Operand::Variable(var_name) => var_name,
+                    };
+                    let var_data = data.get_var(var_name);
                     match var_data {
                         Some(val) => {
                             data.push(val.clone());
                             data.next_line();
-                            Ok((data, get_state(States::ExecuteState)))
+                            Ok(get_state(States::ExecuteState))
                         },
                         None => Err(format!("No such variable: ${}\nAborting...", var_name))
                     }
@@ -249,66 +328,541 @@ impl StateMachine for PushState {
     }
 }
 
+/// Jumps to line `target` of the program, as 'goto' does, unless the program has no such line.
+fn goto_line(data: &mut ProgramData, target: usize) -> Transition {
+    if target >= data.code_size() {
+        return Err("Goto statement points to region out of bounds!\nAborting...".to_string());
+    }
+    data.set_index(target);
+    Ok(get_state(States::ExecuteState))
+}
+
 impl StateMachine for GotoState {
     fn execute(&self, data: ProgramData) -> NewState {
         decode_and_execute(
             data,
-            &GOTO_REGEX,
-            |mut data, _, goto_capture| -> NewState
+            |data, value, instruction| -> Transition
                 {
-                    let goto_ptr = goto_capture[1].parse::<usize>().unwrap();
-                    if goto_ptr >= data.code_size() {
-                        Err(format!("Goto statement points to region out of bounds!\nAborting..."))
-                    } else {
-                        data.set_index(goto_ptr);
-                        Ok((data, get_state(States::ExecuteState)))
-                    }
+                    let Instruction::Goto(goto_ptr) = instruction else {
+                        return invalid("Invalid goto statement", value);
+                    };
+                    goto_line(data, *goto_ptr)
                 },
             "Invalid goto statement")
     }
 }
 
+/// Deepest that subroutine and function calls can be nested, so that a subroutine that calls
+/// itself forever stops with an error.
+const MAX_CALL_DEPTH: usize = 10_000;
+
+impl StateMachine for SubroutineState {
+    fn execute(&self, data: ProgramData) -> NewState {
+        decode_and_execute(
+            data,
+            |data, value, instruction| {
+                match instruction {
+                    Instruction::Gosub(target) => {
+                        if *target >= data.code_size() {
+                            return Err(String::from("Gosub statement points to region out of bounds!\nAborting..."));
+                        }
+                        if data.call_depth() >= MAX_CALL_DEPTH {
+                            return Err(format!("Too many nested subroutine calls! (more than {})\nAborting...", MAX_CALL_DEPTH));
+                        }
+                        let call_site = data.get_index();
+                        data.push_call(call_site);
+                        data.set_index(*target);
+                    },
+                    // The program carries on with the line after the call, which may be the end of it.
+                    // A value is returned from the function's own scope, before its frame is gone
+                    Instruction::Return(returned) => {
+                        let returned = match returned {
+                            Some(Operand::Literal(literal)) => Some(Value::from_text(literal)),
+                            Some(Operand::Variable(var_name)) => Some(data.get_var(var_name).cloned()
+                                .ok_or(format!("Variable ${} does not exist!\nAborting...", var_name))?),
+                            None => None,
+                        };
+                        let frame = data.current_frame()
+                            .ok_or(String::from("Return without a matching gosub!\nAborting..."))?;
+                        let result = match (frame.result.clone(), returned) {
+                            (Some(_), None) => return Err(String::from("Function returned without a value!\nAborting...")),
+                            (None, Some(_)) => return Err(String::from("Cannot return a value from a subroutine!\nAborting...")),
+                            (result, returned) => result.zip(returned),
+                        };
+                        let call_site = data.pop_frame().unwrap().call_site;
+                        if let Some((result, returned)) = result {
+                            data.set_var(result, returned);
+                        }
+                        data.set_index(call_site + 1);
+                    },
+                    Instruction::Local(name) => {
+                        if !data.declare_local(name.clone()) {
+                            return Err(String::from("Local variables can only be declared inside a subroutine or function!\nAborting..."));
+                        }
+                        data.next_line();
+                    },
+                    _ => return invalid("Invalid subroutine statement", value),
+                }
+                Ok(get_state(States::ExecuteState))
+            },
+            "Invalid subroutine statement"
+        )
+    }
+}
+
+/// Evaluates a comparison used by 'if' and 'assert' statements. From dialect v2 onwards, two
+/// numbers are compared by value; otherwise values are compared as strings.
+fn compare(dialect: Dialect, lhs: &Value, condition: Condition, rhs: &Value) -> bool {
+    let by_value = match (lhs.as_int(), rhs.as_int()) {
+        _ if dialect < Dialect::V2 => None,
+        (Some(lhs), Some(rhs)) => Some(lhs.cmp(&rhs)),
+        _ => lhs.as_float().zip(rhs.as_float()).and_then(|(lhs, rhs)| lhs.partial_cmp(&rhs)),
+    };
+    let ordering = by_value.unwrap_or_else(|| lhs.as_text().cmp(&rhs.as_text()));
+    match condition {
+        Condition::GreaterEqual => ordering.is_ge(),
+        Condition::Greater => ordering.is_gt(),
+        Condition::LessEqual => ordering.is_le(),
+        Condition::Less => ordering.is_lt(),
+        Condition::Equal => ordering.is_eq(),
+        Condition::NotEqual => ordering.is_ne(),
+    }
+}
+
+/// Evaluates the predicate of an 'if' statement. 'and' and 'or' stop as soon as the result is
+/// known, so the variables of comparisons that are not needed do not have to exist.
+fn evaluate(data: &ProgramData, predicate: &Predicate) -> Result<bool, String> {
+    match predicate {
+        Predicate::Compare { lhs, condition, rhs } => {
+            let lhs_val = operand_value(data, lhs)?;
+            let rhs_val = operand_value(data, rhs)?;
+            Ok(compare(data.get_dialect(), &lhs_val, *condition, &rhs_val))
+        },
+        Predicate::Not(predicate) => Ok(!evaluate(data, predicate)?),
+        Predicate::And(lhs, rhs) => Ok(evaluate(data, lhs)? && evaluate(data, rhs)?),
+        Predicate::Or(lhs, rhs) => Ok(evaluate(data, lhs)? || evaluate(data, rhs)?),
+    }
+}
+
 impl StateMachine for IfState {
     fn execute(&self, data: ProgramData) -> NewState {
         decode_and_execute(
             data,
-            &IF_REGEX,
-            |mut data, _, captures| {
-                let lhs_name = captures[1].to_string();
-                let rhs_name = captures[3].to_string();
-                let code_pos = captures[4].parse::<usize>().unwrap();
-                let condition = captures[2].to_string();
-
-                if !data.contains_var(&lhs_name) {
-                    return Err(format!("Variable ${} does not exist!\nAborting...", &lhs_name));
+            |data, value, instruction| {
+                let Instruction::If { predicate, then, then_source, otherwise } = instruction else {
+                    return invalid("Invalid if statement", value);
+                };
+
+                let (branch, branch_source) = match evaluate(data, predicate)? {
+                    true => (then, then_source),
+                    false => match otherwise {
+                        Some((otherwise, otherwise_source)) => (otherwise, otherwise_source),
+                        None => {
+                            data.next_line();
+                            return Ok(get_state(States::ExecuteState));
+                        },
+                    },
+                };
+                if let Instruction::Goto(code_pos) = **branch {
+                    return goto_line(data, code_pos);
                 }
+                // Any other statement is run as though it were on this line
+                data.set_inline_instruction(branch_source, branch);
+                Ok(get_state(States::ExecuteState))
+            },
+            "Invalid if statement"
+        )
+    }
+}
 
-                if !data.contains_var(&rhs_name) {
-                    return Err(format!("Variable ${} does not exist!\nAborting...", &rhs_name));
+impl StateMachine for ErrorState {
+    fn execute(&self, data: ProgramData) -> NewState {
+        decode_and_execute(
+            data,
+            |data, value, instruction| {
+                let Instruction::Error(message) = instruction else {
+                    return invalid("Invalid error statement", value);
+                };
+                let message = operand_value(data, message)?;
+                Err(format!("Line {}: {}\nAborting...", data.get_index(), message.as_text()))
+            },
+            "Invalid error statement"
+        )
+    }
+}
+
+impl StateMachine for AssertState {
+    fn execute(&self, data: ProgramData) -> NewState {
+        decode_and_execute(
+            data,
+            |data, value, instruction| {
+                let Instruction::Assert { lhs, condition, rhs } = instruction else {
+                    return invalid("Invalid assert statement", value);
+                };
+
+                let lhs_val = operand_value(data, lhs)?;
+                let rhs_val = operand_value(data, rhs)?;
+
+                if !compare(data.get_dialect(), &lhs_val, *condition, &rhs_val) {
+                    // Only the values of variables are shown, as literals are already in the statement
+                    let shown: Vec<String> = [(&lhs, &lhs_val), (&rhs, &rhs_val)].into_iter()
+                        .filter_map(|(operand, value)| match operand {
+                            Operand::Variable(name) => Some(format!("${} is {}", name, value)),
+                            Operand::Literal(_) => None,
+                        })
+                        .collect();
+                    return Err(match shown.is_empty() {
+                        true => format!("Assertion failed: {}\nAborting...", value),
+                        false => format!("Assertion failed: {} ({})\nAborting...", value, shown.join(", ")),
+                    });
                 }
+                data.next_line();
+                Ok(get_state(States::ExecuteState))
+            },
+            "Invalid assert statement"
+        )
+    }
+}
+
+/// Finds the line holding the 'endtest' that closes the testcase starting at `start`.
+pub fn find_endtest(data: &ProgramData, start: usize) -> Option<usize> {
+    (start + 1..data.code_size()).find(|line| data.get_code_at(*line).unwrap() == "endtest")
+}
 
-                let lhs_val = data.get_var(&lhs_name).unwrap();
-                let rhs_val = data.get_var(&rhs_name).unwrap();
-
-                let predicate: fn(&str, &str) -> bool = match condition.as_ref() {
-                    ">=" => |lhs, rhs| {return lhs.ge(rhs)},
-                    ">" => |lhs, rhs| {return lhs.gt(rhs)},
-                    "<=" => |lhs, rhs| {return lhs.le(rhs)},
-                    "<" => |lhs, rhs| {return lhs.lt(rhs)},
-                    "=" => |lhs, rhs| {return lhs.eq(rhs)},
-                    "!=" => |lhs, rhs| {return !lhs.eq(rhs)},
-                    _ => |_, _| {return false}
+impl StateMachine for TestcaseState {
+    fn execute(&self, data: ProgramData) -> NewState {
+        decode_and_execute(
+            data,
+            |data, value, instruction| {
+                let Instruction::Testcase(name) = instruction else {
+                    return invalid("Invalid testcase statement", value);
                 };
+                // Tests only run with the 'test' command, so carry on after the block
+                match find_endtest(data, data.get_index()) {
+                    Some(end) => {
+                        data.set_index(end + 1);
+                        Ok(get_state(States::ExecuteState))
+                    },
+                    None => Err(format!("Testcase {} has no matching endtest!\nAborting...", name))
+                }
+            },
+            "Invalid testcase statement"
+        )
+    }
+}
 
-                let goto_pos = if predicate(lhs_val, rhs_val) {
-                    code_pos
-                } else {
-                    data.get_index() + 1
+impl StateMachine for DialectState {
+    fn execute(&self, mut data: ProgramData) -> NewState {
+        if data.get_index() != 0 {
+            return NewState::Error(data, String::from("The dialect can only be declared on the first line!\nAborting..."));
+        }
+        data.next_line();
+        NewState::Continue(data, get_state(States::ExecuteState))
+    }
+}
+
+impl StateMachine for LabelState {
+    fn execute(&self, mut data: ProgramData) -> NewState {
+        data.next_line();
+        NewState::Continue(data, get_state(States::ExecuteState))
+    }
+}
+
+/// Finds the text of a map key, given as a literal or a variable.
+fn get_key(data: &ProgramData, key: &Operand) -> Result<String, String> {
+    match key {
+        Operand::Literal(key) => Ok(key.clone()),
+        Operand::Variable(var_name) => get_string_var(data, var_name).map(Cow::into_owned),
+    }
+}
+
+impl StateMachine for MapState {
+    fn execute(&self, data: ProgramData) -> NewState {
+        decode_and_execute(
+            data,
+            |data, value, instruction| {
+                match instruction {
+                    Instruction::Map(name) => data.set_var(name.clone(), Value::Map(BTreeMap::new())),
+                    Instruction::SetEntry { map, key, value: entry } => {
+                        let key = get_key(data, key)?;
+                        let entry = match entry {
+                            Operand::Literal(literal) => Value::from_text(literal),
+                            Operand::Variable(var_name) => data.get_var(var_name).cloned()
+                                .ok_or(format!("Variable ${} does not exist!\nAborting...", var_name))?,
+                        };
+                        // The map is taken out of its variable while it changes, so it is not copied
+                        let mut entries = match data.remove_var(map) {
+                            Some(Value::Map(entries)) => entries,
+                            Some(other) => {
+                                data.set_var(map.clone(), other);
+                                return Err(format!("${} is not a map!\nAborting...", map));
+                            },
+                            None => return Err(format!("Variable ${} does not exist!\nAborting...", map)),
+                        };
+                        entries.insert(key, entry);
+                        data.set_var(map.clone(), Value::Map(entries));
+                    },
+                    Instruction::Let { name, value: Expression::Lookup { map, key } } => {
+                        let key = get_key(data, key)?;
+                        let entry = match data.get_var(map) {
+                            Some(Value::Map(entries)) => entries.get(&key).cloned()
+                                .ok_or(format!("${} has no entry \"{}\"!\nAborting...", map, key))?,
+                            Some(_) => return Err(format!("${} is not a map!\nAborting...", map)),
+                            None => return Err(format!("Variable ${} does not exist!\nAborting...", map)),
+                        };
+                        data.set_var(name.clone(), entry);
+                    },
+                    _ => return invalid("Invalid map instruction", value),
+                }
+                data.next_line();
+                Ok(get_state(States::ExecuteState))
+            },
+            "Invalid map instruction"
+        )
+    }
+}
+
+/// Finds the line of the instruction that closes a block (such as the 'next' of a 'for' loop),
+/// looking through `lines` in order and skipping over blocks of the same kind nested inside it.
+/// Searching backwards for the start of a block works the same way, with `opens` and `closes`
+/// swapped.
+///
+/// # Arguments
+/// * `lines` - The lines to look through, starting next to the line of the block itself
+/// * `instruction_at` - Finds the instruction on a line, if it holds a valid one
+/// * `opens` - Checks whether an instruction opens a nested block
+/// * `closes` - Checks whether an instruction closes a block
+///
+/// # Returns
+/// * `Option<usize>` - The line closing the block, if there is one
+pub(crate) fn find_block_end<'a>(lines: impl Iterator<Item = usize>, mut instruction_at: impl FnMut(usize) -> Option<&'a Instruction>,
+                             opens: fn(&Instruction) -> bool, closes: fn(&Instruction) -> bool) -> Option<usize> {
+    let mut depth = 0;
+    for line in lines {
+        match instruction_at(line) {
+            Some(instruction) if opens(instruction) => depth += 1,
+            Some(instruction) if closes(instruction) && depth == 0 => return Some(line),
+            Some(instruction) if closes(instruction) => depth -= 1,
+            _ => {},
+        }
+    }
+    None
+}
+
+pub(crate) fn is_for(instruction: &Instruction) -> bool {
+    matches!(instruction, Instruction::For { .. })
+}
+
+pub(crate) fn is_next(instruction: &Instruction) -> bool {
+    matches!(instruction, Instruction::Next(_))
+}
+
+pub(crate) fn is_while(instruction: &Instruction) -> bool {
+    matches!(instruction, Instruction::While(_))
+}
+
+pub(crate) fn is_endwhile(instruction: &Instruction) -> bool {
+    matches!(instruction, Instruction::EndWhile)
+}
+
+fn instruction_at(data: &ProgramData, line: usize) -> Option<&Instruction> {
+    data.instruction_at(line)?.as_ref().ok()
+}
+
+/// Reads a value that must be a whole number, such as those a loop counts with.
+fn whole_number(data: &ProgramData, operand: &Operand) -> Result<i128, String> {
+    match operand {
+        Operand::Literal(literal) => literal.parse::<i128>()
+            .map_err(|_| format!("{} is not a whole number!\nAborting...", literal)),
+        Operand::Variable(var_name) => data.get_var(var_name)
+            .ok_or(format!("Variable ${} does not exist!\nAborting...", var_name))?
+            .as_int()
+            .ok_or(format!("${} is not an integer value!\nAborting...", var_name)),
+    }
+}
+
+/// Reads the end and step of the loop over `var`.
+fn loop_bounds(data: &ProgramData, var: &str, to: &Operand, step: &Option<Operand>) -> Result<(i128, i128), String> {
+    let end = whole_number(data, to)?;
+    let step = step.as_ref().map(|step| whole_number(data, step)).transpose()?.unwrap_or(1);
+    if step == 0 {
+        return Err(format!("The step of the loop over ${} cannot be 0!\nAborting...", var));
+    }
+    Ok((end, step))
+}
+
+/// Checks whether a loop whose counter has reached `value` runs its body again.
+fn loop_runs(value: i128, end: i128, step: i128) -> bool {
+    if step > 0 { value <= end } else { value >= end }
+}
+
+impl StateMachine for LoopState {
+    fn execute(&self, data: ProgramData) -> NewState {
+        decode_and_execute(
+            data,
+            |data, value, instruction| {
+                let index = data.get_index();
+                match instruction {
+                    Instruction::For { var, .. } | Instruction::Next(var) if data.is_constant(var) => {
+                        return Err(format!("Cannot reassign constant ${}!\nAborting...", var));
+                    },
+                    Instruction::For { var, from, to, step } => {
+                        let start = whole_number(data, from)?;
+                        let (end, step) = loop_bounds(data, var, to, step)?;
+                        data.set_var(var.clone(), Value::Int(start));
+                        if loop_runs(start, end, step) {
+                            data.next_line();
+                        } else {
+                            // A loop that has nothing to count skips its body
+                            let next = find_block_end(index + 1..data.code_size(), |line| instruction_at(data, line), is_for, is_next)
+                                .ok_or(format!("Loop over ${} has no matching next!\nAborting...", var))?;
+                            data.set_index(next + 1);
+                        }
+                    },
+                    Instruction::Next(var) => {
+                        let start = find_block_end((0..index).rev(), |line| instruction_at(data, line), is_next, is_for)
+                            .ok_or(format!("next ${} has no matching for!\nAborting...", var))?;
+                        let Some(Instruction::For { var: counter, to, step, .. }) = instruction_at(data, start) else {
+                            return invalid("Invalid loop statement", value);
+                        };
+                        if counter != var {
+                            return Err(format!("next ${} cannot close the loop over ${}!\nAborting...", var, counter));
+                        }
+                        let (end, step) = loop_bounds(data, var, to, step)?;
+                        let counted = whole_number(data, &Operand::Variable(var.clone()))?.checked_add(step)
+                            .ok_or(format!("The counter ${} is too large to hold!\nAborting...", var))?;
+                        data.set_var(var.clone(), Value::Int(counted));
+                        if loop_runs(counted, end, step) {
+                            data.set_index(start + 1);
+                        } else {
+                            data.next_line();
+                        }
+                    },
+                    Instruction::While(predicate) => {
+                        if evaluate(data, predicate)? {
+                            data.next_line();
+                        } else {
+                            let end = find_block_end(index + 1..data.code_size(), |line| instruction_at(data, line), is_while, is_endwhile)
+                                .ok_or(String::from("while has no matching endwhile!\nAborting..."))?;
+                            data.set_index(end + 1);
+                        }
+                    },
+                    // The predicate is checked again at the start of the loop
+                    Instruction::EndWhile => {
+                        let start = find_block_end((0..index).rev(), |line| instruction_at(data, line), is_endwhile, is_while)
+                            .ok_or(String::from("endwhile has no matching while!\nAborting..."))?;
+                        data.set_index(start);
+                    },
+                    _ => return invalid("Invalid loop statement", value),
+                }
+                Ok(get_state(States::ExecuteState))
+            },
+            "Invalid loop statement"
+        )
+    }
+}
+
+/// Reads the value of an operand: a literal, or the value of the variable it names.
+fn operand_value(data: &ProgramData, operand: &Operand) -> Result<Value, String> {
+    match operand {
+        Operand::Literal(literal) => Ok(Value::from_text(literal)),
+        Operand::Variable(var_name) => data.get_var(var_name).cloned()
+            .ok_or(format!("Variable ${} does not exist!\nAborting...", var_name)),
+    }
+}
+
+impl StateMachine for SwitchState {
+    fn execute(&self, data: ProgramData) -> NewState {
+        decode_and_execute(
+            data,
+            |data, value, instruction| {
+                let Instruction::Switch(var_name) = instruction else {
+                    // The cases are only looked at by the switch above them
+                    return Err(format!("{} can only be reached through its switch!\nAborting...", value));
+                };
+                let switched = data.get_var(var_name).cloned()
+                    .ok_or(format!("Variable ${} does not exist!\nAborting...", var_name))?;
+                let mut line = data.get_index() + 1;
+                let target = loop {
+                    match instruction_at(data, line) {
+                        Some(Instruction::Case { value, target }) => {
+                            if compare(data.get_dialect(), &switched, Condition::Equal, &operand_value(data, value)?) {
+                                break Some(*target);
+                            }
+                        },
+                        Some(Instruction::Default(target)) => break Some(*target),
+                        _ => break None,
+                    }
+                    line += 1;
                 };
-                data.set_index(goto_pos);
-                Ok((data, get_state(States::ExecuteState)))
+                match target {
+                    Some(target) => goto_line(data, target),
+                    // No case matched, so carry on after the cases
+                    None => {
+                        data.set_index(line);
+                        Ok(get_state(States::ExecuteState))
+                    },
+                }
             },
-            "Invalid if statement"
+            "Invalid switch statement"
+        )
+    }
+}
+
+/// Finds the line a function is defined on, along with its parameters.
+fn find_function<'a>(data: &'a ProgramData, name: &str) -> Option<(usize, &'a [String])> {
+    (0..data.code_size()).find_map(|line| match instruction_at(data, line) {
+        Some(Instruction::Func { name: found, params }) if found == name => Some((line, params.as_slice())),
+        _ => None,
+    })
+}
+
+/// Finds the line holding the 'endfunc' that closes the function starting at `start`.
+fn find_endfunc(data: &ProgramData, start: usize) -> Option<usize> {
+    (start + 1..data.code_size()).find(|line| data.get_code_at(*line).unwrap() == "endfunc")
+}
+
+impl StateMachine for FunctionState {
+    fn execute(&self, data: ProgramData) -> NewState {
+        decode_and_execute(
+            data,
+            |data, value, instruction| {
+                match instruction {
+                    // The body of a function only runs when it is called, so carry on after it
+                    Instruction::Func { name, .. } => match find_endfunc(data, data.get_index()) {
+                        Some(end) => data.set_index(end + 1),
+                        None => return Err(format!("Function {} has no matching endfunc!\nAborting...", name)),
+                    },
+                    Instruction::EndFunc => return Err(String::from("Function ended without returning a value!\nAborting...")),
+                    Instruction::Let { name, value: Expression::Call { function, args } } => {
+                        let (start, params) = find_function(data, function)
+                            .ok_or(format!("Function {} does not exist!\nAborting...", function))?;
+                        if args.len() != params.len() {
+                            return Err(format!("Function {} takes {} arguments but was given {}!\nAborting...",
+                                               function, params.len(), args.len()));
+                        }
+                        if data.call_depth() >= MAX_CALL_DEPTH {
+                            return Err(format!("Too many nested subroutine calls! (more than {})\nAborting...", MAX_CALL_DEPTH));
+                        }
+                        // The arguments are read in the scope of the caller
+                        let mut locals = HashMap::new();
+                        for (param, arg) in params.iter().zip(args) {
+                            let arg = match arg {
+                                Operand::Literal(literal) => Value::from_text(literal),
+                                Operand::Variable(var_name) => data.get_var(var_name).cloned()
+                                    .ok_or(format!("Variable ${} does not exist!\nAborting...", var_name))?,
+                            };
+                            locals.insert(param.clone(), arg);
+                        }
+                        let call_site = data.get_index();
+                        data.push_frame(Frame { call_site, result: Some(name.clone()), locals });
+                        data.set_index(start + 1);
+                    },
+                    _ => return invalid("Invalid function statement", value),
+                }
+                Ok(get_state(States::ExecuteState))
+            },
+            "Invalid function statement"
         )
     }
 }
@@ -317,97 +871,188 @@ impl StateMachine for OutputState {
     fn execute(&self, data: ProgramData) -> NewState {
         decode_and_execute(
             data,
-            &OUTPUT_REGEX,
-            |mut data, _, output_capture| -> NewState
+            |data, value, instruction| -> Transition
                 {
-                    let var_name = output_capture[1].to_string();
-                    let var_data = data.get_var(&var_name);
-                    match var_data {
-                        Some(value) => write_output(value.to_string()),
-                        None => return Err(format!("Memory index out of bounds!\nAborting..."))
-                    };
+                    match instruction {
+                        Instruction::Output(values) => {
+                            let mut line = String::new();
+                            for value in values {
+                                match value {
+                                    Operand::Literal(literal) => line.push_str(literal),
+                                    Operand::Variable(var_name) => match data.get_var(var_name) {
+                                        Some(value) => line.push_str(&value.as_text()),
+                                        None => return Err("Memory index out of bounds!\nAborting...".to_string())
+                                    },
+                                }
+                            }
+                            data.console().write_line(&line).map_err(|err| format!("{}\nAborting...", err))?
+                        },
+                        Instruction::Flush => data.console().flush().map_err(|err| format!("{}\nAborting...", err))?,
+                        _ => return invalid("Invalid output statement", value),
+                    }
                     data.next_line();
-                    Ok((data, get_state(States::ExecuteState)))
+                    Ok(get_state(States::ExecuteState))
                 },
-            "Lolwut")
+            "Invalid output statement")
     }
 }
 
 impl StateMachine for AssignState {
     fn execute(&self, mut data: ProgramData) -> NewState {
-        let code = data.get_code();
+        let result = self.assign(&mut data);
+        transition(data, result)
+    }
+}
 
-        //ensure that we actually have a line of code to work with
-        match code {
+impl AssignState {
+    fn assign(&self, data: &mut ProgramData) -> Transition {
+        let line = current_line(data)?;
+        let value = line.source();
 
-            //We have code.
-            Some(value) => {
+        let (var_name, expression) = match line.instruction() {
+            Ok(Instruction::Let { name, .. }) | Ok(Instruction::SetEntry { map: name, .. }) if data.is_constant(name) => {
+                return Err(format!("Cannot reassign constant ${}!\nAborting...", name));
+            },
+            Ok(Instruction::Let { name, value }) => (name, value),
+            Ok(Instruction::Const { name, value: constant }) => {
+                let constant = match constant {
+                    Operand::Literal(literal) => Value::from_text(literal),
+                    Operand::Variable(var_name) => data.get_var(var_name).cloned()
+                        .ok_or(format!("Variable ${} does not exist!\nAborting...", var_name))?,
+                };
+                if !data.declare_constant(name.clone(), constant) {
+                    return Err(format!("Cannot reassign constant ${}!\nAborting...", name));
+                }
+                data.next_line();
+                return Ok(get_state(States::ExecuteState));
+            },
+            Ok(Instruction::Unset(name)) => {
+                if data.is_constant(name) {
+                    return Err(format!("Cannot unset constant ${}!\nAborting...", name));
+                }
+                data.remove_var(name).ok_or(format!("Variable ${} does not exist!\nAborting...", name))?;
+                data.next_line();
+                return Ok(get_state(States::ExecuteState));
+            },
+            // Entries of maps are stored by the map state
+            Ok(Instruction::SetEntry { .. }) => return Ok(get_state(States::MapState)),
+            Ok(_) => return invalid("Invalid assign instruction", value),
+            Err(err) => return Err(format!("Invalid assign instruction: {} ({})\nAborting...", value, err)),
+        };
+
+        match expression {
+            // Assigning from a hardcoded value
+            Expression::Literal(literal) => data.set_var(var_name.clone(), literal.as_str()),
+
+            // Assigning from the stack
+            Expression::Pop => {
+                let stack_val = data.pop().ok_or(String::from("Stack is empty!\nAborting..."))?;
+                data.set_var(var_name.clone(), stack_val);
+            },
 
-                //Regex used to process the assign statement
-                let assign_from_code = &ASSIGN_REGEXES[0];
-                let assign_from_memory = &ASSIGN_REGEXES[1];
-                let assign_from_input = &ASSIGN_REGEXES[2];
-                let assign_from_operation = &ASSIGN_REGEXES[3];
-                let assign_from_stack = &ASSIGN_REGEXES[4];
+            // Arithmetic is handled by the math state
+            Expression::Operation { .. } | Expression::Negate(_) => return Ok(get_state(States::MathState)),
 
-                // Check if assigning from a hardcoded value
-                if assign_from_code.is_match(&format!("{}", value)) {
-                    let assign_tokens = assign_from_code.captures(&value).unwrap();
-                    let var_name = assign_tokens[1].to_string(); // get the variable name
-                    let var_val = assign_tokens[2].to_string().replace("\"", "");
+            // So are the string builtins, by the string state
+            Expression::Pad { .. } | Expression::Repeat { .. } | Expression::Reverse(_) | Expression::Concat { .. } =>
+                return Ok(get_state(States::StringState)),
 
-                    //Set variable and go to the next line
-                    data.set_var(var_name, var_val);
-                    data.next_line();
-                    Ok((data, get_state(States::ExecuteState)))
+            // Entries of maps are looked up by the map state
+            Expression::Lookup { .. } => return Ok(get_state(States::MapState)),
 
-                    //check if assigning from stack
-                } else if assign_from_stack.is_match(&format!("{}", value)) {
-                    let stack_value = data.pop();
+            // Functions are called by the function state
+            Expression::Call { .. } => return Ok(get_state(States::FunctionState)),
 
-                    match stack_value {
-                        Some(stack_val) => {
-                            let assign_tokens = assign_from_stack.captures(&value).unwrap();
-                            let var_val = assign_tokens[1].to_string();
-                            data.set_var(var_val, stack_val);
-                            data.next_line();
-                            Ok((data, get_state(States::ExecuteState)))
-                        },
-                        None => Err(String::from("Stack is empty!\nAborting..."))
-                    }
-                    // Check if assigning from operation
-                } else if assign_from_operation.is_match(&format!("{}", value)) {
-                    Ok((data, get_state(States::MathState)))
-                } else if assign_from_input.is_match(&format!("{}", value)) {
-                    let assign_tokens = assign_from_input.captures(&value).unwrap();
-                    let var_name = assign_tokens[1].to_string(); // get the variable name
-
-                    data.set_var(var_name, get_input());
-                    data.next_line();
-                    Ok((data, get_state(States::ExecuteState)))
-                    // Check if assigning from operation
+            // And builtins, by the builtin state
+            Expression::Builtin { .. } => return Ok(get_state(States::BuiltinState)),
+
+            // Shell commands are run by the exec state
+            Expression::Exec(_) => return Ok(get_state(States::ExecState)),
+
+            // As are filesystem queries, by the filesystem state
+            Expression::Exists(_) | Expression::ReadFile(_) | Expression::OpenFile(_) | Expression::ReadLine(_) =>
+                return Ok(get_state(States::FsState)),
+
+            // And environment variables and program arguments, by the environment state
+            Expression::Env(_) | Expression::Arg(_) | Expression::ArgCount => return Ok(get_state(States::EnvState)),
+
+            // And random numbers, by the random state
+            Expression::Random(_) => return Ok(get_state(States::RandomState)),
+
+            // Socket reads and connections are handled by the network state
+            #[cfg(feature = "net")]
+            Expression::Connect(_) | Expression::NetRecv(_) | Expression::HttpGet(_) =>
+                return Ok(get_state(States::NetState)),
 
-                } else if assign_from_memory.is_match(&format!("{}", value)) {
-                    let assign_tokens = assign_from_memory.captures(&value).unwrap();
-                    let lhs_key = assign_tokens[1].to_string(); // get the variable name for LHS
-                    let rhs_key = assign_tokens[2].to_string(); // get the variable_name for RHS
+            Expression::InputAll => {
+                // Read everything up to EOF, dropping the final line terminator
+                let input = data.console().read_all()
+                    .map_err(|err| format!("{}\nAborting...", err))?;
+                let input = input.strip_suffix('\n').unwrap_or(&input);
+                let input = input.strip_suffix('\r').unwrap_or(input).to_string();
+                data.set_var(var_name.clone(), input);
+            },
+
+            Expression::TimedInput { timeout, default } => {
+                // Fall back to the default if nothing was entered in time, and tell the program
+                let input = data.console().read_line_timeout(Duration::from_secs(*timeout))
+                    .map_err(|err| format!("{}\nAborting...", err))?;
+                let timed_out = input.is_none();
+
+                data.set_var(var_name.clone(), input.map(|line| line.trim().to_string()).unwrap_or_else(|| default.clone()));
+                data.set_var(String::from("timedout"), Value::Bool(timed_out));
+            },
+
+            Expression::Input | Expression::PromptedInput(_) => {
+                if let Expression::PromptedInput(prompt) = expression {
+                    data.console().write_prompt(prompt).map_err(|err| format!("{}\nAborting...", err))?;
+                }
+                // Reading past the end of the input gives an empty string
+                let input = data.console().read_line()
+                    .map_err(|err| format!("{}\nAborting...", err))?
+                    .unwrap_or_default();
+                data.set_var(var_name.clone(), input.trim().to_string());
+            },
 
-                    if !data.contains_var(&rhs_key) {
-                        return Err(format!("Variable ${} does not exist!\nAborting...", &rhs_key));
+            Expression::InputNum(prompt) => {
+                // Someone at a terminal is asked again, but input from anywhere else cannot be fixed
+                let number = loop {
+                    if let Some(prompt) = prompt {
+                        data.console().write_prompt(prompt).map_err(|err| format!("{}\nAborting...", err))?;
                     }
+                    let input = data.console().read_line()
+                        .map_err(|err| format!("{}\nAborting...", err))?
+                        .ok_or(String::from("Input ended before a whole number was entered!\nAborting..."))?;
+                    if let Ok(number) = input.trim().parse::<i128>() {
+                        break number;
+                    }
+                    if !data.console().is_interactive().map_err(|err| format!("{}\nAborting...", err))? {
+                        return Err(format!("Input \"{}\" is not a whole number!\nAborting...", input.trim()));
+                    }
+                    data.console().write_line("Please enter a whole number")
+                        .map_err(|err| format!("{}\nAborting...", err))?;
+                };
+                data.set_var(var_name.clone(), Value::Int(number));
+            },
 
-                    data.set_var_to_var(lhs_key, rhs_key);
-                    data.next_line();
+            // Assigning one of two values, depending on the predicate
+            Expression::Conditional { then, predicate, otherwise } => {
+                let chosen = if evaluate(data, predicate)? { then } else { otherwise };
+                let chosen = operand_value(data, chosen)?;
+                data.set_var(var_name.clone(), chosen);
+            },
 
-                    Ok((data, get_state(States::ExecuteState)))
-                    // No valid assign statement
-                } else {
-                    Err(format!("Invalid assign instruction: {}\nAborting...", value))
+            // Assigning from another variable
+            Expression::Variable(rhs_key) => {
+                if !data.contains_var(rhs_key) {
+                    return Err(format!("Variable ${} does not exist!\nAborting...", rhs_key));
                 }
+                data.set_var_to_var(var_name.clone(), rhs_key.clone());
             },
-            // If we have no code to run, go straight to the exit state
-            None => Ok((data, get_state(States::QuitState)))
         }
+
+        data.next_line();
+        Ok(get_state(States::ExecuteState))
     }
 }
 
@@ -415,69 +1060,548 @@ impl StateMachine for MathState {
     fn execute(&self, data: ProgramData) -> NewState {
         decode_and_execute(
             data,
-            &MATH_REGEX,
-            |mut data, _, captures| {
-                let lhs_name = captures[2].to_string();
-                let rhs_name = captures[4].to_string();
-                let assign_name = captures[1].to_string();
-                let operation = captures[3].to_string();
-
-                if !data.contains_var(&lhs_name) {
+            |data, value, instruction| {
+                let (assign_name, lhs_name, operator, rhs_name) = match instruction {
+                    Instruction::Let { name, value: Expression::Negate(var_name) } => {
+                        let integer = data.get_var(var_name)
+                            .ok_or(format!("Variable ${} does not exist!\nAborting...", var_name))?
+                            .as_int();
+                        let result = match integer {
+                            Some(number) => Value::Int(number.checked_neg()
+                                .ok_or(format!("The result of negating ${} is too large to hold!\nAborting...", var_name))?),
+                            None => Value::Float(-get_float_var(data, var_name)?),
+                        };
+                        data.set_var(name.clone(), result);
+                        data.next_line();
+                        return Ok(get_state(States::ExecuteState));
+                    },
+                    Instruction::Let { name, value: Expression::Operation { lhs, operator, rhs } } => (name, lhs, operator, rhs),
+                    _ => return invalid("Invalid math instruction", value),
+                };
+
+                if !data.contains_var(lhs_name) {
                     return Err(format!("Variable ${} does not exist!\nAborting...", &lhs_name));
                 }
 
-                if !data.contains_var(&rhs_name) {
+                if !data.contains_var(rhs_name) {
                     return Err(format!("Variable ${} does not exist!\nAborting...", &rhs_name));
                 }
 
-                let lhs_val = data.get_var(&lhs_name).unwrap().parse::<i128>();
-                let rhs_val = data.get_var(&rhs_name).unwrap().parse::<i128>();
+                let overflow = || format!("The result of ${} and ${} is too large to hold!\nAborting...", lhs_name, rhs_name);
+                let integers = (data.get_var(lhs_name).unwrap().as_int(), data.get_var(rhs_name).unwrap().as_int());
+                // From dialect v3, '/' divides integers as floats too
+                let true_division = *operator == Operator::Divide && data.get_dialect() >= Dialect::V3;
+                let result = match integers {
+                    (Some(lhs_val), Some(rhs_val)) if !true_division => Value::Int(match operator {
+                        Operator::Multiply => lhs_val.checked_mul(rhs_val).ok_or_else(overflow)?,
+                        Operator::Divide => {
+                            if rhs_val == 0 {
+                                return Err(String::from("Division by zero!\nAborting..."));
+                            }
+                            //assign the quotient and push the remainder onto the stack
+                            let (quotient, remainder) = div_rem(lhs_val, rhs_val);
+                            data.push(Value::Int(remainder));
+                            quotient
+                        },
+                        Operator::IntegerDivide => {
+                            if rhs_val == 0 {
+                                return Err(String::from("Division by zero!\nAborting..."));
+                            }
+                            // The quotient is rounded towards zero, so that it goes with the remainder '%' gives
+                            lhs_val.checked_div(rhs_val).ok_or_else(overflow)?
+                        },
+                        Operator::Modulo => {
+                            if rhs_val == 0 {
+                                return Err(String::from("Division by zero!\nAborting..."));
+                            }
+                            // The remainder has the sign of the dividend, as it does for division
+                            lhs_val.checked_rem(rhs_val).ok_or_else(overflow)?
+                        },
+                        Operator::Power => {
+                            if rhs_val < 0 {
+                                return Err(format!("Cannot raise ${} to the negative power ${}!\nAborting...", lhs_name, rhs_name));
+                            }
+                            u32::try_from(rhs_val).ok().and_then(|exponent| lhs_val.checked_pow(exponent)).ok_or_else(overflow)?
+                        },
+                        Operator::BitAnd => lhs_val & rhs_val,
+                        Operator::BitOr => lhs_val | rhs_val,
+                        Operator::BitXor => lhs_val ^ rhs_val,
+                        Operator::ShiftLeft | Operator::ShiftRight => {
+                            let Some(bits) = u32::try_from(rhs_val).ok().filter(|bits| *bits < i128::BITS) else {
+                                return Err(format!("Cannot shift ${} by ${} bits!\nAborting...", lhs_name, rhs_name));
+                            };
+                            if *operator == Operator::ShiftRight {
+                                lhs_val >> bits
+                            } else if (lhs_val << bits) >> bits == lhs_val {
+                                lhs_val << bits
+                            } else {
+                                return Err(overflow());
+                            }
+                        },
+                        Operator::Add => lhs_val.checked_add(rhs_val).ok_or_else(overflow)?,
+                        Operator::Subtract => lhs_val.checked_sub(rhs_val).ok_or_else(overflow)?,
+                    }),
+                    // Arithmetic with a float gives a float, as does true division, and neither pushes a remainder
+                    _ => {
+                        let lhs_val = get_float_var(data, lhs_name)?;
+                        let rhs_val = get_float_var(data, rhs_name)?;
+                        let result = match operator {
+                            Operator::BitAnd | Operator::BitOr | Operator::BitXor | Operator::ShiftLeft | Operator::ShiftRight => {
+                                let name = if integers.0.is_none() { &lhs_name } else { &rhs_name };
+                                return Err(format!("${} is not an integer value!\nAborting...", name));
+                            },
+                            Operator::Multiply => lhs_val * rhs_val,
+                            Operator::Divide if rhs_val == 0.0 => return Err(String::from("Division by zero!\nAborting...")),
+                            Operator::Divide => lhs_val / rhs_val,
+                            Operator::IntegerDivide if rhs_val == 0.0 => return Err(String::from("Division by zero!\nAborting...")),
+                            Operator::IntegerDivide => (lhs_val / rhs_val).trunc(),
+                            Operator::Modulo if rhs_val == 0.0 => return Err(String::from("Division by zero!\nAborting...")),
+                            Operator::Modulo => lhs_val % rhs_val,
+                            Operator::Power => lhs_val.powf(rhs_val),
+                            Operator::Add => lhs_val + rhs_val,
+                            Operator::Subtract => lhs_val - rhs_val,
+                        };
+                        if !result.is_finite() {
+                            return Err(overflow());
+                        }
+                        Value::Float(result)
+                    },
+                };
+                data.set_var(assign_name.clone(), result);
+
+                data.next_line();
+
+                Ok(get_state(States::ExecuteState))
+            },
+            "Invalid math instruction"
+        )
+    }
+}
+
+/// Looks up a variable holding a number used by an arithmetic statement.
+fn get_number_var(data: &ProgramData, var_name: &str) -> Result<i128, String> {
+    let value = data.get_var(var_name).ok_or(format!("Variable ${} does not exist!\nAborting...", var_name))?;
+    value.as_int().ok_or(format!("${} is not a numeric value!\nAborting...", var_name))
+}
+
+/// Looks up a variable holding a number used by arithmetic with floats.
+fn get_float_var(data: &ProgramData, var_name: &str) -> Result<f64, String> {
+    let value = data.get_var(var_name).ok_or(format!("Variable ${} does not exist!\nAborting...", var_name))?;
+    value.as_float().ok_or(format!("${} is not a numeric value!\nAborting...", var_name))
+}
+
+/// Looks up a variable used by a string builtin.
+fn get_string_var<'a>(data: &'a ProgramData, var_name: &str) -> Result<Cow<'a, str>, String> {
+    data.get_var(var_name).map(Value::as_text).ok_or(format!("Variable ${} does not exist!\nAborting...", var_name))
+}
 
-                if lhs_val.is_err() {
-                    return Err(format!("${} is not a numeric value!\nAborting...", &lhs_name));
+impl StateMachine for StringState {
+    fn execute(&self, data: ProgramData) -> NewState {
+        decode_and_execute(
+            data,
+            |data, value, instruction| {
+                let Instruction::Let { name, value: expression } = instruction else {
+                    return invalid("Invalid string instruction", value);
+                };
+                let result = match expression {
+                    // A positive width aligns the value to the right and a negative width to the
+                    // left, as in printf. Values that are already wide enough are left alone.
+                    Expression::Pad { value, width, fill } => {
+                        let text = get_string_var(data, value)?;
+                        let width = get_number_var(data, width)?;
+                        let fill = match fill {
+                            Some(fill) => {
+                                let fill_text = get_string_var(data, fill)?;
+                                let mut chars = fill_text.chars();
+                                match (chars.next(), chars.next()) {
+                                    (Some(c), None) => c,
+                                    _ => return Err(format!("${} must hold a single character!\nAborting...", fill)),
+                                }
+                            },
+                            None => ' ',
+                        };
+                        let length = text.chars().count();
+                        let padding = usize::try_from(width.unsigned_abs()).unwrap_or(usize::MAX).saturating_sub(length);
+                        let padding: String = std::iter::repeat_n(fill, padding).collect();
+                        if width < 0 {
+                            format!("{}{}", text, padding)
+                        } else {
+                            format!("{}{}", padding, text)
+                        }
+                    },
+                    Expression::Repeat { value, count: count_name } => {
+                        let text = get_string_var(data, value)?;
+                        let count = get_number_var(data, count_name)?;
+                        if count < 0 {
+                            return Err(format!("${} cannot be negative!\nAborting...", count_name));
+                        }
+                        match usize::try_from(count).ok().filter(|count| count.checked_mul(text.len()).is_some()) {
+                            Some(count) => text.repeat(count),
+                            None => return Err(format!("${} is too large!\nAborting...", count_name)),
+                        }
+                    },
+                    Expression::Reverse(value) => get_string_var(data, value)?.chars().rev().collect(),
+                    // Numbers are joined as they are written out
+                    Expression::Concat { lhs, rhs } => format!("{}{}", get_string_var(data, lhs)?, get_string_var(data, rhs)?),
+                    _ => return invalid("Invalid string instruction", value),
+                };
+                data.set_var(name.clone(), Value::Str(result));
+                data.next_line();
+                Ok(get_state(States::ExecuteState))
+            },
+            "Invalid string instruction"
+        )
+    }
+}
+
+impl StateMachine for EnvState {
+    fn execute(&self, data: ProgramData) -> NewState {
+        decode_and_execute(
+            data,
+            |data, value, instruction| {
+                match instruction {
+                    Instruction::Let { name, value: Expression::Env(var_name) } => {
+                        if !data.env_allowed() {
+                            return Err(String::from("Environment access is disabled! Run with --allow-env to enable it.\nAborting..."));
+                        }
+                        // A variable that is not set reads as ""
+                        let var_name = operand_value(data, var_name)?.as_text().into_owned();
+                        let env_value = data.host_backend().env(&var_name)?;
+                        data.set_var(name.clone(), env_value);
+                    },
+                    // The arguments were given by whoever ran the program, so they need no capability
+                    Instruction::Let { name, value: Expression::Arg(position) } => {
+                        let position = whole_number(data, position)?;
+                        let arg = usize::try_from(position).ok()
+                            .and_then(|position| position.checked_sub(1))
+                            .and_then(|index| data.args().get(index))
+                            .ok_or(format!("Argument {} does not exist!\nAborting...", position))?
+                            .clone();
+                        data.set_var(name.clone(), arg);
+                    },
+                    Instruction::Let { name, value: Expression::ArgCount } => {
+                        let count = data.args().len() as i128;
+                        data.set_var(name.clone(), count);
+                    },
+                    _ => return invalid("Invalid environment instruction", value),
                 }
-                if rhs_val.is_err() {
-                    return Err(format!("${} is not a numeric value!\nAborting...", &rhs_name));
+                data.next_line();
+                Ok(get_state(States::ExecuteState))
+            },
+            "Invalid environment instruction"
+        )
+    }
+}
+
+impl StateMachine for BuiltinState {
+    fn execute(&self, data: ProgramData) -> NewState {
+        decode_and_execute(
+            data,
+            |data, value, instruction| {
+                let Instruction::Let { name, value: Expression::Builtin { function, args } } = instruction else {
+                    return invalid("Invalid builtin call", value);
+                };
+                let builtin = find_builtin(function).ok_or(format!("Builtin {} does not exist!\nAborting...", function))?;
+                let args = args.iter()
+                    .map(|arg| {
+                        let source = match arg {
+                            Operand::Literal(literal) => literal.clone(),
+                            Operand::Variable(var_name) => format!("${}", var_name),
+                        };
+                        Ok((source, operand_value(data, arg)?))
+                    })
+                    .collect::<Result<Vec<Argument>, String>>()?;
+                let result = (builtin.apply)(&args).map_err(|err| format!("{}\nAborting...", err))?;
+                data.set_var(name.clone(), result);
+                data.next_line();
+                Ok(get_state(States::ExecuteState))
+            },
+            "Invalid builtin call"
+        )
+    }
+}
+
+impl StateMachine for ExecState {
+    fn execute(&self, data: ProgramData) -> NewState {
+        decode_and_execute(
+            data,
+            |data, value, instruction| {
+                let Instruction::Let { name, value: Expression::Exec(command) } = instruction else {
+                    return invalid("Invalid exec instruction", value);
+                };
+                if !data.exec_allowed() {
+                    return Err(String::from("Running commands is disabled! Run with --allow-exec to enable it.\nAborting..."));
                 }
-                let result = match operation.as_ref() {
-                    "*" => format!("{}", lhs_val.unwrap() * rhs_val.unwrap()),
-                    "/" => {
-                        let result = div_rem(lhs_val.unwrap(), rhs_val.unwrap());
-                        format!("{}.{}", result.0, result.1)
-                    },
-                    "+" => format!("{}", lhs_val.unwrap() + rhs_val.unwrap()),
-                    "-" => format!("{}", lhs_val.unwrap() - rhs_val.unwrap()),
-                    _ =>  panic!()
+                let command = match command {
+                    Operand::Literal(command) => command.clone(),
+                    Operand::Variable(var_name) => get_string_var(data, var_name)?.into_owned(),
                 };
+                let dir = data.base_dir().map(Path::to_path_buf);
+                let (stdout, exit_code) = data.host_backend().exec(&command, dir.as_deref())
+                    .map_err(|err| format!("Could not run {}: {}\nAborting...", command, err))?;
+                let stdout = stdout.strip_suffix('\n').unwrap_or(&stdout);
+                let stdout = stdout.strip_suffix('\r').unwrap_or(stdout).to_string();
+
+                data.set_var(name.clone(), stdout);
+                data.set_var(String::from("exitcode"), Value::Int(exit_code));
+                data.next_line();
+                Ok(get_state(States::ExecuteState))
+            },
+            "Invalid exec instruction"
+        )
+    }
+}
+
+/// Reads the handle of an open file from a variable.
+fn get_file_handle(data: &mut ProgramData, var_name: &str) -> Result<usize, String> {
+    let handle = data.get_var(var_name)
+        .ok_or(format!("Variable ${} does not exist!\nAborting...", var_name))?
+        .as_int()
+        .and_then(|handle| usize::try_from(handle).ok());
+    match handle {
+        Some(handle) if data.file(handle).is_some() => Ok(handle),
+        _ => Err(format!("${} is not an open file!\nAborting...", var_name)),
+    }
+}
+
+/// Writes a value to the end of a file, or in place of its contents, followed by a line
+/// terminator, so that a file written in one go reads back as the value that was written. The
+/// file is created if it does not exist.
+fn write_file(data: &mut ProgramData, path: &Operand, contents: &Operand, append: bool) -> Result<(), String> {
+    let path = operand_value(data, path)?.as_text().into_owned();
+    let contents = format!("{}\n", operand_value(data, contents)?.as_text());
+    let resolved = data.resolve_path(&path);
+    data.host_backend().write_file(&resolved, &contents, append)
+        .map_err(|err| format!("Could not write to {}: {}\nAborting...", path, err))
+}
 
-                //assign the quotient and division to two registers
-                if result.contains(".") {
-                    let division: Vec<&str> = result.split(r".").collect();
-                    data.set_var(assign_name, division[0].to_string());
-                    data.push(division[1].to_string()); // push remainder onto the stack
-                } else {
-                    data.set_var(assign_name, result);
+impl StateMachine for FsState {
+    fn execute(&self, data: ProgramData) -> NewState {
+        decode_and_execute(
+            data,
+            |data, value, instruction| {
+                if !data.fs_allowed() {
+                    return Err(String::from("File access is disabled! Run with --allow-fs to enable it.\nAborting..."));
+                }
+                match instruction {
+                    Instruction::Let { name, value: Expression::Exists(path) } => {
+                        let resolved = data.resolve_path(&get_string_var(data, path)?);
+                        let exists = data.host_backend().exists(&resolved)?;
+                        data.set_var(name.clone(), Value::Bool(exists));
+                    },
+                    // Directories can be deleted once they are empty
+                    Instruction::Delete(path) => {
+                        let path = get_string_var(data, path)?.into_owned();
+                        let resolved = data.resolve_path(&path);
+                        data.host_backend().delete(&resolved)
+                            .map_err(|err| format!("Could not delete {}: {}\nAborting...", path, err))?;
+                    },
+                    // Entries are pushed so that they are popped in order of their names, and
+                    // $entries tells the program how many there are
+                    Instruction::ListDir(path) => {
+                        let path = get_string_var(data, path)?.into_owned();
+                        let resolved = data.resolve_path(&path);
+                        let entries = data.host_backend().list_dir(&resolved)
+                            .map_err(|err| format!("Could not list {}: {}\nAborting...", path, err))?;
+                        data.set_var(String::from("entries"), Value::Int(entries.len() as i128));
+                        for entry in entries.into_iter().rev() {
+                            data.push(entry);
+                        }
+                    },
+                    Instruction::Let { name, value: Expression::ReadFile(path) } => {
+                        let path = operand_value(data, path)?.as_text().into_owned();
+                        let resolved = data.resolve_path(&path);
+                        let contents = data.host_backend().read_file(&resolved)
+                            .map_err(|err| format!("Could not read {}: {}\nAborting...", path, err))?;
+                        let contents = contents.strip_suffix('\n').unwrap_or(&contents);
+                        data.set_var(name.clone(), contents.strip_suffix('\r').unwrap_or(contents).to_string());
+                    },
+                    Instruction::Let { name, value: Expression::OpenFile(path) } => {
+                        let path = operand_value(data, path)?.as_text().into_owned();
+                        let resolved = data.resolve_path(&path);
+                        let file = data.host_backend().open_file(&resolved)
+                            .map_err(|err| format!("Could not open {}: {}\nAborting...", path, err))?;
+                        let handle = data.add_file(file);
+                        data.set_var(name.clone(), Value::Int(handle as i128));
+                    },
+                    // $eof tells the program when there are no lines left, which reads as ""
+                    Instruction::Let { name, value: Expression::ReadLine(file) } => {
+                        let handle = get_file_handle(data, file)?;
+                        let line = data.file(handle).unwrap().read_line()
+                            .map_err(|err| format!("Could not read from ${}: {}\nAborting...", file, err))?;
+                        let eof = line.is_empty();
+                        let line = line.strip_suffix('\n').unwrap_or(&line);
+                        data.set_var(name.clone(), line.strip_suffix('\r').unwrap_or(line).to_string());
+                        data.set_var(String::from("eof"), Value::Bool(eof));
+                    },
+                    Instruction::CloseFile(file) => {
+                        let handle = get_file_handle(data, file)?;
+                        data.close_file(handle);
+                    },
+                    Instruction::WriteFile { path, contents } => write_file(data, path, contents, false)?,
+                    Instruction::AppendFile { path, contents } => write_file(data, path, contents, true)?,
+                    _ => return invalid("Invalid filesystem instruction", value),
                 }
+                data.next_line();
+                Ok(get_state(States::ExecuteState))
+            },
+            "Invalid filesystem instruction"
+        )
+    }
+}
 
+impl StateMachine for RandomState {
+    fn execute(&self, data: ProgramData) -> NewState {
+        decode_and_execute(
+            data,
+            |data, value, instruction| {
+                match instruction {
+                    Instruction::Seed(var_name) => {
+                        let seed = u64::try_from(get_number_var(data, var_name)?)
+                            .map_err(|_| format!("${} is not a valid seed!\nAborting...", var_name))?;
+                        data.seed_rng(seed);
+                    },
+                    Instruction::Let { name, value: Expression::Random(bound_name) } => {
+                        let bound = get_number_var(data, bound_name)?;
+                        if bound <= 0 {
+                            return Err(format!("${} must be greater than 0!\nAborting...", bound_name));
+                        }
+                        let number = data.random_below(bound);
+                        data.set_var(name.clone(), Value::Int(number));
+                    },
+                    _ => return invalid("Invalid random instruction", value),
+                }
                 data.next_line();
+                Ok(get_state(States::ExecuteState))
+            },
+            "Invalid random instruction"
+        )
+    }
+}
 
-                Ok((data, get_state(States::ExecuteState)))
+impl StateMachine for SortState {
+    fn execute(&self, data: ProgramData) -> NewState {
+        decode_and_execute(
+            data,
+            |data, value, instruction| {
+                let Instruction::Sort = instruction else {
+                    return invalid("Invalid sort statement", value);
+                };
+                data.sort_stack();
+                data.next_line();
+                Ok(get_state(States::ExecuteState))
             },
-            "Lolwut"
+            "Invalid sort statement"
         )
     }
 }
 
+/// Looks up a variable used as an operand of a network instruction.
+#[cfg(feature = "net")]
+fn get_net_var(data: &ProgramData, var_name: &str) -> Result<String, String> {
+    match data.get_var(var_name) {
+        Some(value) => Ok(value.to_string()),
+        None => Err(format!("Variable ${} does not exist!\nAborting...", var_name))
+    }
+}
+
+/// Looks up a variable holding a socket handle returned by 'connect'.
+#[cfg(feature = "net")]
+fn get_socket_handle(data: &ProgramData, var_name: &str) -> Result<usize, String> {
+    get_net_var(data, var_name)?
+        .parse::<usize>()
+        .map_err(|_| format!("${} is not a socket handle!\nAborting...", var_name))
+}
+
+#[cfg(feature = "net")]
+impl StateMachine for NetState {
+    fn execute(&self, mut data: ProgramData) -> NewState {
+        let result = self.run_net_instruction(&mut data);
+        transition(data, result)
+    }
+}
+
+#[cfg(feature = "net")]
+impl NetState {
+    fn run_net_instruction(&self, data: &mut ProgramData) -> Transition {
+        let line = current_line(data)?;
+        let value = line.source();
+        let net_error = |err: String| format!("{}\nAborting...", err);
+
+        if data.net_backend().is_none() {
+            return Err(String::from("Network access is disabled! Run with --allow-net to enable it.\nAborting..."));
+        }
+
+        let instruction = line.instruction().as_ref()
+            .map_err(|err| format!("Invalid network instruction: {} ({})\nAborting...", value, err))?;
+        match instruction {
+            Instruction::Let { name, value: Expression::Connect(address) } => {
+                let address = get_net_var(data, address)?;
+                let handle = data.net_backend().unwrap().connect(&address).map_err(net_error)?;
+                data.set_var(name.clone(), handle.to_string());
+            },
+            Instruction::NetSend { socket, message } => {
+                let handle = get_socket_handle(data, socket)?;
+                let message = get_net_var(data, message)?;
+                data.net_backend().unwrap().send(handle, &message).map_err(net_error)?;
+            },
+            Instruction::Let { name, value: Expression::NetRecv(socket) } => {
+                let handle = get_socket_handle(data, socket)?;
+                let message = data.net_backend().unwrap().recv(handle).map_err(net_error)?;
+                data.set_var(name.clone(), message);
+            },
+            Instruction::Close(socket) => {
+                let handle = get_socket_handle(data, socket)?;
+                data.net_backend().unwrap().close(handle).map_err(net_error)?;
+            },
+            Instruction::Let { name, value: Expression::HttpGet(url) } => {
+                let url = get_net_var(data, url)?;
+                let body = data.net_backend().unwrap().http_get(&url).map_err(net_error)?;
+                data.set_var(name.clone(), body);
+            },
+            _ => return invalid("Invalid network instruction", value),
+        }
+
+        data.next_line();
+        Ok(get_state(States::ExecuteState))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::collections::{HashMap, LinkedList};
-    use std::thread;
-    use std::time::Duration;
-    use rand::Rng;
-    use crate::{get_state, States};
-    use crate::prog_data::ProgramData;
-    use crate::states::{EndState, GotoState, IO_BUFFER, IS_EXIT, MathState, PushState};
-    use super::{AssignState, StateMachine, ExecuteState, OutputState, IfState};
+    use crate::states::{get_state, States};
+    use crate::prog_data::{ProgramData, Value};
+    use crate::console::BufferConsole;
+    use crate::dialect::Dialect;
+    use crate::states::{AssertState, GotoState, PushState, TestcaseState};
+    use crate::runner::run;
+    use super::{AssignState, NewState, StateMachine, ExecuteState, IfState};
+
+    // Lets tests step through states the same way they would with a `Result`
+    impl NewState {
+        fn ok(self) -> Option<(ProgramData, Box<dyn StateMachine>)> {
+            match self {
+                NewState::Continue(data, state) => Some((data, state)),
+                _ => None,
+            }
+        }
+
+        fn err(self) -> Option<String> {
+            match self {
+                NewState::Error(_, msg) => Some(msg),
+                _ => None,
+            }
+        }
+
+        fn unwrap(self) -> (ProgramData, Box<dyn StateMachine>) {
+            match self {
+                NewState::Continue(data, state) => (data, state),
+                NewState::Finished(_, code) => panic!("Program finished with exit code {}", code),
+                NewState::Error(_, msg) => panic!("{}", msg),
+            }
+        }
+    }
 
     #[test]
     fn check_that_start_returns_0() {
@@ -486,6 +1610,19 @@ mod test {
         assert_eq!(0, state.ok().unwrap().0.get_index())
     }
 
+    #[test]
+    fn execute_past_last_line_quits() {
+        let data = ProgramData::new(vec![String::from("let $a = 5")], HashMap::new(), LinkedList::new(), 1);
+        let (data, state) = get_state(States::ExecuteState).execute(data).unwrap();
+        assert!(matches!(state.execute(data), NewState::Finished(_, 0)));
+    }
+
+    #[test]
+    fn instruction_state_without_line_is_an_error() {
+        let data = ProgramData::new(Vec::new(), HashMap::new(), LinkedList::new(), 0);
+        assert_eq!(get_state(States::PushState).execute(data).err().unwrap(), "Line 0 has no instruction to run!\nAborting...");
+    }
+
     #[test]
     fn execute_state_calls_assign_state() {
         let mut state = get_state(States::ExecuteState);
@@ -504,7 +1641,7 @@ mod test {
 
     #[test]
     fn assign_number_to_variable() {
-        let mut data = ProgramData::new(
+        let data = ProgramData::new(
             vec![String::from("let $a = 5")],
             HashMap::new(),
             LinkedList::new(),
@@ -519,7 +1656,7 @@ mod test {
 
     #[test]
     fn assign_string_to_variable() {
-        let mut data = ProgramData::new(
+        let data = ProgramData::new(
             vec![String::from(r#"let $a = "hello""#)],
             HashMap::new(),
             LinkedList::new(),
@@ -556,15 +1693,13 @@ mod test {
 
     #[test]
     fn assign_register_to_input() {
-        unsafe {
-            IO_BUFFER = String::from("hello")
-        }
         let mut data = ProgramData::new(
             vec![String::from(r#"let $a = input"#)],
             HashMap::new(),
             LinkedList::new(),
             0
         );
+        data.set_console(Box::new(BufferConsole::new("hello\n")));
         let result = AssignState{}.execute(data);
         let res = result.ok().unwrap()
             .0.get_var(&String::from("a"))
@@ -573,19 +1708,109 @@ mod test {
     }
 
     #[test]
-    fn output_int_register() {
+    fn assign_prompted_input() {
+        let mut data = ProgramData::new(vec![String::from("let $a = input \"Enter your name\"")], HashMap::new(), LinkedList::new(), 0);
+        let console = BufferConsole::new("Bob\n");
+        let output = console.output.clone();
+        data.set_console(Box::new(console));
+        let data = run(data).ok().unwrap().data;
+        assert_eq!(data.get_var("a").unwrap(), "Bob");
+        assert_eq!(*output.lock().unwrap(), vec![String::from("Enter your name")]);
+    }
 
-        //Save the static global variable to ensure other test data is saved
-        let mut old_str = String::new();
-        unsafe {
-            old_str = IO_BUFFER.clone();
-        }
+    #[test]
+    fn assign_number_input() {
+        let run_with = |input: &str, interactive: bool| {
+            let mut data = ProgramData::new(vec![String::from("let $n = inputnum \"Age\"")], HashMap::new(), LinkedList::new(), 0);
+            let mut console = BufferConsole::new(input);
+            console.interactive = interactive;
+            let output = console.output.clone();
+            data.set_console(Box::new(console));
+            let result = run(data).map(|finished| finished.data.get_var("n").cloned());
+            let output = output.lock().unwrap().clone();
+            (result, output)
+        };
+
+        let (result, output) = run_with(" 42 \n", false);
+        assert_eq!(result.ok().unwrap(), Some(Value::Int(42)));
+        assert_eq!(output, vec![String::from("Age")]);
+
+        let (result, _) = run_with("abc\n42\n", false);
+        assert_eq!(result.err().unwrap().message, "Input \"abc\" is not a whole number!");
+
+        let (result, output) = run_with("abc\n4.5\n-7\n", true);
+        assert_eq!(result.ok().unwrap(), Some(Value::Int(-7)));
+        assert_eq!(output, vec!["Age", "Please enter a whole number", "Age", "Please enter a whole number", "Age"]);
+
+        let (result, _) = run_with("abc\n", true);
+        assert_eq!(result.err().unwrap().message, "Input ended before a whole number was entered!");
+    }
+
+    #[test]
+    fn assign_input_past_end() {
+        let mut data = ProgramData::new(
+            vec![String::from("let $a = input")],
+            HashMap::new(),
+            LinkedList::new(),
+            0
+        );
+        data.set_console(Box::new(BufferConsole::new("")));
+        let result = AssignState{}.execute(data);
+        assert_eq!(result.ok().unwrap().0.get_var(&String::from("a")).unwrap(), "")
+    }
+
+    #[test]
+    fn assign_all_input() {
+        let mut data = ProgramData::new(
+            vec![String::from("let $a = input all")],
+            HashMap::new(),
+            LinkedList::new(),
+            0
+        );
+        data.set_console(Box::new(BufferConsole::new("line one\nline two\n")));
+        let result = AssignState{}.execute(data);
+        assert_eq!(result.ok().unwrap().0.get_var(&String::from("a")).unwrap(), "line one\nline two")
+    }
+
+    #[test]
+    fn assign_timed_input() {
+        let mut data = ProgramData::new(
+            vec![String::from("let $a = input timeout 5 default \"none\"")],
+            HashMap::new(),
+            LinkedList::new(),
+            0
+        );
+        data.set_console(Box::new(BufferConsole::new("hello\n")));
+        data = AssignState{}.execute(data).unwrap().0;
+        assert_eq!(data.get_var(&String::from("a")).unwrap(), "hello");
+        assert_eq!(data.get_var(&String::from("timedout")).unwrap(), "0");
+    }
+
+    #[test]
+    fn assign_timed_input_uses_default() {
+        let mut data = ProgramData::new(
+            vec![String::from("let $a = input timeout 5 default \"none\"")],
+            HashMap::new(),
+            LinkedList::new(),
+            0
+        );
+        data.set_console(Box::new(BufferConsole::new("")));
+        data = AssignState{}.execute(data).unwrap().0;
+        assert_eq!(data.get_var(&String::from("a")).unwrap(), "none");
+        assert_eq!(data.get_var(&String::from("timedout")).unwrap(), "1");
+    }
+
+    #[test]
+    fn output_int_register() {
         let mut data = ProgramData::new(
             vec![String::from("let $a = 5"), String::from("output $a")],
             HashMap::new(),
             LinkedList::new(),
             0
         );
+        let console = BufferConsole::new("");
+        let output = console.output.clone();
+        data.set_console(Box::new(console));
         //Assign $a
         let mut result = ExecuteState{}.execute(data).unwrap();
         data = result.0;
@@ -597,35 +1822,83 @@ mod test {
 
         //Output $a
         data = result.0;
-        result = result.1.execute(data).unwrap();
+        result.1.execute(data).unwrap();
 
-        let mut output_str = String::new();
-        unsafe {
-            output_str = IO_BUFFER.clone();
-            IO_BUFFER = old_str;
-        }
-        assert_eq!("5", output_str);
+        assert_eq!(*output.lock().unwrap(), vec![String::from("5")]);
     }
 
     #[test]
-    fn output_str_variable() {
+    fn compound_assignment() {
+        let code = ["let $sum = 0", "let $i = 5", "let $one = 1", "let $sum += $i", "let $i -= $one", "if $i > $one goto 3",
+                    "let $sum *= $sum", "let $sum /= $i"];
+        let data = ProgramData::new(code.iter().map(|line| line.to_string()).collect(), HashMap::new(), LinkedList::new(), 0);
+        let data = run(data).ok().unwrap().data;
+        assert_eq!(data.get_var("sum").unwrap(), &Value::Int(196));
+        assert_eq!(data.get_var("i").unwrap(), &Value::Int(1));
+    }
+
+    #[test]
+    fn negation() {
+        let code = ["let $a = 5", "let $b = -$a", "let $c = neg $b", "let $d = -2.5", "let $e = -$d", "let $f = \"x\"", "let $g = -$f"];
+        let data = ProgramData::new(code.iter().map(|line| line.to_string()).collect(), HashMap::new(), LinkedList::new(), 0);
+        let error = run(data).err().unwrap();
+        assert_eq!((error.line, error.message.as_str()), (6, "$f is not a numeric value!"));
+        let data = ProgramData::new(code[..5].iter().map(|line| line.to_string()).collect(), HashMap::new(), LinkedList::new(), 0);
+        let data = run(data).ok().unwrap().data;
+        assert_eq!(data.get_var("b").unwrap(), &Value::Int(-5));
+        assert_eq!(data.get_var("c").unwrap(), &Value::Int(5));
+        assert_eq!(data.get_var("e").unwrap(), &Value::Float(2.5));
+
+        let mut data = ProgramData::new(vec![String::from("let $b = -$a")], HashMap::new(), LinkedList::new(), 0);
+        data.set_var(String::from("a"), Value::Int(i128::MIN));
+        assert_eq!(run(data).err().unwrap().message, "The result of negating $a is too large to hold!");
+    }
 
-        //Hack to stop static global variable for being accessed by multiple tests simultaneously
-        let sleep_time = rand::thread_rng().gen_range(100..500);
-        thread::sleep(Duration::from_millis(sleep_time));
+    #[test]
+    fn negative_numbers() {
+        let code = vec!["let $a = -5", "let $b = 3", "let $c = $a + $b", "if $a < $b let $d = $a * $a", "push -7", "let $e = pop"];
+        let data = ProgramData::new(code.into_iter().map(String::from).collect(), HashMap::new(), LinkedList::new(), 0);
+        let data = run(data).ok().unwrap().data;
+        assert_eq!(data.get_var("c").unwrap(), &Value::Int(-2));
+        assert_eq!(data.get_var("d").unwrap(), &Value::Int(25));
+        assert_eq!(data.get_var("e").unwrap(), &Value::Int(-7));
+
+        // Literals can be compared as well, including negative ones
+        let code = vec!["let $a = -5", "if $a < -1 goto 3", "let $b = 1", "let $two = 2", "while 0 > $a", "let $a = $a + $two", "endwhile"];
+        let mut data = ProgramData::new(code.into_iter().map(String::from).collect(), HashMap::new(), LinkedList::new(), 0);
+        data.set_dialect(Dialect::V2);
+        let data = run(data).ok().unwrap().data;
+        assert_eq!(data.get_var("a").unwrap(), &Value::Int(1));
+        assert!(data.get_var("b").is_none());
+    }
 
+    #[test]
+    fn output_several_values() {
+        let code = vec![String::from("let $a = 3"), String::from("let $b = \"four\""), String::from("output \"Result: \" $a \" of \" $b 5")];
+        let mut data = ProgramData::new(code, HashMap::new(), LinkedList::new(), 0);
+        let console = BufferConsole::new("");
+        let output = console.output.clone();
+        data.set_console(Box::new(console));
+        run(data).ok().unwrap();
+        assert_eq!(*output.lock().unwrap(), vec![String::from("Result: 3 of four5")]);
+
+        let data = ProgramData::new(vec![String::from("output \"Result \" $a")], HashMap::new(), LinkedList::new(), 0);
+        assert_eq!(run(data).err().unwrap().message, "Memory index out of bounds!");
+        let data = ProgramData::new(vec![String::from("output (")], HashMap::new(), LinkedList::new(), 0);
+        assert_eq!(run(data).err().unwrap().message, "Invalid output statement: output ( (column 8: unexpected character '(')");
+    }
+
+    #[test]
+    fn output_str_variable() {
         let mut data = ProgramData::new(
             vec![String::from("let $a = \"meme\""), String::from("output $a")],
             HashMap::new(),
             LinkedList::new(),
             0
         );
-
-        //Save the static global variable to ensure other test data is saved
-        let mut old_str = String::new();
-        unsafe {
-            old_str = IO_BUFFER.clone();
-        }
+        let console = BufferConsole::new("");
+        let output = console.output.clone();
+        data.set_console(Box::new(console));
         //Assign $a
         let mut result = ExecuteState{}.execute(data).unwrap();
         data = result.0;
@@ -637,19 +1910,14 @@ mod test {
 
         //Output $a
         data = result.0;
-        result = result.1.execute(data).unwrap();
-        let mut output_str = String::new();
-        unsafe {
-            output_str = IO_BUFFER.clone();
-            IO_BUFFER = old_str;
-        }
-        println!("{}", output_str);
-        assert_eq!("meme", output_str);
+        result.1.execute(data).unwrap();
+
+        assert_eq!(*output.lock().unwrap(), vec![String::from("meme")]);
     }
 
     #[test]
     fn goto_valid_block() {
-        let mut data = ProgramData::new(
+        let data = ProgramData::new(
             vec![String::from("goto 2"), String::from("quit"), String::from("quit")],
             HashMap::new(),
             LinkedList::new(),
@@ -661,7 +1929,7 @@ mod test {
 
     #[test]
     fn goto_invalid_block() {
-        let mut data = ProgramData::new(
+        let data = ProgramData::new(
             vec![String::from("goto 4"), String::from("quit"), String::from("quit")],
             HashMap::new(),
             LinkedList::new(),
@@ -759,7 +2027,7 @@ mod test {
         result = result.1.execute(data).unwrap();
         data = result.0;
 
-        assert_eq!(data.get_var(&String::from("c")).unwrap().as_str(), "3")
+        assert_eq!(data.get_var(&String::from("c")).unwrap().to_string(), "3")
     }
 
     #[test]
@@ -794,7 +2062,7 @@ mod test {
         result = result.1.execute(data).unwrap();
         data = result.0;
 
-        assert_eq!(data.get_var(&String::from("c")).unwrap().as_str(), "0")
+        assert_eq!(data.get_var(&String::from("c")).unwrap().to_string(), "0")
     }
 
     #[test]
@@ -829,7 +2097,7 @@ mod test {
         result = result.1.execute(data).unwrap();
         data = result.0;
 
-        assert_eq!(data.get_var(&String::from("c")).unwrap().as_str(), "8")
+        assert_eq!(data.get_var(&String::from("c")).unwrap().to_string(), "8")
     }
 
     #[test]
@@ -864,8 +2132,8 @@ mod test {
         result = result.1.execute(data).unwrap();
         data = result.0;
 
-        assert_eq!(data.get_var(&String::from("c")).unwrap().as_str(), "2");
-        assert_eq!(data.pop().unwrap().as_str(), "1")
+        assert_eq!(data.get_var(&String::from("c")).unwrap().to_string(), "2");
+        assert_eq!(data.pop().unwrap().to_string(), "1")
     }
 
     #[test]
@@ -945,19 +2213,16 @@ mod test {
             0
         );
         //Quit program
-        let mut result = ExecuteState{}.execute(data).unwrap();
+        let result = ExecuteState{}.execute(data).unwrap();
         data = result.0;
         let res = result.1.execute(data);
 
-        assert_eq!(res.err().unwrap(), "Exit");
-        unsafe {
-            assert_eq!(IS_EXIT, true)
-        }
+        assert!(matches!(res, NewState::Finished(_, 0)));
     }
 
     #[test]
     fn execute_invalid_instruction() {
-        let mut data = ProgramData::new(
+        let data = ProgramData::new(
             vec![
                 String::from("go to 0")
             ],
@@ -970,6 +2235,31 @@ mod test {
         assert_eq!(res.err().unwrap(), "Unknown instruction: go to 0\nAborting...")
     }
 
+    #[test]
+    fn execute_dispatches_on_first_word() {
+        let mut data = ProgramData::new(
+            vec![String::from("output $letter")],
+            HashMap::from([(String::from("letter"), String::from("a"))]),
+            LinkedList::new(),
+            0
+        );
+        let console = BufferConsole::new("");
+        let output = console.output.clone();
+        data.set_console(Box::new(console));
+
+        // 'let' appears inside '$letter', which must not make this an assign statement
+        let result = ExecuteState{}.execute(data).unwrap();
+        result.1.execute(result.0).unwrap();
+        assert_eq!(*output.lock().unwrap(), vec![String::from("a")]);
+    }
+
+    #[test]
+    fn execute_keyword_prefix_is_unknown() {
+        let data = ProgramData::new(vec![String::from("outputs $a")], HashMap::new(), LinkedList::new(), 0);
+        let res = ExecuteState{}.execute(data);
+        assert_eq!(res.err().unwrap(), "Unknown instruction: outputs $a\nAborting...")
+    }
+
     #[test]
     fn assign_from_invalid_variable() {
         let mut data = ProgramData::new(
@@ -981,7 +2271,7 @@ mod test {
             0
         );
         //Try assign $a
-        let mut result = ExecuteState{}.execute(data).unwrap();
+        let result = ExecuteState{}.execute(data).unwrap();
         data = result.0;
         let res = result.1.execute(data);
 
@@ -999,11 +2289,11 @@ mod test {
             0
         );
         //Try assign $a
-        let mut result = ExecuteState{}.execute(data).unwrap();
+        let result = ExecuteState{}.execute(data).unwrap();
         data = result.0;
         let res = result.1.execute(data);
 
-        assert_eq!(res.err().unwrap(), "Invalid goto statement: goto e\nAborting...")
+        assert_eq!(res.err().unwrap(), "Invalid goto statement: goto e (column 6: expected a line number)\nAborting...")
     }
 
     #[test]
@@ -1017,11 +2307,11 @@ mod test {
             0
         );
         //Try assign $a
-        let mut result = ExecuteState{}.execute(data).unwrap();
+        let result = ExecuteState{}.execute(data).unwrap();
         data = result.0;
         let res = result.1.execute(data);
 
-        assert_eq!(res.err().unwrap(), "Invalid assign instruction: let $a = e\nAborting...")
+        assert_eq!(res.err().unwrap(), "Invalid assign instruction: let $a = e (column 10: expected a value)\nAborting...")
     }
 
     #[test]
@@ -1035,11 +2325,11 @@ mod test {
             0
         );
         //Try assign $a
-        let mut result = ExecuteState{}.execute(data).unwrap();
+        let result = ExecuteState{}.execute(data).unwrap();
         data = result.0;
         let res = result.1.execute(data);
 
-        assert_eq!(res.err().unwrap(), "Invalid assign instruction: let e = $a\nAborting...")
+        assert_eq!(res.err().unwrap(), "Invalid assign instruction: let e = $a (column 5: expected a variable)\nAborting...")
     }
 
     #[test]
@@ -1102,7 +2392,7 @@ mod test {
         result = result.1.execute(data).unwrap();
 
         let res = IfState{}.execute(result.0);
-        assert_eq!(res.err().unwrap(), "Invalid if statement: if e < $b goto 3\nAborting...")
+        assert_eq!(res.err().unwrap(), "Invalid if statement: if e < $b goto 3 (column 4: expected a variable or a value)\nAborting...")
     }
 
     #[test]
@@ -1117,13 +2407,46 @@ mod test {
             LinkedList::new(),
             0
         );
-        //Assign $a
-        let mut result = ExecuteState{}.execute(data).unwrap();
-        data = result.0;
-        result = result.1.execute(data).unwrap();
+        //Assign $a
+        let mut result = ExecuteState{}.execute(data).unwrap();
+        data = result.0;
+        result = result.1.execute(data).unwrap();
+
+        let res = IfState{}.execute(result.0);
+        assert_eq!(res.err().unwrap(), "Invalid if statement: if $a < e goto 3 (column 9: expected a variable or a value)\nAborting...")
+    }
+
+    #[test]
+    fn if_runs_inline_statement() {
+        let mut data = ProgramData::new(
+            vec![
+                String::from("let $a = 1"),
+                String::from("let $b = 1"),
+                String::from("let $msg = \"same\""),
+                String::from("if $a = $b let $c = $a + $b"),
+                String::from("if $a = $b output $msg"),
+                String::from("if $a != $b output $msg"),
+                String::from("output $c"),
+            ],
+            HashMap::new(),
+            LinkedList::new(),
+            0
+        );
+        let console = BufferConsole::new("");
+        let output = console.output.clone();
+        data.set_console(Box::new(console));
 
-        let res = IfState{}.execute(result.0);
-        assert_eq!(res.err().unwrap(), "Invalid if statement: if $a < e goto 3\nAborting...")
+        let outcome = run(data).ok().unwrap();
+        assert_eq!(outcome.data.get_var("c").unwrap(), "2");
+        assert_eq!(*output.lock().unwrap(), vec![String::from("same"), String::from("2")]);
+    }
+
+    #[test]
+    fn if_goto_out_of_bounds() {
+        let result = run_string_program(vec!["let $a = 1", "if $a = $a goto 99"], &[]);
+        assert_eq!(result.err().unwrap(), "Goto statement points to region out of bounds!");
+        let result = run_string_program(vec!["let $a = 1", "if $a != $a goto 0 else goto 99"], &[]);
+        assert_eq!(result.err().unwrap(), "Goto statement points to region out of bounds!");
     }
 
     #[test]
@@ -1200,7 +2523,7 @@ mod test {
         data = result.0;
         let res = result.1.execute(data);
 
-        assert_eq!(res.err().unwrap(), "Exit")
+        assert!(matches!(res, NewState::Finished(_, 0)))
     }
 
     #[test]
@@ -1212,20 +2535,20 @@ mod test {
             0
         );
         data.push(String::from("test"));
-        let mut result = AssignState{}.execute(data).unwrap();
+        let result = AssignState{}.execute(data).unwrap();
         data = result.0;
         assert_eq!(data.get_var(&String::from("a")).unwrap(), "test")
     }
 
     #[test]
     fn assign_empty_stack() {
-        let mut data = ProgramData::new(
+        let data = ProgramData::new(
             vec![String::from("let $a = pop")],
             HashMap::new(),
             LinkedList::new(),
             0
         );
-        let mut result = AssignState{}.execute(data);
+        let result = AssignState{}.execute(data);
         assert_eq!(result.err().unwrap(), "Stack is empty!\nAborting...")
     }
 
@@ -1336,16 +2659,24 @@ mod test {
         );
 
         //Assign $a
-        let mut result = AssignState{}.execute(data).unwrap();
+        let result = AssignState{}.execute(data).unwrap();
         data = result.0;
         let res = PushState{}.execute(data);
 
-        assert_eq!(res.err().unwrap(), "Invalid push statement: push a\nAborting...")
+        assert_eq!(res.err().unwrap(), "Invalid push statement: push a (column 6: expected a variable or a value)\nAborting...")
+    }
+
+    #[test]
+    fn push_literals() {
+        let mut data = run_string_program(vec!["push 5", "push \"hello\"", "push 2.5"], &[]).unwrap();
+        assert!(matches!(data.pop(), Some(Value::Float(_))));
+        assert_eq!(data.pop().unwrap(), "hello");
+        assert!(matches!(data.pop(), Some(Value::Int(5))));
     }
 
     #[test]
     fn test_invalid_variable_push() {
-        let mut data = ProgramData::new(
+        let data = ProgramData::new(
             vec![
                  String::from("push $a"),
             ],
@@ -1358,4 +2689,920 @@ mod test {
 
         assert_eq!(res.err().unwrap(), "No such variable: $a\nAborting...")
     }
-}
\ No newline at end of file
+
+    #[cfg(feature = "net")]
+    fn run_net_program(code: Vec<&str>, backend: Option<crate::net::MockBackend>) -> Result<ProgramData, String> {
+        let mut data = ProgramData::new(
+            code.into_iter().map(String::from).collect(),
+            HashMap::from([(String::from("addr"), String::from("localhost:4000"))]),
+            LinkedList::new(),
+            0
+        );
+        if let Some(backend) = backend {
+            data.set_net_backend(Box::new(backend));
+        }
+
+        // Run every instruction up to the end of the program
+        let mut state = get_state(States::ExecuteState);
+        while data.get_index() < data.code_size() {
+            match state.execute(data) {
+                NewState::Continue(next_data, next_state) => {
+                    data = next_data;
+                    state = next_state;
+                },
+                NewState::Finished(..) => return Err(String::from("Program finished early")),
+                NewState::Error(_, msg) => return Err(msg),
+            }
+        }
+        Ok(data)
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn net_send_and_receive() {
+        let backend = crate::net::MockBackend::new(vec!["pong"]);
+        let sent = backend.sent.clone();
+        let data = run_net_program(vec![
+            "let $s = connect $addr",
+            "let $msg = \"ping\"",
+            "netsend $s $msg",
+            "let $reply = netrecv $s",
+            "close $s",
+        ], Some(backend)).unwrap();
+
+        assert_eq!(data.get_var(&String::from("s")).unwrap(), "0");
+        assert_eq!(data.get_var(&String::from("reply")).unwrap(), "pong");
+        assert_eq!(*sent.lock().unwrap(), vec![String::from("ping")]);
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn net_use_closed_socket() {
+        let backend = crate::net::MockBackend::new(vec![]);
+        let res = run_net_program(vec![
+            "let $s = connect $addr",
+            "close $s",
+            "close $s",
+        ], Some(backend));
+
+        assert_eq!(res.err().unwrap(), "Socket 0 is not open!\nAborting...")
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn net_disabled_without_backend() {
+        let res = run_net_program(vec!["let $s = connect $addr"], None);
+        assert_eq!(res.err().unwrap(), "Network access is disabled! Run with --allow-net to enable it.\nAborting...")
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn net_invalid_socket_handle() {
+        let backend = crate::net::MockBackend::new(vec![]);
+        let res = run_net_program(vec!["close $addr"], Some(backend));
+        assert_eq!(res.err().unwrap(), "$addr is not a socket handle!\nAborting...")
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn net_http_get() {
+        let backend = crate::net::MockBackend::new(vec!["1 2 3"]);
+        let data = run_net_program(vec!["let $body = httpget $addr"], Some(backend)).unwrap();
+        assert_eq!(data.get_var(&String::from("body")).unwrap(), "1 2 3");
+    }
+
+    #[test]
+    fn assert_passes() {
+        let data = ProgramData::new(
+            vec![String::from("assert $a <= $b")],
+            HashMap::from([(String::from("a"), String::from("1")), (String::from("b"), String::from("2"))]),
+            LinkedList::new(),
+            0
+        );
+        let res = AssertState{}.execute(data);
+        assert_eq!(res.unwrap().0.get_index(), 1)
+    }
+
+    #[test]
+    fn assert_fails() {
+        let data = ProgramData::new(
+            vec![String::from("assert $a = $b")],
+            HashMap::from([(String::from("a"), String::from("1")), (String::from("b"), String::from("2"))]),
+            LinkedList::new(),
+            0
+        );
+        let res = AssertState{}.execute(data);
+        assert_eq!(res.err().unwrap(), "Assertion failed: assert $a = $b ($a is 1, $b is 2)\nAborting...")
+    }
+
+    #[test]
+    fn assert_fails_against_literal() {
+        let data = ProgramData::new(
+            vec![String::from("assert $a = -1")],
+            HashMap::from([(String::from("a"), String::from("1"))]),
+            LinkedList::new(),
+            0
+        );
+        let res = AssertState{}.execute(data);
+        assert_eq!(res.err().unwrap(), "Assertion failed: assert $a = -1 ($a is 1)\nAborting...");
+
+        let data = ProgramData::new(vec![String::from("assert 1 > 2")], HashMap::new(), LinkedList::new(), 0);
+        let res = AssertState{}.execute(data);
+        assert_eq!(res.err().unwrap(), "Assertion failed: assert 1 > 2\nAborting...");
+    }
+
+    #[test]
+    fn testcase_skipped_during_normal_execution() {
+        let data = ProgramData::new(
+            vec![
+                String::from("testcase adds"),
+                String::from("assert $a = $b"),
+                String::from("endtest"),
+                String::from("quit"),
+            ],
+            HashMap::new(),
+            LinkedList::new(),
+            0
+        );
+        let result = ExecuteState{}.execute(data).unwrap();
+        let res = result.1.execute(result.0);
+        assert_eq!(res.unwrap().0.get_index(), 3)
+    }
+
+    #[test]
+    fn testcase_without_endtest() {
+        let data = ProgramData::new(
+            vec![String::from("testcase adds"), String::from("quit")],
+            HashMap::new(),
+            LinkedList::new(),
+            0
+        );
+        let res = TestcaseState{}.execute(data);
+        assert_eq!(res.err().unwrap(), "Testcase adds has no matching endtest!\nAborting...")
+    }
+
+    #[test]
+    fn if_compares_numbers_as_strings_in_v1() {
+        let data = ProgramData::new(
+            vec![String::from("if $a < $b goto 2"), String::from("quit"), String::from("quit")],
+            HashMap::from([(String::from("a"), String::from("10")), (String::from("b"), String::from("9"))]),
+            LinkedList::new(),
+            0
+        );
+        let res = IfState{}.execute(data);
+        assert_eq!(res.unwrap().0.get_index(), 2)
+    }
+
+    #[test]
+    fn if_compares_numbers_by_value_in_v2() {
+        let mut data = ProgramData::new(
+            vec![String::from("if $a < $b goto 2"), String::from("quit"), String::from("quit")],
+            HashMap::from([(String::from("a"), String::from("10")), (String::from("b"), String::from("9"))]),
+            LinkedList::new(),
+            0
+        );
+        data.set_dialect(Dialect::V2);
+        let res = IfState{}.execute(data);
+        assert_eq!(res.unwrap().0.get_index(), 1)
+    }
+
+    #[test]
+    fn dialect_not_on_first_line() {
+        let mut data = ProgramData::new(
+            vec![String::from("quit"), String::from("dialect v2")],
+            HashMap::new(),
+            LinkedList::new(),
+            0
+        );
+        data.set_index(1);
+        let res = ExecuteState{}.execute(data).unwrap();
+        let res = res.1.execute(res.0);
+        assert_eq!(res.err().unwrap(), "The dialect can only be declared on the first line!\nAborting...")
+    }
+
+    fn run_string_program(code: Vec<&str>, vars: &[(&str, &str)]) -> Result<ProgramData, String> {
+        let data = ProgramData::new(
+            code.into_iter().map(String::from).collect(),
+            vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            LinkedList::new(),
+            0
+        );
+        run(data).map(|outcome| outcome.data).map_err(|err| err.message)
+    }
+
+    #[test]
+    fn string_builtins() {
+        let data = run_string_program(
+            vec!["let $a = pad $s $w", "let $b = pad $s $l $dot", "let $c = pad $s $n", "let $d = repeat $s $w",
+                 "let $e = reverse $s"],
+            &[("s", "abc"), ("w", "5"), ("l", "-6"), ("n", "2"), ("dot", ".")]
+        ).unwrap();
+        assert_eq!(data.get_var("a").unwrap(), "  abc");
+        assert_eq!(data.get_var("b").unwrap(), "abc...");
+        assert_eq!(data.get_var("c").unwrap(), "abc");
+        assert_eq!(data.get_var("d").unwrap(), "abcabcabcabcabc");
+        assert_eq!(data.get_var("e").unwrap(), "cba");
+    }
+
+    #[test]
+    fn concatenate_strings() {
+        let data = run_string_program(vec!["let $a = $s . $space", "let $b = $a . $n", "let $c = $n . $n", "let $d = $f . $s"],
+                                      &[("s", "total"), ("space", " "), ("n", "42"), ("f", "1.5")]).unwrap();
+        assert_eq!(data.get_var("a").unwrap(), "total ");
+        assert_eq!(data.get_var("b").unwrap(), "total 42");
+        // Joining numbers gives a string, not a sum
+        assert!(matches!(data.get_var("c"), Some(Value::Str(text)) if text == "4242"));
+        assert_eq!(data.get_var("d").unwrap(), "1.5total");
+        assert_eq!(run_string_program(vec!["let $a = $s . $missing"], &[("s", "total")]).err().unwrap(),
+                   "Variable $missing does not exist!");
+    }
+
+    #[test]
+    fn string_builtins_invalid_operands() {
+        let vars = [("s", "abc"), ("w", "wide"), ("n", "-1"), ("fill", "ab")];
+        assert_eq!(run_string_program(vec!["let $a = pad $s $w"], &vars).err().unwrap(), "$w is not a numeric value!");
+        assert_eq!(run_string_program(vec!["let $a = pad $s $n $fill"], &vars).err().unwrap(),
+                   "$fill must hold a single character!");
+        assert_eq!(run_string_program(vec!["let $a = repeat $s $n"], &vars).err().unwrap(), "$n cannot be negative!");
+        assert_eq!(run_string_program(vec!["let $a = reverse $t"], &vars).err().unwrap(), "Variable $t does not exist!");
+    }
+
+    #[test]
+    fn exec_disabled_by_default() {
+        let result = run_string_program(vec!["let $a = exec \"echo hi\""], &[]);
+        assert_eq!(result.err().unwrap(), "Running commands is disabled! Run with --allow-exec to enable it.");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exec_captures_output_and_exit_code() {
+        let mut data = ProgramData::new(
+            vec![String::from("let $a = exec \"echo hello world\""), String::from("let $b = exec $cmd"),
+                 String::from("let $c = exec \"printf '%s-%s' -n 2 | tr n N\"")],
+            HashMap::from([(String::from("cmd"), String::from("printf 'x\\ny'; exit 3"))]),
+            LinkedList::new(),
+            0
+        );
+        data.set_exec_allowed(true);
+        let data = run(data).ok().unwrap().data;
+        assert_eq!(data.get_var("a").unwrap(), "hello world");
+        assert_eq!(data.get_var("b").unwrap(), "x\ny");
+        assert_eq!(data.get_var("c").unwrap(), "-N-2");
+        // The exit code comes from the last command
+        assert_eq!(data.get_var("exitcode").unwrap(), "0");
+    }
+
+    #[test]
+    fn environment_variables() {
+        let result = run_string_program(vec!["let $path = env \"PATH\""], &[]);
+        assert_eq!(result.err().unwrap(), "Environment access is disabled! Run with --allow-env to enable it.");
+
+        std::env::set_var("COS341BASIC_TEST_ENV", "set here");
+        let mut data = ProgramData::new(
+            vec![String::from("let $a = env $name"), String::from("let $b = env \"COSBASIC NEVER SET\"")],
+            HashMap::from([(String::from("name"), String::from("COS341BASIC_TEST_ENV"))]),
+            LinkedList::new(),
+            0
+        );
+        data.set_env_allowed(true);
+        let data = run(data).ok().unwrap().data;
+        assert_eq!(data.get_var("a").unwrap(), "set here");
+        assert_eq!(data.get_var("b").unwrap(), "");
+    }
+
+    #[test]
+    fn program_arguments() {
+        let mut data = ProgramData::new(
+            vec![String::from("let $n = argc"), String::from("let $a = arg 1"), String::from("let $b = arg $n")],
+            HashMap::new(),
+            LinkedList::new(),
+            0
+        );
+        data.set_args(vec![String::from("5"), String::from("hello")]);
+        let data = run(data).ok().unwrap().data;
+        assert_eq!(data.get_var("n").unwrap(), "2");
+        assert_eq!(data.get_var("a").unwrap(), "5");
+        assert_eq!(data.get_var("b").unwrap(), "hello");
+
+        let data = run_string_program(vec!["let $n = argc"], &[]).unwrap();
+        assert_eq!(data.get_var("n").unwrap(), "0");
+        let result = run_string_program(vec!["let $a = arg 1"], &[]);
+        assert_eq!(result.err().unwrap(), "Argument 1 does not exist!");
+        let result = run_string_program(vec!["let $a = arg 0"], &[]);
+        assert_eq!(result.err().unwrap(), "Argument 0 does not exist!");
+        let result = run_string_program(vec!["let $a = arg $i"], &[("i", "x")]);
+        assert_eq!(result.err().unwrap(), "$i is not an integer value!");
+    }
+
+    #[test]
+    fn builtins() {
+        let data = run_string_program(
+            vec!["let $a = abs $x", "let $s = sign $x", "let $r = sqrt 16", "let $m = min $x 3", "let $n = max $x $y"],
+            &[("x", "-7"), ("y", "2.5")]
+        ).unwrap();
+        assert_eq!(data.get_var("a").unwrap(), "7");
+        assert_eq!(data.get_var("s").unwrap(), "-1");
+        assert_eq!(data.get_var("r").unwrap(), "4.0");
+        assert_eq!(data.get_var("m").unwrap(), "-7");
+        assert_eq!(data.get_var("n").unwrap(), "2.5");
+
+        let result = run_string_program(vec!["let $r = sqrt $x"], &[("x", "-4")]);
+        assert_eq!(result.err().unwrap(), "Cannot take the square root of $x, which is negative!");
+        let result = run_string_program(vec!["let $m = max $x $y"], &[("x", "1"), ("y", "two")]);
+        assert_eq!(result.err().unwrap(), "$y is not a numeric value!");
+        let result = run_string_program(vec!["let $a = abs $x"], &[]);
+        assert_eq!(result.err().unwrap(), "Variable $x does not exist!");
+    }
+
+    #[test]
+    fn filesystem_disabled_by_default() {
+        let result = run_string_program(vec!["listdir $path"], &[("path", ".")]);
+        assert_eq!(result.err().unwrap(), "File access is disabled! Run with --allow-fs to enable it.");
+    }
+
+    #[test]
+    fn filesystem_queries() {
+        let dir = std::env::temp_dir().join(format!("cos341basic_fs_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("b.txt"), "b").unwrap();
+        std::fs::write(dir.join("a.txt"), "a").unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let program = |code: Vec<&str>| {
+            let mut data = ProgramData::new(
+                code.into_iter().map(String::from).collect(),
+                HashMap::from([
+                    (String::from("dir"), path("")),
+                    (String::from("file"), path("a.txt")),
+                    (String::from("sub"), path("sub")),
+                ]),
+                LinkedList::new(),
+                0
+            );
+            data.set_fs_allowed(true);
+            data
+        };
+
+        let mut data = run(program(vec!["listdir $dir", "let $first = pop", "delete $file", "let $gone = exists $file",
+                                        "let $here = exists $sub", "delete $sub"])).ok().unwrap().data;
+        let error = run(program(vec!["delete $sub"])).err().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(data.get_var("entries").unwrap(), "3");
+        assert_eq!(data.get_var("first").unwrap(), "a.txt");
+        assert_eq!(data.pop().unwrap(), "b.txt");
+        assert_eq!(data.get_var("gone").unwrap(), "0");
+        assert_eq!(data.get_var("here").unwrap(), "1");
+        assert!(error.message.starts_with(&format!("Could not delete {}: ", path("sub"))));
+    }
+
+    #[test]
+    fn file_reads() {
+        let dir = std::env::temp_dir().join(format!("cos341basic_read_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lines.txt"), "first\r\nsecond\n").unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let program = |code: Vec<&str>| {
+            let mut data = ProgramData::new(
+                code.into_iter().map(String::from).collect(),
+                HashMap::from([(String::from("path"), path("lines.txt")), (String::from("missing"), path("missing.txt"))]),
+                LinkedList::new(),
+                0
+            );
+            data.set_fs_allowed(true);
+            run(data).map(|outcome| outcome.data).map_err(|err| err.message)
+        };
+
+        let data = program(vec!["let $text = readfile $path", "let $f = openfile $path", "let $g = openfile $path",
+                                "let $a = readline $f", "let $b = readline $f", "let $eof_b = $eof", "let $c = readline $f",
+                                "closefile $f"]).unwrap();
+        let missing = program(vec!["let $text = readfile $missing"]).err().unwrap();
+        let closed = program(vec!["let $f = openfile $path", "closefile $f", "let $a = readline $f"]).err().unwrap();
+        let literal = program(vec![&format!("let $text = readfile \"{}\"", path("lines.txt"))]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(data.get_var("text").unwrap(), "first\r\nsecond");
+        assert!(matches!(data.get_var("f"), Some(Value::Int(1))));
+        assert!(matches!(data.get_var("g"), Some(Value::Int(2))));
+        assert_eq!(data.get_var("a").unwrap(), "first");
+        assert_eq!(data.get_var("b").unwrap(), "second");
+        assert_eq!(data.get_var("eof_b").unwrap(), "0");
+        assert_eq!(data.get_var("c").unwrap(), "");
+        assert_eq!(data.get_var("eof").unwrap(), "1");
+        assert!(missing.starts_with(&format!("Could not read {}: ", path("missing.txt"))));
+        assert_eq!(closed, "$f is not an open file!");
+        assert_eq!(literal.get_var("text").unwrap(), "first\r\nsecond");
+    }
+
+    #[test]
+    fn file_writes() {
+        let dir = std::env::temp_dir().join(format!("cos341basic_write_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let program = |code: Vec<&str>| {
+            let mut data = ProgramData::new(
+                code.into_iter().map(String::from).collect(),
+                HashMap::from([
+                    (String::from("path"), path("out.txt")),
+                    (String::from("sub"), path("missing/out.txt")),
+                    (String::from("n"), String::from("42")),
+                ]),
+                LinkedList::new(),
+                0
+            );
+            data.set_fs_allowed(true);
+            run(data).map(|outcome| outcome.data).map_err(|err| err.message)
+        };
+
+        let data = program(vec!["appendfile $path \"old\"", "writefile $path $n", "appendfile $path \"more\"",
+                                "let $text = readfile $path"]).unwrap();
+        let written = std::fs::read_to_string(path("out.txt")).unwrap();
+        program(vec![&format!("writefile \"{}\" \"line 1: ok\"", path("literal.txt")),
+                     &format!("appendfile \"{}\" $n", path("literal.txt"))]).unwrap();
+        let literal = std::fs::read_to_string(path("literal.txt")).unwrap();
+        let error = program(vec!["writefile $sub $n"]).err().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(written, "42\nmore\n");
+        assert_eq!(literal, "line 1: ok\n42\n");
+        assert_eq!(data.get_var("text").unwrap(), "42\nmore");
+        assert!(error.starts_with(&format!("Could not write to {}: ", path("missing/out.txt"))));
+    }
+
+    #[test]
+    fn seeded_random_numbers_repeat() {
+        let code = vec!["seed $s", "let $a = random $n", "let $b = random $n", "seed $s", "let $c = random $n",
+                        "let $d = random $n"];
+        let data = run_string_program(code, &[("s", "42"), ("n", "1000000")]).unwrap();
+        assert_eq!(data.get_var("a"), data.get_var("c"));
+        assert_eq!(data.get_var("b"), data.get_var("d"));
+        let a = data.get_var("a").unwrap().as_int().unwrap();
+        assert!((0..1000000).contains(&a));
+    }
+
+    #[test]
+    fn random_invalid_operands() {
+        let vars = [("zero", "0"), ("neg", "-1"), ("word", "abc")];
+        assert_eq!(run_string_program(vec!["let $a = random $zero"], &vars).err().unwrap(), "$zero must be greater than 0!");
+        assert_eq!(run_string_program(vec!["let $a = random $word"], &vars).err().unwrap(), "$word is not a numeric value!");
+        assert_eq!(run_string_program(vec!["seed $neg"], &vars).err().unwrap(), "$neg is not a valid seed!");
+    }
+
+    #[test]
+    fn sort_stack() {
+        let sorted = |values: &[&str]| {
+            let stack = values.iter().map(|value| value.to_string()).collect();
+            let mut data = run(ProgramData::new(vec![String::from("sort")], HashMap::new(), stack, 0)).ok().unwrap().data;
+            std::iter::from_fn(|| data.pop().map(|value| value.to_string())).collect::<Vec<String>>()
+        };
+        assert_eq!(sorted(&["10", "9", "-3", "100"]), vec!["-3", "9", "10", "100"]);
+        assert_eq!(sorted(&["10", "9", "b", "a"]), vec!["10", "9", "a", "b"]);
+        assert_eq!(sorted(&["10.5", "9.25", "-1", "10", "2.0", "2"]), vec!["-1", "2", "2.0", "9.25", "10", "10.5"]);
+        assert!(sorted(&[]).is_empty());
+    }
+
+    #[test]
+    fn quit_flushes_output() {
+        let output_path = std::env::temp_dir().join(format!("cos341basic_quit_flush_{}.txt", std::process::id()));
+        let output_path = output_path.to_str().unwrap();
+        let mut console = crate::console::StdConsole::new();
+        console.set_output_file(output_path).unwrap();
+        let mut data = ProgramData::new(
+            vec![String::from("output $a"), String::from("flush"), String::from("output $a")],
+            HashMap::from([(String::from("a"), String::from("5"))]),
+            LinkedList::new(),
+            0
+        );
+        data.set_console(Box::new(console));
+
+        // The outcome still holds the console, so nothing has been written by dropping it
+        let outcome = run(data).ok().unwrap();
+        assert_eq!(std::fs::read_to_string(output_path).unwrap(), "5\n5\n");
+        drop(outcome);
+        std::fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn call_subroutines() {
+        let data = run_string_program(
+            vec!["gosub 4", "if $n < $limit gosub 4", "output $n", "quit", "let $n = $n + $n", "gosub 7", "return",
+                 "let $calls = $n", "return"],
+            &[("n", "1"), ("limit", "5")]
+        ).unwrap();
+        assert_eq!(data.get_var("n").unwrap(), "4");
+        assert_eq!(data.get_var("calls").unwrap(), "4");
+        assert_eq!(data.call_depth(), 0);
+
+        // Returning from a call on the last line ends the program
+        let data = run_string_program(vec!["goto 2", "return", "gosub 1"], &[]).unwrap();
+        assert_eq!(data.get_index(), 3);
+    }
+
+    #[test]
+    fn subroutine_errors() {
+        assert_eq!(run_string_program(vec!["return"], &[]).err().unwrap(), "Return without a matching gosub!");
+        assert_eq!(run_string_program(vec!["gosub 1"], &[]).err().unwrap(),
+                   "Gosub statement points to region out of bounds!");
+        assert_eq!(run_string_program(vec!["gosub 0"], &[]).err().unwrap(),
+                   "Too many nested subroutine calls! (more than 10000)");
+    }
+
+    #[test]
+    fn functions() {
+        let data = run_string_program(
+            vec!["let $z = call add $a 2", "let $f = call fact 5", "quit",
+                 "func add $x $y", "let $s = $x + $y", "return $s", "endfunc",
+                 "func fact $n", "if $n <= $one return $one", "let $m = $n - $one", "let $r = call fact $m",
+                 "let $r = $r * $n", "return $r", "endfunc"],
+            &[("a", "40"), ("x", "outside"), ("one", "1")]).unwrap();
+        assert!(matches!(data.get_var("z"), Some(Value::Int(42))));
+        assert!(matches!(data.get_var("f"), Some(Value::Int(120))));
+        // Parameters only exist inside the call, while other variables are shared with the caller
+        assert_eq!(data.get_var("x").unwrap(), "outside");
+        assert!(!data.contains_var("n"));
+        assert!(matches!(data.get_var("s"), Some(Value::Int(42))));
+        assert_eq!(data.call_depth(), 0);
+
+        // A function is passed over when the program reaches it
+        let data = run_string_program(vec!["func one", "return 1", "endfunc", "let $a = call one"], &[]).unwrap();
+        assert!(matches!(data.get_var("a"), Some(Value::Int(1))));
+    }
+
+    #[test]
+    fn for_loops() {
+        let data = run_string_program(
+            vec!["let $sum = 0", "for $i = 1 to $n", "for $j = 10 to 0 step -5", "let $sum += $j", "next $j",
+                 "let $sum += $i", "next $i"],
+            &[("n", "4")]).unwrap();
+        // Each of the 4 passes adds 10 + 5 + 0 and the counter
+        assert!(matches!(data.get_var("sum"), Some(Value::Int(70))));
+        assert!(matches!(data.get_var("i"), Some(Value::Int(5))));
+        assert!(matches!(data.get_var("j"), Some(Value::Int(-5))));
+
+        // A loop with nothing to count skips its body
+        let data = run_string_program(vec!["for $i = 3 to 1", "let $a = 1", "next $i"], &[]).unwrap();
+        assert!(!data.contains_var("a"));
+        assert!(matches!(data.get_var("i"), Some(Value::Int(3))));
+
+        let run = |code: Vec<&str>| run_string_program(code, &[]).err().unwrap();
+        assert_eq!(run(vec!["for $i = 3 to 1"]), "Loop over $i has no matching next!");
+        assert_eq!(run(vec!["next $i"]), "next $i has no matching for!");
+        assert_eq!(run(vec!["for $i = 1 to 2", "next $j"]), "next $j cannot close the loop over $i!");
+        assert_eq!(run(vec!["for $i = 1 to 2 step 0", "next $i"]), "The step of the loop over $i cannot be 0!");
+        assert_eq!(run(vec!["for $i = 1.5 to 2", "next $i"]), "1.5 is not a whole number!");
+        assert_eq!(run(vec!["const $i = 1", "for $i = 1 to 2", "next $i"]), "Cannot reassign constant $i!");
+    }
+
+    #[test]
+    fn while_loops() {
+        let data = run_string_program(
+            vec!["let $one = 1", "let $a = 0", "let $sum = 0", "while $a < $n", "let $a += $one", "let $b = 0",
+                 "while $b < $a", "let $b += $one", "let $sum += $one", "endwhile", "endwhile"],
+            &[("n", "3")]).unwrap();
+        // The inner loop runs 1 + 2 + 3 times
+        assert!(matches!(data.get_var("a"), Some(Value::Int(3))));
+        assert!(matches!(data.get_var("sum"), Some(Value::Int(6))));
+
+        // A loop whose predicate does not hold skips its body
+        let data = run_string_program(vec!["while $a < $a", "let $b = 1", "endwhile", "let $c = 2"], &[("a", "1")]).unwrap();
+        assert!(!data.contains_var("b"));
+        assert_eq!(data.get_var("c").unwrap(), "2");
+
+        let run = |code: Vec<&str>| run_string_program(code, &[("a", "1")]).err().unwrap();
+        assert_eq!(run(vec!["while $a < $a"]), "while has no matching endwhile!");
+        assert_eq!(run(vec!["endwhile"]), "endwhile has no matching while!");
+        assert_eq!(run(vec!["while $a < $b", "endwhile"]), "Variable $b does not exist!");
+    }
+
+    #[test]
+    fn custom_errors() {
+        assert_eq!(run_string_program(vec!["let $a = 1", "error \"something went wrong\"", "let $b = 2"], &[]).err().unwrap(),
+                   "Line 1: something went wrong");
+        assert_eq!(run_string_program(vec!["if $m = $m error $m"], &[("m", "bad input")]).err().unwrap(), "Line 0: bad input");
+        assert_eq!(run_string_program(vec!["error $m"], &[]).err().unwrap(), "Variable $m does not exist!");
+    }
+
+    #[test]
+    fn quit_with_exit_code() {
+        let exit_code = |code: Vec<&str>| {
+            let data = ProgramData::new(code.into_iter().map(String::from).collect(), [(String::from("c"), String::from("7"))].into(),
+                                        LinkedList::new(), 0);
+            run(data).map(|outcome| outcome.exit_code).map_err(|err| err.message)
+        };
+        assert_eq!(exit_code(vec!["quit 3"]), Ok(3));
+        assert_eq!(exit_code(vec!["quit $c"]), Ok(7));
+        assert_eq!(exit_code(vec!["if $c = $c quit 4", "quit 5"]), Ok(4));
+        assert_eq!(exit_code(vec!["quit"]), Ok(0));
+        // Running past the last line quits with 0
+        assert_eq!(exit_code(vec!["let $a = 1"]), Ok(0));
+        assert_eq!(exit_code(vec!["quit 256"]), Err(String::from("Exit code 256 is not between 0 and 255!")));
+        assert_eq!(exit_code(vec!["quit -1"]), Err(String::from("Exit code -1 is not between 0 and 255!")));
+        assert_eq!(exit_code(vec!["quit 1.5"]), Err(String::from("1.5 is not a whole number!")));
+        assert_eq!(exit_code(vec!["quit $d"]), Err(String::from("Variable $d does not exist!")));
+    }
+
+    #[test]
+    fn conditional_assignments() {
+        let data = run_string_program(vec!["let $max = $a if $a > $b else $b", "let $min = $a if $a < $b else $b",
+                                           "let $same = \"yes\" if $a = $b else \"no\""],
+                                      &[("a", "3"), ("b", "7")]).unwrap();
+        assert!(matches!(data.get_var("max"), Some(Value::Int(7))));
+        assert!(matches!(data.get_var("min"), Some(Value::Int(3))));
+        assert_eq!(data.get_var("same").unwrap(), "no");
+
+        // Only the value that is chosen has to exist
+        let data = run_string_program(vec!["let $c = $a if $a = $a else $missing"], &[("a", "1")]).unwrap();
+        assert_eq!(data.get_var("c").unwrap(), "1");
+        assert_eq!(run_string_program(vec!["let $c = $a if $a = $b else $missing"], &[("a", "1"), ("b", "2")]).err().unwrap(),
+                   "Variable $missing does not exist!");
+    }
+
+    #[test]
+    fn switches() {
+        let code = vec!["switch $x", "case 1 goto 5", "case $y goto 6", "case \"one\" goto 7", "default goto 8",
+                        "let $a = 1", "let $a = 2", "let $a = 3", "let $b = 4"];
+        let run = |x: &str| run_string_program(code.clone(), &[("x", x), ("y", "two")]).unwrap();
+        assert_eq!(run("1").get_var("a").unwrap(), "3");
+        assert_eq!(run("two").get_var("a").unwrap(), "3");
+        assert_eq!(run("one").get_var("a").unwrap(), "3");
+        let data = run("three");
+        assert!(!data.contains_var("a"));
+        assert_eq!(data.get_var("b").unwrap(), "4");
+        // Only the matching case is jumped to
+        let data = run_string_program(vec!["switch $x", "case 1 goto 4", "case 2 goto 3", "quit", "let $a = 1"],
+                                      &[("x", "2")]).unwrap();
+        assert!(!data.contains_var("a"));
+
+        // Without a default, a switch that matches no case carries on after its cases
+        let data = run_string_program(vec!["switch $x", "case 1 goto 3", "let $a = 1", "quit"], &[("x", "2")]).unwrap();
+        assert_eq!(data.get_var("a").unwrap(), "1");
+
+        let run = |code: Vec<&str>| run_string_program(code, &[("x", "1")]).err().unwrap();
+        assert_eq!(run(vec!["switch $z", "case 1 goto 0"]), "Variable $z does not exist!");
+        assert_eq!(run(vec!["switch $x", "case $z goto 0"]), "Variable $z does not exist!");
+        assert_eq!(run(vec!["switch $x", "case 1 goto 5"]), "Goto statement points to region out of bounds!");
+        assert_eq!(run(vec!["case 1 goto 0"]), "case 1 goto 0 can only be reached through its switch!");
+        assert_eq!(run(vec!["default goto 0"]), "default goto 0 can only be reached through its switch!");
+    }
+
+    #[test]
+    fn local_variables() {
+        let data = run_string_program(
+            vec!["let $i = 7", "gosub 3", "quit", "local $i", "let $i = 1", "let $f = call f", "let $j = $i", "return",
+                 "func f", "local $i", "let $i = 2", "return $i", "endfunc"],
+            &[]).unwrap();
+        assert_eq!(data.get_var("i").unwrap(), "7");
+        assert_eq!(data.get_var("f").unwrap(), "2");
+        // The function's local variable is gone once it returns, uncovering the subroutine's
+        assert_eq!(data.get_var("j").unwrap(), "1");
+
+        assert_eq!(run_string_program(vec!["local $i"], &[]).err().unwrap(),
+                   "Local variables can only be declared inside a subroutine or function!");
+    }
+
+    #[test]
+    fn constants() {
+        let data = run_string_program(vec!["const $PI = 3", "const $name = $a", "let $b = $PI + $PI"], &[("a", "bob")]).unwrap();
+        assert!(matches!(data.get_var("b"), Some(Value::Int(6))));
+        assert_eq!(data.get_var("name").unwrap(), "bob");
+
+        let run = |code: Vec<&str>| run_string_program(code, &[]).err().unwrap();
+        assert_eq!(run(vec!["const $PI = 3", "let $PI = 4"]), "Cannot reassign constant $PI!");
+        assert_eq!(run(vec!["const $PI = 3", "const $PI = 3"]), "Cannot reassign constant $PI!");
+        assert_eq!(run(vec!["const $m = 3", "let $m{\"a\"} = 4"]), "Cannot reassign constant $m!");
+        assert_eq!(run(vec!["const $PI = $a"]), "Variable $a does not exist!");
+        // A parameter of the same name hides the constant
+        let data = run_string_program(vec!["const $x = 1", "let $y = call f 2", "quit", "func f $x", "let $x = $x + $x",
+                                           "return $x", "endfunc"], &[]).unwrap();
+        assert!(matches!(data.get_var("y"), Some(Value::Int(4))));
+    }
+
+    #[test]
+    fn unset_variables() {
+        let data = run_string_program(vec!["let $a = 1", "unset $a", "unset $b"], &[("b", "2"), ("c", "3")]).unwrap();
+        assert!(!data.contains_var("a"));
+        assert!(!data.contains_var("b"));
+        assert!(data.contains_var("c"));
+
+        let run = |code: Vec<&str>| run_string_program(code, &[]).err().unwrap();
+        assert_eq!(run(vec!["let $a = 1", "unset $a", "output $a"]), "Memory index out of bounds!");
+        assert_eq!(run(vec!["unset $a"]), "Variable $a does not exist!");
+        assert_eq!(run(vec!["const $a = 1", "unset $a"]), "Cannot unset constant $a!");
+        // Unsetting a local variable uncovers the variable outside the call
+        let data = run_string_program(vec!["let $a = 1", "gosub 3", "quit", "local $a", "unset $a", "let $b = $a", "return"], &[]).unwrap();
+        assert_eq!(data.get_var("b").unwrap(), "1");
+    }
+
+    #[test]
+    fn function_errors() {
+        let run = |code: Vec<&str>| run_string_program(code, &[]).err().unwrap();
+        assert_eq!(run(vec!["let $a = call missing"]), "Function missing does not exist!");
+        assert_eq!(run(vec!["let $a = call f 1", "quit", "func f $x $y", "return $x", "endfunc"]),
+                   "Function f takes 2 arguments but was given 1!");
+        assert_eq!(run(vec!["let $a = call f $b", "quit", "func f $x", "return $x", "endfunc"]), "Variable $b does not exist!");
+        assert_eq!(run(vec!["let $a = call f", "quit", "func f", "endfunc"]), "Function ended without returning a value!");
+        assert_eq!(run(vec!["let $a = call f", "quit", "func f", "return"]), "Function returned without a value!");
+        assert_eq!(run(vec!["gosub 2", "quit", "return 5"]), "Cannot return a value from a subroutine!");
+        assert_eq!(run(vec!["func f", "return 1"]), "Function f has no matching endfunc!");
+        assert_eq!(run(vec!["let $a = call f", "func f", "let $a = call f", "return $a", "endfunc"]),
+                   "Too many nested subroutine calls! (more than 10000)");
+    }
+
+    #[test]
+    fn typed_arithmetic() {
+        let data = run_string_program(vec!["let $a = $x + $one", "let $b = $x * $x", "let $c = $s + $one"],
+                                      &[("x", "007"), ("one", "1"), ("s", "hello")]);
+        assert_eq!(data.err().unwrap(), "$s is not a numeric value!");
+
+        let data = run_string_program(vec!["let $a = $x + $one", "let $b = $x * $x", "let $s = reverse $x"],
+                                      &[("x", "007"), ("one", "1")]).unwrap();
+        assert!(matches!(data.get_var("a"), Some(Value::Int(8))));
+        assert!(matches!(data.get_var("b"), Some(Value::Int(49))));
+        // The text of a value read as a string is kept
+        assert_eq!(data.get_var("x").unwrap(), "007");
+        assert!(matches!(data.get_var("s"), Some(Value::Str(_))));
+
+        assert_eq!(run_string_program(vec!["let $a = $x / $zero"], &[("x", "5"), ("zero", "0")]).err().unwrap(),
+                   "Division by zero!");
+        let big = i128::MAX.to_string();
+        assert_eq!(run_string_program(vec!["let $a = $x + $x"], &[("x", &big)]).err().unwrap(),
+                   "The result of $x and $x is too large to hold!");
+    }
+
+    #[test]
+    fn float_arithmetic() {
+        let data = run_string_program(vec!["let $a = 3.5", "let $b = $a * $two", "let $c = $a / $two", "let $d = $b - $one"],
+                                      &[("two", "2"), ("one", "1")]).unwrap();
+        assert!(matches!(data.get_var("a"), Some(Value::Float(_))));
+        assert_eq!(data.get_var("b").unwrap(), "7.0");
+        assert_eq!(data.get_var("c").unwrap(), "1.75");
+        assert_eq!(data.get_var("d").unwrap(), "6.0");
+        // Dividing floats gives no remainder
+        assert_eq!(data.stack_depth(), 0);
+
+        assert_eq!(run_string_program(vec!["let $a = $x / $zero"], &[("x", "1.5"), ("zero", "0")]).err().unwrap(),
+                   "Division by zero!");
+        assert_eq!(run_string_program(vec!["let $a = $x + $s"], &[("x", "1.5"), ("s", "hello")]).err().unwrap(),
+                   "$s is not a numeric value!");
+    }
+
+    #[test]
+    fn modulo() {
+        let data = run_string_program(vec!["let $a = $x % $y", "let $b = $neg % $y", "let $c = $f % $y"],
+                                      &[("x", "17"), ("y", "5"), ("neg", "-17"), ("f", "7.5")]).unwrap();
+        assert!(matches!(data.get_var("a"), Some(Value::Int(2))));
+        // The remainder has the sign of the dividend
+        assert!(matches!(data.get_var("b"), Some(Value::Int(-2))));
+        assert_eq!(data.get_var("c").unwrap(), "2.5");
+        // Unlike division, nothing is pushed onto the stack
+        assert_eq!(data.stack_depth(), 0);
+
+        assert_eq!(run_string_program(vec!["let $a = $x % $zero"], &[("x", "5"), ("zero", "0")]).err().unwrap(),
+                   "Division by zero!");
+        assert_eq!(run_string_program(vec!["let $a = $x % $zero"], &[("x", "5.5"), ("zero", "0")]).err().unwrap(),
+                   "Division by zero!");
+    }
+
+    #[test]
+    fn integer_division() {
+        let data = run_string_program(vec!["let $a = $x // $y", "let $b = $neg // $y", "let $c = $f // $y", "let $x //= $y"],
+                                      &[("x", "17"), ("y", "5"), ("neg", "-17"), ("f", "7.5")]).unwrap();
+        assert!(matches!(data.get_var("a"), Some(Value::Int(3))));
+        // The quotient is rounded towards zero, like the remainder from '%'
+        assert!(matches!(data.get_var("b"), Some(Value::Int(-3))));
+        assert_eq!(data.get_var("c").unwrap(), "1.0");
+        assert!(matches!(data.get_var("x"), Some(Value::Int(3))));
+        assert_eq!(data.stack_depth(), 0);
+
+        assert_eq!(run_string_program(vec!["let $a = $x // $zero"], &[("x", "5"), ("zero", "0")]).err().unwrap(),
+                   "Division by zero!");
+        let min = i128::MIN.to_string();
+        assert_eq!(run_string_program(vec!["let $a = $x // $y"], &[("x", &min), ("y", "-1")]).err().unwrap(),
+                   "The result of $x and $y is too large to hold!");
+    }
+
+    #[test]
+    fn true_division_in_v3() {
+        let mut data = ProgramData::new(
+            vec![String::from("let $a = $x / $y"), String::from("let $b = $y / $y"), String::from("let $c = $x // $y")],
+            HashMap::from([(String::from("x"), String::from("7")), (String::from("y"), String::from("2"))]),
+            LinkedList::new(),
+            0
+        );
+        data.set_dialect(Dialect::V3);
+        let data = run(data).ok().unwrap().data;
+        assert_eq!(data.get_var("a").unwrap(), "3.5");
+        assert_eq!(data.get_var("b").unwrap(), "1.0");
+        assert!(matches!(data.get_var("c"), Some(Value::Int(3))));
+        // No remainder is pushed onto the stack
+        assert_eq!(data.stack_depth(), 0);
+    }
+
+    #[test]
+    fn power() {
+        let data = run_string_program(vec!["let $a = $x ^ $y", "let $b = $neg ^ $y", "let $c = $x ^ $zero", "let $d = $f ^ $two"],
+                                      &[("x", "2"), ("y", "3"), ("neg", "-2"), ("zero", "0"), ("f", "1.5"), ("two", "2")]).unwrap();
+        assert!(matches!(data.get_var("a"), Some(Value::Int(8))));
+        assert!(matches!(data.get_var("b"), Some(Value::Int(-8))));
+        assert!(matches!(data.get_var("c"), Some(Value::Int(1))));
+        assert_eq!(data.get_var("d").unwrap(), "2.25");
+
+        assert_eq!(run_string_program(vec!["let $a = $x ^ $y"], &[("x", "2"), ("y", "-1")]).err().unwrap(),
+                   "Cannot raise $x to the negative power $y!");
+        assert_eq!(run_string_program(vec!["let $a = $x ^ $y"], &[("x", "2"), ("y", "127")]).err().unwrap(),
+                   "The result of $x and $y is too large to hold!");
+        assert_eq!(run_string_program(vec!["let $a = $x ^ $y"], &[("x", "2"), ("y", "5000000000")]).err().unwrap(),
+                   "The result of $x and $y is too large to hold!");
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        let data = run_string_program(
+            vec!["let $a = $x & $y", "let $b = $x | $y", "let $c = $x xor $y", "let $d = $x << $two", "let $e = $neg >> $two"],
+            &[("x", "12"), ("y", "10"), ("two", "2"), ("neg", "-12")]).unwrap();
+        assert!(matches!(data.get_var("a"), Some(Value::Int(8))));
+        assert!(matches!(data.get_var("b"), Some(Value::Int(14))));
+        assert!(matches!(data.get_var("c"), Some(Value::Int(6))));
+        assert!(matches!(data.get_var("d"), Some(Value::Int(48))));
+        // Shifting right keeps the sign
+        assert!(matches!(data.get_var("e"), Some(Value::Int(-3))));
+
+        assert_eq!(run_string_program(vec!["let $a = $x & $y"], &[("x", "12"), ("y", "1.5")]).err().unwrap(),
+                   "$y is not an integer value!");
+        assert_eq!(run_string_program(vec!["let $a = $x << $y"], &[("x", "1"), ("y", "128")]).err().unwrap(),
+                   "Cannot shift $x by $y bits!");
+        assert_eq!(run_string_program(vec!["let $a = $x >> $y"], &[("x", "1"), ("y", "-1")]).err().unwrap(),
+                   "Cannot shift $x by $y bits!");
+        assert_eq!(run_string_program(vec!["let $a = $x << $y"], &[("x", "3"), ("y", "126")]).err().unwrap(),
+                   "The result of $x and $y is too large to hold!");
+    }
+
+    #[test]
+    fn compound_conditions() {
+        let vars = [("one", "1"), ("two", "2")];
+        let branch = |condition: &str| {
+            let code = format!("if {} let $taken = $one", condition);
+            run_string_program(vec![&code], &vars).map(|data| data.contains_var("taken"))
+        };
+        assert_eq!(branch("$one < $two and $two > $one"), Ok(true));
+        assert_eq!(branch("$one < $two and $two < $one"), Ok(false));
+        assert_eq!(branch("$one > $two or $two > $one"), Ok(true));
+        assert_eq!(branch("not $one = $two"), Ok(true));
+        assert_eq!(branch("not $one = $one or $one = $two"), Ok(false));
+        // 'and' binds tighter than 'or'
+        assert_eq!(branch("$one = $one or $one = $two and $one = $two"), Ok(true));
+
+        // Comparisons that are not needed are not made
+        assert_eq!(branch("$one = $one or $missing = $one"), Ok(true));
+        assert_eq!(branch("$one = $two and $missing = $one"), Ok(false));
+        assert_eq!(branch("$one = $two or $missing = $one").err().unwrap(), "Variable $missing does not exist!");
+    }
+
+    #[test]
+    fn else_clauses() {
+        let vars = [("one", "1"), ("two", "2")];
+        let data = run_string_program(vec!["if $one > $two goto 2 else goto 3", "quit", "let $a = $one", "let $b = $one"],
+                                      &vars).unwrap();
+        assert!(!data.contains_var("a"));
+        assert!(data.contains_var("b"));
+
+        let data = run_string_program(vec!["if $one > $two let $a = $one else let $a = $two", "let $b = $a"], &vars).unwrap();
+        assert_eq!(data.get_var("b").unwrap(), "2");
+
+        // An 'else' belongs to the closest 'if' before it
+        let data = run_string_program(vec!["if $one < $two if $one > $two let $a = $one else let $a = $two"], &vars).unwrap();
+        assert_eq!(data.get_var("a").unwrap(), "2");
+        let data = run_string_program(vec!["if $one > $two let $a = $one else if $one = $one let $a = $two else quit"],
+                                      &vars).unwrap();
+        assert_eq!(data.get_var("a").unwrap(), "2");
+    }
+
+    #[test]
+    fn maps() {
+        let data = run_string_program(
+            vec!["map $m", "let $m{\"name\"} = $x", "let $m{$k} = 5", "let $m{\"name\"} = \"bob\"", "let $a = $m{\"name\"}",
+                 "let $b = $m{\"age\"}"],
+            &[("x", "alice"), ("k", "age")]).unwrap();
+        assert_eq!(data.get_var("a").unwrap(), "bob");
+        assert!(matches!(data.get_var("b"), Some(Value::Int(5))));
+        assert_eq!(data.get_var("m").unwrap().to_string(), "{age: 5, name: bob}");
+
+        assert_eq!(run_string_program(vec!["map $m", "let $a = $m{\"name\"}"], &[]).err().unwrap(),
+                   "$m has no entry \"name\"!");
+        assert_eq!(run_string_program(vec!["let $m{\"name\"} = 5"], &[("m", "5")]).err().unwrap(), "$m is not a map!");
+        assert_eq!(run_string_program(vec!["let $a = $m{\"name\"}"], &[]).err().unwrap(), "Variable $m does not exist!");
+        assert_eq!(run_string_program(vec!["map $m", "let $m{$k} = 5"], &[]).err().unwrap(), "Variable $k does not exist!");
+    }
+
+    #[test]
+    fn compare_floats_by_value() {
+        let mut data = ProgramData::new(
+            vec![String::from("if $a < $b goto 2"), String::from("quit"), String::from("quit")],
+            HashMap::from([(String::from("a"), String::from("2.5")), (String::from("b"), String::from("10"))]),
+            LinkedList::new(),
+            0
+        );
+        data.set_dialect(Dialect::V2);
+        let res = IfState{}.execute(data);
+        assert_eq!(res.unwrap().0.get_index(), 2)
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+use crate::json::Json;
+use crate::prog_data::ProgramData;
+use crate::runner::Observer;
+
+/// A summary of how a program ran.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of instructions that were run
+    pub instructions: u64,
+    /// Number of instructions that were run, by the keyword they start with
+    pub per_kind: BTreeMap<String, u64>,
+    /// Largest number of values that were on the stack at once
+    pub max_stack_depth: usize,
+    /// Number of variables that were set when the program ended
+    pub variables: usize,
+    /// How long the program ran for
+    pub wall_time: Duration,
+}
+
+impl Stats {
+    /// Writes the stats as a JSON object, with the wall time in microseconds.
+    pub fn to_json(&self) -> String {
+        let count = |value: u64| Json::Number(i64::try_from(value).unwrap_or(i64::MAX));
+        Json::Object(vec![
+            (String::from("instructions"), count(self.instructions)),
+            (String::from("per_kind"), Json::Object(self.per_kind.iter()
+                .map(|(kind, &runs)| (kind.clone(), count(runs)))
+                .collect())),
+            (String::from("max_stack_depth"), count(self.max_stack_depth as u64)),
+            (String::from("variables"), count(self.variables as u64)),
+            (String::from("wall_time_us"), count(u64::try_from(self.wall_time.as_micros()).unwrap_or(u64::MAX))),
+        ]).to_string()
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Instructions executed: {}", self.instructions)?;
+        for (kind, runs) in &self.per_kind {
+            writeln!(f, "  {}: {}", kind, runs)?;
+        }
+        writeln!(f, "Max stack depth: {}", self.max_stack_depth)?;
+        writeln!(f, "Variables: {}", self.variables)?;
+        write!(f, "Wall time: {:?}", self.wall_time)
+    }
+}
+
+/// Collects the stats of a program, when passed to `runner::run_observed`. The wall time is
+/// counted from when the collector is created.
+pub struct StatsCollector {
+    stats: Stats,
+    started: Instant,
+}
+
+impl StatsCollector {
+    pub fn new() -> Self {
+        StatsCollector { stats: Stats::default(), started: Instant::now() }
+    }
+
+    /// Returns the stats collected so far.
+    pub fn finish(mut self) -> Stats {
+        self.stats.wall_time = self.started.elapsed();
+        self.stats
+    }
+}
+
+impl Default for StatsCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Observer for StatsCollector {
+    fn before_instruction(&mut self, data: &ProgramData) {
+        let code = data.get_code().unwrap_or_default();
+        let kind = code.split_whitespace().next().unwrap_or("");
+        self.stats.instructions += 1;
+        match self.stats.per_kind.get_mut(kind) {
+            Some(runs) => *runs += 1,
+            None => {
+                self.stats.per_kind.insert(kind.to_string(), 1);
+            },
+        }
+    }
+
+    fn after_instruction(&mut self, data: &ProgramData, _error: Option<&str>) -> Result<(), String> {
+        self.stats.max_stack_depth = self.stats.max_stack_depth.max(data.stack_depth());
+        self.stats.variables = data.var_count();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, LinkedList};
+    use std::time::Duration;
+    use crate::prog_data::ProgramData;
+    use crate::runner::run_observed;
+    use super::StatsCollector;
+
+    fn program(lines: &[&str]) -> ProgramData {
+        ProgramData::new(lines.iter().map(|line| line.to_string()).collect(), HashMap::new(), LinkedList::new(), 0)
+    }
+
+    #[test]
+    fn collect_stats() {
+        let data = program(&["let $a = 1", "push $a", "push $a", "let $b = pop", "let $c = pop", "if $b = $c goto 7",
+                             "output $a", "let $d = 2"]);
+        let mut collector = StatsCollector::new();
+        run_observed(data, &mut collector).ok().unwrap();
+        let mut stats = collector.finish();
+        assert_eq!(stats.instructions, 7);
+        assert_eq!(stats.per_kind.get("let"), Some(&4));
+        assert_eq!(stats.per_kind.get("push"), Some(&2));
+        assert_eq!(stats.per_kind.get("output"), None);
+        assert_eq!(stats.max_stack_depth, 2);
+        assert_eq!(stats.variables, 4);
+
+        stats.wall_time = Duration::from_micros(1500);
+        assert_eq!(stats.to_string(), "Instructions executed: 7\n  if: 1\n  let: 4\n  push: 2\nMax stack depth: 2\n\
+                                       Variables: 4\nWall time: 1.5ms");
+        assert_eq!(stats.to_json(), "{\"instructions\":7,\"per_kind\":{\"if\":1,\"let\":4,\"push\":2},\
+                                     \"max_stack_depth\":2,\"variables\":4,\"wall_time_us\":1500}");
+    }
+
+    #[test]
+    fn collect_stats_of_failed_program() {
+        let mut collector = StatsCollector::new();
+        assert!(run_observed(program(&["let $a = 1", "let $b = pop"]), &mut collector).is_err());
+        let stats = collector.finish();
+        assert_eq!(stats.instructions, 2);
+        assert_eq!(stats.variables, 1);
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::{HashMap, LinkedList};
+use std::sync::Arc;
+use crate::parser::ParsedCode;
+use crate::prog_data::Value;
+
+/// Stores the variables of a program, keyed by name (without the leading '$').
+pub trait VariableStore: Send + Sync {
+    fn get(&self, key: &str) -> Option<&Value>;
+
+    fn set(&mut self, key: String, value: Value);
+
+    /// Removes a variable, returning its value if it was set.
+    fn remove(&mut self, key: &str) -> Option<Value>;
+
+    /// Returns every variable and its value, in no particular order.
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &Value)> + '_>;
+
+    fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns the number of variables that are set.
+    fn count(&self) -> usize {
+        self.iter().count()
+    }
+}
+
+/// Stores the values pushed onto the stack by a program.
+pub trait StackStore: Send + Sync {
+    fn push(&mut self, value: Value);
+
+    /// Removes and returns the most recently pushed value.
+    fn pop(&mut self) -> Option<Value>;
+
+    /// Returns every value on the stack, starting with the most recently pushed value.
+    fn iter(&self) -> Box<dyn Iterator<Item = &Value> + '_>;
+
+    /// Returns the number of values on the stack.
+    fn depth(&self) -> usize {
+        self.iter().count()
+    }
+}
+
+/// Stores the instructions of a program, indexed by line number.
+pub trait CodeStore: Send + Sync {
+    fn get(&self, index: usize) -> Option<&String>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the instructions of the code, if they were parsed when it was loaded. Otherwise
+    /// the program data parses the code itself when it is created.
+    fn parsed(&self) -> Option<Arc<ParsedCode>> {
+        None
+    }
+}
+
+impl VariableStore for HashMap<String, Value> {
+    fn get(&self, key: &str) -> Option<&Value> {
+        HashMap::get(self, key)
+    }
+
+    fn set(&mut self, key: String, value: Value) {
+        self.insert(key, value);
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Value> {
+        HashMap::remove(self, key)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &Value)> + '_> {
+        Box::new(HashMap::iter(self))
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.contains_key(key)
+    }
+
+    fn count(&self) -> usize {
+        self.len()
+    }
+}
+
+impl StackStore for LinkedList<Value> {
+    fn push(&mut self, value: Value) {
+        self.push_front(value);
+    }
+
+    fn pop(&mut self) -> Option<Value> {
+        self.pop_front()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = &Value> + '_> {
+        Box::new(LinkedList::iter(self))
+    }
+
+    fn depth(&self) -> usize {
+        self.len()
+    }
+}
+
+impl CodeStore for Vec<String> {
+    fn get(&self, index: usize) -> Option<&String> {
+        self.as_slice().get(index)
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, LinkedList};
+    use std::sync::{Arc, Mutex};
+    use crate::prog_data::{ProgramData, Value};
+    use crate::runner::run;
+    use super::VariableStore;
+
+    // Variable store that records every assignment, as a teaching visualization might
+    struct RecordingStore {
+        vars: HashMap<String, Value>,
+        writes: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl VariableStore for RecordingStore {
+        fn get(&self, key: &str) -> Option<&Value> {
+            self.vars.get(key)
+        }
+
+        fn set(&mut self, key: String, value: Value) {
+            self.writes.lock().unwrap().push(format!("${} = {}", key, value));
+            self.vars.insert(key, value);
+        }
+
+        fn remove(&mut self, key: &str) -> Option<Value> {
+            self.vars.remove(key)
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = (&String, &Value)> + '_> {
+            Box::new(self.vars.iter())
+        }
+    }
+
+    #[test]
+    fn run_program_with_custom_variable_store() {
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let store = RecordingStore { vars: HashMap::new(), writes: writes.clone() };
+        let code: Vec<String> = vec!["let $a = 2", "let $b = $a", "let $c = $a * $b", "quit"]
+            .into_iter().map(String::from).collect();
+        let data = ProgramData::with_storage(Box::new(code), Box::new(store), Box::new(LinkedList::new()), 0);
+
+        let outcome = run(data).ok().unwrap();
+        assert_eq!(outcome.data.get_var(&String::from("c")).unwrap(), "4");
+        assert_eq!(*writes.lock().unwrap(), vec!["$a = 2", "$b = 2", "$c = 4"]);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::prog_data::ProgramData;
+use crate::runner::run;
+use crate::parser::{parse_line, Instruction};
+use crate::states::find_endtest;
+
+/// Result of running a single testcase block.
+pub struct TestResult {
+    pub name: String,
+    pub error: Option<String>,
+}
+
+/// Finds every 'testcase <name>' block in a program.
+///
+/// # Returns
+/// * `Vec<(String, usize)>` - The name of each testcase along with the line it starts on
+pub fn find_testcases(code: &[String]) -> Vec<(String, usize)> {
+    code.iter()
+        .enumerate()
+        .filter_map(|(index, line)| match parse_line(line) {
+            Ok(Instruction::Testcase(name)) => Some((name, index)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Runs every testcase block in a program. Each testcase is started on the line after its
+/// 'testcase' statement with fresh variables and an empty stack. A testcase passes if it reaches
+/// its 'endtest' (or the program quits) without a failed assertion or runtime error.
+///
+/// # Arguments
+/// * `new_data` - Creates the program data each testcase runs against
+///
+/// # Returns
+/// * `Ok(Vec<TestResult>)` - The result of each testcase, in the order they appear in the program
+/// * `Err(String)` - A message detailing why the testcases could not be run
+pub fn run_testcases<F>(mut new_data: F) -> Result<Vec<TestResult>, String>
+    where F: FnMut() -> Result<ProgramData, String> {
+    let template = new_data()?;
+    let code: Vec<String> = (0..template.code_size())
+        .map(|index| template.get_code_at(index).unwrap().clone())
+        .collect();
+
+    let mut results = Vec::new();
+    for (name, start) in find_testcases(&code) {
+        if find_endtest(&template, start).is_none() {
+            return Err(format!("Testcase {} has no matching endtest!", name));
+        }
+
+        let mut data = new_data()?;
+        data.set_index(start + 1);
+        let error = run_testcase(data);
+        results.push(TestResult { name, error });
+    }
+    Ok(results)
+}
+
+/// Runs a single testcase, returning the reason it failed (if it did).
+fn run_testcase(data: ProgramData) -> Option<String> {
+    run(data).err().map(|error| error.message)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, LinkedList};
+    use crate::prog_data::ProgramData;
+    use super::{find_testcases, run_testcases};
+
+    fn program(lines: Vec<&str>) -> Vec<String> {
+        lines.into_iter().map(String::from).collect()
+    }
+
+    #[test]
+    fn find_testcase_blocks() {
+        let code = program(vec!["quit", "testcase first", "endtest", "testcase second", "endtest"]);
+        assert_eq!(find_testcases(&code), vec![(String::from("first"), 1), (String::from("second"), 3)]);
+    }
+
+    #[test]
+    fn run_passing_and_failing_testcases() {
+        let code = program(vec![
+            "let $a = 1",
+            "quit",
+            "testcase passes",
+            "let $a = 2",
+            "let $b = 2",
+            "assert $a = $b",
+            "endtest",
+            "testcase fails",
+            "let $b = 3",
+            "assert $a = $b",
+            "endtest",
+        ]);
+        let results = run_testcases(|| Ok(ProgramData::new(code.clone(), HashMap::new(), LinkedList::new(), 0))).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].name, "passes");
+        assert!(results[0].error.is_none());
+        assert_eq!(results[1].name, "fails");
+        // $a was set by the previous testcase, which must not leak into this one
+        assert_eq!(results[1].error.as_ref().unwrap(), "Variable $a does not exist!");
+    }
+
+    #[test]
+    fn run_testcase_without_endtest() {
+        let code = program(vec!["testcase broken", "quit"]);
+        let result = run_testcases(|| Ok(ProgramData::new(code.clone(), HashMap::new(), LinkedList::new(), 0)));
+        assert_eq!(result.err().unwrap(), "Testcase broken has no matching endtest!");
+    }
+}
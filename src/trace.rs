@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use crate::json::{self, Json};
+use crate::prog_data::ProgramData;
+use crate::runner::{run_observed, Observer, Outcome, RuntimeError};
+
+/// A single instruction of an execution trace, along with the variables it set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceStep {
+    /// The line the instruction is on
+    pub index: usize,
+    /// The instruction that was run
+    pub code: String,
+    /// The variables the instruction set, and the values it set them to
+    pub writes: Vec<(String, String)>,
+    /// The error the instruction failed with, if it did
+    pub error: Option<String>,
+}
+
+/// Writes every instruction a program runs to a trace, when passed to `runner::run_observed`.
+/// Each instruction is written as 'line: instruction', followed by an indented '$var = "value"'
+/// line for every variable it changed, and an indented 'error: message' line if it failed.
+pub struct TraceWriter<W: Write> {
+    trace: W,
+    step: Option<TraceStep>,
+    before: BTreeMap<String, String>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(trace: W) -> Self {
+        TraceWriter { trace, step: None, before: BTreeMap::new() }
+    }
+
+    /// Writes a step to the trace.
+    fn write_step(&mut self, step: &TraceStep) -> std::io::Result<()> {
+        writeln!(self.trace, "{}: {}", step.index, step.code)?;
+        for (name, value) in &step.writes {
+            writeln!(self.trace, "  ${} = {}", name, Json::String(value.clone()))?;
+        }
+        if let Some(error) = &step.error {
+            writeln!(self.trace, "  error: {}", error)?;
+        }
+        self.trace.flush()
+    }
+}
+
+impl<W: Write> Observer for TraceWriter<W> {
+    fn before_instruction(&mut self, data: &ProgramData) {
+        self.step = Some(TraceStep {
+            index: data.get_index(),
+            code: data.get_code().unwrap_or_default().to_string(),
+            writes: Vec::new(),
+            error: None,
+        });
+        self.before = data.iter_vars().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+    }
+
+    fn after_instruction(&mut self, data: &ProgramData, error: Option<&str>) -> Result<(), String> {
+        let Some(mut step) = self.step.take() else {
+            return Ok(());
+        };
+        step.writes = data.iter_vars()
+            .filter(|(name, value)| self.before.get(*name).map(String::as_str) != Some(&value.as_text()))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        step.error = error.map(String::from);
+        self.write_step(&step).map_err(|err| format!("Could not write the trace: {}", err))
+    }
+}
+
+/// Runs a program like `runner::run`, writing every instruction it runs to `trace` in the
+/// format described for `TraceWriter`.
+///
+/// # Arguments
+/// * `data` - The program to run, along with its variables, stack and console
+/// * `trace` - Where the trace is written to
+///
+/// # Returns
+/// * `Ok(Outcome)` - The exit code and the final program data of a program that finished
+/// * `Err(RuntimeError)` - The error that stopped the program, or the trace could not be written
+pub fn run_traced(data: ProgramData, trace: &mut dyn Write) -> Result<Outcome, RuntimeError> {
+    run_observed(data, &mut TraceWriter::new(trace))
+}
+
+/// Parses an execution trace written by `run_traced`.
+///
+/// # Returns
+/// * `Ok(Vec<TraceStep>)` - The instructions of the trace, in the order they were run
+/// * `Err(String)` - A message detailing the first line that is not part of a trace
+pub fn parse_trace(trace: &str) -> Result<Vec<TraceStep>, String> {
+    let mut steps: Vec<TraceStep> = Vec::new();
+    for (number, line) in trace.lines().enumerate() {
+        let invalid = || format!("Line {} is not part of a trace: {}", number + 1, line);
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(detail) = line.strip_prefix("  ") {
+            let step = steps.last_mut().ok_or_else(invalid)?;
+            if let Some(error) = detail.strip_prefix("error: ") {
+                step.error = Some(error.to_string());
+                continue;
+            }
+            let (name, value) = detail.strip_prefix('$').and_then(|detail| detail.split_once(" = ")).ok_or_else(invalid)?;
+            let value = json::parse(value).ok().and_then(|value| value.as_str().map(String::from)).ok_or_else(invalid)?;
+            step.writes.push((name.to_string(), value));
+            continue;
+        }
+        let (index, code) = line.split_once(": ").ok_or_else(invalid)?;
+        steps.push(TraceStep {
+            index: index.parse().map_err(|_| invalid())?,
+            code: code.to_string(),
+            writes: Vec::new(),
+            error: None,
+        });
+    }
+    Ok(steps)
+}
+
+/// Loads an execution trace from the file at `file_path`.
+pub fn load_trace(file_path: &str) -> Result<Vec<TraceStep>, String> {
+    let trace = fs::read_to_string(file_path).map_err(|err| format!("{}: {}", file_path, err))?;
+    parse_trace(&trace).map_err(|err| format!("{}: {}", file_path, err))
+}
+
+/// Describes a step of a trace for a divergence report.
+fn describe(step: Option<&TraceStep>) -> String {
+    match step {
+        None => String::from("(end of trace)"),
+        Some(TraceStep { index, code, error: Some(error), .. }) => format!("{}: {} (error: {})", index, code, error),
+        Some(TraceStep { index, code, .. }) => format!("{}: {}", index, code),
+    }
+}
+
+/// Compares two execution traces and reports the first instruction at which they diverge: where
+/// they run different instructions, or the same instruction sets different values or fails on
+/// one side only.
+///
+/// # Arguments
+/// * `names` - The names the traces are shown with in the report, such as their file names
+/// * `first` / `second` - The traces to compare
+///
+/// # Returns
+/// * `Ok(usize)` - The number of instructions in the traces, which are identical
+/// * `Err(String)` - A report of the first divergence, showing the instruction run on each side
+///   and the variables as they were on each side. Variables with the same value on both sides
+///   show a single value.
+pub fn diff_traces(names: (&str, &str), first: &[TraceStep], second: &[TraceStep]) -> Result<usize, String> {
+    let mut vars = (BTreeMap::new(), BTreeMap::new());
+    let mut steps = 0;
+    loop {
+        let (a, b) = (first.get(steps), second.get(steps));
+        let diverged = match (a, b) {
+            (None, None) => return Ok(steps),
+            (Some(a), Some(b)) if a == b => false,
+            // The same instruction gave different results, which are shown along with the variables
+            (Some(a), Some(b)) if (a.index, &a.code) == (b.index, &b.code) => {
+                vars.0.extend(a.writes.iter().cloned());
+                vars.1.extend(b.writes.iter().cloned());
+                true
+            },
+            _ => true,
+        };
+        if diverged {
+            let width = names.0.len().max(names.1.len()) + 1;
+            let mut report = format!("Traces diverge at step {} (after {} matching steps):\n", steps + 1, steps);
+            report.push_str(&format!("  {:<width$} {}\n", format!("{}:", names.0), describe(a)));
+            report.push_str(&format!("  {:<width$} {}\n", format!("{}:", names.1), describe(b)));
+            report.push_str(&format!("Variables ({} | {}):", names.0, names.1));
+            let mut names: Vec<&String> = vars.0.keys().chain(vars.1.keys()).collect();
+            names.sort();
+            names.dedup();
+            let not_set = String::from("(not set)");
+            for name in names {
+                let values = (vars.0.get(name).unwrap_or(&not_set), vars.1.get(name).unwrap_or(&not_set));
+                if values.0 == values.1 {
+                    report.push_str(&format!("\n  ${} = {}", name, values.0));
+                } else {
+                    report.push_str(&format!("\n  ${} = {} | {}", name, values.0, values.1));
+                }
+            }
+            return Err(report);
+        }
+        if let (Some(a), Some(b)) = (a, b) {
+            vars.0.extend(a.writes.iter().cloned());
+            vars.1.extend(b.writes.iter().cloned());
+        }
+        steps += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, LinkedList};
+    use crate::console::BufferConsole;
+    use crate::prog_data::ProgramData;
+    use super::{diff_traces, load_trace, parse_trace, run_traced, TraceStep};
+
+    fn program(lines: &[&str], inputs: &[&str]) -> ProgramData {
+        let mut data = ProgramData::new(lines.iter().map(|line| line.to_string()).collect(),
+                                        HashMap::new(), LinkedList::new(), 0);
+        data.set_console(Box::new(BufferConsole::new(&inputs.join("\n"))));
+        data
+    }
+
+    fn trace(lines: &[&str], inputs: &[&str]) -> String {
+        let mut trace = Vec::new();
+        let _ = run_traced(program(lines, inputs), &mut trace);
+        String::from_utf8(trace).unwrap()
+    }
+
+    const MAX: [&str; 6] = ["let $a = input", "let $b = input", "if $a < $b goto 4", "output $a", "quit",
+                            "output $b"];
+
+    #[test]
+    fn trace_instructions_and_writes() {
+        assert_eq!(trace(&MAX, &["3", "5"]), "0: let $a = input\n  $a = \"3\"\n1: let $b = input\n  $b = \"5\"\n\
+                                               2: if $a < $b goto 4\n4: quit\n");
+        assert_eq!(trace(&["let $a = pop"], &[]), "0: let $a = pop\n  error: Stack is empty!\n");
+    }
+
+    #[test]
+    fn parse_written_trace() {
+        let steps = parse_trace(&trace(&["let $a = input all", "let $b = pop"], &["x", "y"])).unwrap();
+        assert_eq!(steps, vec![
+            TraceStep { index: 0, code: String::from("let $a = input all"), writes: vec![(String::from("a"), String::from("x\ny"))], error: None },
+            TraceStep { index: 1, code: String::from("let $b = pop"), writes: Vec::new(), error: Some(String::from("Stack is empty!")) },
+        ]);
+        assert_eq!(parse_trace("0: quit\n  $a 5\n").err().unwrap(), "Line 2 is not part of a trace:   $a 5");
+        assert!(load_trace("testfiles/missing.trace").err().unwrap().starts_with("testfiles/missing.trace: "));
+    }
+
+    #[test]
+    fn identical_traces() {
+        let steps = parse_trace(&trace(&MAX, &["3", "5"])).unwrap();
+        assert_eq!(diff_traces(("a", "b"), &steps, &steps), Ok(4));
+    }
+
+    #[test]
+    fn traces_taking_different_branches() {
+        let a = parse_trace(&trace(&MAX, &["3", "5"])).unwrap();
+        let b = parse_trace(&trace(&MAX, &["3", "1"])).unwrap();
+        assert_eq!(diff_traces(("a.trace", "b.trace"), &a, &b).err().unwrap(),
+                   "Traces diverge at step 2 (after 1 matching steps):\n  a.trace: 1: let $b = input\n  b.trace: 1: let $b = input\n\
+                    Variables (a.trace | b.trace):\n  $a = 3\n  $b = 5 | 1");
+
+        let a = parse_trace("0: let $a = 1\n  $a = \"1\"\n1: goto 3\n3: quit\n").unwrap();
+        let b = parse_trace("0: let $a = 1\n  $a = \"1\"\n1: goto 3\n").unwrap();
+        assert_eq!(diff_traces(("a", "bb"), &a, &b).err().unwrap(),
+                   "Traces diverge at step 3 (after 2 matching steps):\n  a:  3: quit\n  bb: (end of trace)\n\
+                    Variables (a | bb):\n  $a = 1");
+    }
+}
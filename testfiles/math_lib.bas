@@ -0,0 +1,12 @@
+# Greatest common divisor, by Euclid's algorithm
+0 func gcd $a $b
+1 let $zero = 0
+2 label top
+3 if $b = $zero goto done
+4 let $r = $a % $b
+5 let $a = $b
+6 let $b = $r
+7 goto top
+8 label done
+9 return $a
+10 endfunc
@@ -0,0 +1,5 @@
+0 macro swap(a, b)
+1 let $tmp = $a
+2 let $a = $b
+3 let $b = $tmp
+4 endmacro
@@ -0,0 +1,5 @@
+0 let $a = "hello"
+1 let $b = "world"
+2 swap($a, $b)
+3 output $a
+4 quit